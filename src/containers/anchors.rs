@@ -20,8 +20,10 @@
 // limitations under the License.
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::vec;
 
+use amplify::confinement::Confined;
 use amplify::ByteArray;
 use bp::dbc::opret::OpretProof;
 use bp::dbc::tapret::TapretProof;
@@ -40,7 +42,7 @@ use crate::{MergeReveal, MergeRevealError, TypedAssignsExt, LIB_NAME_RGB_STD};
 
 #[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
 #[display("state transition {0} is not a part of the bundle.")]
-pub struct UnrelatedTransition(OpId, Transition);
+pub struct UnrelatedTransition(OpId, Box<Transition>);
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
 #[display(doc_comments)]
@@ -304,13 +306,18 @@ impl<D: dbc::Proof> ClientBundle<D> {
     #[inline]
     pub fn bundle_id(&self) -> BundleId { self.bundle.bundle_id() }
 
+    #[inline]
+    pub fn known_transitions(&self) -> impl Iterator<Item = &Transition> {
+        self.bundle.known_transitions.values()
+    }
+
     pub fn reveal_transition(
         &mut self,
         transition: Transition,
     ) -> Result<bool, UnrelatedTransition> {
         let opid = transition.id();
         if self.bundle.input_map.values().all(|id| *id != opid) {
-            return Err(UnrelatedTransition(opid, transition));
+            return Err(UnrelatedTransition(opid, Box::new(transition)));
         }
         if self.bundle.known_transitions.contains_key(&opid) {
             return Ok(false);
@@ -321,6 +328,31 @@ impl<D: dbc::Proof> ClientBundle<D> {
             .expect("same size as input map");
         Ok(true)
     }
+
+    /// Keeps only the known transitions for which `f` returns `true`, allowing
+    /// them to be modified (for instance, concealed) in place. Since the
+    /// bundle id doesn't commit to the known transitions, it is not affected.
+    ///
+    /// If none of the transitions are kept, the bundle is left unchanged and
+    /// `false` is returned.
+    pub fn retain_transitions(&mut self, mut f: impl FnMut(OpId, &mut Transition) -> bool) -> bool {
+        let known = self
+            .bundle
+            .known_transitions
+            .iter()
+            .filter_map(|(opid, transition)| {
+                let mut transition = transition.clone();
+                f(*opid, &mut transition).then_some((*opid, transition))
+            })
+            .collect::<BTreeMap<_, _>>();
+        match Confined::try_from(known) {
+            Ok(known) => {
+                self.bundle.known_transitions = known;
+                true
+            }
+            Err(_) => false,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -42,10 +42,11 @@ use rgbcore::validation::ConsignmentApi;
 use strict_encoding::{StrictDeserialize, StrictDumb, StrictSerialize};
 use strict_types::TypeSystem;
 
+use super::util::strip_sigs;
 use super::{
-    ContainerVer, ContentId, ContentSigs, IndexedConsignment, Supplement, WitnessBundle,
-    ASCII_ARMOR_CONSIGNMENT_TYPE, ASCII_ARMOR_CONTRACT, ASCII_ARMOR_IFACE, ASCII_ARMOR_SCHEMA,
-    ASCII_ARMOR_TERMINAL, ASCII_ARMOR_VERSION,
+    ContainerVer, ContentId, ContentSigs, IndexedConsignment, SigValidator, Supplement,
+    WitnessBundle, ASCII_ARMOR_CONSIGNMENT_TYPE, ASCII_ARMOR_CONTRACT, ASCII_ARMOR_IFACE,
+    ASCII_ARMOR_SCHEMA, ASCII_ARMOR_TERMINAL, ASCII_ARMOR_VERSION,
 };
 use crate::interface::{Iface, IfaceImpl};
 use crate::persistence::{MemContract, MemContractState};
@@ -322,11 +323,13 @@ impl<const TRANSFER: bool> Consignment<TRANSFER> {
         }
     }
 
+    /// Validates the consignment, removing signatures which are invalid or
+    /// untrusted according to the `sig_validator`.
+    #[allow(clippy::result_large_err)]
     pub fn validate(
-        self,
+        mut self,
         resolver: &impl ResolveWitness,
-        // TODO: Add sig validator
-        //_: &impl SigValidator,
+        sig_validator: &impl SigValidator,
         testnet: bool,
     ) -> Result<ValidConsignment<TRANSFER>, (validation::Status, Consignment<TRANSFER>)> {
         let index = IndexedConsignment::new(&self);
@@ -362,8 +365,8 @@ impl<const TRANSFER: bool> Consignment<TRANSFER> {
                 )));
             }
         }
+        strip_sigs(&mut self.signatures, sig_validator, &mut status);
         // TODO: check attach ids from data containers are present in operations
-        // TODO: validate sigs and remove untrusted
         // TODO: Check that all extensions present in the consignment are used by state
         // transitions
 
@@ -19,5 +19,336 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-// TODO: Implement disclosures
-pub struct Disclosure;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::str::FromStr;
+
+use amplify::confinement::{LargeOrdSet, MediumOrdSet, TinyOrdMap};
+use amplify::{ByteArray, Bytes32};
+use armor::{ArmorHeader, AsciiArmor, StrictArmor};
+use baid64::{Baid64ParseError, DisplayBaid64, FromBaid64Str};
+use bp::dbc::Proof;
+use commit_verify::{CommitEncode, CommitEngine, CommitId, CommitmentId, DigestExt, Sha256};
+use rgb::validation::{Failure, ResolveWitness};
+use rgb::{
+    impl_serde_baid64, validation, ContractId, OpId, Operation, Opout, Schema, Transition,
+    XWitnessId,
+};
+use strict_encoding::{StrictDeserialize, StrictSerialize};
+
+use super::util::strip_sigs;
+use super::{
+    ContainerVer, ContentId, ContentSigs, SigValidator, WitnessBundle, ASCII_ARMOR_CONTRACT,
+    ASCII_ARMOR_VERSION,
+};
+use crate::{TypedAssignsExt, LIB_NAME_RGB_STD};
+
+/// Disclosure identifier.
+///
+/// Disclosure identifier commits to all data provided within the disclosure.
+#[derive(Wrapper, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, From)]
+#[wrapper(Deref, BorrowSlice, Hex, Index, RangeOps)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub struct DisclosureId(
+    #[from]
+    #[from([u8; 32])]
+    Bytes32,
+);
+
+impl From<Sha256> for DisclosureId {
+    fn from(hasher: Sha256) -> Self { hasher.finish().into() }
+}
+
+impl CommitmentId for DisclosureId {
+    const TAG: &'static str = "urn:lnp-bp:rgb:disclosure#2024-10-17";
+}
+
+impl DisplayBaid64 for DisclosureId {
+    const HRI: &'static str = "rgb:dis";
+    const CHUNKING: bool = true;
+    const PREFIX: bool = true;
+    const EMBED_CHECKSUM: bool = false;
+    const MNEMONIC: bool = false;
+    fn to_baid64_payload(&self) -> [u8; 32] { self.to_byte_array() }
+}
+impl FromBaid64Str for DisclosureId {}
+impl FromStr for DisclosureId {
+    type Err = Baid64ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Self::from_baid64_str(s) }
+}
+impl Display for DisclosureId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { self.fmt_baid64(f) }
+}
+
+impl_serde_baid64!(DisclosureId);
+
+impl DisclosureId {
+    pub const fn from_array(id: [u8; 32]) -> Self { Self(Bytes32::from_array(id)) }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display("{disclosure}")]
+pub struct ValidDisclosure {
+    /// Status of the latest validation.
+    validation_status: validation::Status,
+    disclosure: Disclosure,
+}
+
+impl ValidDisclosure {
+    pub fn validation_status(&self) -> &validation::Status { &self.validation_status }
+
+    pub fn into_disclosure(self) -> Disclosure { self.disclosure }
+
+    pub fn into_validation_status(self) -> validation::Status { self.validation_status }
+
+    pub fn split(self) -> (Disclosure, validation::Status) {
+        (self.disclosure, self.validation_status)
+    }
+}
+
+impl Deref for ValidDisclosure {
+    type Target = Disclosure;
+
+    fn deref(&self) -> &Self::Target { &self.disclosure }
+}
+
+/// Disclosure reveals the seals and the state of some of the contract
+/// operation outputs to a third party (like an auditor) without transferring
+/// the ownership over them.
+///
+/// The disclosed outputs are provided together with the state transitions
+/// defining them and the witness bundles anchoring the transitions. All other
+/// transitions of the bundles are omitted, and the assignments of the
+/// disclosed transitions which are not a part of the disclosure are
+/// concealed.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(AsciiArmor::to_ascii_armored_string)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct Disclosure {
+    /// Version.
+    pub version: ContainerVer,
+
+    /// Contract which state is disclosed.
+    pub contract_id: ContractId,
+
+    /// Bundles containing the state transitions defining the disclosed
+    /// outputs, together with their anchors and witnesses.
+    pub bundles: LargeOrdSet<WitnessBundle>,
+
+    /// Operation outputs which seals and state are disclosed.
+    pub opouts: MediumOrdSet<Opout>,
+
+    /// Signatures on the pieces of content which are the part of the
+    /// disclosure.
+    pub signatures: TinyOrdMap<ContentId, ContentSigs>,
+}
+
+impl StrictSerialize for Disclosure {}
+impl StrictDeserialize for Disclosure {}
+
+impl CommitEncode for Disclosure {
+    type CommitmentId = DisclosureId;
+
+    fn commit_encode(&self, e: &mut CommitEngine) {
+        e.commit_to_serialized(&self.version);
+        e.commit_to_serialized(&self.contract_id);
+
+        e.commit_to_set(&LargeOrdSet::from_iter_checked(
+            self.bundles.iter().map(WitnessBundle::commit_id),
+        ));
+        e.commit_to_set(&self.opouts);
+
+        e.commit_to_map(&self.signatures);
+    }
+}
+
+impl Disclosure {
+    #[inline]
+    pub fn disclosure_id(&self) -> DisclosureId { self.commit_id() }
+
+    pub fn witness_ids(&self) -> impl Iterator<Item = XWitnessId> + '_ {
+        self.bundles.iter().map(WitnessBundle::witness_id)
+    }
+
+    pub fn known_transitions(&self) -> impl Iterator<Item = &Transition> {
+        self.bundles
+            .iter()
+            .flat_map(WitnessBundle::known_transitions)
+    }
+
+    /// Validates the disclosure against the contract `schema`.
+    ///
+    /// Checks that the transitions belong to the contract and its bundles,
+    /// that the bundles are committed to by their witness transactions and
+    /// that all disclosed outputs are present and revealed. Witness
+    /// transactions which are not included into the disclosure are retrieved
+    /// using the `resolver`. Signatures which are invalid or untrusted
+    /// according to the `sig_validator` are removed.
+    #[allow(clippy::result_large_err)]
+    pub fn validate(
+        mut self,
+        schema: &Schema,
+        resolver: &impl ResolveWitness,
+        sig_validator: &impl SigValidator,
+    ) -> Result<ValidDisclosure, (validation::Status, Disclosure)> {
+        let mut status = validation::Status::new();
+
+        let mut transitions = BTreeMap::<OpId, &Transition>::new();
+        for witness_bundle in &self.bundles {
+            let witness_id = witness_bundle.witness_id();
+            let witness_tx = match witness_bundle.pub_witness.as_reduced_unsafe().tx() {
+                Some(tx) => Ok(tx.clone()),
+                None => resolver
+                    .resolve_pub_witness(witness_id)
+                    .map(|tx| tx.as_reduced_unsafe().clone()),
+            };
+            for (anchor, bundle) in witness_bundle.anchored_bundles() {
+                let bundle_id = bundle.bundle_id();
+                for (opid, transition) in &bundle.known_transitions {
+                    if transition.id() != *opid || bundle.input_map.values().all(|id| id != opid) {
+                        status.add_failure(Failure::BundleExtraTransition(bundle_id, *opid));
+                    }
+                    if transition.contract_id != self.contract_id {
+                        status
+                            .add_failure(Failure::ContractMismatch(*opid, transition.contract_id));
+                    }
+                    if !schema.transitions.contains_key(&transition.transition_type) {
+                        status.add_failure(Failure::SchemaUnknownTransitionType(
+                            *opid,
+                            transition.transition_type,
+                        ));
+                    }
+                    transitions.insert(*opid, transition);
+                }
+
+                let commitment = match anchor.convolve(self.contract_id, bundle_id) {
+                    Ok(commitment) => commitment,
+                    Err(err) => {
+                        status.add_failure(Failure::MpcInvalid(bundle_id, witness_id, err));
+                        continue;
+                    }
+                };
+                match &witness_tx {
+                    Ok(tx) => {
+                        if let Err(err) = anchor.dbc_proof.verify(&commitment, tx) {
+                            status.add_failure(Failure::SealsInvalid(
+                                bundle_id,
+                                witness_id,
+                                err.to_string(),
+                            ));
+                        }
+                    }
+                    Err(err) => {
+                        status.add_failure(Failure::SealNoPubWitness(
+                            bundle_id,
+                            witness_id,
+                            err.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        for opout in &self.opouts {
+            let Some(transition) = transitions.get(&opout.op) else {
+                status.add_failure(Failure::OperationAbsent(opout.op));
+                continue;
+            };
+            let Some(assigns) = transition.assignments.get(&opout.ty) else {
+                status.add_failure(Failure::Custom(format!("disclosed output {opout} is absent")));
+                continue;
+            };
+            match assigns.revealed_seal_at(opout.no) {
+                Err(_) => {
+                    status.add_failure(Failure::Custom(format!(
+                        "disclosed output {opout} is absent"
+                    )));
+                }
+                Ok(None) => {
+                    status.add_failure(Failure::ConfidentialSeal(*opout));
+                }
+                Ok(Some(_)) if !assigns.is_revealed_at(opout.no) => {
+                    status.add_failure(Failure::Custom(format!(
+                        "state of the disclosed output {opout} is concealed"
+                    )));
+                }
+                Ok(Some(_)) => {}
+            }
+        }
+
+        strip_sigs(&mut self.signatures, sig_validator, &mut status);
+        if !status.failures.is_empty() {
+            return Err((status, self));
+        }
+        Ok(ValidDisclosure {
+            validation_status: status,
+            disclosure: self,
+        })
+    }
+}
+
+impl StrictArmor for Disclosure {
+    type Id = DisclosureId;
+    const PLATE_TITLE: &'static str = "RGB DISCLOSURE";
+
+    fn armor_id(&self) -> Self::Id { self.disclosure_id() }
+    fn armor_headers(&self) -> Vec<ArmorHeader> {
+        vec![
+            ArmorHeader::new(ASCII_ARMOR_VERSION, format!("{:#}", self.version)),
+            ArmorHeader::new(ASCII_ARMOR_CONTRACT, self.contract_id.to_string()),
+        ]
+    }
+}
+
+impl FromStr for Disclosure {
+    type Err = armor::StrictArmorError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Self::from_ascii_armored_str(s) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::containers::{ConsignmentExt, Contract, DumbValidator};
+    use crate::interface::resolver::DumbResolver;
+
+    #[test]
+    fn disclosure_str_round_trip_and_validation() {
+        let contract = Contract::from_str(include_str!("../../asset/armored_contract.default"))
+            .expect("contract from str should work");
+        let contract_id = contract.contract_id();
+        let mut disclosure = Disclosure {
+            version: ContainerVer::V2,
+            contract_id,
+            bundles: none!(),
+            opouts: none!(),
+            signatures: none!(),
+        };
+
+        let s = disclosure.to_string();
+        assert!(s.starts_with("-----BEGIN RGB DISCLOSURE-----"));
+        assert_eq!(Disclosure::from_str(&s).expect("disclosure from str should work"), disclosure);
+
+        let valid = disclosure
+            .clone()
+            .validate(&contract.schema, &DumbResolver, &DumbValidator)
+            .expect("empty disclosure is valid");
+        assert_eq!(valid.disclosure_id(), disclosure.disclosure_id());
+
+        let opout = Opout::new(OpId::from_byte_array([1u8; 32]), 0x10.into(), 0);
+        disclosure.opouts.push(opout).unwrap();
+        assert_ne!(valid.disclosure_id(), disclosure.disclosure_id());
+        let (status, _) = disclosure
+            .validate(&contract.schema, &DumbResolver, &DumbValidator)
+            .expect_err("disclosure misses the transition for the output");
+        assert_eq!(status.failures, vec![Failure::OperationAbsent(opout.op)]);
+    }
+}
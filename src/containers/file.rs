@@ -23,10 +23,12 @@ use std::fmt::{self, Debug, Display, Formatter};
 use std::io::{self, Read, Write};
 
 use amplify::confinement::U32 as FILE_MAX_LEN;
+use amplify::ByteArray;
 use armor::{AsciiArmor, StrictArmor};
 use strict_encoding::{StreamReader, StreamWriter, StrictDecode, StrictEncode};
 
-use crate::containers::{Contract, Kit, Transfer};
+use crate::containers::{Contract, Disclosure, Kit, Transfer};
+use crate::persistence::StockBackup;
 
 const RGB_PREFIX: [u8; 4] = *b"RGB\x00";
 const MAGIC_LEN: usize = 3;
@@ -37,6 +39,9 @@ pub enum LoadError {
     /// invalid file data.
     InvalidMagic,
 
+    /// file data don't match their checksum.
+    ChecksumMismatch,
+
     #[display(inner)]
     #[from]
     #[from(io::Error)]
@@ -113,7 +118,46 @@ impl FileContent for Transfer {
     const MAGIC: [u8; MAGIC_LEN] = *b"TFR";
 }
 
-// TODO: Add disclosure
+impl FileContent for Disclosure {
+    const MAGIC: [u8; MAGIC_LEN] = *b"DIS";
+}
+
+/// Stock backup files are followed by the backup id, which is checked on load.
+impl FileContent for StockBackup {
+    const MAGIC: [u8; MAGIC_LEN] = *b"BAK";
+
+    fn load(mut data: impl Read) -> Result<Self, LoadError> {
+        let mut rgb = [0u8; 4];
+        let mut magic = [0u8; MAGIC_LEN];
+        data.read_exact(&mut rgb)?;
+        data.read_exact(&mut magic)?;
+        if rgb != RGB_PREFIX || magic != Self::MAGIC {
+            return Err(LoadError::InvalidMagic);
+        }
+
+        let reader = StreamReader::new::<FILE_MAX_LEN>(&mut data);
+        let me = Self::strict_read(reader)?;
+
+        let mut checksum = [0u8; 32];
+        data.read_exact(&mut checksum)?;
+        if me.backup_id().to_byte_array() != checksum {
+            return Err(LoadError::ChecksumMismatch);
+        }
+
+        Ok(me)
+    }
+
+    fn save(&self, mut writer: impl Write) -> Result<(), io::Error> {
+        writer.write_all(&RGB_PREFIX)?;
+        writer.write_all(&Self::MAGIC)?;
+
+        let stream = StreamWriter::new::<FILE_MAX_LEN>(&mut writer);
+        self.strict_write(stream)?;
+
+        writer.write_all(&self.backup_id().to_byte_array())
+    }
+}
+
 // TODO: Add batch and fascia
 
 #[derive(Clone, Debug, From)]
@@ -131,7 +175,9 @@ pub enum UniversalFile {
 
     #[from]
     Transfer(Transfer),
-    // TODO: Add disclosure
+
+    #[from]
+    Disclosure(Disclosure),
     // TODO: Add batch and fascia
 }
 
@@ -149,6 +195,7 @@ impl UniversalFile {
             x if x == Kit::MAGIC => Kit::strict_read(&mut reader)?.into(),
             x if x == Contract::MAGIC => Contract::strict_read(&mut reader)?.into(),
             x if x == Transfer::MAGIC => Transfer::strict_read(&mut reader)?.into(),
+            x if x == Disclosure::MAGIC => Disclosure::strict_read(&mut reader)?.into(),
             _ => return Err(LoadError::InvalidMagic),
         })
     }
@@ -159,6 +206,7 @@ impl UniversalFile {
             UniversalFile::Kit(_) => Kit::MAGIC,
             UniversalFile::Contract(_) => Contract::MAGIC,
             UniversalFile::Transfer(_) => Transfer::MAGIC,
+            UniversalFile::Disclosure(_) => Disclosure::MAGIC,
         };
         writer.write_all(&magic)?;
 
@@ -168,6 +216,7 @@ impl UniversalFile {
             UniversalFile::Kit(content) => content.strict_write(writer),
             UniversalFile::Contract(content) => content.strict_write(writer),
             UniversalFile::Transfer(content) => content.strict_write(writer),
+            UniversalFile::Disclosure(content) => content.strict_write(writer),
         }
     }
 
@@ -190,6 +239,7 @@ impl Display for UniversalFile {
             UniversalFile::Kit(content) => Display::fmt(&content.display_ascii_armored(), f),
             UniversalFile::Contract(content) => Display::fmt(&content.display_ascii_armored(), f),
             UniversalFile::Transfer(content) => Display::fmt(&content.display_ascii_armored(), f),
+            UniversalFile::Disclosure(content) => Display::fmt(&content.display_ascii_armored(), f),
         }
     }
 }
@@ -208,6 +258,7 @@ mod test {
     #[cfg(feature = "fs")]
     static ARMORED_CONTRACT_PATH: &str = "asset/armored_contract.default";
 
+    #[cfg(feature = "fs")]
     static DEFAULT_TRANSFER_PATH: &str = "asset/transfer.default";
     #[cfg(feature = "fs")]
     static ARMORED_TRANSFER_PATH: &str = "asset/armored_transfer.default";
@@ -355,6 +406,7 @@ mod test {
     }
 
     // A transfer with almost default fields
+    #[cfg(feature = "fs")]
     fn almost_default_transfer() -> Transfer {
         Transfer {
             version: Default::default(),
@@ -114,7 +114,7 @@ impl<'c, const TRANSFER: bool> ConsignmentApi for IndexedConsignment<'c, TRANSFE
 
     fn scripts(&self) -> &Scripts { &self.scripts }
 
-    fn operation(&self, opid: OpId) -> Option<OpRef> {
+    fn operation(&self, opid: OpId) -> Option<OpRef<'_>> {
         if opid == self.genesis.id() {
             return Some(OpRef::Genesis(&self.genesis));
         }
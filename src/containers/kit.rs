@@ -38,7 +38,8 @@ use super::{
     ContentRef, Supplement, ASCII_ARMOR_IFACE, ASCII_ARMOR_IIMPL, ASCII_ARMOR_SCHEMA,
     ASCII_ARMOR_SCRIPT, ASCII_ARMOR_TYPE_SYSTEM, ASCII_ARMOR_VERSION,
 };
-use crate::containers::{ContainerVer, ContentId, ContentSigs};
+use crate::containers::util::strip_sigs;
+use crate::containers::{ContainerVer, ContentId, ContentSigs, SigValidator};
 use crate::interface::{Iface, IfaceImpl};
 use crate::LIB_NAME_RGB_STD;
 
@@ -175,17 +176,19 @@ impl Kit {
     #[inline]
     pub fn kit_id(&self) -> KitId { self.commit_id() }
 
+    /// Validates the kit, removing signatures which are invalid or untrusted
+    /// according to the `sig_validator`.
+    #[allow(clippy::result_large_err)]
     pub fn validate(
-        self,
-        // TODO: Add sig validator
-        //_: &impl SigValidator,
+        mut self,
+        sig_validator: &impl SigValidator,
     ) -> Result<ValidKit, (validation::Status, Kit)> {
-        let status = validation::Status::new();
+        let mut status = validation::Status::new();
+        strip_sigs(&mut self.signatures, sig_validator, &mut status);
         // TODO:
         //  - Verify integrity for each interface
         //  - Verify implementations against interfaces
         //  - Check schema integrity
-        //  - Validate content sigs and remove untrusted ones
         Ok(ValidKit {
             validation_status: status,
             kit: self,
@@ -272,6 +275,7 @@ impl FromStr for Kit {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::containers::DumbValidator;
     #[test]
     fn kit_str_round_trip() {
         let kit = Kit::from_str(include_str!("../../asset/armored_kit.default"))
@@ -281,7 +285,7 @@ mod test {
         assert_eq!(kit.to_string(), hardcoded, "kit string round trip fails");
 
         assert_eq!(
-            kit.validate().unwrap().to_string(),
+            kit.validate(&DumbValidator).unwrap().to_string(),
             hardcoded,
             "validated kit string round trip fails"
         );
@@ -24,7 +24,7 @@
 //! 1. [`Consignment`]s, containing information about partial state of a *single contract*,
 //!    extending from its genesis up to certain contract endpoints.
 //! 2. [`Disclosure`]s, containing extracts from (possibly) independent state transitions and
-//!    extensions under multiple contracts. Useful fro disclosing the concealed state for some other
+//!    extensions under multiple contracts. Useful for disclosing the concealed state for some other
 //!    parties, and also for performing "change" operations on inventory during state transfers.
 
 mod seal;
@@ -46,7 +46,7 @@ pub use consignment::{
     Consignment, ConsignmentExt, ConsignmentId, ConsignmentParseError, Contract, Transfer,
     ValidConsignment, ValidContract, ValidTransfer,
 };
-pub use disclosure::Disclosure;
+pub use disclosure::{Disclosure, DisclosureId, ValidDisclosure};
 pub use file::{FileContent, LoadError, UniversalFile};
 pub use indexed::IndexedConsignment;
 pub use kit::{Kit, KitId, ValidKit};
@@ -61,7 +61,9 @@ pub use suppl::{
     SUPPL_ANNOT_VELOCITY,
 };
 pub use util::{
-    ContainerVer, ContentId, ContentSigs, DumbValidator, SigBlob, SigValidator, TrustLevel,
+    bip340_identity, bip340_key, Bip340Validator, ContainerVer, ContentId, ContentSigs,
+    DumbValidator, SigBlob, SigValidator, TrustLevel, TrustedValidator, CONTENT_SIG_TAG,
+    IDENTITY_BIP340_PREFIX,
 };
 
 pub const ASCII_ARMOR_NAME: &str = "Name";
@@ -20,11 +20,15 @@
 // limitations under the License.
 
 use std::collections::btree_map;
+use std::mem;
+use std::str::FromStr;
 
-use amplify::confinement::{NonEmptyBlob, NonEmptyOrdMap};
-use commit_verify::StrictHash;
+use amplify::confinement::{Confined, NonEmptyBlob, NonEmptyOrdMap, TinyOrdMap};
+use bp::secp256k1::{schnorr, Keypair, XOnlyPublicKey, SECP256K1};
+use commit_verify::{DigestExt, Sha256, StrictHash};
+use rgb::validation::{Info, Status};
 use rgb::{ContractId, Identity, SchemaId};
-use strict_encoding::StrictDumb;
+use strict_encoding::{StrictDumb, StrictSerialize};
 
 use super::SupplId;
 use crate::interface::{IfaceId, ImplId};
@@ -47,13 +51,70 @@ pub enum ContainerVer {
     V2 = 2,
 }
 
+pub const CONTENT_SIG_TAG: &str = "urn:lnp-bp:rgb:content-sig#2024-10-17";
+pub const IDENTITY_BIP340_PREFIX: &str = "ssi:bip340:";
+
+/// Validator of the [`ContentSigs`] made by some [`Identity`] over a
+/// [`ContentId`].
 pub trait SigValidator {
-    fn validate_sig(&self, identity: &Identity, sig: SigBlob) -> bool;
+    fn validate_sig(&self, content_id: ContentId, identity: &Identity, sig: &SigBlob) -> bool;
 }
 
+impl<V: SigValidator> SigValidator for &V {
+    fn validate_sig(&self, content_id: ContentId, identity: &Identity, sig: &SigBlob) -> bool {
+        (*self).validate_sig(content_id, identity, sig)
+    }
+}
+
+/// Validator which rejects all signatures.
 pub struct DumbValidator;
 impl SigValidator for DumbValidator {
-    fn validate_sig(&self, _: &Identity, _: SigBlob) -> bool { false }
+    fn validate_sig(&self, _: ContentId, _: &Identity, _: &SigBlob) -> bool { false }
+}
+
+/// Validator of BIP-340 Schnorr signatures over the [`ContentId::sig_msg`],
+/// made by the identities of `ssi:bip340:<x-only public key in hex>` form.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Bip340Validator;
+impl SigValidator for Bip340Validator {
+    fn validate_sig(&self, content_id: ContentId, identity: &Identity, sig: &SigBlob) -> bool {
+        let Some(key) = bip340_key(identity) else {
+            return false;
+        };
+        let Ok(sig) = schnorr::Signature::from_slice(sig.as_slice()) else {
+            return false;
+        };
+        SECP256K1
+            .verify_schnorr(&sig, &content_id.sig_msg(), &key)
+            .is_ok()
+    }
+}
+
+/// Validator accepting only signatures made by identities which should be
+/// accepted according to their [`TrustLevel`], and which are valid according
+/// to the inner `validator`.
+pub struct TrustedValidator<V: SigValidator, F: Fn(&Identity) -> TrustLevel> {
+    pub validator: V,
+    pub trust: F,
+}
+impl<V: SigValidator, F: Fn(&Identity) -> TrustLevel> SigValidator for TrustedValidator<V, F> {
+    fn validate_sig(&self, content_id: ContentId, identity: &Identity, sig: &SigBlob) -> bool {
+        (self.trust)(identity).should_accept()
+            && self.validator.validate_sig(content_id, identity, sig)
+    }
+}
+
+/// Constructs identity of `ssi:bip340:<x-only public key in hex>` form.
+pub fn bip340_identity(key: XOnlyPublicKey) -> Identity {
+    Identity::from_str(&format!("{IDENTITY_BIP340_PREFIX}{key}"))
+        .expect("BIP-340 identity is always a valid ASCII string")
+}
+
+/// Extracts public key from the identity of `ssi:bip340:<x-only public key in
+/// hex>` form.
+pub fn bip340_key(identity: &Identity) -> Option<XOnlyPublicKey> {
+    let key = identity.as_str().strip_prefix(IDENTITY_BIP340_PREFIX)?;
+    XOnlyPublicKey::from_str(key).ok()
 }
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
@@ -76,12 +137,13 @@ pub enum TrustLevel {
 }
 
 impl TrustLevel {
+    pub fn is_distrusted(self) -> bool { matches!(self, Self::Malicious | Self::Untrusted) }
     pub fn should_accept(self) -> bool { self >= Self::Unknown }
     pub fn should_use(self) -> bool { self >= Self::Trusted }
     pub fn must_use(self) -> bool { self >= Self::Ultimate }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
 #[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
 #[strict_type(lib = LIB_NAME_RGB_STD, tags = order, dumb = ContentId::Schema(strict_dumb!()))]
 #[cfg_attr(
@@ -90,13 +152,32 @@ impl TrustLevel {
     serde(crate = "serde_crate", rename_all = "camelCase")
 )]
 pub enum ContentId {
+    #[display("schema {0}")]
     Schema(SchemaId),
+    #[display("contract {0}")]
     Genesis(ContractId),
+    #[display("interface {0}")]
     Iface(IfaceId),
+    #[display("interface implementation {0}")]
     IfaceImpl(ImplId),
+    #[display("supplement {0}")]
     Suppl(SupplId),
 }
 
+impl StrictSerialize for ContentId {}
+
+impl ContentId {
+    /// Message signed by the [`ContentSigs`] over the content.
+    pub fn sig_msg(&self) -> [u8; 32] {
+        let mut engine = Sha256::from_tag(CONTENT_SIG_TAG);
+        let data = self
+            .to_strict_serialized::<{ u8::MAX as usize }>()
+            .expect("content id has a fixed size");
+        engine.input_raw(&data);
+        engine.finish()
+    }
+}
+
 #[derive(Wrapper, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, From, Display)]
 #[wrapper(Deref, AsSlice, BorrowSlice, Hex)]
 #[display(LowerHex)]
@@ -115,6 +196,15 @@ impl Default for SigBlob {
     fn default() -> Self { SigBlob(NonEmptyBlob::with(0)) }
 }
 
+impl SigBlob {
+    /// Creates BIP-340 Schnorr signature over the [`ContentId::sig_msg`],
+    /// which is valid for the [`bip340_identity`] of the `keypair`.
+    pub fn bip340(content_id: ContentId, keypair: &Keypair) -> Self {
+        let sig = SECP256K1.sign_schnorr(&content_id.sig_msg(), keypair);
+        SigBlob(Confined::try_from(sig.as_ref().to_vec()).expect("signature has 64 bytes"))
+    }
+}
+
 #[derive(Wrapper, WrapperMut, Clone, PartialEq, Eq, Hash, Debug, From)]
 #[wrapper(Deref)]
 #[wrapper_mut(DerefMut)]
@@ -135,3 +225,70 @@ impl IntoIterator for ContentSigs {
 
     fn into_iter(self) -> Self::IntoIter { self.0.into_iter() }
 }
+
+/// Strips invalid and untrusted signatures from a container, reporting them in
+/// the validation `status`.
+pub(super) fn strip_sigs(
+    signatures: &mut TinyOrdMap<ContentId, ContentSigs>,
+    validator: &impl SigValidator,
+    status: &mut Status,
+) {
+    for (content_id, sigs) in mem::take(signatures) {
+        let mut valid = Vec::with_capacity(sigs.len());
+        for (identity, sig) in sigs {
+            if validator.validate_sig(content_id, &identity, &sig) {
+                valid.push((identity, sig));
+            } else {
+                status.add_info(Info::Custom(format!(
+                    "invalid or untrusted signature by {identity} over {content_id} was removed"
+                )));
+            }
+        }
+        if let Ok(sigs) = Confined::try_from_iter(valid) {
+            signatures
+                .insert(content_id, ContentSigs(sigs))
+                .expect("the number of items is not increased");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::ByteArray;
+    use bp::secp256k1::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn bip340_sigs() {
+        let keypair =
+            Keypair::from_secret_key(SECP256K1, &SecretKey::from_slice(&[1u8; 32]).unwrap());
+        let identity = bip340_identity(keypair.x_only_public_key().0);
+        let content_id = ContentId::Schema(SchemaId::from_byte_array([2u8; 32]));
+        let other_id = ContentId::Schema(SchemaId::from_byte_array([3u8; 32]));
+        let sig = SigBlob::bip340(content_id, &keypair);
+
+        assert_eq!(bip340_key(&identity), Some(keypair.x_only_public_key().0));
+        assert!(Bip340Validator.validate_sig(content_id, &identity, &sig));
+        assert!(!Bip340Validator.validate_sig(other_id, &identity, &sig));
+        assert!(!Bip340Validator.validate_sig(content_id, &Identity::default(), &sig));
+        assert!(!DumbValidator.validate_sig(content_id, &identity, &sig));
+
+        let mut signatures = TinyOrdMap::from_checked(bmap! {
+            content_id => ContentSigs(NonEmptyOrdMap::with_key_value(identity.clone(), sig.clone())),
+            other_id => ContentSigs(NonEmptyOrdMap::with_key_value(identity.clone(), sig.clone())),
+        });
+        let mut status = Status::new();
+        strip_sigs(&mut signatures, &Bip340Validator, &mut status);
+        assert_eq!(signatures.len(), 1);
+        assert!(signatures.contains_key(&content_id));
+        assert_eq!(status.info.len(), 1);
+
+        let untrusted = TrustedValidator {
+            validator: Bip340Validator,
+            trust: |_: &Identity| TrustLevel::Malicious,
+        };
+        strip_sigs(&mut signatures, &untrusted, &mut status);
+        assert!(signatures.is_empty());
+    }
+}
@@ -199,6 +199,18 @@ pub trait TypedAssignsExt<Seal: ExposedSeal> {
     fn reveal_seal(&mut self, seal: XChain<Seal>);
 
     fn filter_revealed_seals(&self) -> Vec<XChain<Seal>>;
+
+    /// Checks whether both the seal and the state of the assignment with the
+    /// given `index` are revealed.
+    fn is_revealed_at(&self, index: u16) -> bool;
+
+    /// Conceals all assignments except the ones with the indexes matching the
+    /// `keep` predicate.
+    fn conceal_except(&mut self, keep: impl Fn(u16) -> bool);
+
+    /// Conceals seals of all assignments except the ones with the indexes
+    /// matching the `keep` predicate, leaving their state unchanged.
+    fn conceal_seals_except(&mut self, keep: impl Fn(u16) -> bool);
 }
 
 impl<Seal: ExposedSeal> TypedAssignsExt<Seal> for TypedAssigns<Seal> {
@@ -252,4 +264,71 @@ impl<Seal: ExposedSeal> TypedAssignsExt<Seal> for TypedAssigns<Seal> {
             }
         }
     }
+
+    fn is_revealed_at(&self, index: u16) -> bool {
+        let index = index as usize;
+        match self {
+            TypedAssigns::Declarative(s) => s.get(index).and_then(Assign::as_revealed).is_some(),
+            TypedAssigns::Fungible(s) => s.get(index).and_then(Assign::as_revealed).is_some(),
+            TypedAssigns::Structured(s) => s.get(index).and_then(Assign::as_revealed).is_some(),
+            TypedAssigns::Attachment(s) => s.get(index).and_then(Assign::as_revealed).is_some(),
+        }
+    }
+
+    fn conceal_except(&mut self, keep: impl Fn(u16) -> bool) {
+        fn conceal<State: ExposedState, Seal: ExposedSeal>(
+            vec: &mut SmallVec<Assign<State, Seal>>,
+            keep: impl Fn(u16) -> bool,
+        ) {
+            for (no, assign) in vec.iter_mut().enumerate() {
+                if !keep(no as u16) {
+                    *assign = assign.conceal();
+                }
+            }
+        }
+
+        match self {
+            TypedAssigns::Declarative(v) => conceal(v, keep),
+            TypedAssigns::Fungible(v) => conceal(v, keep),
+            TypedAssigns::Structured(v) => conceal(v, keep),
+            TypedAssigns::Attachment(v) => conceal(v, keep),
+        }
+    }
+
+    fn conceal_seals_except(&mut self, keep: impl Fn(u16) -> bool) {
+        fn conceal<State: ExposedState, Seal: ExposedSeal>(
+            vec: &mut SmallVec<Assign<State, Seal>>,
+            keep: impl Fn(u16) -> bool,
+        ) {
+            for (no, assign) in vec.iter_mut().enumerate() {
+                if keep(no as u16) {
+                    continue;
+                }
+                match assign {
+                    Assign::Revealed { seal, state, lock } => {
+                        *assign = Assign::ConfidentialSeal {
+                            seal: seal.conceal(),
+                            state: state.clone(),
+                            lock: *lock,
+                        }
+                    }
+                    Assign::ConfidentialState { seal, state, lock } => {
+                        *assign = Assign::Confidential {
+                            seal: seal.conceal(),
+                            state: *state,
+                            lock: *lock,
+                        }
+                    }
+                    Assign::ConfidentialSeal { .. } | Assign::Confidential { .. } => {}
+                }
+            }
+        }
+
+        match self {
+            TypedAssigns::Declarative(v) => conceal(v, keep),
+            TypedAssigns::Fungible(v) => conceal(v, keep),
+            TypedAssigns::Structured(v) => conceal(v, keep),
+            TypedAssigns::Attachment(v) => conceal(v, keep),
+        }
+    }
 }
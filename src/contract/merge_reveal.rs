@@ -152,7 +152,7 @@ impl<Seal: ExposedSeal> MergeReveal for TypedAssigns<Seal> {
         match (self, other) {
             (TypedAssigns::Declarative(first_vec), TypedAssigns::Declarative(second_vec)) => {
                 let mut result = Vec::with_capacity(first_vec.len());
-                for (first, second) in first_vec.into_iter().zip(second_vec.into_iter()) {
+                for (first, second) in first_vec.into_iter().zip(second_vec) {
                     result.push(first.merge_reveal(second)?);
                 }
                 Ok(TypedAssigns::Declarative(
@@ -162,7 +162,7 @@ impl<Seal: ExposedSeal> MergeReveal for TypedAssigns<Seal> {
 
             (TypedAssigns::Fungible(first_vec), TypedAssigns::Fungible(second_vec)) => {
                 let mut result = Vec::with_capacity(first_vec.len());
-                for (first, second) in first_vec.into_iter().zip(second_vec.into_iter()) {
+                for (first, second) in first_vec.into_iter().zip(second_vec) {
                     result.push(first.merge_reveal(second)?);
                 }
                 Ok(TypedAssigns::Fungible(
@@ -172,7 +172,7 @@ impl<Seal: ExposedSeal> MergeReveal for TypedAssigns<Seal> {
 
             (TypedAssigns::Structured(first_vec), TypedAssigns::Structured(second_vec)) => {
                 let mut result = Vec::with_capacity(first_vec.len());
-                for (first, second) in first_vec.into_iter().zip(second_vec.into_iter()) {
+                for (first, second) in first_vec.into_iter().zip(second_vec) {
                     result.push(first.merge_reveal(second)?);
                 }
                 Ok(TypedAssigns::Structured(
@@ -182,7 +182,7 @@ impl<Seal: ExposedSeal> MergeReveal for TypedAssigns<Seal> {
 
             (TypedAssigns::Attachment(first_vec), TypedAssigns::Attachment(second_vec)) => {
                 let mut result = Vec::with_capacity(first_vec.len());
-                for (first, second) in first_vec.into_iter().zip(second_vec.into_iter()) {
+                for (first, second) in first_vec.into_iter().zip(second_vec) {
                     result.push(first.merge_reveal(second)?);
                 }
                 Ok(TypedAssigns::Attachment(
@@ -200,11 +200,7 @@ impl<Seal: ExposedSeal> MergeReveal for TypedAssigns<Seal> {
 impl<Seal: ExposedSeal> MergeReveal for Assignments<Seal> {
     fn merge_reveal(self, other: Self) -> Result<Self, MergeRevealError> {
         let mut result = BTreeMap::new();
-        for (first, second) in self
-            .into_inner()
-            .into_iter()
-            .zip(other.into_inner().into_iter())
-        {
+        for (first, second) in self.into_inner().into_iter().zip(other.into_inner()) {
             debug_assert_eq!(first.0, second.0);
             result.insert(first.0, first.1.merge_reveal(second.1)?);
         }
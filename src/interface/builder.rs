@@ -39,7 +39,7 @@ use rgbcore::{GlobalStateSchema, GlobalStateType, MetaType, Metadata, ValencyTyp
 use strict_encoding::{FieldName, SerializeError, StrictSerialize};
 use strict_types::{decode, SemId, TypeSystem};
 
-use crate::containers::{BuilderSeal, ContainerVer, Contract, ValidConsignment};
+use crate::containers::{BuilderSeal, ContainerVer, Contract, DumbValidator, ValidConsignment};
 use crate::interface::resolver::DumbResolver;
 use crate::interface::{Iface, IfaceImpl, TransitionIface};
 use crate::persistence::PersistedState;
@@ -423,7 +423,7 @@ impl ContractBuilder {
         };
 
         let valid_contract = contract
-            .validate(&DumbResolver, self.testnet)
+            .validate(&DumbResolver, &DumbValidator, self.testnet)
             .map_err(|(status, _)| status)?;
 
         Ok(valid_contract)
@@ -32,7 +32,10 @@ use strict_types::{StrictVal, TypeSystem};
 
 use crate::contract::{KnownState, OutputAssignment, WitnessInfo};
 use crate::info::ContractInfo;
-use crate::interface::{AssignmentsFilter, IfaceImpl};
+use crate::interface::{
+    AssignmentsFilter, ConfirmationDepth, ConfirmationStatus, FilterConfirmed, FilterIncludeAll,
+    IfaceImpl,
+};
 use crate::persistence::ContractStateRead;
 use crate::LIB_NAME_RGB_STD;
 
@@ -95,6 +98,28 @@ pub type FungibleAllocation = OutputAssignment<Amount>;
 pub type DataAllocation = OutputAssignment<DataState>;
 pub type AttachAllocation = OutputAssignment<AttachState>;
 
+/// Fungible state totals split by the confirmation status of the witness
+/// transactions.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct FungibleBalance {
+    pub confirmed: Amount,
+    pub pending: Amount,
+    pub tentative: Amount,
+}
+
+impl FungibleBalance {
+    pub fn total(&self) -> Amount {
+        self.confirmed
+            .saturating_add(self.pending)
+            .saturating_add(self.tentative)
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
 #[cfg_attr(
     feature = "serde",
@@ -360,10 +385,57 @@ impl<S: ContractStateRead> ContractIface<S> {
         }
 
         f(filter, self.state.rights_all())
-            .map(OwnedAllocation::from)
-            .chain(f(filter, self.state.fungible_all()).map(OwnedAllocation::from))
-            .chain(f(filter, self.state.data_all()).map(OwnedAllocation::from))
-            .chain(f(filter, self.state.attach_all()).map(OwnedAllocation::from))
+            .chain(f(filter, self.state.fungible_all()))
+            .chain(f(filter, self.state.data_all()))
+            .chain(f(filter, self.state.attach_all()))
+    }
+
+    /// Constructs filter which includes only the state matching `filter` and
+    /// confirmed to the required `depth`.
+    pub fn confirmed<F: AssignmentsFilter>(
+        &self,
+        depth: ConfirmationDepth,
+        filter: F,
+    ) -> FilterConfirmed<'_, S, F> {
+        FilterConfirmed::new(&self.state, depth, filter)
+    }
+
+    /// Returns all allocations matching the `filter` together with their
+    /// confirmation status. Allocations with archived or unknown witnesses are
+    /// skipped.
+    pub fn allocations_by_status<'c>(
+        &'c self,
+        depth: ConfirmationDepth,
+        filter: impl AssignmentsFilter + Copy + 'c,
+    ) -> impl Iterator<Item = (ConfirmationStatus, OwnedAllocation)> + 'c {
+        let confirmed = self.confirmed(depth, FilterIncludeAll);
+        self.allocations(filter).filter_map(move |allocation| {
+            confirmed
+                .status(allocation.witness)
+                .map(|status| (status, allocation))
+        })
+    }
+
+    /// Computes fungible balance of the state with the given name matching the
+    /// `filter`, split into confirmed, pending and tentative totals.
+    pub fn fungible_balance(
+        &self,
+        name: impl Into<FieldName>,
+        depth: ConfirmationDepth,
+        filter: impl AssignmentsFilter,
+    ) -> Result<FungibleBalance, ContractError> {
+        let confirmed = self.confirmed(depth, FilterIncludeAll);
+        let mut balance = FungibleBalance::default();
+        for allocation in self.fungible(name, filter)? {
+            let amount = match confirmed.status(allocation.witness) {
+                Some(ConfirmationStatus::Confirmed) => &mut balance.confirmed,
+                Some(ConfirmationStatus::Pending) => &mut balance.pending,
+                Some(ConfirmationStatus::Tentative) => &mut balance.tentative,
+                None => continue,
+            };
+            amount.saturating_add_assign(allocation.state);
+        }
+        Ok(balance)
     }
 
     pub fn outpoint_allocations(
@@ -22,7 +22,10 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::Deref;
 
-use rgb::{XOutpoint, XWitnessId};
+use rgb::vm::WitnessOrd;
+use rgb::{Layer1, XOutpoint, XWitnessId};
+
+use crate::persistence::ContractStateRead;
 
 pub trait AssignmentsFilter {
     fn should_include(
@@ -35,6 +38,115 @@ pub trait AssignmentsFilter {
 pub struct FilterIncludeAll;
 pub struct FilterExclude<T>(pub T);
 
+/// Confirmation status of the contract state, defined by the witness
+/// transaction status.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum ConfirmationStatus {
+    /// The witness is mined and has at least the required number of
+    /// confirmations, or the state is assigned by genesis.
+    Confirmed,
+    /// The witness is mined, but has less than the required number of
+    /// confirmations.
+    Pending,
+    /// The witness is not mined yet.
+    Tentative,
+}
+
+/// Minimal number of confirmations of the witness transaction for the state
+/// to be considered as confirmed, relative to the tip heights of the layer 1
+/// blockchains.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ConfirmationDepth {
+    /// Heights of the blockchain tips. Witnesses mined on a layer 1 without a
+    /// known tip height have no confirmations.
+    pub tip_heights: BTreeMap<Layer1, u32>,
+    pub min_depth: u32,
+}
+
+impl ConfirmationDepth {
+    pub fn new(min_depth: u32) -> Self {
+        Self {
+            tip_heights: none!(),
+            min_depth,
+        }
+    }
+
+    /// Sets the height of the blockchain tip for the given layer 1.
+    pub fn with_tip(mut self, layer1: Layer1, tip_height: u32) -> Self {
+        self.tip_heights.insert(layer1, tip_height);
+        self
+    }
+
+    /// Number of confirmations of a witness mined at the given height of the
+    /// `layer1` blockchain. Witness mined in the tip block has a single
+    /// confirmation; witnesses above the tip (if the tip provided is
+    /// outdated) or on a layer 1 without a known tip have no confirmations.
+    pub fn depth(&self, layer1: Layer1, height: u32) -> u32 {
+        self.tip_heights
+            .get(&layer1)
+            .and_then(|tip| tip.checked_sub(height))
+            .map(|depth| depth.saturating_add(1))
+            .unwrap_or_default()
+    }
+
+    /// Detects status of the state with the given witness ordering. The state
+    /// without a witness (i.e. assigned by genesis) is always confirmed;
+    /// archived witnesses have no status.
+    pub fn status(&self, witness_ord: Option<WitnessOrd>) -> Option<ConfirmationStatus> {
+        match witness_ord {
+            None => Some(ConfirmationStatus::Confirmed),
+            Some(WitnessOrd::Archived) => None,
+            Some(WitnessOrd::Tentative) => Some(ConfirmationStatus::Tentative),
+            Some(WitnessOrd::Mined(pos))
+                if self.depth(pos.layer1(), pos.height().get()) >= self.min_depth =>
+            {
+                Some(ConfirmationStatus::Confirmed)
+            }
+            Some(WitnessOrd::Mined(_)) => Some(ConfirmationStatus::Pending),
+        }
+    }
+}
+
+/// Filter including only the state matching the inner filter and having the
+/// witness confirmed to the required depth.
+pub struct FilterConfirmed<'s, S: ContractStateRead, F: AssignmentsFilter> {
+    pub state: &'s S,
+    pub depth: ConfirmationDepth,
+    pub filter: F,
+}
+
+impl<'s, S: ContractStateRead, F: AssignmentsFilter> FilterConfirmed<'s, S, F> {
+    pub fn new(state: &'s S, depth: ConfirmationDepth, filter: F) -> Self {
+        Self {
+            state,
+            depth,
+            filter,
+        }
+    }
+
+    /// Status of the state with the given witness. Witnesses unknown to the
+    /// contract state have no status.
+    pub fn status(&self, witness_id: Option<XWitnessId>) -> Option<ConfirmationStatus> {
+        let ord = match witness_id {
+            None => None,
+            Some(id) => Some(self.state.witness_ord(id)?),
+        };
+        self.depth.status(ord)
+    }
+}
+
+impl<S: ContractStateRead, F: AssignmentsFilter> AssignmentsFilter for FilterConfirmed<'_, S, F> {
+    fn should_include(
+        &self,
+        outpoint: impl Into<XOutpoint>,
+        witness_id: Option<XWitnessId>,
+    ) -> bool {
+        self.status(witness_id) == Some(ConfirmationStatus::Confirmed)
+            && self.filter.should_include(outpoint, witness_id)
+    }
+}
+
 impl AssignmentsFilter for FilterIncludeAll {
     fn should_include(&self, _: impl Into<XOutpoint>, _: Option<XWitnessId>) -> bool { true }
 }
@@ -130,3 +242,46 @@ impl<V> AssignmentsFilter for BTreeMap<XOutpoint, V> {
         self.keys().any(|o| *o == outpoint)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use rgb::vm::WitnessPos;
+
+    use super::*;
+
+    #[test]
+    fn confirmation_status() {
+        let mined = |height| {
+            Some(WitnessOrd::Mined(
+                WitnessPos::bitcoin(NonZeroU32::new(height).unwrap(), 1_700_000_000).unwrap(),
+            ))
+        };
+        let liquid = |height| {
+            Some(WitnessOrd::Mined(
+                WitnessPos::liquid(NonZeroU32::new(height).unwrap(), 1_700_000_000).unwrap(),
+            ))
+        };
+        let depth = ConfirmationDepth::new(6).with_tip(Layer1::Bitcoin, 100);
+        assert_eq!(depth.status(None), Some(ConfirmationStatus::Confirmed));
+        assert_eq!(depth.status(Some(WitnessOrd::Archived)), None);
+        assert_eq!(depth.status(Some(WitnessOrd::Tentative)), Some(ConfirmationStatus::Tentative));
+        assert_eq!(depth.status(mined(95)), Some(ConfirmationStatus::Confirmed));
+        assert_eq!(depth.status(mined(96)), Some(ConfirmationStatus::Pending));
+        assert_eq!(depth.status(mined(101)), Some(ConfirmationStatus::Pending));
+        assert_eq!(depth.status(liquid(95)), Some(ConfirmationStatus::Pending));
+        let depth = depth.with_tip(Layer1::Liquid, 1000);
+        assert_eq!(depth.status(liquid(995)), Some(ConfirmationStatus::Confirmed));
+        assert_eq!(depth.status(liquid(996)), Some(ConfirmationStatus::Pending));
+        assert_eq!(
+            ConfirmationDepth::new(0)
+                .with_tip(Layer1::Bitcoin, 100)
+                .status(mined(101)),
+            Some(ConfirmationStatus::Confirmed)
+        );
+        let depth = ConfirmationDepth::new(1).with_tip(Layer1::Bitcoin, u32::MAX);
+        assert_eq!(depth.depth(Layer1::Bitcoin, 1), u32::MAX);
+        assert_eq!(depth.depth(Layer1::Bitcoin, u32::MAX), 1);
+    }
+}
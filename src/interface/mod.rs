@@ -35,10 +35,13 @@ mod inheritance;
 pub use builder::{BuilderError, ContractBuilder, TransitionBuilder, TxOutpoint};
 pub use contract::{
     AllocatedState, AttachAllocation, ContractError, ContractIface, ContractOp, DataAllocation,
-    FungibleAllocation, OpDirection, OwnedAllocation, RightsAllocation,
+    FungibleAllocation, FungibleBalance, OpDirection, OwnedAllocation, RightsAllocation,
 };
 pub use contractum::IfaceDisplay;
-pub use filter::{AssignmentsFilter, FilterExclude, FilterIncludeAll};
+pub use filter::{
+    AssignmentsFilter, ConfirmationDepth, ConfirmationStatus, FilterConfirmed, FilterExclude,
+    FilterIncludeAll,
+};
 pub use iface::{
     ArgMap, AssignIface, ExtensionIface, GenesisIface, GlobalIface, Iface, IfaceClass, IfaceId,
     IfaceInconsistency, IfaceRef, IfaceWrapper, Modifier, OpName, OwnedIface, Req, TransitionIface,
@@ -46,6 +49,9 @@ pub use iface::{
 };
 pub use iimpl::{IfaceImpl, ImplId, NamedField, NamedType, NamedVariant, SchemaTypeIndex};
 pub use inheritance::{CheckInheritance, ExtensionError, InheritanceFailure};
+#[cfg(not(target_arch = "wasm32"))]
+pub use resolver::ParallelResolver;
+pub use resolver::{ResolveWitnessBatch, SequentialResolver};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
 #[derive(StrictType, StrictEncode, StrictDecode)]
@@ -19,6 +19,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::num::NonZeroUsize;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+
 use rgb::validation::{ResolveWitness, WitnessResolverError};
 use rgb::vm::{WitnessOrd, XWitnessTx};
 use strict_encoding::StrictDumb;
@@ -36,3 +42,147 @@ impl ResolveWitness for DumbResolver {
         Ok(WitnessOrd::strict_dumb())
     }
 }
+
+/// Resolver reporting the witnesses replaced by RBF transactions as archived,
+/// while resolving all other witnesses with the wrapped resolver.
+pub(crate) struct ReplacedResolver<R> {
+    pub resolver: R,
+    pub replaced: BTreeSet<XWitnessId>,
+}
+
+impl<R: ResolveWitness> ResolveWitness for ReplacedResolver<R> {
+    fn resolve_pub_witness(&self, id: XWitnessId) -> Result<XWitnessTx, WitnessResolverError> {
+        self.resolver.resolve_pub_witness(id)
+    }
+
+    fn resolve_pub_witness_ord(&self, id: XWitnessId) -> Result<WitnessOrd, WitnessResolverError> {
+        if self.replaced.contains(&id) {
+            return Ok(WitnessOrd::Archived);
+        }
+        self.resolver.resolve_pub_witness_ord(id)
+    }
+}
+
+impl<R: ResolveWitnessBatch> ResolveWitnessBatch for ReplacedResolver<R> {
+    fn batch_size(&self) -> usize { self.resolver.batch_size() }
+
+    fn resolve_pub_witness_ords(
+        &self,
+        witness_ids: &[XWitnessId],
+    ) -> Vec<Result<WitnessOrd, WitnessResolverError>> {
+        let mut ords = self.resolver.resolve_pub_witness_ords(witness_ids);
+        for (id, ord) in witness_ids.iter().zip(&mut ords) {
+            if self.replaced.contains(id) {
+                *ord = Ok(WitnessOrd::Archived);
+            }
+        }
+        ords
+    }
+}
+
+/// Resolver returning witness ordering already known to the contract state.
+pub(crate) struct KnownOrdResolver(pub BTreeMap<XWitnessId, WitnessOrd>);
+
+impl ResolveWitness for KnownOrdResolver {
+    fn resolve_pub_witness(&self, id: XWitnessId) -> Result<XWitnessTx, WitnessResolverError> {
+        Err(WitnessResolverError::Unknown(id))
+    }
+
+    fn resolve_pub_witness_ord(&self, id: XWitnessId) -> Result<WitnessOrd, WitnessResolverError> {
+        self.0
+            .get(&id)
+            .copied()
+            .ok_or(WitnessResolverError::Unknown(id))
+    }
+}
+
+/// Resolver of the witness ordering for multiple witnesses at once, used when
+/// updating witnesses of the contract state.
+pub trait ResolveWitnessBatch {
+    /// Maximal number of witnesses passed to a single
+    /// [`Self::resolve_pub_witness_ords`] call. Progress of the update is
+    /// reported after each batch.
+    fn batch_size(&self) -> usize { 64 }
+
+    /// Resolves ordering of the witnesses, returning results in the same order
+    /// as `witness_ids`.
+    fn resolve_pub_witness_ords(
+        &self,
+        witness_ids: &[XWitnessId],
+    ) -> Vec<Result<WitnessOrd, WitnessResolverError>>;
+}
+
+impl<T: ResolveWitnessBatch> ResolveWitnessBatch for &T {
+    fn batch_size(&self) -> usize { (*self).batch_size() }
+
+    fn resolve_pub_witness_ords(
+        &self,
+        witness_ids: &[XWitnessId],
+    ) -> Vec<Result<WitnessOrd, WitnessResolverError>> {
+        (*self).resolve_pub_witness_ords(witness_ids)
+    }
+}
+
+/// Batch resolver resolving witnesses one by one with the wrapped resolver.
+pub struct SequentialResolver<R: ResolveWitness>(pub R);
+
+impl<R: ResolveWitness> ResolveWitnessBatch for SequentialResolver<R> {
+    fn resolve_pub_witness_ords(
+        &self,
+        witness_ids: &[XWitnessId],
+    ) -> Vec<Result<WitnessOrd, WitnessResolverError>> {
+        witness_ids
+            .iter()
+            .map(|id| self.0.resolve_pub_witness_ord(*id))
+            .collect()
+    }
+}
+
+/// Batch resolver splitting each batch between several threads, which
+/// resolve witnesses with the wrapped resolver.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ParallelResolver<R: ResolveWitness + Sync> {
+    pub resolver: R,
+    pub threads: NonZeroUsize,
+    pub batch_size: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R: ResolveWitness + Sync> ParallelResolver<R> {
+    pub fn new(resolver: R, threads: NonZeroUsize) -> Self {
+        Self {
+            resolver,
+            threads,
+            batch_size: threads.get() * 16,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R: ResolveWitness + Sync> ResolveWitnessBatch for ParallelResolver<R> {
+    fn batch_size(&self) -> usize { self.batch_size }
+
+    fn resolve_pub_witness_ords(
+        &self,
+        witness_ids: &[XWitnessId],
+    ) -> Vec<Result<WitnessOrd, WitnessResolverError>> {
+        let chunk_size = witness_ids.len().div_ceil(self.threads.get()).max(1);
+        thread::scope(|scope| {
+            let handles = witness_ids
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|id| self.resolver.resolve_pub_witness_ord(*id))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("witness resolver thread panicked"))
+                .collect()
+        })
+    }
+}
@@ -0,0 +1,534 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stock backup archives.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::{ByteArray, Bytes32};
+use armor::{ArmorHeader, AsciiArmor, StrictArmor};
+use baid64::{Baid64ParseError, DisplayBaid64, FromBaid64Str};
+use commit_verify::{CommitEncode, CommitEngine, CommitId, CommitmentId, DigestExt, Sha256};
+use rgb::validation::{ResolveWitness, WitnessResolverError};
+use rgb::vm::{WitnessOrd, XWitnessTx};
+use rgb::{Operation, XWitnessId};
+use strict_encoding::{StrictDeserialize, StrictSerialize};
+
+use super::{
+    Index, IndexProvider, MemIndex, MemStash, MemState, Stash, StashDataError, StashError,
+    StashProvider, StashProviderError, StashReadProvider, StashWriteProvider, State, StateProvider,
+    Stock, StockError, StockErrorMem,
+};
+use crate::containers::{ContentId, ContentRef, ASCII_ARMOR_VERSION};
+use crate::interface::resolver::{KnownOrdResolver, ReplacedResolver};
+use crate::LIB_NAME_RGB_STORAGE;
+
+pub const ASCII_ARMOR_BACKUP_CONTENT: &str = "Content";
+
+/// Stock backup identifier.
+///
+/// Backup identifier commits to all data provided within the backup and is
+/// used as its checksum.
+#[derive(Wrapper, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, From)]
+#[wrapper(Deref, BorrowSlice, Hex, Index, RangeOps)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct BackupId(
+    #[from]
+    #[from([u8; 32])]
+    Bytes32,
+);
+
+impl From<Sha256> for BackupId {
+    fn from(hasher: Sha256) -> Self { hasher.finish().into() }
+}
+
+impl CommitmentId for BackupId {
+    const TAG: &'static str = "urn:lnp-bp:rgb:backup#2026-10-17";
+}
+
+impl DisplayBaid64 for BackupId {
+    const HRI: &'static str = "rgb:bak";
+    const CHUNKING: bool = true;
+    const PREFIX: bool = true;
+    const EMBED_CHECKSUM: bool = false;
+    const MNEMONIC: bool = false;
+    fn to_baid64_payload(&self) -> [u8; 32] { self.to_byte_array() }
+}
+impl FromBaid64Str for BackupId {}
+impl FromStr for BackupId {
+    type Err = Baid64ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Self::from_baid64_str(s) }
+}
+impl Display for BackupId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { self.fmt_baid64(f) }
+}
+
+impl_serde_baid64!(BackupId);
+
+impl BackupId {
+    pub const fn from_array(id: [u8; 32]) -> Self { BackupId(Bytes32::from_array(id)) }
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE, tags = repr, into_u8, try_from_u8)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum BackupVer {
+    #[default]
+    #[display("v1", alt = "1")]
+    V1 = 1,
+}
+
+/// Archive with the data of a [`Stock`], independent of the layout used by
+/// its persistence provider.
+///
+/// The archive always contains the consensus-critical stash data. Contract
+/// state and index may be omitted, in which case they are re-created from the
+/// stash during [`Stock::import_backup`].
+#[derive(Debug, Display)]
+#[display(AsciiArmor::to_ascii_armored_string)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct StockBackup {
+    /// Version.
+    pub version: BackupVer,
+    pub stash: MemStash,
+    pub state: Option<MemState>,
+    pub index: Option<MemIndex>,
+}
+
+impl StrictSerialize for StockBackup {}
+impl StrictDeserialize for StockBackup {}
+
+impl CommitEncode for StockBackup {
+    type CommitmentId = BackupId;
+
+    fn commit_encode(&self, e: &mut CommitEngine) { e.commit_to_serialized(self); }
+}
+
+impl StockBackup {
+    #[inline]
+    pub fn backup_id(&self) -> BackupId { self.commit_id() }
+
+    /// Detects whether the backup contains contract state and index data.
+    pub fn is_full(&self) -> bool { self.state.is_some() && self.index.is_some() }
+}
+
+impl StrictArmor for StockBackup {
+    type Id = BackupId;
+    const PLATE_TITLE: &'static str = "RGB STOCK BACKUP";
+
+    fn armor_id(&self) -> Self::Id { self.backup_id() }
+    fn checksum_armor(&self) -> bool { true }
+    fn armor_headers(&self) -> Vec<ArmorHeader> {
+        let mut content = vec![s!("stash")];
+        if self.state.is_some() {
+            content.push(s!("state"));
+        }
+        if self.index.is_some() {
+            content.push(s!("index"));
+        }
+        vec![
+            ArmorHeader::new(ASCII_ARMOR_VERSION, format!("{:#}", self.version)),
+            ArmorHeader::new(ASCII_ARMOR_BACKUP_CONTENT, content.join(", ")),
+        ]
+    }
+}
+
+impl FromStr for StockBackup {
+    type Err = armor::StrictArmorError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Self::from_ascii_armored_str(s) }
+}
+
+/// Errors of packing stock data into a [`StockBackup`] or restoring them from
+/// it.
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BackupError {
+    /// unable to pack stock data into the backup archive: {0}
+    Archive(StockErrorMem),
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<BackupError>
+    for StockError<S, H, P, BackupError>
+{
+    fn from(err: BackupError) -> Self { Self::InvalidInput(err) }
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
+    /// Packs stock data into a backup archive.
+    ///
+    /// If `full` is false, only the stash data are included, which makes the
+    /// archive smaller, but requires contract state to be re-computed on
+    /// import. Otherwise, contract state and index are re-created from the
+    /// stash data using witness ordering known to the stock, such that the
+    /// archive doesn't depend on the providers used by the stock. Since the
+    /// state of contracts with pruned history can't be re-created, such
+    /// stocks can be archived only without it.
+    pub fn export_backup(
+        &self,
+        full: bool,
+    ) -> Result<StockBackup, StockError<S, H, P, BackupError>> {
+        let mut stash = MemStash::in_memory();
+        copy_stash(self.as_stash_provider(), &mut stash).map_err(|err| match err {
+            CopyError::Read(err) => StockError::from(StashError::<S>::from(err)),
+            CopyError::Write(err) => BackupError::Archive(StockError::StashWrite(err)).into(),
+        })?;
+        if !full {
+            return Ok(StockBackup {
+                version: default!(),
+                stash,
+                state: None,
+                index: None,
+            });
+        }
+
+        let mut ords = BTreeMap::new();
+        let witness_ids = self
+            .as_stash_provider()
+            .witness_ids()
+            .map_err(StockError::<S, H, P, BackupError>::StashRead)?;
+        for witness_id in witness_ids {
+            if let Ok(ord) = self.as_state_provider().witness_ord(witness_id) {
+                ords.insert(witness_id, ord);
+            }
+        }
+        let stash = Stash::new(stash);
+        let mut state = State::new(MemState::in_memory());
+        let mut index = Index::new(MemIndex::in_memory());
+        Stock::index_stash(&stash, &mut index)
+            .and_then(|_| {
+                Stock::replay_contracts(&stash, &mut state, |_| true, KnownOrdResolver(ords))
+            })
+            .map_err(BackupError::Archive)?;
+        Ok(StockBackup {
+            version: default!(),
+            stash: stash.into_provider(),
+            state: Some(state.into_provider()),
+            index: Some(index.into_provider()),
+        })
+    }
+
+    /// Restores stock from a backup archive into the provided `stash`,
+    /// `state` and `index` providers, which must not contain any data.
+    ///
+    /// The index is re-created from the stash data. Contract state is
+    /// re-created by replaying contract operations (see
+    /// [`Stock::rebuild_state`]) using witness ordering from the archive;
+    /// `resolver` is used to retrieve ordering of witnesses which are absent
+    /// in it. The returned stock is not persistent; use
+    /// [`Stock::make_persistent`] to store it.
+    ///
+    /// Fails with [`StockError::PrunedState`] if the history of some contract
+    /// was pruned (see [`Stock::prune`]).
+    pub fn import_backup(
+        backup: StockBackup,
+        stash: S,
+        state: H,
+        index: P,
+        resolver: impl ResolveWitness,
+    ) -> Result<Self, StockError<S, H, P, BackupError>> {
+        let StockBackup {
+            version: _,
+            stash: backup,
+            state: backup_state,
+            index: _,
+        } = backup;
+        let ords = backup_state
+            .map(|state| {
+                state
+                    .debug_witnesses()
+                    .iter()
+                    .map(|(id, ord)| (*id, *ord))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut stock = Self::with(stash, state, index);
+        stock.store_transaction(|stash, state, index| {
+            copy_stash(&backup, stash.as_provider_mut()).map_err(|err| match err {
+                CopyError::Read(StashProviderError::Inconsistency(err)) => err.into(),
+                CopyError::Read(StashProviderError::Iface(err)) => StashDataError::from(err).into(),
+                CopyError::Read(StashProviderError::Connectivity(err)) => match err {},
+                CopyError::Write(err) => StockError::<S, H, P, BackupError>::StashWrite(err),
+            })?;
+            Self::index_stash(stash, index)?;
+            let resolver = ReplacedResolver {
+                resolver: BackupResolver {
+                    ords: KnownOrdResolver(ords),
+                    resolver,
+                },
+                replaced: stash.replaced_witnesses()?,
+            };
+            Ok(Self::replay_contracts(stash, state, |_| true, resolver)?)
+        })?;
+        Ok(stock)
+    }
+}
+
+/// Resolver of the witness ordering saved in a backup, using another resolver
+/// for the witnesses which are absent in it.
+struct BackupResolver<R> {
+    ords: KnownOrdResolver,
+    resolver: R,
+}
+
+impl<R: ResolveWitness> ResolveWitness for BackupResolver<R> {
+    fn resolve_pub_witness(&self, id: XWitnessId) -> Result<XWitnessTx, WitnessResolverError> {
+        self.resolver.resolve_pub_witness(id)
+    }
+
+    fn resolve_pub_witness_ord(&self, id: XWitnessId) -> Result<WitnessOrd, WitnessResolverError> {
+        self.ords
+            .resolve_pub_witness_ord(id)
+            .or_else(|_| self.resolver.resolve_pub_witness_ord(id))
+    }
+}
+
+enum CopyError<R: Error, W: Error> {
+    Read(StashProviderError<R>),
+    Write(W),
+}
+
+impl<R: Error, W: Error> From<StashProviderError<R>> for CopyError<R, W> {
+    fn from(err: StashProviderError<R>) -> Self { Self::Read(err) }
+}
+
+/// Copies all stash data from one provider to another.
+fn copy_stash<R: StashReadProvider, W: StashWriteProvider>(
+    from: &R,
+    to: &mut W,
+) -> Result<(), CopyError<R::Error, <W as StashWriteProvider>::Error>> {
+    let read = StashProviderError::Connectivity;
+    let mut content_refs = BTreeSet::new();
+
+    let types = from.type_system().map_err(read)?;
+    to.consume_types(types.clone()).map_err(CopyError::Write)?;
+    for iface in from.ifaces().map_err(read)? {
+        to.replace_iface(iface.clone()).map_err(CopyError::Write)?;
+        content_refs.insert(ContentRef::Iface(iface.iface_id()));
+    }
+    for schema_ifaces in from.schemata().map_err(read)? {
+        let schema = &schema_ifaces.schema;
+        for lib_id in schema.libs() {
+            to.replace_lib(from.lib(lib_id)?.clone())
+                .map_err(CopyError::Write)?;
+        }
+        to.replace_schema(schema.clone())
+            .map_err(CopyError::Write)?;
+        content_refs.insert(ContentRef::Schema(schema.schema_id()));
+        for iimpl in schema_ifaces.iimpls.values() {
+            to.replace_iimpl(iimpl.clone()).map_err(CopyError::Write)?;
+            content_refs.insert(ContentRef::IfaceImpl(iimpl.impl_id()));
+        }
+    }
+
+    for genesis in from.geneses().map_err(read)? {
+        let contract_id = genesis.contract_id();
+        to.replace_genesis(genesis.clone())
+            .map_err(CopyError::Write)?;
+        content_refs.insert(ContentRef::Genesis(contract_id));
+        if let Some(checkpoint) = from.prune_checkpoint(contract_id).map_err(read)? {
+            to.set_prune_checkpoint(contract_id, checkpoint.clone())
+                .map_err(CopyError::Write)?;
+        }
+    }
+    for opid in from.extension_ids().map_err(read)? {
+        to.replace_extension(from.extension(opid)?.clone())
+            .map_err(CopyError::Write)?;
+    }
+    for bundle_id in from.bundle_ids().map_err(read)? {
+        to.replace_bundle(from.bundle(bundle_id)?.clone())
+            .map_err(CopyError::Write)?;
+    }
+    for witness_id in from.witness_ids().map_err(read)? {
+        to.replace_witness(from.witness(witness_id)?.clone())
+            .map_err(CopyError::Write)?;
+    }
+    for (replaced, replacement) in from.witness_replacements().map_err(read)? {
+        to.set_witness_replacement(replaced, replacement)
+            .map_err(CopyError::Write)?;
+    }
+    for id in from.attachment_ids().map_err(read)? {
+        if let Some(attach) = from.attachment(id).map_err(read)? {
+            to.replace_attachment(id, attach.clone())
+                .map_err(CopyError::Write)?;
+        }
+    }
+    for seal in from.secret_seals().map_err(read)? {
+        to.add_secret_seal(seal).map_err(CopyError::Write)?;
+    }
+
+    let mut content_ids = content_refs
+        .iter()
+        .map(|content_ref| match *content_ref {
+            ContentRef::Schema(id) => ContentId::Schema(id),
+            ContentRef::Genesis(id) => ContentId::Genesis(id),
+            ContentRef::Iface(id) => ContentId::Iface(id),
+            ContentRef::IfaceImpl(id) => ContentId::IfaceImpl(id),
+        })
+        .collect::<BTreeSet<_>>();
+    for content_ref in content_refs {
+        for suppl in from.supplements(content_ref).map_err(read)? {
+            content_ids.insert(ContentId::Suppl(suppl.suppl_id()));
+            to.add_supplement(suppl).map_err(CopyError::Write)?;
+        }
+    }
+    for content_id in content_ids {
+        if let Some(sigs) = from.sigs_for(&content_id).map_err(read)? {
+            let sigs = sigs
+                .iter()
+                .map(|(identity, sig)| (identity.clone(), sig.clone()));
+            to.import_sigs(content_id, sigs).map_err(CopyError::Write)?;
+        }
+    }
+    for (identity, trust) in from.identities().map_err(read)? {
+        to.set_trust(identity, trust).map_err(CopyError::Write)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use bp::Vout;
+    use rgb::{GraphSeal, XChain};
+
+    use super::*;
+    use crate::containers::{FileContent, LoadError};
+    use crate::interface::resolver::DumbResolver;
+    use crate::persistence::fixture::transferred_stock;
+    use crate::persistence::StashReadProvider;
+
+    fn serialize(data: &impl StrictSerialize) -> Vec<u8> {
+        data.to_strict_serialized::<{ u32::MAX as usize }>()
+            .unwrap()
+            .release()
+    }
+
+    fn stock() -> Stock {
+        let mut stock = Stock::in_memory();
+        let seal = XChain::with(
+            rgb::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        stock.store_secret_seal(seal).unwrap();
+        stock
+    }
+
+    #[test]
+    fn backup_roundtrip() {
+        let stock = stock();
+        for full in [true, false] {
+            let backup = stock.export_backup(full).unwrap();
+            let id = backup.backup_id();
+
+            let mut data = vec![];
+            backup.save(&mut data).unwrap();
+            let backup = StockBackup::load(data.as_slice()).unwrap();
+            assert_eq!(backup.backup_id(), id);
+            assert_eq!(backup.is_full(), full);
+
+            let backup = StockBackup::from_str(&backup.to_string()).unwrap();
+            assert_eq!(backup.backup_id(), id);
+
+            let restored = Stock::import_backup(
+                backup,
+                MemStash::in_memory(),
+                MemState::in_memory(),
+                MemIndex::in_memory(),
+                DumbResolver,
+            )
+            .unwrap();
+            assert_eq!(restored.as_stash_provider().secret_seals().unwrap().count(), 1);
+        }
+    }
+
+    #[test]
+    fn corrupted_backup() {
+        let mut data = vec![];
+        stock()
+            .export_backup(true)
+            .unwrap()
+            .save(&mut data)
+            .unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        assert!(matches!(StockBackup::load(data.as_slice()), Err(LoadError::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn backup_transfers() {
+        let (stock, _) = transferred_stock();
+        let backup = stock.export_backup(true).unwrap();
+        assert_eq!(serialize(&backup.stash), serialize(stock.as_stash_provider()));
+        assert_eq!(serialize(backup.state.as_ref().unwrap()), serialize(stock.as_state_provider()));
+        assert_eq!(serialize(backup.index.as_ref().unwrap()), serialize(stock.as_index_provider()));
+
+        // Witness ordering is taken from the backup, so the resolver is not used
+        let restored = Stock::import_backup(
+            backup,
+            MemStash::in_memory(),
+            MemState::in_memory(),
+            MemIndex::in_memory(),
+            DumbResolver,
+        )
+        .unwrap();
+        assert_eq!(serialize(restored.as_stash_provider()), serialize(stock.as_stash_provider()));
+        assert_eq!(serialize(restored.as_state_provider()), serialize(stock.as_state_provider()));
+        assert_eq!(serialize(restored.as_index_provider()), serialize(stock.as_index_provider()));
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn backup_dir_stock() {
+        use crate::persistence::dir::{DirIndex, DirStash, DirState};
+        use crate::persistence::fixture::{mined, Transfers};
+        use crate::persistence::StateReadProvider;
+
+        let mut stock =
+            Stock::with(DirStash::in_memory(), DirState::in_memory(), DirIndex::in_memory());
+        let transfers = Transfers::consume(&mut stock);
+        let backup = stock.export_backup(true).unwrap();
+        let id = backup.backup_id();
+
+        let restored = Stock::import_backup(
+            backup,
+            DirStash::in_memory(),
+            DirState::in_memory(),
+            DirIndex::in_memory(),
+            DumbResolver,
+        )
+        .unwrap();
+        assert_eq!(restored.export_backup(true).unwrap().backup_id(), id);
+        assert_eq!(
+            restored
+                .as_state_provider()
+                .witness_ord(transfers.witnesses[1])
+                .unwrap(),
+            mined(101)
+        );
+    }
+}
@@ -0,0 +1,1843 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Directory-based storage keeping data of each contract in a separate file.
+//!
+//! [`DirStore`] keeps data shared by all contracts (type system, interfaces,
+//! schemata, secret seals, witness ordering, operation and terminal indexes
+//! etc.) in `stash.dat`, `state.dat` and `index.dat` files, while genesis,
+//! extensions, bundles and witnesses of each contract, together with its
+//! contract state and index, are kept in the `contracts` subdirectory, one
+//! file per contract and provider. Loading a stock reads the shared data and
+//! lists the contracts present in the directory; contract data, as well as
+//! catalogs listing contract genesis, tapret commitments and ids of the
+//! operations and witnesses of each contract, are read on the first access,
+//! such that startup time and memory use do not grow with the number of
+//! contracts.
+//!
+//! Contracts which get updated are moved into the in-memory [`MemStash`],
+//! [`MemState`] and [`MemIndex`], which are written back into the
+//! per-contract files on each store operation.
+
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{self, LargeOrdMap, LargeOrdSet, MediumBlob, SmallOrdSet};
+use amplify::hex::{FromHex, ToHex};
+use amplify::ByteArray;
+use bp::dbc::tapret::TapretCommitment;
+use nonasync::persistence::{
+    CloneNoPersistence, Persistence, PersistenceError, PersistenceProvider, Persisting,
+};
+use rgb::vm::WitnessOrd;
+use rgb::{
+    Assign, AssignmentType, AttachId, BundleId, ContractId, ExposedState, Extension, Genesis,
+    GenesisSeal, GraphSeal, Identity, Layer1, OpId, Operation, Opout, Schema, SchemaId, SecretSeal,
+    Transition, TransitionBundle, XChain, XOutpoint, XWitnessId,
+};
+use strict_encoding::{StrictDecode, StrictEncode};
+use strict_types::TypeSystem;
+
+use super::fs::{read_versioned, sync_dir, with_suffix, write_synced, FsVersioned};
+use super::memory::{ContractIndex, MemContractWriter};
+use super::{
+    ContractStateWrite, IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider,
+    IndexWriteError, IndexWriteProvider, MemContract, MemContractState, MemError, MemIndex,
+    MemStash, MemState, PruneCheckpoint, SchemaIfaces, StashProvider, StashProviderError,
+    StashReadProvider, StashWriteProvider, StateInconsistency, StateProvider, StateReadError,
+    StateReadProvider, StateWriteProvider, StockPersistence, StoreTransaction, UpdateRes,
+};
+use crate::containers::{
+    ContentId, ContentRef, ContentSigs, SealWitness, SigBlob, Supplement, TrustLevel,
+};
+use crate::interface::{Iface, IfaceClass, IfaceImpl, IfaceRef, ResolveWitnessBatch};
+use crate::LIB_NAME_RGB_STORAGE;
+
+const CONTRACTS_DIR: &str = "contracts";
+const STASH_FILE: &str = "stash.dat";
+const STATE_FILE: &str = "state.dat";
+const STATE_CATALOG_FILE: &str = "state-catalog.dat";
+const INDEX_FILE: &str = "index.dat";
+const STASH_EXT: &str = "stash";
+const CATALOG_EXT: &str = "catalog";
+const STATE_EXT: &str = "state";
+const INDEX_EXT: &str = "index";
+
+/// Error loading data of a contract from its file.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display("unable to load data of contract {contract_id}: {details}")]
+pub struct LoadError {
+    pub contract_id: ContractId,
+    pub details: String,
+}
+
+#[derive(Debug, Display, Error, From)]
+#[display(inner)]
+pub enum DirError {
+    #[from]
+    #[from(confinement::Error)]
+    #[from(PersistenceError)]
+    Mem(MemError),
+
+    #[from]
+    Load(LoadError),
+
+    #[from]
+    Io(io::Error),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum DirStateError {
+    #[from]
+    Inconsistency(StateInconsistency),
+
+    #[from]
+    Load(LoadError),
+}
+
+impl StateReadError for DirStateError {
+    fn inconsistency(&self) -> Option<&StateInconsistency> {
+        match self {
+            DirStateError::Inconsistency(e) => Some(e),
+            DirStateError::Load(_) => None,
+        }
+    }
+}
+
+impl From<IndexWriteError<MemError>> for IndexWriteError<DirError> {
+    fn from(err: IndexWriteError<MemError>) -> Self {
+        match err {
+            IndexWriteError::Inconsistency(e) => IndexWriteError::Inconsistency(e),
+            IndexWriteError::Connectivity(e) => IndexWriteError::Connectivity(e.into()),
+        }
+    }
+}
+
+fn infallible<T>(res: Result<T, Infallible>) -> T {
+    match res {
+        Ok(val) => val,
+        Err(err) => match err {},
+    }
+}
+
+fn lift_stash<T>(
+    res: Result<T, StashProviderError<Infallible>>,
+) -> Result<T, StashProviderError<LoadError>> {
+    res.map_err(|err| match err {
+        StashProviderError::Inconsistency(e) => StashProviderError::Inconsistency(e),
+        StashProviderError::Iface(e) => StashProviderError::Iface(e),
+        StashProviderError::Connectivity(e) => match e {},
+    })
+}
+
+fn lift_index<T>(
+    res: Result<T, IndexReadError<Infallible>>,
+) -> Result<T, IndexReadError<LoadError>> {
+    res.map_err(|err| match err {
+        IndexReadError::Inconsistency(e) => IndexReadError::Inconsistency(e),
+        IndexReadError::Connectivity(e) => match e {},
+    })
+}
+
+/// Directory-based storage for stash, state and index, keeping data of each
+/// contract in a separate file.
+///
+/// Similarly to [`super::fs::FsBinStore`], the files are replaced only once
+/// their new versions are completely written, and storing all three providers
+/// with [`StockPersistence::store_all`] is protected with a commit journal.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DirStore {
+    pub path: PathBuf,
+}
+
+impl DirStore {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(path.join(CONTRACTS_DIR))?;
+        Ok(Self { path })
+    }
+
+    fn contracts_dir(&self) -> PathBuf { self.path.join(CONTRACTS_DIR) }
+
+    fn journal(&self) -> PathBuf { self.path.join("commit.journal") }
+
+    fn sync_dirs(&self) -> io::Result<()> {
+        sync_dir(&self.path.join(STASH_FILE))?;
+        sync_dir(&self.contracts_dir().join(STASH_FILE))
+    }
+
+    /// Brings the files to a consistent state after an interrupted store
+    /// operation.
+    pub fn recover(&self) -> io::Result<()> {
+        let journal = self.journal();
+        let committed = journal.exists();
+        if committed {
+            for line in fs::read_to_string(&journal)?.lines() {
+                let path = PathBuf::from(line);
+                let staged = with_suffix(&path, "new");
+                if staged.exists() {
+                    replace_file(&staged, &path)?;
+                }
+            }
+            self.sync_dirs()?;
+        }
+        for dir in [self.path.clone(), self.contracts_dir()] {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if matches!(path.extension().and_then(OsStr::to_str), Some("new" | "tmp")) {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        if committed {
+            fs::remove_file(&journal)?;
+        }
+        Ok(())
+    }
+
+    fn store_files(&self, data: &impl DirData) -> Result<(), DirError> {
+        self.recover()?;
+        for path in data.write_files(self, "tmp")? {
+            replace_file(&with_suffix(&path, "tmp"), &path)?;
+        }
+        Ok(self.sync_dirs()?)
+    }
+
+    fn store_all_files(
+        &self,
+        stash: &DirStash,
+        state: &DirState,
+        index: &DirIndex,
+    ) -> Result<(), DirError> {
+        self.recover()?;
+
+        // Phase 1: prepare new data without touching the existing files
+        let mut paths = stash.write_files(self, "new")?;
+        paths.extend(state.write_files(self, "new")?);
+        paths.extend(index.write_files(self, "new")?);
+        self.sync_dirs()?;
+
+        // Phase 2: commit. Once the journal is on disk, the staged data will replace the old
+        // ones even if we get interrupted.
+        let journal = self.journal();
+        let lines = paths
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect::<String>();
+        let tmp = with_suffix(&journal, "tmp");
+        fs::write(&tmp, lines)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &journal)?;
+        sync_dir(&journal)?;
+
+        Ok(self.recover()?)
+    }
+}
+
+/// Data of a stock provider stored by [`DirStore`].
+trait DirData {
+    /// Writes the data into files with the given suffix, returning the paths
+    /// of the files which must be replaced with them.
+    fn write_files(&self, store: &DirStore, suffix: &str) -> Result<Vec<PathBuf>, DirError>;
+}
+
+/// Replaces file with its staged version. An empty staged file means that the
+/// file must be removed.
+fn replace_file(staged: &Path, path: &Path) -> io::Result<()> {
+    if fs::metadata(staged)?.len() > 0 {
+        return fs::rename(staged, path);
+    }
+    fs::remove_file(staged)?;
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+fn write_file(
+    object: &impl StrictEncode,
+    path: PathBuf,
+    suffix: &str,
+    paths: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    write_synced(object, &with_suffix(&path, suffix))?;
+    paths.push(path);
+    Ok(())
+}
+
+/// Data of contracts stored in per-contract files, which are read on the first
+/// access.
+#[derive(Debug)]
+struct Shards<T> {
+    dir: Option<PathBuf>,
+    ext: &'static str,
+    cells: BTreeMap<ContractId, OnceCell<T>>,
+    /// Contracts which were removed and whose files must be deleted.
+    removed: BTreeSet<ContractId>,
+    snapshot: Option<BTreeSet<ContractId>>,
+}
+
+impl<T: FsVersioned> Shards<T> {
+    fn new(ext: &'static str) -> Self {
+        Self {
+            dir: None,
+            ext,
+            cells: empty!(),
+            removed: empty!(),
+            snapshot: None,
+        }
+    }
+
+    fn open(dir: PathBuf, ext: &'static str) -> io::Result<Self> {
+        let mut cells = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new(ext)) {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .and_then(|stem| <[u8; 32]>::from_hex(stem).ok())
+            else {
+                continue;
+            };
+            cells.insert(ContractId::from_byte_array(id), OnceCell::new());
+        }
+        Ok(Self {
+            dir: Some(dir),
+            ext,
+            cells,
+            removed: empty!(),
+            snapshot: None,
+        })
+    }
+
+    fn path(dir: &Path, contract_id: ContractId, ext: &str) -> PathBuf {
+        dir.join(format!("{}.{ext}", contract_id.to_byte_array().to_hex()))
+    }
+
+    fn ids(&self) -> impl Iterator<Item = ContractId> + '_ {
+        self.cells
+            .keys()
+            .copied()
+            .filter(|id| !self.removed.contains(id))
+    }
+
+    fn is_removed(&self, contract_id: ContractId) -> bool { self.removed.contains(&contract_id) }
+
+    fn get(&self, contract_id: ContractId) -> Result<Option<&T>, LoadError> {
+        if self.is_removed(contract_id) {
+            return Ok(None);
+        }
+        let (Some(cell), Some(dir)) = (self.cells.get(&contract_id), &self.dir) else {
+            return Ok(None);
+        };
+        if let Some(data) = cell.get() {
+            return Ok(Some(data));
+        }
+        let data =
+            read_versioned(&Self::path(dir, contract_id, self.ext)).map_err(|err| LoadError {
+                contract_id,
+                details: err.to_string(),
+            })?;
+        Ok(Some(cell.get_or_init(|| data)))
+    }
+
+    fn load_all(&self) -> Result<(), LoadError> {
+        for contract_id in self.ids() {
+            self.get(contract_id)?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over contracts which must be written to the store in addition
+    /// to the updated ones: if the store is located in a different directory,
+    /// all contracts not present in memory must be copied there.
+    fn to_copy<'a>(
+        &'a self,
+        store: &DirStore,
+        resident: impl Fn(&ContractId) -> bool + 'a,
+    ) -> Result<impl Iterator<Item = (ContractId, &'a T)> + 'a, LoadError> {
+        let copy = self.dir.as_ref() != Some(&store.contracts_dir());
+        if copy {
+            self.load_all()?;
+        }
+        Ok(self
+            .cells
+            .iter()
+            .filter(move |(id, _)| copy && !resident(id) && !self.removed.contains(*id))
+            .filter_map(|(id, cell)| cell.get().map(|data| (*id, data))))
+    }
+
+    /// Marks contract data as removed, returning whether they were present.
+    fn remove(&mut self, contract_id: ContractId) -> bool {
+        self.cells.contains_key(&contract_id) && self.removed.insert(contract_id)
+    }
+
+    /// Writes empty files replacing the files of the removed contracts.
+    fn write_removed(
+        &self,
+        store: &DirStore,
+        resident: impl Fn(&ContractId) -> bool,
+        suffix: &str,
+        paths: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let dir = store.contracts_dir();
+        for contract_id in self.removed.iter().filter(|id| !resident(id)) {
+            let path = Self::path(&dir, *contract_id, self.ext);
+            fs::File::create(with_suffix(&path, suffix))?.sync_all()?;
+            paths.push(path);
+        }
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) {
+        if self.snapshot.is_none() {
+            self.snapshot = Some(self.removed.clone());
+        }
+    }
+
+    fn commit_transaction(&mut self) { self.snapshot = None; }
+
+    fn rollback_transaction(&mut self) {
+        if let Some(removed) = self.snapshot.take() {
+            self.removed = removed;
+        }
+    }
+}
+
+//////////
+// STASH
+//////////
+
+/// Manifest of the data stored in a per-contract file: contract genesis, ids
+/// of the operations and witnesses and tapret commitments, which are read
+/// without reading the rest of the contract data.
+#[derive(Clone, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+struct StashCatalog {
+    genesis: Genesis,
+    bundles: LargeOrdSet<BundleId>,
+    extensions: LargeOrdSet<OpId>,
+    witnesses: LargeOrdSet<XWitnessId>,
+    taprets: LargeOrdMap<XWitnessId, TapretCommitment>,
+}
+
+impl StashCatalog {
+    fn with(genesis: &Genesis, shard: &MemStash) -> Result<Self, confinement::Error> {
+        Ok(Self {
+            genesis: genesis.clone(),
+            bundles: LargeOrdSet::try_from_iter(shard.debug_bundles().keys().copied())?,
+            extensions: LargeOrdSet::try_from_iter(shard.debug_extensions().keys().copied())?,
+            witnesses: LargeOrdSet::try_from_iter(shard.debug_witnesses().keys().copied())?,
+            taprets: LargeOrdMap::try_from_iter(infallible(shard.taprets()))?,
+        })
+    }
+}
+
+impl FsVersioned for StashCatalog {}
+impl FsVersioned for MemContractState {}
+impl FsVersioned for ContractIndex {}
+
+fn bundle_contract(bundle: &TransitionBundle) -> Option<ContractId> {
+    bundle
+        .known_transitions
+        .values()
+        .next()
+        .map(|transition| transition.contract_id)
+}
+
+/// Copies operations and witnesses which are not yet known to the target
+/// stash.
+fn copy_operations(from: &MemStash, to: &mut MemStash) -> Result<(), MemError> {
+    for (id, genesis) in from.debug_geneses() {
+        if to.genesis(*id).is_err() {
+            to.replace_genesis(genesis.clone())?;
+        }
+    }
+    for (id, extension) in from.debug_extensions() {
+        if to.extension(*id).is_err() {
+            to.replace_extension(extension.clone())?;
+        }
+    }
+    for (id, bundle) in from.debug_bundles() {
+        if to.bundle(*id).is_err() {
+            to.replace_bundle(bundle.clone())?;
+        }
+    }
+    for (id, witness) in from.debug_witnesses() {
+        if to.witness(*id).is_err() {
+            to.replace_witness(witness.clone())?;
+        }
+    }
+    Ok(())
+}
+
+/// Stash keeping operations of each contract in a separate file.
+#[derive(Debug)]
+pub struct DirStash {
+    persistence: Option<Persistence<Self>>,
+    /// Data shared by all contracts and data of the contracts updated since
+    /// the stash was loaded.
+    mem: MemStash,
+    catalogs: Shards<StashCatalog>,
+    shards: Shards<MemStash>,
+}
+
+impl DirStash {
+    pub fn in_memory() -> Self {
+        Self {
+            persistence: None,
+            mem: MemStash::in_memory(),
+            catalogs: Shards::new(CATALOG_EXT),
+            shards: Shards::new(STASH_EXT),
+        }
+    }
+
+    fn is_resident(&self, contract_id: ContractId) -> bool {
+        self.mem.debug_geneses().contains_key(&contract_id)
+    }
+
+    /// Moves contract data into memory, such that they can be updated.
+    fn make_resident(&mut self, contract_id: ContractId) -> Result<(), DirError> {
+        if self.is_resident(contract_id) {
+            return Ok(());
+        }
+        if let Some(shard) = self.shards.get(contract_id)? {
+            copy_operations(shard, &mut self.mem)?;
+        }
+        Ok(())
+    }
+
+    /// Returns catalogs of the contracts which are not moved into memory,
+    /// reading them on the first access.
+    fn stored_catalogs(&self) -> Result<Vec<(ContractId, &StashCatalog)>, LoadError> {
+        let mut catalogs = vec![];
+        for contract_id in self.catalogs.ids().filter(|id| !self.is_resident(*id)) {
+            if let Some(catalog) = self.catalogs.get(contract_id)? {
+                catalogs.push((contract_id, catalog));
+            }
+        }
+        Ok(catalogs)
+    }
+
+    /// Finds contracts not moved into memory, which catalogs match the
+    /// predicate.
+    fn stored_owners(
+        &self,
+        owns: impl Fn(&StashCatalog) -> bool,
+    ) -> Result<BTreeSet<ContractId>, LoadError> {
+        Ok(self
+            .stored_catalogs()?
+            .into_iter()
+            .filter(|(_, catalog)| owns(catalog))
+            .map(|(contract_id, _)| contract_id)
+            .collect())
+    }
+
+    /// Looks up the data in memory and, if they are absent there, in the file
+    /// of the contract which catalog matches the `owns` predicate. Data of the
+    /// contracts moved into memory are never looked up in their files.
+    fn lookup<'a, T: 'a>(
+        &'a self,
+        owns: impl Fn(&StashCatalog) -> bool,
+        f: impl Fn(&'a MemStash) -> Result<&'a T, StashProviderError<Infallible>>,
+    ) -> Result<&'a T, StashProviderError<LoadError>> {
+        let res = f(&self.mem);
+        if res.is_ok() {
+            return lift_stash(res);
+        }
+        let owner = self
+            .stored_owners(owns)
+            .map_err(StashProviderError::Connectivity)?
+            .into_iter()
+            .next();
+        let shard = match owner {
+            Some(id) => self
+                .shards
+                .get(id)
+                .map_err(StashProviderError::Connectivity)?,
+            None => None,
+        };
+        match shard {
+            Some(shard) => lift_stash(f(shard)),
+            None => lift_stash(res),
+        }
+    }
+
+    fn all_geneses(&self) -> Result<impl Iterator<Item = &Genesis>, LoadError> {
+        let mut geneses = self
+            .stored_catalogs()?
+            .into_iter()
+            .map(|(contract_id, catalog)| (contract_id, &catalog.genesis))
+            .collect::<BTreeMap<_, _>>();
+        geneses.extend(
+            self.mem
+                .debug_geneses()
+                .iter()
+                .map(|(id, genesis)| (*id, genesis)),
+        );
+        Ok(geneses.into_values())
+    }
+
+    /// Collects ids from the data in memory and from the catalogs of the
+    /// contracts which are not moved into memory.
+    fn all_ids<T: Ord + Copy>(
+        &self,
+        mem: impl Iterator<Item = T>,
+        stored: impl Fn(&StashCatalog) -> &LargeOrdSet<T>,
+    ) -> Result<impl Iterator<Item = T>, LoadError> {
+        let mut ids = mem.collect::<BTreeSet<_>>();
+        for (_, catalog) in self.stored_catalogs()? {
+            ids.extend(stored(catalog));
+        }
+        Ok(ids.into_iter())
+    }
+}
+
+impl DirData for DirStash {
+    fn write_files(&self, store: &DirStore, suffix: &str) -> Result<Vec<PathBuf>, DirError> {
+        let mem = &self.mem;
+        let mut contracts = BTreeMap::new();
+        for genesis in mem.debug_geneses().values() {
+            let mut shard = MemStash::in_memory();
+            shard.replace_genesis(genesis.clone())?;
+            contracts.insert(genesis.contract_id(), shard);
+        }
+
+        let mut extensions = BTreeSet::new();
+        for (id, extension) in mem.debug_extensions() {
+            if let Some(shard) = contracts.get_mut(&extension.contract_id) {
+                shard.replace_extension(extension.clone())?;
+                extensions.insert(*id);
+            }
+        }
+        let mut bundles = BTreeMap::new();
+        for (id, bundle) in mem.debug_bundles() {
+            let Some(contract_id) = bundle_contract(bundle) else {
+                continue;
+            };
+            if let Some(shard) = contracts.get_mut(&contract_id) {
+                shard.replace_bundle(bundle.clone())?;
+                bundles.insert(*id, contract_id);
+            }
+        }
+        let mut witnesses = BTreeSet::new();
+        for (id, witness) in mem.debug_witnesses() {
+            let owners = witness
+                .anchors
+                .known_bundle_ids()
+                .filter_map(|bundle_id| bundles.get(&bundle_id).copied())
+                .collect::<BTreeSet<_>>();
+            for contract_id in &owners {
+                let shard = contracts.get_mut(contract_id).expect("resident contract");
+                shard.replace_witness(witness.clone())?;
+                witnesses.insert(*id);
+            }
+        }
+
+        let mut shared = mem.clone_no_persistence();
+        let geneses = contracts.keys().copied().collect();
+        let bundles = bundles.into_keys().collect();
+        shared.remove_operations(&geneses, &extensions, &bundles, &witnesses);
+
+        let mut paths = vec![];
+        write_file(&shared, store.path.join(STASH_FILE), suffix, &mut paths)?;
+        let dir = store.contracts_dir();
+        for (contract_id, shard) in &contracts {
+            let genesis = mem
+                .debug_geneses()
+                .get(contract_id)
+                .expect("resident contract");
+            let catalog = StashCatalog::with(genesis, shard)?;
+            let path = Shards::<StashCatalog>::path(&dir, *contract_id, CATALOG_EXT);
+            write_file(&catalog, path, suffix, &mut paths)?;
+            let path = Shards::<MemStash>::path(&dir, *contract_id, STASH_EXT);
+            write_file(shard, path, suffix, &mut paths)?;
+        }
+        let resident = |id: &ContractId| contracts.contains_key(id);
+        for (contract_id, catalog) in self.catalogs.to_copy(store, resident)? {
+            let path = Shards::<StashCatalog>::path(&dir, contract_id, CATALOG_EXT);
+            write_file(catalog, path, suffix, &mut paths)?;
+        }
+        for (contract_id, shard) in self.shards.to_copy(store, resident)? {
+            let path = Shards::<MemStash>::path(&dir, contract_id, STASH_EXT);
+            write_file(shard, path, suffix, &mut paths)?;
+        }
+        self.catalogs
+            .write_removed(store, resident, suffix, &mut paths)?;
+        self.shards
+            .write_removed(store, resident, suffix, &mut paths)?;
+        Ok(paths)
+    }
+}
+
+impl CloneNoPersistence for DirStash {
+    fn clone_no_persistence(&self) -> Self {
+        let mut mem = self.mem.clone_no_persistence();
+        self.shards
+            .load_all()
+            .expect("unable to load contract data");
+        for id in self.shards.ids().filter(|id| !self.is_resident(*id)) {
+            let shard = self.shards.get(id).ok().flatten().expect("loaded above");
+            copy_operations(shard, &mut mem).expect("unable to copy contract data");
+        }
+        Self {
+            persistence: None,
+            mem,
+            catalogs: Shards::new(CATALOG_EXT),
+            shards: Shards::new(STASH_EXT),
+        }
+    }
+}
+
+impl Persisting for DirStash {
+    #[inline]
+    fn persistence(&self) -> Option<&Persistence<Self>> { self.persistence.as_ref() }
+    #[inline]
+    fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
+    #[inline]
+    fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+}
+
+impl StoreTransaction for DirStash {
+    type TransactionErr = DirError;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.mem.begin_transaction()?;
+        self.catalogs.begin_transaction();
+        self.shards.begin_transaction();
+        self.mark_dirty();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.precommit_transaction()?;
+        self.complete_transaction()
+    }
+
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.store()?;
+        Ok(self.mem.precommit_transaction()?)
+    }
+
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.catalogs.commit_transaction();
+        self.shards.commit_transaction();
+        Ok(self.mem.complete_transaction()?)
+    }
+
+    fn rollback_transaction(&mut self) {
+        self.mem.rollback_transaction();
+        self.catalogs.rollback_transaction();
+        self.shards.rollback_transaction();
+    }
+}
+
+impl StashProvider for DirStash {}
+
+impl StashReadProvider for DirStash {
+    type Error = LoadError;
+
+    fn type_system(&self) -> Result<&TypeSystem, Self::Error> {
+        Ok(infallible(self.mem.type_system()))
+    }
+
+    fn lib(&self, id: LibId) -> Result<&Lib, StashProviderError<Self::Error>> {
+        lift_stash(self.mem.lib(id))
+    }
+
+    fn ifaces(&self) -> Result<impl Iterator<Item = &Iface>, Self::Error> {
+        Ok(infallible(self.mem.ifaces()))
+    }
+
+    fn iface(&self, iface: impl Into<IfaceRef>) -> Result<&Iface, StashProviderError<Self::Error>> {
+        lift_stash(self.mem.iface(iface))
+    }
+
+    fn schemata(&self) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        Ok(infallible(self.mem.schemata()))
+    }
+
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> Result<&SchemaIfaces, StashProviderError<Self::Error>> {
+        lift_stash(self.mem.schema(schema_id))
+    }
+
+    fn schemata_by<C: IfaceClass>(
+        &self,
+    ) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        Ok(infallible(self.mem.schemata_by::<C>()))
+    }
+
+    fn impl_for<'a, C: IfaceClass + 'a>(
+        &'a self,
+        schema_ifaces: &'a SchemaIfaces,
+    ) -> Result<&'a IfaceImpl, StashProviderError<Self::Error>> {
+        lift_stash(self.mem.impl_for::<C>(schema_ifaces))
+    }
+
+    fn geneses(&self) -> Result<impl Iterator<Item = &Genesis>, Self::Error> { self.all_geneses() }
+
+    fn geneses_by<C: IfaceClass>(&self) -> Result<impl Iterator<Item = &Genesis>, Self::Error> {
+        let schemata = self
+            .schemata_by::<C>()?
+            .map(|schema_ifaces| schema_ifaces.schema.schema_id())
+            .collect::<BTreeSet<_>>();
+        Ok(self
+            .all_geneses()?
+            .filter(move |genesis| schemata.contains(&genesis.schema_id)))
+    }
+
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&Genesis, StashProviderError<Self::Error>> {
+        if self.is_resident(contract_id) {
+            return lift_stash(self.mem.genesis(contract_id));
+        }
+        match self
+            .catalogs
+            .get(contract_id)
+            .map_err(StashProviderError::Connectivity)?
+        {
+            Some(catalog) => Ok(&catalog.genesis),
+            None => lift_stash(self.mem.genesis(contract_id)),
+        }
+    }
+
+    fn get_trust(&self, identity: &Identity) -> Result<TrustLevel, Self::Error> {
+        Ok(infallible(self.mem.get_trust(identity)))
+    }
+
+    fn identities(&self) -> Result<impl Iterator<Item = (Identity, TrustLevel)>, Self::Error> {
+        Ok(infallible(self.mem.identities()))
+    }
+
+    fn supplement(&self, content_ref: ContentRef) -> Result<Option<&Supplement>, Self::Error> {
+        Ok(infallible(self.mem.supplement(content_ref)))
+    }
+
+    fn supplements(
+        &self,
+        content_ref: ContentRef,
+    ) -> Result<impl Iterator<Item = Supplement>, Self::Error> {
+        Ok(infallible(self.mem.supplements(content_ref)))
+    }
+
+    fn sigs_for(&self, content_id: &ContentId) -> Result<Option<&ContentSigs>, Self::Error> {
+        Ok(infallible(self.mem.sigs_for(content_id)))
+    }
+
+    fn witness_ids(&self) -> Result<impl Iterator<Item = XWitnessId>, Self::Error> {
+        self.all_ids(infallible(self.mem.witness_ids()), |catalog| &catalog.witnesses)
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId>, Self::Error> {
+        self.all_ids(infallible(self.mem.bundle_ids()), |catalog| &catalog.bundles)
+    }
+
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<&TransitionBundle, StashProviderError<Self::Error>> {
+        self.lookup(|catalog| catalog.bundles.contains(&bundle_id), |stash| stash.bundle(bundle_id))
+    }
+
+    fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error> {
+        self.all_ids(infallible(self.mem.extension_ids()), |catalog| &catalog.extensions)
+    }
+
+    fn extension(&self, op_id: OpId) -> Result<&Extension, StashProviderError<Self::Error>> {
+        self.lookup(|catalog| catalog.extensions.contains(&op_id), |stash| stash.extension(op_id))
+    }
+
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        Ok(infallible(self.mem.attachment_ids()))
+    }
+
+    fn attachment(&self, id: AttachId) -> Result<Option<&MediumBlob>, Self::Error> {
+        Ok(infallible(self.mem.attachment(id)))
+    }
+
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<&SealWitness, StashProviderError<Self::Error>> {
+        self.lookup(
+            |catalog| catalog.witnesses.contains(&witness_id),
+            |stash| stash.witness(witness_id),
+        )
+    }
+
+    fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
+        let mut taprets = BTreeMap::new();
+        for (_, catalog) in self.stored_catalogs()? {
+            taprets.extend(
+                catalog
+                    .taprets
+                    .iter()
+                    .map(|(id, tapret)| (*id, tapret.clone())),
+            );
+        }
+        taprets.extend(infallible(self.mem.taprets()));
+        Ok(taprets.into_iter())
+    }
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> Result<Option<XChain<GraphSeal>>, Self::Error> {
+        Ok(infallible(self.mem.seal_secret(secret)))
+    }
+
+    fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
+        Ok(infallible(self.mem.secret_seals()))
+    }
+
+    fn prune_checkpoint(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&PruneCheckpoint>, Self::Error> {
+        Ok(infallible(self.mem.prune_checkpoint(contract_id)))
+    }
+
+    fn witness_replacements(
+        &self,
+    ) -> Result<impl Iterator<Item = (XWitnessId, XWitnessId)>, Self::Error> {
+        Ok(infallible(self.mem.witness_replacements()))
+    }
+}
+
+impl StashWriteProvider for DirStash {
+    type Error = DirError;
+
+    fn replace_schema(&mut self, schema: Schema) -> Result<bool, Self::Error> {
+        Ok(self.mem.replace_schema(schema)?)
+    }
+
+    fn replace_iface(&mut self, iface: Iface) -> Result<bool, Self::Error> {
+        Ok(self.mem.replace_iface(iface)?)
+    }
+
+    fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, Self::Error> {
+        Ok(self.mem.replace_iimpl(iimpl)?)
+    }
+
+    fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, Self::Error> {
+        self.make_resident(genesis.contract_id())?;
+        Ok(self.mem.replace_genesis(genesis)?)
+    }
+
+    fn replace_extension(&mut self, extension: Extension) -> Result<bool, Self::Error> {
+        self.make_resident(extension.contract_id)?;
+        Ok(self.mem.replace_extension(extension)?)
+    }
+
+    fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, Self::Error> {
+        if let Some(contract_id) = bundle_contract(&bundle) {
+            self.make_resident(contract_id)?;
+        }
+        Ok(self.mem.replace_bundle(bundle)?)
+    }
+
+    fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, Self::Error> {
+        let mut contracts = BTreeSet::new();
+        let mut stored = BTreeSet::new();
+        for bundle_id in witness.anchors.known_bundle_ids() {
+            match self.mem.bundle(bundle_id) {
+                Ok(bundle) => contracts.extend(bundle_contract(bundle)),
+                Err(_) => {
+                    stored.insert(bundle_id);
+                }
+            }
+        }
+        if !stored.is_empty() {
+            contracts.extend(
+                self.stored_owners(|catalog| stored.iter().any(|id| catalog.bundles.contains(id)))?,
+            );
+        }
+        for contract_id in contracts {
+            self.make_resident(contract_id)?;
+        }
+        Ok(self.mem.replace_witness(witness)?)
+    }
+
+    fn replace_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> Result<bool, Self::Error> {
+        Ok(self.mem.replace_attachment(id, attach)?)
+    }
+
+    fn replace_lib(&mut self, lib: Lib) -> Result<bool, Self::Error> {
+        Ok(self.mem.replace_lib(lib)?)
+    }
+
+    fn consume_types(&mut self, types: TypeSystem) -> Result<(), Self::Error> {
+        Ok(self.mem.consume_types(types)?)
+    }
+
+    fn set_trust(&mut self, identity: Identity, trust: TrustLevel) -> Result<(), Self::Error> {
+        Ok(self.mem.set_trust(identity, trust)?)
+    }
+
+    fn add_supplement(&mut self, suppl: Supplement) -> Result<(), Self::Error> {
+        Ok(self.mem.add_supplement(suppl)?)
+    }
+
+    fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), Self::Error>
+    where I: IntoIterator<Item = (Identity, SigBlob)> {
+        Ok(self.mem.import_sigs(content_id, sigs)?)
+    }
+
+    fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        Ok(self.mem.add_secret_seal(seal)?)
+    }
+
+    fn set_prune_checkpoint(
+        &mut self,
+        contract_id: ContractId,
+        checkpoint: PruneCheckpoint,
+    ) -> Result<(), Self::Error> {
+        Ok(self.mem.set_prune_checkpoint(contract_id, checkpoint)?)
+    }
+
+    fn set_witness_replacement(
+        &mut self,
+        replaced: XWitnessId,
+        replacement: XWitnessId,
+    ) -> Result<(), Self::Error> {
+        Ok(self.mem.set_witness_replacement(replaced, replacement)?)
+    }
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        self.catalogs.remove(contract_id);
+        let present = self.shards.remove(contract_id);
+        Ok(self.mem.remove_genesis(contract_id)? || present)
+    }
+
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, Self::Error> {
+        for contract_id in self.stored_owners(|catalog| catalog.extensions.contains(&opid))? {
+            self.make_resident(contract_id)?;
+        }
+        Ok(self.mem.remove_extension(opid)?)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        for contract_id in self.stored_owners(|catalog| catalog.bundles.contains(&bundle_id))? {
+            self.make_resident(contract_id)?;
+        }
+        Ok(self.mem.remove_bundle(bundle_id)?)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        for contract_id in self.stored_owners(|catalog| catalog.witnesses.contains(&witness_id))? {
+            self.make_resident(contract_id)?;
+        }
+        Ok(self.mem.remove_witness(witness_id)?)
+    }
+
+    fn remove_sigs(&mut self, content_id: ContentId) -> Result<bool, Self::Error> {
+        Ok(self.mem.remove_sigs(content_id)?)
+    }
+
+    fn remove_supplements(&mut self, content_ref: ContentRef) -> Result<bool, Self::Error> {
+        Ok(self.mem.remove_supplements(content_ref)?)
+    }
+
+    fn remove_attachment(&mut self, id: AttachId) -> Result<bool, Self::Error> {
+        Ok(self.mem.remove_attachment(id)?)
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        Ok(self.mem.remove_secret_seal(seal)?)
+    }
+}
+
+impl PersistenceProvider<DirStash> for DirStore {
+    fn load(&self) -> Result<DirStash, PersistenceError> {
+        self.recover().map_err(PersistenceError::with)?;
+        Ok(DirStash {
+            persistence: None,
+            mem: read_versioned(&self.path.join(STASH_FILE)).map_err(PersistenceError::with)?,
+            catalogs: Shards::open(self.contracts_dir(), CATALOG_EXT)
+                .map_err(PersistenceError::with)?,
+            shards: Shards::open(self.contracts_dir(), STASH_EXT)
+                .map_err(PersistenceError::with)?,
+        })
+    }
+
+    fn store(&self, object: &DirStash) -> Result<(), PersistenceError> {
+        self.store_files(object).map_err(PersistenceError::with)
+    }
+}
+
+//////////
+// STATE
+//////////
+
+/// Contracts whose state stored in per-contract files depends on each of the
+/// witnesses.
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+struct StateCatalog {
+    witnesses: LargeOrdMap<XWitnessId, SmallOrdSet<ContractId>>,
+}
+
+impl FsVersioned for StateCatalog {}
+
+/// Contract state keeping data of each contract in a separate file.
+#[derive(Debug)]
+pub struct DirState {
+    persistence: Option<Persistence<Self>>,
+    /// Witness ordering and state of the contracts updated since the state
+    /// was loaded.
+    mem: MemState,
+    catalog: StateCatalog,
+    shards: Shards<MemContractState>,
+}
+
+impl DirState {
+    pub fn in_memory() -> Self {
+        Self {
+            persistence: None,
+            mem: MemState::in_memory(),
+            catalog: default!(),
+            shards: Shards::new(STATE_EXT),
+        }
+    }
+
+    /// Moves contract state into memory, such that it can be updated.
+    fn make_resident(&mut self, contract_id: ContractId) -> Result<(), DirError> {
+        if self.mem.contract(contract_id).is_some() {
+            return Ok(());
+        }
+        if let Some(contract) = self.shards.get(contract_id)? {
+            self.mem.insert_contract(contract.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl DirData for DirState {
+    fn write_files(&self, store: &DirStore, suffix: &str) -> Result<Vec<PathBuf>, DirError> {
+        let mut paths = vec![];
+        let shared = self.mem.clone_without_contracts();
+        write_file(&shared, store.path.join(STATE_FILE), suffix, &mut paths)?;
+        let dir = store.contracts_dir();
+        let resident = self.mem.debug_contracts();
+
+        let mut witnesses = BTreeMap::<_, BTreeSet<_>>::new();
+        for (witness_id, contracts) in &self.catalog.witnesses {
+            let contracts = contracts
+                .iter()
+                .filter(|id| !resident.contains_key(*id) && !self.shards.is_removed(**id));
+            witnesses.entry(*witness_id).or_default().extend(contracts);
+        }
+        for (contract_id, contract) in resident {
+            for witness_id in contract.witness_ids() {
+                witnesses
+                    .entry(witness_id)
+                    .or_default()
+                    .insert(*contract_id);
+            }
+        }
+        let mut catalog = StateCatalog::default();
+        for (witness_id, contracts) in witnesses {
+            if !contracts.is_empty() {
+                catalog
+                    .witnesses
+                    .insert(witness_id, SmallOrdSet::try_from(contracts)?)?;
+            }
+        }
+        write_file(&catalog, store.path.join(STATE_CATALOG_FILE), suffix, &mut paths)?;
+
+        let copied = self.shards.to_copy(store, |id| resident.contains_key(id))?;
+        for (contract_id, contract) in resident.iter().map(|(id, c)| (*id, c)).chain(copied) {
+            let path = Shards::<MemContractState>::path(&dir, contract_id, STATE_EXT);
+            write_file(contract, path, suffix, &mut paths)?;
+        }
+        self.shards
+            .write_removed(store, |id| resident.contains_key(id), suffix, &mut paths)?;
+        Ok(paths)
+    }
+}
+
+impl CloneNoPersistence for DirState {
+    fn clone_no_persistence(&self) -> Self {
+        let mut mem = self.mem.clone_no_persistence();
+        self.shards
+            .load_all()
+            .expect("unable to load contract data");
+        for id in self.shards.ids() {
+            if mem.contract(id).is_none() {
+                let contract = self.shards.get(id).ok().flatten().expect("loaded above");
+                mem.insert_contract(contract.clone())
+                    .expect("unable to copy contract data");
+            }
+        }
+        Self {
+            persistence: None,
+            mem,
+            catalog: default!(),
+            shards: Shards::new(STATE_EXT),
+        }
+    }
+}
+
+impl Persisting for DirState {
+    #[inline]
+    fn persistence(&self) -> Option<&Persistence<Self>> { self.persistence.as_ref() }
+    #[inline]
+    fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
+    #[inline]
+    fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+}
+
+impl StoreTransaction for DirState {
+    type TransactionErr = DirError;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.mem.begin_transaction()?;
+        self.shards.begin_transaction();
+        self.mark_dirty();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.precommit_transaction()?;
+        self.complete_transaction()
+    }
+
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.store()?;
+        Ok(self.mem.precommit_transaction()?)
+    }
+
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.shards.commit_transaction();
+        Ok(self.mem.complete_transaction()?)
+    }
+
+    fn rollback_transaction(&mut self) {
+        self.mem.rollback_transaction();
+        self.shards.rollback_transaction();
+    }
+}
+
+impl StateProvider for DirState {}
+
+impl DirState {
+    fn unfiltered_contract(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&MemContractState, DirStateError> {
+        match self.mem.contract(contract_id) {
+            Some(contract) => Ok(contract),
+            None => Ok(self
+                .shards
+                .get(contract_id)?
+                .ok_or(StateInconsistency::UnknownContract(contract_id))?),
+        }
+    }
+}
+
+impl StateReadProvider for DirState {
+    type ContractRead<'a> = MemContract<&'a MemContractState>;
+    type Error = DirStateError;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Self::ContractRead<'_>, Self::Error> {
+        let unfiltered = self.unfiltered_contract(contract_id)?;
+        Ok(self.mem.contract_read(unfiltered))
+    }
+
+    fn contract_state_at(
+        &self,
+        contract_id: ContractId,
+        layer1: Layer1,
+        height: u32,
+    ) -> Result<Self::ContractRead<'_>, Self::Error> {
+        let unfiltered = self.unfiltered_contract(contract_id)?;
+        Ok(self.mem.contract_read_at(unfiltered, layer1, height))
+    }
+
+    fn is_valid_witness(&self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        Ok(self.mem.is_valid_witness(witness_id)?)
+    }
+
+    fn witness_ord(&self, witness_id: XWitnessId) -> Result<WitnessOrd, Self::Error> {
+        Ok(self.mem.witness_ord(witness_id)?)
+    }
+}
+
+/// Writer of the contract state moved into memory by [`DirState`].
+pub struct DirContractWriter<'a>(MemContractWriter<'a>);
+
+impl ContractStateWrite for DirContractWriter<'_> {
+    type Error = DirError;
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<(), Self::Error> {
+        Ok(self.0.add_genesis(genesis)?)
+    }
+
+    fn add_transition(
+        &mut self,
+        transition: &Transition,
+        witness_id: XWitnessId,
+        witness_ord: WitnessOrd,
+    ) -> Result<(), Self::Error> {
+        Ok(self.0.add_transition(transition, witness_id, witness_ord)?)
+    }
+
+    fn add_extension(
+        &mut self,
+        extension: &Extension,
+        witness_id: XWitnessId,
+        witness_ord: WitnessOrd,
+    ) -> Result<(), Self::Error> {
+        Ok(self.0.add_extension(extension, witness_id, witness_ord)?)
+    }
+}
+
+impl StateWriteProvider for DirState {
+    type ContractWrite<'a> = DirContractWriter<'a>;
+    type Error = DirError;
+
+    fn register_contract(
+        &mut self,
+        schema: &Schema,
+        genesis: &Genesis,
+    ) -> Result<Self::ContractWrite<'_>, Self::Error> {
+        self.make_resident(genesis.contract_id())?;
+        Ok(DirContractWriter(self.mem.register_contract(schema, genesis)?))
+    }
+
+    fn update_contract(
+        &mut self,
+        contract_id: ContractId,
+    ) -> Result<Option<Self::ContractWrite<'_>>, Self::Error> {
+        self.make_resident(contract_id)?;
+        Ok(self
+            .mem
+            .update_contract(contract_id)?
+            .map(DirContractWriter))
+    }
+
+    fn update_witnesses(
+        &mut self,
+        resolver: impl ResolveWitnessBatch,
+        after_height: u32,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<UpdateRes, Self::Error> {
+        let res = self
+            .mem
+            .update_witnesses(resolver, after_height, progress)?;
+        let affected = res
+            .updated
+            .keys()
+            .filter_map(|witness_id| self.catalog.witnesses.get(witness_id))
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<_>>();
+        for contract_id in affected {
+            self.make_resident(contract_id)?;
+        }
+        self.mark_dirty();
+        self.store()?;
+        Ok(res)
+    }
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let present = self.shards.remove(contract_id);
+        Ok(self.mem.remove_contract(contract_id)? || present)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        Ok(self.mem.remove_witness(witness_id)?)
+    }
+}
+
+impl PersistenceProvider<DirState> for DirStore {
+    fn load(&self) -> Result<DirState, PersistenceError> {
+        self.recover().map_err(PersistenceError::with)?;
+        Ok(DirState {
+            persistence: None,
+            mem: read_versioned(&self.path.join(STATE_FILE)).map_err(PersistenceError::with)?,
+            catalog: read_versioned(&self.path.join(STATE_CATALOG_FILE))
+                .map_err(PersistenceError::with)?,
+            shards: Shards::open(self.contracts_dir(), STATE_EXT)
+                .map_err(PersistenceError::with)?,
+        })
+    }
+
+    fn store(&self, object: &DirState) -> Result<(), PersistenceError> {
+        self.store_files(object).map_err(PersistenceError::with)
+    }
+}
+
+//////////
+// INDEX
+//////////
+
+/// Index keeping data of each contract in a separate file.
+#[derive(Debug)]
+pub struct DirIndex {
+    persistence: Option<Persistence<Self>>,
+    /// Operation, bundle and terminal indexes together with index data of
+    /// the contracts updated since the index was loaded.
+    mem: MemIndex,
+    shards: Shards<ContractIndex>,
+}
+
+impl DirIndex {
+    pub fn in_memory() -> Self {
+        Self {
+            persistence: None,
+            mem: MemIndex::in_memory(),
+            shards: Shards::new(INDEX_EXT),
+        }
+    }
+
+    fn contract_index(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&ContractIndex, IndexReadError<LoadError>> {
+        if let Some(index) = self.mem.contract_index(contract_id) {
+            return Ok(index);
+        }
+        self.shards
+            .get(contract_id)
+            .map_err(IndexReadError::Connectivity)?
+            .ok_or(IndexInconsistency::ContractAbsent(contract_id).into())
+    }
+
+    /// Moves contract index into memory, such that it can be updated.
+    fn make_resident(&mut self, contract_id: ContractId) -> Result<(), DirError> {
+        if self.mem.contract_index(contract_id).is_some() {
+            return Ok(());
+        }
+        if let Some(index) = self.shards.get(contract_id)? {
+            self.mem.insert_contract_index(contract_id, index.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl DirData for DirIndex {
+    fn write_files(&self, store: &DirStore, suffix: &str) -> Result<Vec<PathBuf>, DirError> {
+        let mut paths = vec![];
+        let shared = self.mem.clone_without_contracts();
+        write_file(&shared, store.path.join(INDEX_FILE), suffix, &mut paths)?;
+        let dir = store.contracts_dir();
+        let resident = self.mem.debug_contract_index();
+        let copied = self.shards.to_copy(store, |id| resident.contains_key(id))?;
+        for (contract_id, index) in resident.iter().map(|(id, i)| (*id, i)).chain(copied) {
+            let path = Shards::<ContractIndex>::path(&dir, contract_id, INDEX_EXT);
+            write_file(index, path, suffix, &mut paths)?;
+        }
+        self.shards
+            .write_removed(store, |id| resident.contains_key(id), suffix, &mut paths)?;
+        Ok(paths)
+    }
+}
+
+impl CloneNoPersistence for DirIndex {
+    fn clone_no_persistence(&self) -> Self {
+        let mut mem = self.mem.clone_no_persistence();
+        self.shards
+            .load_all()
+            .expect("unable to load contract data");
+        for id in self.shards.ids() {
+            if mem.contract_index(id).is_none() {
+                let index = self.shards.get(id).ok().flatten().expect("loaded above");
+                mem.insert_contract_index(id, index.clone())
+                    .expect("unable to copy contract data");
+            }
+        }
+        Self {
+            persistence: None,
+            mem,
+            shards: Shards::new(INDEX_EXT),
+        }
+    }
+}
+
+impl Persisting for DirIndex {
+    #[inline]
+    fn persistence(&self) -> Option<&Persistence<Self>> { self.persistence.as_ref() }
+    #[inline]
+    fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
+    #[inline]
+    fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+}
+
+impl StoreTransaction for DirIndex {
+    type TransactionErr = DirError;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.mem.begin_transaction()?;
+        self.shards.begin_transaction();
+        self.mark_dirty();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.precommit_transaction()?;
+        self.complete_transaction()
+    }
+
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.store()?;
+        Ok(self.mem.precommit_transaction()?)
+    }
+
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.shards.commit_transaction();
+        Ok(self.mem.complete_transaction()?)
+    }
+
+    fn rollback_transaction(&mut self) {
+        self.mem.rollback_transaction();
+        self.shards.rollback_transaction();
+    }
+}
+
+impl IndexProvider for DirIndex {}
+
+impl IndexReadProvider for DirIndex {
+    type Error = LoadError;
+
+    /// Reads data of all contracts stored in the directory.
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutpoint>,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, Self::Error> {
+        self.shards.load_all()?;
+        let ids = self
+            .mem
+            .debug_contract_index()
+            .keys()
+            .copied()
+            .chain(self.shards.ids())
+            .collect::<BTreeSet<_>>();
+        let mut contracts = vec![];
+        for contract_id in ids {
+            let index = match self.contract_index(contract_id) {
+                Ok(index) => index,
+                Err(IndexReadError::Connectivity(err)) => return Err(err),
+                Err(IndexReadError::Inconsistency(_)) => continue,
+            };
+            if outputs.iter().any(|outpoint| index.assigns(*outpoint)) {
+                contracts.push(contract_id);
+            }
+        }
+        Ok(contracts.into_iter())
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        Ok(self.contract_index(contract_id)?.public_opouts())
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutpoint>>,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        Ok(self
+            .contract_index(contract_id)?
+            .opouts_by_outputs(contract_id, outputs)?)
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<BTreeSet<Opout>, Self::Error> {
+        Ok(infallible(self.mem.opouts_by_terminals(terminals)))
+    }
+
+    fn bundle_id_for_op(&self, opid: OpId) -> Result<BundleId, IndexReadError<Self::Error>> {
+        lift_index(self.mem.bundle_id_for_op(opid))
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<(impl Iterator<Item = XWitnessId>, ContractId), IndexReadError<Self::Error>> {
+        lift_index(self.mem.bundle_info(bundle_id))
+    }
+
+    fn indexed_ops(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        Ok(infallible(self.mem.indexed_ops()))
+    }
+}
+
+impl IndexWriteProvider for DirIndex {
+    type Error = DirError;
+
+    fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        self.make_resident(contract_id)?;
+        Ok(self.mem.register_contract(contract_id)?)
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        Ok(self
+            .mem
+            .register_bundle(bundle_id, witness_id, contract_id)?)
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        Ok(self.mem.register_operation(opid, bundle_id)?)
+    }
+
+    fn index_genesis_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GenesisSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        self.make_resident(contract_id)
+            .map_err(IndexWriteError::Connectivity)?;
+        Ok(self
+            .mem
+            .index_genesis_assignments(contract_id, vec, opid, type_id)?)
+    }
+
+    fn index_transition_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GraphSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        witness_id: XWitnessId,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        self.make_resident(contract_id)
+            .map_err(IndexWriteError::Connectivity)?;
+        Ok(self
+            .mem
+            .index_transition_assignments(contract_id, vec, opid, type_id, witness_id)?)
+    }
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let present = self.shards.remove(contract_id);
+        Ok(self.mem.remove_contract(contract_id)? || present)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        Ok(self.mem.remove_bundle(bundle_id)?)
+    }
+
+    fn remove_terminals(&mut self, opids: &BTreeSet<OpId>) -> Result<(), Self::Error> {
+        Ok(self.mem.remove_terminals(opids)?)
+    }
+}
+
+impl PersistenceProvider<DirIndex> for DirStore {
+    fn load(&self) -> Result<DirIndex, PersistenceError> {
+        self.recover().map_err(PersistenceError::with)?;
+        Ok(DirIndex {
+            persistence: None,
+            mem: read_versioned(&self.path.join(INDEX_FILE)).map_err(PersistenceError::with)?,
+            shards: Shards::open(self.contracts_dir(), INDEX_EXT)
+                .map_err(PersistenceError::with)?,
+        })
+    }
+
+    fn store(&self, object: &DirIndex) -> Result<(), PersistenceError> {
+        self.store_files(object).map_err(PersistenceError::with)
+    }
+}
+
+impl StockPersistence<DirStash, DirState, DirIndex> for DirStore {
+    fn store_all(
+        &self,
+        stash: &DirStash,
+        state: &DirState,
+        index: &DirIndex,
+    ) -> Result<(), PersistenceError> {
+        self.store_all_files(stash, state, index)
+            .map_err(PersistenceError::with)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interface::resolver::KnownOrdResolver;
+    use crate::interface::SequentialResolver;
+    use crate::persistence::fixture::{mined, Transfers};
+    use crate::persistence::Stock;
+
+    fn store_transfers(store: &DirStore) -> Transfers {
+        let mut stock =
+            Stock::with(DirStash::in_memory(), DirState::in_memory(), DirIndex::in_memory());
+        let transfers = Transfers::consume(&mut stock);
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        transfers
+    }
+
+    #[test]
+    fn contracts_are_loaded_lazily() {
+        let path = std::env::temp_dir().join(format!("rgb-dir-{}", rand::random::<u64>()));
+        let store = DirStore::new(path.clone()).unwrap();
+        let contract_id = ContractId::from_byte_array([0xAB; 32]);
+
+        let mut index = DirIndex::in_memory();
+        index.register_contract(contract_id).unwrap();
+        let mut stock = Stock::with(DirStash::in_memory(), DirState::in_memory(), index);
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let file = Shards::<ContractIndex>::path(&store.contracts_dir(), contract_id, INDEX_EXT);
+        assert!(file.exists());
+
+        let index: DirIndex = store.load().unwrap();
+        assert!(index.mem.contract_index(contract_id).is_none());
+        assert!(index.shards.cells[&contract_id].get().is_none());
+        assert!(index.public_opouts(contract_id).unwrap().is_empty());
+        assert!(index.shards.cells[&contract_id].get().is_some());
+
+        Stock::<DirStash, DirState, DirIndex>::load_atomic(store, false).unwrap();
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn removed_contracts_are_deleted() {
+        let path = std::env::temp_dir().join(format!("rgb-dir-{}", rand::random::<u64>()));
+        let store = DirStore::new(path.clone()).unwrap();
+        let contract_id = ContractId::from_byte_array([0xCD; 32]);
+
+        let mut index = DirIndex::in_memory();
+        index.register_contract(contract_id).unwrap();
+        index.make_persistent(store.clone(), true).unwrap();
+        let file = Shards::<ContractIndex>::path(&store.contracts_dir(), contract_id, INDEX_EXT);
+        assert!(file.exists());
+
+        let mut index: DirIndex = store.load().unwrap();
+        index.make_persistent(store.clone(), true).unwrap();
+        index.begin_transaction().unwrap();
+        assert!(index.remove_contract(contract_id).unwrap());
+        index.rollback_transaction();
+        assert!(index.public_opouts(contract_id).is_ok());
+
+        index.begin_transaction().unwrap();
+        assert!(index.remove_contract(contract_id).unwrap());
+        index.commit_transaction().unwrap();
+        assert!(!file.exists());
+        assert!(index.public_opouts(contract_id).is_err());
+
+        let index: DirIndex = store.load().unwrap();
+        assert!(index.public_opouts(contract_id).is_err());
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn catalog_lists_contracts() {
+        let path = std::env::temp_dir().join(format!("rgb-dir-{}", rand::random::<u64>()));
+        let store = DirStore::new(path.clone()).unwrap();
+        let transfers = store_transfers(&store);
+        let contract_id = transfers.fixture.contract_id();
+
+        let stash: DirStash = store.load().unwrap();
+        assert!(stash.catalogs.cells[&contract_id].get().is_none());
+        assert_eq!(stash.geneses().unwrap().collect::<Vec<_>>(), vec![&transfers.fixture.genesis]);
+        assert!(stash.catalogs.cells[&contract_id].get().is_some());
+        assert_eq!(stash.genesis(contract_id).unwrap(), &transfers.fixture.genesis);
+        assert_eq!(stash.taprets().unwrap().count(), 0);
+        assert_eq!(stash.bundle_ids().unwrap().count(), 2);
+        assert!(stash.shards.cells[&contract_id].get().is_none());
+
+        let bundle_id = stash.bundle_ids().unwrap().next().unwrap();
+        assert_eq!(stash.bundle(bundle_id).unwrap().bundle_id(), bundle_id);
+        assert!(stash.shards.cells[&contract_id].get().is_some());
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn stored_operations_are_removed() {
+        let path = std::env::temp_dir().join(format!("rgb-dir-{}", rand::random::<u64>()));
+        let store = DirStore::new(path.clone()).unwrap();
+        let transfers = store_transfers(&store);
+        let contract_id = transfers.fixture.contract_id();
+        let [witness1, witness2] = transfers.witnesses;
+
+        let mut stash: DirStash = store.load().unwrap();
+        stash.make_persistent(store.clone(), true).unwrap();
+        stash.begin_transaction().unwrap();
+        assert!(stash.remove_witness(witness2).unwrap());
+        assert!(stash.is_resident(contract_id));
+        assert!(stash.witness(witness2).is_err());
+        stash.commit_transaction().unwrap();
+
+        let stash: DirStash = store.load().unwrap();
+        assert!(stash.witness(witness2).is_err());
+        assert!(stash.witness(witness1).is_ok());
+        assert_eq!(stash.witness_ids().unwrap().collect::<Vec<_>>(), vec![witness1]);
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn witness_updates_load_contracts() {
+        let path = std::env::temp_dir().join(format!("rgb-dir-{}", rand::random::<u64>()));
+        let store = DirStore::new(path.clone()).unwrap();
+        let transfers = store_transfers(&store);
+        let contract_id = transfers.fixture.contract_id();
+        let [witness1, witness2] = transfers.witnesses;
+
+        let mut state: DirState = store.load().unwrap();
+        assert!(state.mem.contract(contract_id).is_none());
+        let resolver = KnownOrdResolver(bmap! {
+            witness1 => mined(100),
+            witness2 => WitnessOrd::Tentative,
+        });
+        let res = state
+            .update_witnesses(SequentialResolver(resolver), 0, |_, _| {})
+            .unwrap();
+        assert_eq!(res.updated.keys().collect::<Vec<_>>(), vec![&witness2]);
+        assert!(state.mem.contract(contract_id).is_some());
+        fs::remove_dir_all(path).ok();
+    }
+}
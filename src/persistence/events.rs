@@ -0,0 +1,133 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Notifications about the changes in the [`Stock`] data.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug, Formatter};
+
+use rgb::vm::WitnessOrd;
+use rgb::{ContractId, Opout, SecretSeal, TransitionBundle, XChain, XWitnessId};
+
+#[cfg(doc)]
+use super::Stock;
+
+/// Event emitted by [`Stock`] once the data change is committed.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum StockEvent {
+    /// Contract was imported.
+    ContractImported { contract_id: ContractId },
+
+    /// Transfer consignment was accepted.
+    TransferAccepted {
+        contract_id: ContractId,
+        /// Outputs of the operations contained in the consignment bundles.
+        opouts: BTreeSet<Opout>,
+        /// Witnesses anchoring the consignment bundles.
+        witness_ids: BTreeSet<XWitnessId>,
+    },
+
+    /// Fascia was consumed, adding new state anchored by the witness.
+    FasciaConsumed {
+        witness_id: XWitnessId,
+        contract_ids: BTreeSet<ContractId>,
+        /// Outputs of the operations contained in the fascia bundles.
+        opouts: BTreeSet<Opout>,
+    },
+
+    /// New secret seal was stored.
+    SecretSealStored { seal: XChain<SecretSeal> },
+
+    /// Witness ordering has changed.
+    WitnessOrdChanged {
+        witness_id: XWitnessId,
+        ord: WitnessOrd,
+        /// Contracts having operations anchored by the witness.
+        contract_ids: BTreeSet<ContractId>,
+        /// Outputs of the operations anchored by the witness.
+        opouts: BTreeSet<Opout>,
+    },
+}
+
+/// Receiver of the [`StockEvent`]s, registered with [`Stock::subscribe`].
+pub trait StockObserver: Send + Sync {
+    fn on_event(&mut self, event: &StockEvent);
+}
+
+impl<F: FnMut(&StockEvent) + Send + Sync> StockObserver for F {
+    fn on_event(&mut self, event: &StockEvent) { self(event) }
+}
+
+/// Identifier of the observer returned by [`Stock::subscribe`], which can be
+/// used to [`Stock::unsubscribe`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display(inner)]
+pub struct ObserverId(u32);
+
+#[derive(Default)]
+pub(super) struct Observers {
+    next_id: u32,
+    observers: BTreeMap<ObserverId, Box<dyn StockObserver>>,
+}
+
+impl Debug for Observers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("count", &self.observers.len())
+            .finish()
+    }
+}
+
+impl Observers {
+    pub(super) fn subscribe(&mut self, observer: impl StockObserver + 'static) -> ObserverId {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.insert(id, Box::new(observer));
+        id
+    }
+
+    pub(super) fn unsubscribe(&mut self, id: ObserverId) -> bool {
+        self.observers.remove(&id).is_some()
+    }
+
+    pub(super) fn emit(&mut self, events: impl IntoIterator<Item = StockEvent>) {
+        for event in events {
+            for observer in self.observers.values_mut() {
+                observer.on_event(&event);
+            }
+        }
+    }
+}
+
+/// Collects outputs of all known operations in the bundle.
+pub(super) fn bundle_opouts(bundle: &TransitionBundle) -> impl Iterator<Item = Opout> + '_ {
+    bundle
+        .known_transitions
+        .iter()
+        .flat_map(|(opid, transition)| {
+            transition
+                .assignments
+                .iter()
+                .flat_map(move |(ty, assigns)| {
+                    (0..assigns.len_u16()).map(move |no| Opout::new(*opid, *ty, no))
+                })
+        })
+}
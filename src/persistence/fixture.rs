@@ -0,0 +1,453 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test fixture producing a contract together with the transfers and witness
+//! transactions which pass the consensus validation.
+
+use std::collections::BTreeMap;
+
+use amplify::confinement::{Confined, MediumBlob, NonEmptyOrdMap};
+use amplify::{ByteArray, Wrapper};
+use bp::dbc::{Anchor, Method};
+use bp::seals::txout::{CloseMethod, ExplicitSeal};
+use bp::{
+    LockTime, OpCode, Outpoint, Sats, ScriptPubkey, SeqNo, SigScript, Tx, TxIn, TxOut, TxVer, Txid,
+    Vout, Witness,
+};
+use commit_verify::mpc::{self, MerkleBlock, MerkleTree, MultiSource};
+use commit_verify::{CommitId, DigestExt, EmbedCommitVerify, Sha256, TryCommitVerify};
+use rgb::validation::{ResolveWitness, WitnessResolverError};
+use rgb::vm::{WitnessOrd, WitnessPos, XWitnessTx};
+use rgb::{
+    Assign, AssignmentType, Assignments, AttachId, ContractId, Extension, ExtensionSchema,
+    ExtensionType, Genesis, GenesisSchema, GenesisSeal, GraphSeal, Input, InputMap, MediaType,
+    Occurrences, OpId, Operation, Opout, OwnedStateSchema, Redeemed, RevealedAttach, Schema,
+    Transition, TransitionBundle, TransitionSchema, TransitionType, TypedAssigns, Valencies,
+    ValencyType, VoidState, XChain, XOutputSeal, XWitnessId,
+};
+use strict_encoding::StrictDumb;
+
+use super::{IndexProvider, StashProvider, StateProvider, Stock};
+use crate::containers::{
+    AnchorSet, Consignment, ContainerVer, DumbValidator, Fascia, PubWitness, ValidContract,
+};
+
+pub(super) const OWNED: AssignmentType = AssignmentType::with(1000);
+pub(super) const ATTACH: AssignmentType = AssignmentType::with(1001);
+pub(super) const TRANSFER: TransitionType = TransitionType::with(10000);
+pub(super) const EXTENSION: ExtensionType = ExtensionType::with(20000);
+pub(super) const VALENCY: ValencyType = ValencyType::with(1);
+
+pub(super) fn mined(height: u32) -> WitnessOrd {
+    WitnessOrd::Mined(WitnessPos::bitcoin(height.try_into().unwrap(), 1_700_000_000).unwrap())
+}
+
+/// Witness transactions known to the test blockchain.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Chain {
+    txes: BTreeMap<XWitnessId, (Tx, WitnessOrd)>,
+}
+
+impl Chain {
+    /// Changes ordering of a known witness, e.g. to simulate a re-org.
+    pub fn set_ord(&mut self, witness_id: XWitnessId, ord: WitnessOrd) {
+        self.txes.get_mut(&witness_id).expect("unknown witness").1 = ord;
+    }
+}
+
+impl ResolveWitness for Chain {
+    fn resolve_pub_witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<XWitnessTx, WitnessResolverError> {
+        self.txes
+            .get(&witness_id)
+            .map(|(tx, _)| XChain::Bitcoin(tx.clone()))
+            .ok_or(WitnessResolverError::Unknown(witness_id))
+    }
+
+    fn resolve_pub_witness_ord(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<WitnessOrd, WitnessResolverError> {
+        self.txes
+            .get(&witness_id)
+            .map(|(_, ord)| *ord)
+            .ok_or(WitnessResolverError::Unknown(witness_id))
+    }
+}
+
+/// Contract with a single declarative owned state type which may be freely
+/// re-assigned by transfers. The genesis also defines a valency, which may be
+/// redeemed by state extensions assigning the declarative and attachment
+/// state.
+#[derive(Clone, Debug)]
+pub(super) struct Fixture {
+    pub schema: Schema,
+    pub genesis: Genesis,
+    pub extensions: Vec<Extension>,
+    pub attachments: BTreeMap<AttachId, MediumBlob>,
+    pub chain: Chain,
+    seals: BTreeMap<Opout, Outpoint>,
+    vouts: BTreeMap<OpId, Vec<u32>>,
+    nonce: u64,
+}
+
+impl Fixture {
+    /// Issues testnet contract with `allocations` assignments to distinct
+    /// outpoints.
+    pub fn issue(allocations: u8) -> Self { Self::issue_on(allocations, true) }
+
+    /// Issues contract with `allocations` assignments to distinct outpoints
+    /// either on testnet or on a production network.
+    pub fn issue_on(allocations: u8, testnet: bool) -> Self {
+        let mut schema = Schema::strict_dumb();
+        schema.owned_types = Confined::from_checked(bmap! {
+            OWNED => OwnedStateSchema::Declarative,
+            ATTACH => OwnedStateSchema::Attachment(MediaType::Any),
+        });
+        schema.valency_types = Confined::from_checked(bset! { VALENCY });
+        schema.genesis = GenesisSchema {
+            assignments: Confined::from_checked(bmap! { OWNED => Occurrences::OnceOrMore }),
+            valencies: Confined::from_checked(bset! { VALENCY }),
+            ..default!()
+        };
+        schema.extensions = Confined::from_checked(bmap! {
+            EXTENSION => ExtensionSchema {
+                redeems: Confined::from_checked(bset! { VALENCY }),
+                assignments: Confined::from_checked(bmap! {
+                    OWNED => Occurrences::NoneOrMore,
+                    ATTACH => Occurrences::NoneOrMore,
+                }),
+                ..default!()
+            }
+        });
+        schema.transitions = Confined::from_checked(bmap! {
+            TRANSFER => TransitionSchema {
+                inputs: Confined::from_checked(bmap! { OWNED => Occurrences::OnceOrMore }),
+                assignments: Confined::from_checked(bmap! { OWNED => Occurrences::OnceOrMore }),
+                ..default!()
+            }
+        });
+
+        let outpoints = (1..=allocations)
+            .map(|no| Outpoint::new(Txid::from_byte_array([no; 32]), Vout::from_u32(0)))
+            .collect::<Vec<_>>();
+        let mut genesis = Genesis::strict_dumb();
+        genesis.schema_id = schema.schema_id();
+        genesis.testnet = testnet;
+        genesis.valencies = Valencies::from_inner(Confined::from_checked(bset! { VALENCY }));
+        genesis.assignments = Assignments::from_inner(Confined::from_checked(bmap! {
+            OWNED => TypedAssigns::Declarative(Confined::from_iter_checked(outpoints.iter().map(
+                |outpoint| {
+                    let seal = GenesisSeal::new_random(Method::OpretFirst, outpoint.txid, outpoint.vout);
+                    Assign::revealed(XChain::Bitcoin(seal), VoidState::default())
+                },
+            )))
+        }));
+
+        let genesis_id = genesis.id();
+        let seals = outpoints
+            .into_iter()
+            .enumerate()
+            .map(|(no, outpoint)| (Opout::new(genesis_id, OWNED, no as u16), outpoint))
+            .collect();
+        Fixture {
+            schema,
+            genesis,
+            extensions: none!(),
+            attachments: none!(),
+            chain: none!(),
+            seals,
+            vouts: none!(),
+            nonce: 0,
+        }
+    }
+
+    pub fn contract_id(&self) -> ContractId { self.genesis.contract_id() }
+
+    /// Returns outpoint of the seal defined by the assignment.
+    pub fn outpoint(&self, opout: Opout) -> Outpoint { self.seals[&opout] }
+
+    /// Returns the seal defined by the assignment.
+    pub fn seal(&self, opout: Opout) -> XOutputSeal {
+        XChain::Bitcoin(ExplicitSeal::new(Method::OpretFirst, self.outpoint(opout)))
+    }
+
+    /// Returns assignments created by the transfer.
+    pub fn outputs(&self, opid: OpId) -> Vec<Opout> {
+        (0..self.vouts[&opid].len())
+            .map(|no| Opout::new(opid, OWNED, no as u16))
+            .collect()
+    }
+
+    pub fn contract(&self) -> ValidContract {
+        let consignment = Consignment::<false> {
+            version: ContainerVer::V2,
+            transfer: false,
+            terminals: none!(),
+            genesis: self.genesis.clone(),
+            extensions: Confined::from_iter_checked(self.extensions.iter().cloned()),
+            bundles: none!(),
+            schema: self.schema.clone(),
+            ifaces: none!(),
+            supplements: none!(),
+            types: none!(),
+            scripts: none!(),
+            attachments: Confined::from_checked(self.attachments.clone()),
+            signatures: none!(),
+        };
+        consignment
+            .validate(&self.chain, &DumbValidator, self.genesis.testnet)
+            .unwrap_or_else(|(status, _)| panic!("invalid contract: {status}"))
+    }
+
+    /// Constructs a state extension redeeming the genesis valency, which
+    /// assigns the declarative state and, optionally, the `attachment` to new
+    /// distinct outpoints. The extension and the attachment are included into
+    /// the [`Fixture::contract`].
+    pub fn extend(&mut self, attachment: Option<&[u8]>) -> Extension {
+        self.nonce += 1;
+        let outpoint = |vout: u32| {
+            Outpoint::new(
+                Txid::from_byte_array([0xEE; 32]),
+                Vout::from_u32(self.nonce as u32 * 2 + vout),
+            )
+        };
+        let outpoints = [outpoint(0), outpoint(1)];
+        let seal = |outpoint: Outpoint| {
+            XChain::Bitcoin(GenesisSeal::new_random(
+                Method::OpretFirst,
+                outpoint.txid,
+                outpoint.vout,
+            ))
+        };
+        let mut assignments = bmap! {
+            OWNED => TypedAssigns::Declarative(Confined::from_checked(vec![
+                Assign::revealed(seal(outpoints[0]), VoidState::default()),
+            ])),
+        };
+        if let Some(attachment) = attachment {
+            let mut hasher = Sha256::default();
+            hasher.input_raw(attachment);
+            let attach_id = AttachId::from(hasher.finish());
+            self.attachments
+                .insert(attach_id, MediumBlob::from_slice_checked(attachment));
+            assignments.insert(
+                ATTACH,
+                TypedAssigns::Attachment(Confined::from_checked(vec![Assign::revealed(
+                    seal(outpoints[1]),
+                    RevealedAttach::new_random_salt(attach_id, MediaType::Any),
+                )])),
+            );
+        }
+        let mut extension = Extension::strict_dumb();
+        extension.contract_id = self.contract_id();
+        extension.extension_type = EXTENSION;
+        extension.nonce = self.nonce;
+        extension.redeemed =
+            Redeemed::from_inner(Confined::from_checked(bmap! { VALENCY => self.genesis.id() }));
+        extension.assignments = Assignments::from_inner(Confined::from_checked(assignments));
+
+        let opid = extension.id();
+        self.seals.insert(Opout::new(opid, OWNED, 0), outpoints[0]);
+        if attachment.is_some() {
+            self.seals.insert(Opout::new(opid, ATTACH, 0), outpoints[1]);
+        }
+        self.extensions.push(extension.clone());
+        extension
+    }
+
+    /// Constructs a transfer spending `inputs` and assigning the state to the
+    /// outputs of the witness transaction with the given numbers.
+    pub fn transfer(&mut self, inputs: &[Opout], vouts: &[u32]) -> Transition {
+        self.nonce += 1;
+        let mut transition = Transition::strict_dumb();
+        transition.contract_id = self.contract_id();
+        transition.transition_type = TRANSFER;
+        transition.nonce = self.nonce;
+        for opout in inputs {
+            transition.inputs.push(Input::with(*opout)).unwrap();
+        }
+        transition.assignments = Assignments::from_inner(Confined::from_checked(bmap! {
+            OWNED => TypedAssigns::Declarative(Confined::from_iter_checked(vouts.iter().map(
+                |vout| {
+                    let seal = GraphSeal::new_random_vout(Method::OpretFirst, Vout::from_u32(*vout));
+                    Assign::revealed(XChain::Bitcoin(seal), VoidState::default())
+                },
+            )))
+        }));
+        self.vouts.insert(transition.id(), vouts.to_vec());
+        transition
+    }
+
+    /// Bundles the transitions and anchors the bundle into a new witness
+    /// transaction closing all their seals, which is mined with `ord`.
+    pub fn witness(&mut self, transitions: Vec<Transition>, ord: WitnessOrd) -> Fascia {
+        let mut inputs = vec![];
+        let mut input_map = BTreeMap::new();
+        for transition in &transitions {
+            for input in &transition.inputs {
+                input_map.insert(Vout::from_u32(inputs.len() as u32), transition.id());
+                inputs.push(TxIn {
+                    prev_output: self.outpoint(input.prev_out),
+                    sig_script: SigScript::default(),
+                    sequence: SeqNo::ZERO,
+                    witness: Witness::default(),
+                });
+            }
+        }
+        let vout_count = transitions
+            .iter()
+            .flat_map(|transition| &self.vouts[&transition.id()])
+            .map(|vout| vout + 1)
+            .max()
+            .unwrap_or_default();
+        let bundle = TransitionBundle {
+            close_method: CloseMethod::OpretFirst,
+            input_map: InputMap::from(Confined::from_checked(input_map)),
+            known_transitions: Confined::from_iter_checked(
+                transitions
+                    .into_iter()
+                    .map(|transition| (transition.id(), transition)),
+            ),
+        };
+
+        let source = MultiSource {
+            min_depth: default!(),
+            messages: Confined::from_checked(bmap! {
+                mpc::ProtocolId::from(self.contract_id()) => mpc::Message::from(bundle.bundle_id())
+            }),
+            static_entropy: Some(1),
+        };
+        let tree = MerkleTree::try_commit(&source).unwrap();
+        let mut outputs = (0..vout_count)
+            .map(|_| TxOut::new(ScriptPubkey::new(), Sats::from_sats(1000u64)))
+            .collect::<Vec<_>>();
+        outputs.push(TxOut::new(ScriptPubkey::from_unsafe(vec![OpCode::Return as u8]), Sats::ZERO));
+        let mut tx = Tx {
+            version: TxVer::V2,
+            inputs: Confined::from_checked(inputs),
+            outputs: Confined::from_checked(outputs),
+            lock_time: LockTime::ZERO,
+        };
+        let dbc_proof = tx.embed_commit(&tree.commit_id()).unwrap();
+        let txid = tx.txid();
+        self.chain
+            .txes
+            .insert(XChain::Bitcoin(txid), (tx.clone(), ord));
+        for opid in bundle.known_transitions.keys() {
+            for (no, vout) in self.vouts[opid].iter().enumerate() {
+                self.seals.insert(
+                    Opout::new(*opid, OWNED, no as u16),
+                    Outpoint::new(txid, Vout::from_u32(*vout)),
+                );
+            }
+        }
+
+        Fascia {
+            witness: XChain::Bitcoin(PubWitness::Tx(tx)),
+            anchor: AnchorSet::Opret(Anchor::new(MerkleBlock::from(tree), dbc_proof)),
+            bundles: NonEmptyOrdMap::with((self.contract_id(), [bundle].into_iter().collect())),
+        }
+    }
+
+    /// Constructs an RBF replacement of the `fascia` witness transaction
+    /// anchoring the same bundles, which is mined with `ord`.
+    pub fn replace(&mut self, fascia: &Fascia, ord: WitnessOrd) -> Fascia {
+        let XChain::Bitcoin(PubWitness::Tx(mut tx)) = fascia.witness.clone() else {
+            unreachable!("fixture witnesses are bitcoin transactions")
+        };
+        for input in &mut tx.inputs {
+            input.sequence = SeqNo::from_consensus_u32(input.sequence.to_consensus_u32() + 1);
+        }
+        let txid = tx.txid();
+        self.chain
+            .txes
+            .insert(XChain::Bitcoin(txid), (tx.clone(), ord));
+        for bundle in fascia.bundles.values().flat_map(|bundles| bundles.iter()) {
+            for opid in bundle.known_transitions.keys() {
+                for (no, vout) in self.vouts[opid].iter().enumerate() {
+                    self.seals.insert(
+                        Opout::new(*opid, OWNED, no as u16),
+                        Outpoint::new(txid, Vout::from_u32(*vout)),
+                    );
+                }
+            }
+        }
+
+        Fascia {
+            witness: XChain::Bitcoin(PubWitness::Tx(tx)),
+            anchor: fascia.anchor.clone(),
+            bundles: fascia.bundles.clone(),
+        }
+    }
+}
+
+/// Contract issued to three outpoints, where the first two allocations are
+/// spent by sibling transfers sharing the same witness, and one of the outputs
+/// of the first transfer is spent again by a subsequent witness.
+pub(super) struct Transfers {
+    pub fixture: Fixture,
+    pub first: Transition,
+    pub sibling: Transition,
+    pub next: Transition,
+    /// Witness of the sibling transfers and witness of the subsequent one.
+    pub witnesses: [XWitnessId; 2],
+}
+
+impl Transfers {
+    pub fn consume<S: StashProvider, H: StateProvider, P: IndexProvider>(
+        stock: &mut Stock<S, H, P>,
+    ) -> Self {
+        let mut fixture = Fixture::issue(3);
+        stock
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+
+        let genesis_id = fixture.genesis.id();
+        let first = fixture.transfer(&[Opout::new(genesis_id, OWNED, 0)], &[0, 1]);
+        let sibling = fixture.transfer(&[Opout::new(genesis_id, OWNED, 1)], &[2]);
+        let fascia = fixture.witness(vec![first.clone(), sibling.clone()], mined(100));
+        let witness1 = fascia.witness_id();
+        stock.consume_fascia(fascia, &fixture.chain).unwrap();
+
+        let next = fixture.transfer(&fixture.outputs(first.id())[..1], &[0]);
+        let fascia = fixture.witness(vec![next.clone()], mined(101));
+        let witness2 = fascia.witness_id();
+        stock.consume_fascia(fascia, &fixture.chain).unwrap();
+
+        Transfers {
+            fixture,
+            first,
+            sibling,
+            next,
+            witnesses: [witness1, witness2],
+        }
+    }
+}
+
+/// In-memory stock with the [`Transfers`].
+pub(super) fn transferred_stock() -> (Stock, Transfers) {
+    let mut stock = Stock::in_memory();
+    let transfers = Transfers::consume(&mut stock);
+    (stock, transfers)
+}
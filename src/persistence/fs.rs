@@ -19,26 +19,153 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::{fs, io};
 
 use amplify::confinement::U32 as U32MAX;
+use fs2::FileExt;
 use nonasync::persistence::{PersistenceError, PersistenceProvider};
-use strict_encoding::{StrictDeserialize, StrictSerialize};
+use strict_encoding::{
+    DecodeError, DeserializeError, StreamReader, StreamWriter, StrictDecode, StrictDeserialize,
+    StrictEncode, StrictReader, StrictSerialize, StrictWriter,
+};
 
-use crate::persistence::{MemIndex, MemStash, MemState};
+#[cfg(feature = "encryption")]
+pub use self::crypt::{CryptError, FsEncryptedStore, FS_ENCRYPTED_MAGIC, FS_ENCRYPTED_VERSION};
+pub(super) use self::log::{DirtyRows, LogData, LogTable};
+pub use self::log::{FsLogStore, FS_LOG_MAGIC, FS_LOG_VERSION};
+use crate::persistence::memory::legacy::{
+    MemIndexV0, MemStashV0, MemStashV1, MemStashV2, MemStateV0,
+};
+use crate::persistence::{MemIndex, MemStash, MemState, StockPersistence};
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+mod log;
+#[cfg(feature = "encryption")]
+mod crypt;
+
+/// Magic bytes starting each of the files written by [`FsBinStore`].
+pub const FS_STORE_MAGIC: [u8; 8] = *b"RGBSTOCK";
+/// Version of the file format used by [`FsBinStore`].
+///
+/// Files lacking [`FS_STORE_MAGIC`] are treated as version 0, which was
+/// limited to 255 contracts, schemata and interfaces; files of version 1 lack
+/// the pruning checkpoints in the stash, and files of version 2 lack the
+/// witness replacements. All of them are converted to the current format on
+/// load.
+pub const FS_STORE_VERSION: u16 = 3;
+
+/// Data stored in a file using the versioned format.
+pub(super) trait FsVersioned: StrictDecode {
+    /// Decodes data written with one of the previous format versions, starting
+    /// from version 1. Types which layout was not changed since then just
+    /// decode the current layout.
+    fn decode_outdated(_version: u16, reader: impl Read + Seek) -> Result<Self, DeserializeError> {
+        decode_all(reader)
+    }
+}
+
+/// Data stored in a file by [`FsBinStore`].
+trait FsData: FsVersioned + StrictSerialize + StrictDeserialize {
+    /// Layout of the data used in files of version 0.
+    type Legacy: StrictDeserialize + Into<Self>;
+}
+
+impl FsVersioned for MemStash {
+    fn decode_outdated(version: u16, reader: impl Read + Seek) -> Result<Self, DeserializeError> {
+        match version {
+            1 => decode_all::<MemStashV1>(reader).map(MemStash::from),
+            _ => decode_all::<MemStashV2>(reader).map(MemStash::from),
+        }
+    }
+}
+impl FsVersioned for MemState {}
+impl FsVersioned for MemIndex {}
+
+impl FsData for MemStash {
+    type Legacy = MemStashV0;
+}
+impl FsData for MemState {
+    type Legacy = MemStateV0;
+}
+impl FsData for MemIndex {
+    type Legacy = MemIndexV0;
+}
+
+/// Name of the file locked by the process writing to the stock files.
+const WRITER_LOCK: &str = "writer.lock";
+/// Name of the file locked during each load and store operation.
+const DATA_LOCK: &str = "data.lock";
+
+/// Errors acquiring locks for the stock files.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum FsLockError {
+    /// directory {0} is already locked for writing by another process.
+    Locked(String),
+
+    /// stock files in {0} are opened in read-only mode and can't be written.
+    ReadOnly(String),
+
+    /// stock files in {0} were left by an interrupted store operation and
+    /// can't be read until they are recovered by opening them for writing.
+    Interrupted(String),
+}
+
+/// File-based storage for in-memory stash, state and index.
+///
+/// Each component is written to a temporary file which replaces the original
+/// file only once it is completely written and synced to disk. When all three
+/// components are stored at once via [`StockPersistence::store_all`], the
+/// store additionally uses a commit journal: new data are first written to
+/// staging files for all components, and only after that the journal is
+/// created, marking the point after which the new data are replacing the
+/// old ones. If the process gets interrupted, the next load either completes
+/// the replacement (if the journal is present) or discards the staged data.
+///
+/// The store uses advisory file locks to coordinate with other processes
+/// using the same directory. On the first load or store operation, the store
+/// locks the directory for writing, and keeps the lock for as long as the
+/// store (or any of its clones) exists; if the directory is already locked by
+/// another process, the operation fails with [`FsLockError::Locked`]. Stores
+/// opened in read-only mode (see [`FsBinStore::read_only`]) do not require
+/// this lock, and can be used while the files are written by another
+/// process. Additionally, each store operation is performed under an exclusive
+/// lock and each load operation under a shared one, such that readers never
+/// observe partially written data. Stores opened in read-only mode never
+/// modify the directory: if the files require recovery after an interrupted
+/// store operation, loading them fails with [`FsLockError::Interrupted`].
+#[derive(Clone, Debug)]
 pub struct FsBinStore {
     pub stash: PathBuf,
     pub state: PathBuf,
     pub index: PathBuf,
+    read_only: bool,
+    writer: Arc<Mutex<Option<File>>>,
+}
+
+impl PartialEq for FsBinStore {
+    fn eq(&self, other: &Self) -> bool {
+        self.paths() == other.paths() && self.read_only == other.read_only
+    }
 }
 
+impl Eq for FsBinStore {}
+
 impl FsBinStore {
     pub fn new(path: PathBuf) -> io::Result<Self> {
         fs::create_dir_all(&path)?;
+        Ok(Self::with_dir(path, false))
+    }
 
+    /// Opens the files in read-only mode: the data can be loaded, but all
+    /// attempts to store them fail with [`FsLockError::ReadOnly`].
+    pub fn read_only(path: PathBuf) -> Self { Self::with_dir(path, true) }
+
+    fn with_dir(path: PathBuf, read_only: bool) -> Self {
         let mut stash = path.clone();
         stash.push("stash.dat");
         let mut state = path.clone();
@@ -46,48 +173,458 @@ impl FsBinStore {
         let mut index = path.clone();
         index.push("index.dat");
 
-        Ok(Self {
+        Self {
             stash,
             state,
             index,
-        })
+            read_only,
+            writer: default!(),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool { self.read_only }
+
+    fn paths(&self) -> [&Path; 3] { [&self.stash, &self.state, &self.index] }
+
+    fn dir(&self) -> &Path { self.stash.parent().unwrap_or(Path::new(".")) }
+
+    /// Acquires locks required to load (if `write` is `false`) or store the
+    /// data. The data lock is exclusive for writing and shared for reading, and
+    /// is held until the returned file is dropped.
+    pub(super) fn lock(&self, write: bool) -> Result<Option<File>, PersistenceError> {
+        let dir = self.dir();
+        if !self.read_only {
+            self.lock_writer()?;
+        } else if write {
+            return Err(PersistenceError::with(FsLockError::ReadOnly(dir.display().to_string())));
+        }
+
+        let path = dir.join(DATA_LOCK);
+        let file = match self.read_only {
+            false => OpenOptions::new().create(true).append(true).open(&path),
+            true => match File::open(&path) {
+                // Nobody has written to the directory yet
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                res => res,
+            },
+        }
+        .map_err(PersistenceError::with)?;
+        match write {
+            true => FileExt::lock_exclusive(&file),
+            false => FileExt::lock_shared(&file),
+        }
+        .map_err(PersistenceError::with)?;
+        Ok(Some(file))
+    }
+
+    fn lock_writer(&self) -> Result<(), PersistenceError> {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        if writer.is_some() {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir().join(WRITER_LOCK))
+            .map_err(PersistenceError::with)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => {
+                *writer = Some(file);
+                Ok(())
+            }
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                Err(PersistenceError::with(FsLockError::Locked(self.dir().display().to_string())))
+            }
+            Err(err) => Err(PersistenceError::with(err)),
+        }
+    }
+
+    fn journal(&self) -> PathBuf { self.stash.with_file_name("commit.journal") }
+
+    /// Brings the files to a consistent state after an interrupted store
+    /// operation.
+    pub fn recover(&self) -> io::Result<()> {
+        let journal = self.journal();
+        let committed = journal.exists();
+        for path in self.paths() {
+            let log = log::log_path(path);
+            for path in [path, &log] {
+                let staged = with_suffix(path, "new");
+                if committed && staged.exists() {
+                    fs::rename(&staged, path)?;
+                } else if staged.exists() {
+                    fs::remove_file(&staged)?;
+                }
+                let tmp = with_suffix(path, "tmp");
+                if tmp.exists() {
+                    fs::remove_file(&tmp)?;
+                }
+            }
+            let appended = with_suffix(&log, "add");
+            if committed {
+                log::apply_staged(path)?;
+            } else if appended.exists() {
+                fs::remove_file(&appended)?;
+            }
+        }
+        if committed {
+            for path in self.paths() {
+                sync_dir(path)?;
+            }
+            fs::remove_file(&journal)?;
+        }
+        Ok(())
+    }
+
+    /// Prepares the files for loading: recovers them after an interrupted
+    /// store operation, or, in read-only mode, fails if they require the
+    /// recovery. Staged files which were not committed do not affect the
+    /// data and are ignored in read-only mode.
+    pub(super) fn recover_for_load(&self) -> Result<(), PersistenceError> {
+        if !self.read_only {
+            return self.recover().map_err(PersistenceError::with);
+        }
+        if self.journal().exists() {
+            return Err(PersistenceError::with(FsLockError::Interrupted(
+                self.dir().display().to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Re-writes files of the previous format versions using the current one.
+    ///
+    /// Returns whether any of the files were migrated. Files of an older
+    /// version are also readable without the migration, but they get
+    /// converted only on the next store operation.
+    pub fn migrate(&self) -> Result<bool, PersistenceError> {
+        let _lock = self.lock(true)?;
+        self.recover().map_err(PersistenceError::with)?;
+        let mut outdated = false;
+        for path in self.paths() {
+            outdated |= file_version(path).map_err(PersistenceError::with)? < FS_STORE_VERSION;
+        }
+        if !outdated {
+            return Ok(false);
+        }
+        let stash = self.load_file::<MemStash>(&self.stash)?;
+        let state = self.load_file::<MemState>(&self.state)?;
+        let index = self.load_file::<MemIndex>(&self.index)?;
+        self.store_all_files(&stash, &state, &index)
+            .map_err(PersistenceError::with)?;
+        Ok(true)
+    }
+
+    fn load_file<T: FsData + LogData>(&self, path: &Path) -> Result<T, PersistenceError> {
+        self.recover_for_load()?;
+        let mut data = read_file::<T>(path).map_err(PersistenceError::with)?;
+        if log::log_path(path).exists() {
+            let base = log::file_digest(path).map_err(PersistenceError::with)?;
+            log::replay(&mut data, path, &base).map_err(PersistenceError::with)?;
+        }
+        Ok(data)
+    }
+
+    fn store_file(&self, object: &impl StrictSerialize, path: &Path) -> io::Result<()> {
+        let tmp = with_suffix(path, "tmp");
+        write_synced(object, &tmp)?;
+        log::discard_log(path, &tmp)?;
+        fs::rename(&tmp, path)?;
+        sync_dir(path)?;
+        log::remove_log(path)
+    }
+
+    fn store_all_files(
+        &self,
+        stash: &MemStash,
+        state: &MemState,
+        index: &MemIndex,
+    ) -> io::Result<()> {
+        self.recover()?;
+
+        // Phase 1: prepare new data without touching the existing files
+        write_synced(stash, &with_suffix(&self.stash, "new"))?;
+        write_synced(state, &with_suffix(&self.state, "new"))?;
+        write_synced(index, &with_suffix(&self.index, "new"))?;
+        for path in self.paths() {
+            log::discard_log(path, &with_suffix(path, "new"))?;
+        }
+
+        // Phase 2: commit
+        self.commit()?;
+
+        // Logs of the previous data files are not used anymore
+        for path in self.paths() {
+            log::remove_log(path)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the files with the staged ones. Once the journal is on disk,
+    /// the staged data will replace the old ones even if we get interrupted.
+    fn commit(&self) -> io::Result<()> {
+        for path in self.paths() {
+            sync_dir(path)?;
+        }
+
+        let journal = self.journal();
+        let mut file = File::create(&journal)?;
+        for path in self.paths() {
+            writeln!(file, "{}", path.display())?;
+        }
+        file.sync_all()?;
+        sync_dir(&journal)?;
+
+        self.recover()
+    }
+}
+
+pub(super) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Reads format version from the file header, returning zero for files
+/// without the header.
+fn read_version(file: &mut impl Read) -> io::Result<u16> {
+    let mut header = Vec::with_capacity(FS_STORE_MAGIC.len() + 2);
+    file.take(FS_STORE_MAGIC.len() as u64 + 2)
+        .read_to_end(&mut header)?;
+    match header.strip_prefix(&FS_STORE_MAGIC) {
+        Some(&[lo, hi]) => Ok(u16::from_le_bytes([lo, hi])),
+        _ => Ok(0),
     }
 }
+
+fn file_version(path: &Path) -> io::Result<u16> { read_version(&mut File::open(path)?) }
+
+fn read_file<T: FsData>(path: &Path) -> Result<T, DeserializeError> {
+    read_data(BufReader::new(File::open(path)?))
+}
+
+/// Reads data in any of the supported format versions.
+fn read_data<T: FsData>(mut reader: impl Read + Seek) -> Result<T, DeserializeError> {
+    if read_version(&mut reader)? == 0 {
+        reader.rewind()?;
+        return decode_all::<T::Legacy>(reader).map(T::Legacy::into);
+    }
+    reader.rewind()?;
+    read_current(reader)
+}
+
+/// Reads data from a file written in the versioned format.
+pub(super) fn read_versioned<T: FsVersioned>(path: &Path) -> Result<T, DeserializeError> {
+    read_current(BufReader::new(File::open(path)?))
+}
+
+fn read_current<T: FsVersioned>(mut reader: impl Read + Seek) -> Result<T, DeserializeError> {
+    match read_version(&mut reader)? {
+        FS_STORE_VERSION => decode_all(reader),
+        version @ 1.. if version < FS_STORE_VERSION => T::decode_outdated(version, reader),
+        version => Err(DecodeError::DataIntegrityError(format!(
+            "unsupported version {version} of the stock file format"
+        ))
+        .into()),
+    }
+}
+
+fn decode_all<T: StrictDecode>(reader: impl Read + Seek) -> Result<T, DeserializeError> {
+    let mut reader = StrictReader::with(StreamReader::new::<U32MAX>(reader));
+    let me = T::strict_decode(&mut reader)?;
+    let mut reader = reader.unbox().unconfine();
+    if reader.stream_position()? != reader.seek(SeekFrom::End(0))? {
+        return Err(DeserializeError::DataNotEntirelyConsumed);
+    }
+    Ok(me)
+}
+
+/// Writes data in the current format version, including the header.
+fn write_data(object: &impl StrictEncode, mut writer: impl Write) -> io::Result<()> {
+    writer.write_all(&FS_STORE_MAGIC)?;
+    writer.write_all(&FS_STORE_VERSION.to_le_bytes())?;
+    let writer = StrictWriter::with(StreamWriter::new::<U32MAX>(BufWriter::new(writer)));
+    object.strict_encode(writer)?.unbox().unconfine().flush()
+}
+
+pub(super) fn write_synced(object: &impl StrictEncode, path: &Path) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    write_data(object, &mut file)?;
+    file.sync_all()
+}
+
+#[cfg(unix)]
+pub(super) fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn sync_dir(_: &Path) -> io::Result<()> { Ok(()) }
+
 impl PersistenceProvider<MemStash> for FsBinStore {
     fn load(&self) -> Result<MemStash, PersistenceError> {
-        MemStash::strict_deserialize_from_file::<U32MAX>(&self.stash)
-            .map_err(PersistenceError::with)
+        let _lock = self.lock(false)?;
+        self.load_file(&self.stash)
     }
 
     fn store(&self, object: &MemStash) -> Result<(), PersistenceError> {
-        object
-            .strict_serialize_to_file::<U32MAX>(&self.stash)
+        let _lock = self.lock(true)?;
+        self.store_file(object, &self.stash)
             .map_err(PersistenceError::with)
     }
 }
 
 impl PersistenceProvider<MemState> for FsBinStore {
     fn load(&self) -> Result<MemState, PersistenceError> {
-        MemState::strict_deserialize_from_file::<U32MAX>(&self.state)
-            .map_err(PersistenceError::with)
+        let _lock = self.lock(false)?;
+        self.load_file(&self.state)
     }
 
     fn store(&self, object: &MemState) -> Result<(), PersistenceError> {
-        object
-            .strict_serialize_to_file::<U32MAX>(&self.state)
+        let _lock = self.lock(true)?;
+        self.store_file(object, &self.state)
             .map_err(PersistenceError::with)
     }
 }
 
 impl PersistenceProvider<MemIndex> for FsBinStore {
     fn load(&self) -> Result<MemIndex, PersistenceError> {
-        MemIndex::strict_deserialize_from_file::<U32MAX>(&self.index)
-            .map_err(PersistenceError::with)
+        let _lock = self.lock(false)?;
+        self.load_file(&self.index)
     }
 
     fn store(&self, object: &MemIndex) -> Result<(), PersistenceError> {
-        object
-            .strict_serialize_to_file::<U32MAX>(&self.index)
+        let _lock = self.lock(true)?;
+        self.store_file(object, &self.index)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl StockPersistence<MemStash, MemState, MemIndex> for FsBinStore {
+    fn store_all(
+        &self,
+        stash: &MemStash,
+        state: &MemState,
+        index: &MemIndex,
+    ) -> Result<(), PersistenceError> {
+        let _lock = self.lock(true)?;
+        self.store_all_files(stash, state, index)
             .map_err(PersistenceError::with)
     }
+
+    fn into_read_only(self) -> Self { Self::with_dir(self.dir().to_owned(), true) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::Stock;
+
+    fn temp_store(name: &str) -> FsBinStore {
+        let dir = std::env::temp_dir().join(format!("rgb-{name}-{}", rand::random::<u64>()));
+        FsBinStore::new(dir).unwrap()
+    }
+
+    #[test]
+    fn interrupted_store_is_discarded() {
+        let store = temp_store("discard");
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let old = fs::read(&store.stash).unwrap();
+
+        // Crash before the journal was written
+        fs::write(with_suffix(&store.stash, "new"), b"garbage").unwrap();
+        Stock::<MemStash, MemState, MemIndex>::load_atomic(store.clone(), false).unwrap();
+        assert_eq!(fs::read(&store.stash).unwrap(), old);
+        assert!(!with_suffix(&store.stash, "new").exists());
+
+        fs::remove_dir_all(store.stash.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn journaled_store_is_completed() {
+        let store = temp_store("complete");
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let stash = fs::read(&store.stash).unwrap();
+
+        // Crash after the journal was written, but before the state file got replaced
+        fs::write(with_suffix(&store.state, "new"), fs::read(&store.state).unwrap()).unwrap();
+        fs::write(&store.state, b"garbage").unwrap();
+        File::create(store.journal()).unwrap();
+        Stock::<MemStash, MemState, MemIndex>::load_atomic(store.clone(), false).unwrap();
+        assert_eq!(fs::read(&store.stash).unwrap(), stash);
+        assert!(!store.journal().exists());
+
+        fs::remove_dir_all(store.stash.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn concurrent_writers_are_refused() {
+        let store = temp_store("lock");
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+
+        let other = FsBinStore::new(store.dir().to_owned()).unwrap();
+        let err =
+            Stock::<MemStash, MemState, MemIndex>::load_atomic(other.clone(), false).unwrap_err();
+        assert!(err.to_string().contains("already locked for writing"));
+
+        let mut reader =
+            Stock::<MemStash, MemState, MemIndex>::load_read_only(other.clone()).unwrap();
+        let err = reader.store().unwrap_err();
+        assert!(err.to_string().contains("read-only mode"));
+
+        drop(stock);
+        drop(store);
+        Stock::<MemStash, MemState, MemIndex>::load_atomic(other.clone(), false).unwrap();
+
+        fs::remove_dir_all(other.dir()).ok();
+    }
+
+    #[test]
+    fn read_only_load_keeps_files() {
+        let store = temp_store("read-only");
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let reader = FsBinStore::read_only(store.dir().to_owned());
+
+        // Uncommitted staged data are ignored and kept for the writer to discard
+        let staged = with_suffix(&store.stash, "new");
+        fs::write(&staged, b"garbage").unwrap();
+        Stock::<MemStash, MemState, MemIndex>::load_read_only(reader.clone()).unwrap();
+        assert!(staged.exists());
+
+        // Committed data must be recovered by the writer first
+        File::create(store.journal()).unwrap();
+        let err =
+            Stock::<MemStash, MemState, MemIndex>::load_read_only(reader.clone()).unwrap_err();
+        assert!(err.to_string().contains("interrupted store operation"));
+        assert!(staged.exists());
+        assert!(store.journal().exists());
+
+        fs::remove_dir_all(store.dir()).ok();
+    }
+
+    #[test]
+    fn legacy_files_are_migrated() {
+        let store = temp_store("migrate");
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        assert!(!store.migrate().unwrap());
+
+        // Empty state in the version 0 layout: no witnesses and a tiny map of contracts
+        fs::write(&store.state, [0u8; 5]).unwrap();
+        assert_eq!(file_version(&store.state).unwrap(), 0);
+        Stock::<MemStash, MemState, MemIndex>::load_atomic(store.clone(), false).unwrap();
+        assert!(store.migrate().unwrap());
+        assert_eq!(file_version(&store.state).unwrap(), FS_STORE_VERSION);
+        Stock::<MemStash, MemState, MemIndex>::load_atomic(store.clone(), false).unwrap();
+
+        fs::remove_dir_all(store.stash.parent().unwrap()).ok();
+    }
 }
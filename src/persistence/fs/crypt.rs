@@ -0,0 +1,458 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encrypted storage for stash, state and index files.
+
+use std::fmt::{self, Debug, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use nonasync::persistence::{PersistenceError, PersistenceProvider};
+use strict_encoding::StrictEncode;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::{
+    log, read_data, sync_dir, with_suffix, write_data, FsBinStore, FsData, FS_STORE_MAGIC,
+};
+use crate::persistence::{MemIndex, MemStash, MemState, StockPersistence};
+
+/// Magic bytes starting each of the files written by [`FsEncryptedStore`].
+pub const FS_ENCRYPTED_MAGIC: [u8; 8] = *b"RGBSTENC";
+/// Version of the file format used by [`FsEncryptedStore`].
+pub const FS_ENCRYPTED_VERSION: u16 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+/// Length of the key derivation parameters: salt, Argon2 version, memory and
+/// time costs and the degree of parallelism.
+const KDF_PARAMS_LEN: usize = SALT_LEN + 4 * 4;
+const HEADER_LEN: usize = FS_ENCRYPTED_MAGIC.len() + 2 + 1 + KDF_PARAMS_LEN + NONCE_LEN;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CryptError {
+    /// file {0} is not encrypted.
+    NotEncrypted(String),
+
+    /// file {0} is not an encrypted stock file.
+    UnknownFormat(String),
+
+    /// file {0} uses unsupported version {1} of the encrypted file format.
+    UnsupportedVersion(String, u16),
+
+    /// file {0} is encrypted with a key derived differently from the one
+    /// provided.
+    KeyMismatch(String),
+
+    /// unable to decrypt file {0}: the key is wrong or the file is corrupted.
+    Decryption(String),
+}
+
+/// Method used to obtain the encryption key.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Kdf {
+    /// Key provided by the caller.
+    Key,
+    /// Key derived from a password with Argon2id.
+    Argon2id(Argon2idParams),
+}
+
+impl Kdf {
+    fn write(self, header: &mut Vec<u8>) {
+        match self {
+            Kdf::Key => {
+                header.push(0);
+                header.extend([0u8; KDF_PARAMS_LEN]);
+            }
+            Kdf::Argon2id(params) => {
+                header.push(1);
+                header.extend(params.salt);
+                header.extend(params.version.to_le_bytes());
+                header.extend(params.m_cost.to_le_bytes());
+                header.extend(params.t_cost.to_le_bytes());
+                header.extend(params.p_cost.to_le_bytes());
+            }
+        }
+    }
+
+    fn from_header(header: &[u8]) -> Option<Self> {
+        let params = &header[11..11 + KDF_PARAMS_LEN];
+        let u32_at = |pos: usize| params[pos..pos + 4].try_into().ok().map(u32::from_le_bytes);
+        match header[10] {
+            0 => Some(Kdf::Key),
+            1 => Some(Kdf::Argon2id(Argon2idParams {
+                salt: params[..SALT_LEN].try_into().ok()?,
+                version: u32_at(SALT_LEN)?,
+                m_cost: u32_at(SALT_LEN + 4)?,
+                t_cost: u32_at(SALT_LEN + 8)?,
+                p_cost: u32_at(SALT_LEN + 12)?,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of the Argon2id key derivation.
+///
+/// The parameters are stored in the file header, such that the key can be
+/// derived again even if the defaults of the `argon2` library change.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Argon2idParams {
+    salt: [u8; SALT_LEN],
+    version: u32,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl Argon2idParams {
+    fn with(salt: [u8; SALT_LEN]) -> Self {
+        Self {
+            salt,
+            version: Version::V0x13.into(),
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive_key(&self, password: &str) -> io::Result<Zeroizing<[u8; KEY_LEN]>> {
+        let err = |err: argon2::Error| io::Error::other(err.to_string());
+        let version = Version::try_from(self.version).map_err(err)?;
+        let params =
+            Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN)).map_err(err)?;
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, version, params)
+            .hash_password_into(password.as_bytes(), &self.salt, key.as_mut_slice())
+            .map_err(err)?;
+        Ok(key)
+    }
+}
+
+/// File-based storage for in-memory stash, state and index, which encrypts
+/// the data with XChaCha20-Poly1305.
+///
+/// The store wraps [`FsBinStore`]: it uses the same files, format of the
+/// encrypted data and the same protocol for atomic updates, but each file is
+/// encrypted as a whole with a new random nonce. The header of the file
+/// (magic bytes, version, key derivation method with its parameters and the
+/// nonce) is authenticated as associated data.
+///
+/// An existing stock stored in plain files can be encrypted by loading it
+/// with [`FsBinStore`] and then making it persistent with this store.
+#[derive(Clone)]
+pub struct FsEncryptedStore {
+    files: FsBinStore,
+    kdf: Kdf,
+    cipher: XChaCha20Poly1305,
+}
+
+impl Debug for FsEncryptedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FsEncryptedStore")
+            .field("files", &self.files)
+            .field("kdf", &self.kdf)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FsEncryptedStore {
+    /// Constructs the store using a 256-bit key provided by the caller.
+    ///
+    /// The key passed to the function is zeroized once the cipher is
+    /// constructed.
+    pub fn with_key(path: PathBuf, mut key: [u8; KEY_LEN]) -> io::Result<Self> {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+        key.zeroize();
+        Ok(Self {
+            files: FsBinStore::new(path)?,
+            kdf: Kdf::Key,
+            cipher,
+        })
+    }
+
+    /// Constructs the store using a key derived from the password with
+    /// Argon2id.
+    ///
+    /// The salt and the key derivation parameters are read from the existing
+    /// files; if there are no files yet, a new random salt and the default
+    /// parameters of Argon2id version 0x13 are used.
+    pub fn with_password(path: PathBuf, password: &str) -> io::Result<Self> {
+        let files = FsBinStore::new(path)?;
+        let mut params = None;
+        for path in files.paths() {
+            if let Some(Kdf::Argon2id(p)) = read_header(path)?.as_deref().and_then(Kdf::from_header)
+            {
+                params = Some(p);
+                break;
+            }
+        }
+        let params = params.unwrap_or_else(|| Argon2idParams::with(rand::random()));
+        let key = params.derive_key(password)?;
+        Ok(Self {
+            files,
+            kdf: Kdf::Argon2id(params),
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key.as_slice())),
+        })
+    }
+
+    /// Returns paths of the encrypted files.
+    pub fn files(&self) -> &FsBinStore { &self.files }
+
+    /// Brings the files to a consistent state after an interrupted store
+    /// operation.
+    pub fn recover(&self) -> io::Result<()> { self.files.recover() }
+
+    fn header(&self, nonce: &[u8; NONCE_LEN]) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend(FS_ENCRYPTED_MAGIC);
+        header.extend(FS_ENCRYPTED_VERSION.to_le_bytes());
+        self.kdf.write(&mut header);
+        header.extend(nonce);
+        header
+    }
+
+    fn write_encrypted(&self, object: &impl StrictEncode, path: &Path) -> io::Result<()> {
+        let mut plaintext = vec![];
+        write_data(object, &mut plaintext)?;
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let header = self.header(&nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload {
+                msg: &plaintext,
+                aad: &header,
+            })
+            .map_err(|_| io::Error::other("data are too large to be encrypted"))?;
+
+        let mut file = File::create(path)?;
+        file.write_all(&header)?;
+        file.write_all(&ciphertext)?;
+        file.sync_all()
+    }
+
+    fn read_encrypted<T: FsData>(&self, path: &Path) -> Result<T, PersistenceError> {
+        let data = fs::read(path).map_err(PersistenceError::with)?;
+        let err =
+            |err: fn(String) -> CryptError| PersistenceError::with(err(path.display().to_string()));
+        if data.starts_with(&FS_STORE_MAGIC) {
+            return Err(err(CryptError::NotEncrypted));
+        }
+        if data.len() < HEADER_LEN || !data.starts_with(&FS_ENCRYPTED_MAGIC) {
+            return Err(err(CryptError::UnknownFormat));
+        }
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version != FS_ENCRYPTED_VERSION {
+            return Err(PersistenceError::with(CryptError::UnsupportedVersion(
+                path.display().to_string(),
+                version,
+            )));
+        }
+        if Kdf::from_header(header) != Some(self.kdf) {
+            return Err(err(CryptError::KeyMismatch));
+        }
+        let nonce = XNonce::from_slice(&header[HEADER_LEN - NONCE_LEN..]);
+        let plaintext = self
+            .cipher
+            .decrypt(nonce, Payload {
+                msg: ciphertext,
+                aad: header,
+            })
+            .map_err(|_| err(CryptError::Decryption))?;
+        read_data(Cursor::new(plaintext)).map_err(PersistenceError::with)
+    }
+
+    fn load_file<T: FsData>(&self, path: &Path) -> Result<T, PersistenceError> {
+        let _lock = self.files.lock(false)?;
+        self.files.recover_for_load()?;
+        self.read_encrypted(path)
+    }
+
+    fn store_file(&self, object: &impl StrictEncode, path: &Path) -> io::Result<()> {
+        let tmp = with_suffix(path, "tmp");
+        self.write_encrypted(object, &tmp)?;
+        fs::rename(&tmp, path)?;
+        sync_dir(path)?;
+        // Logs are not encrypted and are not used by this store
+        log::remove_log(path)
+    }
+
+    fn store_all_files(
+        &self,
+        stash: &MemStash,
+        state: &MemState,
+        index: &MemIndex,
+    ) -> io::Result<()> {
+        self.recover()?;
+
+        // Phase 1: prepare new data without touching the existing files
+        self.write_encrypted(stash, &with_suffix(&self.files.stash, "new"))?;
+        self.write_encrypted(state, &with_suffix(&self.files.state, "new"))?;
+        self.write_encrypted(index, &with_suffix(&self.files.index, "new"))?;
+
+        // Phase 2: commit
+        self.files.commit()?;
+
+        for path in self.files.paths() {
+            log::remove_log(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the header of an encrypted file, if the file exists.
+fn read_header(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok((data.len() >= HEADER_LEN && data.starts_with(&FS_ENCRYPTED_MAGIC))
+        .then(|| data[..HEADER_LEN].to_vec()))
+}
+
+impl PersistenceProvider<MemStash> for FsEncryptedStore {
+    fn load(&self) -> Result<MemStash, PersistenceError> { self.load_file(&self.files.stash) }
+
+    fn store(&self, object: &MemStash) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_file(object, &self.files.stash)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl PersistenceProvider<MemState> for FsEncryptedStore {
+    fn load(&self) -> Result<MemState, PersistenceError> { self.load_file(&self.files.state) }
+
+    fn store(&self, object: &MemState) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_file(object, &self.files.state)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl PersistenceProvider<MemIndex> for FsEncryptedStore {
+    fn load(&self) -> Result<MemIndex, PersistenceError> { self.load_file(&self.files.index) }
+
+    fn store(&self, object: &MemIndex) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_file(object, &self.files.index)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl StockPersistence<MemStash, MemState, MemIndex> for FsEncryptedStore {
+    fn store_all(
+        &self,
+        stash: &MemStash,
+        state: &MemState,
+        index: &MemIndex,
+    ) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_all_files(stash, state, index)
+            .map_err(PersistenceError::with)
+    }
+
+    fn into_read_only(self) -> Self {
+        Self {
+            files: self.files.into_read_only(),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::Stock;
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    #[test]
+    fn wrong_key_is_detected() {
+        let path = std::env::temp_dir().join(format!("rgb-crypt-{}", rand::random::<u64>()));
+        let store = FsEncryptedStore::with_key(path.clone(), [1u8; 32]).unwrap();
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let data = fs::read(&store.files().stash).unwrap();
+        assert!(data.starts_with(&FS_ENCRYPTED_MAGIC));
+
+        MemStock::load_atomic(store, false).unwrap();
+        drop(stock);
+        let store = FsEncryptedStore::with_key(path.clone(), [2u8; 32]).unwrap();
+        let err = MemStock::load_atomic(store, false).unwrap_err();
+        assert!(err.to_string().contains("the key is wrong"));
+        let store = FsEncryptedStore::with_password(path.clone(), "password").unwrap();
+        let err = MemStock::load_atomic(store, false).unwrap_err();
+        assert!(err.to_string().contains("derived differently"));
+        MemStock::load_atomic(FsBinStore::new(path.clone()).unwrap(), false).unwrap_err();
+
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn password_roundtrip() {
+        let path = std::env::temp_dir().join(format!("rgb-crypt-{}", rand::random::<u64>()));
+        let store = FsEncryptedStore::with_password(path.clone(), "password").unwrap();
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store, false).unwrap();
+        drop(stock);
+
+        let store = FsEncryptedStore::with_password(path.clone(), "password").unwrap();
+        MemStock::load_atomic(store, false).unwrap();
+        let store = FsEncryptedStore::with_password(path.clone(), "passw0rd").unwrap();
+        MemStock::load_atomic(store, false).unwrap_err();
+
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn password_kdf_params_are_stored() {
+        let path = std::env::temp_dir().join(format!("rgb-crypt-{}", rand::random::<u64>()));
+        let params = Argon2idParams {
+            m_cost: 8 * 1024,
+            t_cost: 1,
+            ..Argon2idParams::with(rand::random())
+        };
+        let key = params.derive_key("password").unwrap();
+        let store = FsEncryptedStore {
+            files: FsBinStore::new(path.clone()).unwrap(),
+            kdf: Kdf::Argon2id(params),
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key.as_slice())),
+        };
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store, false).unwrap();
+        drop(stock);
+
+        // Parameters differing from the defaults are read back from the header
+        let store = FsEncryptedStore::with_password(path.clone(), "password").unwrap();
+        assert_eq!(store.kdf, Kdf::Argon2id(params));
+        MemStock::load_atomic(store, false).unwrap();
+
+        fs::remove_dir_all(path).ok();
+    }
+}
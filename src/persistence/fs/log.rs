@@ -0,0 +1,752 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Append-only log of changes to the data stored in files.
+//!
+//! Each of the stash, state and index files written by [`FsBinStore`] may be
+//! accompanied by a log file (`stash.log`, `state.log`, `index.log`), which
+//! contains a sequence of batches of changes made to the data since the file
+//! was written. The data are split into tables (maps and sets of the
+//! in-memory providers), and a change is either a new value of a table entry
+//! or its removal.
+//!
+//! Log file starts with a header containing hash of the data file it applies
+//! to; logs not matching their data file are ignored. Each batch is prefixed
+//! with its length and followed by its SHA256 hash, such that a batch which
+//! was not completely written is detected and discarded on load.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use amplify::confinement::{Confined, LargeBlob, LargeVec, U32 as U32MAX};
+use commit_verify::{DigestExt, Sha256};
+use nonasync::persistence::{PersistenceError, PersistenceProvider};
+use strict_encoding::{
+    DecodeError, DeserializeError, StrictDecode, StrictEncode, StrictReader, StrictSerialize,
+    StrictWriter,
+};
+use strict_types::TypeSystem;
+
+use super::{read_file, sync_dir, with_suffix, write_synced, FsBinStore, FsData};
+use crate::persistence::{MemIndex, MemStash, MemState, StockPersistence};
+use crate::LIB_NAME_RGB_STORAGE;
+
+/// Magic bytes starting each of the log files.
+pub const FS_LOG_MAGIC: [u8; 8] = *b"RGBSTLOG";
+/// Version of the log file format.
+pub const FS_LOG_VERSION: u16 = 1;
+
+const HEADER_LEN: u64 = FS_LOG_MAGIC.len() as u64 + 2 + 32;
+/// Logs are compacted only once they grow larger than the data file and
+/// this size.
+const MIN_COMPACT_LEN: u64 = 1 << 20;
+
+/// Table numbers and serialized keys of the entries.
+type Rows = BTreeSet<(u8, Vec<u8>)>;
+
+/// Collection of entries within the data which may be updated independently
+/// from each other.
+pub(crate) trait LogTable {
+    /// Returns serialized value of the entry with the given serialized key.
+    fn row_value(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn insert_row(&mut self, key: &[u8], value: &[u8]) -> Result<(), DecodeError>;
+
+    fn remove_row(&mut self, key: &[u8]) -> Result<(), DecodeError>;
+}
+
+/// Data which can be stored as a snapshot followed by a log of changes.
+pub(crate) trait LogData {
+    /// Returns tables the data consist of. The order of the tables is a part
+    /// of the log format and must not be changed.
+    fn tables(&self) -> Vec<&dyn LogTable>;
+
+    /// Returns tables the data consist of, in the same order as
+    /// [`LogData::tables`].
+    fn tables_mut(&mut self) -> Vec<&mut dyn LogTable>;
+
+    /// Returns rows changed since the data were loaded or stored by a
+    /// [`FsLogStore`].
+    fn dirty_rows(&self) -> &DirtyRows;
+}
+
+/// Rows of [`LogData`] changed since the data were loaded or stored by a
+/// [`FsLogStore`], identified by the table number and serialized key.
+///
+/// Changes are tracked only for the data which were loaded or stored by a
+/// [`FsLogStore`]; the store which did it is the only one which may use the
+/// tracked changes, other stores re-write the whole data file.
+#[derive(Debug, Default)]
+pub(crate) struct DirtyRows(Mutex<Option<(u64, Rows)>>);
+
+impl DirtyRows {
+    /// Marks the row as changed, if the changes are tracked.
+    pub fn mark(&mut self, table: u8, key: &impl StrictEncode) {
+        let tracked = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, rows)) = tracked {
+            rows.insert((table, to_bytes(key)));
+        }
+    }
+
+    /// Marks the table stored as a single entry (see [`LogTable`] for
+    /// [`TypeSystem`]) as changed, if the changes are tracked.
+    pub fn mark_table(&mut self, table: u8) {
+        let tracked = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, rows)) = tracked {
+            rows.insert((table, vec![]));
+        }
+    }
+
+    /// Stops tracking the changes, such that the next store operation
+    /// re-writes the whole data. Used when the changes are made in a way
+    /// which can't be tracked.
+    pub fn invalidate(&mut self) {
+        *self.0.get_mut().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Returns rows changed since the data were loaded or stored by the given
+    /// store, or `None` if the changes are not tracked for it.
+    fn rows(&self, store: u64) -> Option<Rows> {
+        match &*self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some((id, rows)) if *id == store => Some(rows.clone()),
+            _ => None,
+        }
+    }
+
+    /// Starts tracking the changes for the given store.
+    fn track(&self, store: u64) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some((store, empty!()));
+    }
+}
+
+impl<K, V, const MIN: usize, const MAX: usize> LogTable for Confined<BTreeMap<K, V>, MIN, MAX>
+where
+    K: Ord + Hash + StrictEncode + StrictDecode,
+    V: StrictEncode + StrictDecode,
+{
+    fn row_value(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(&from_bytes::<K>(key).ok()?).map(to_bytes)
+    }
+
+    fn insert_row(&mut self, key: &[u8], value: &[u8]) -> Result<(), DecodeError> {
+        self.insert(from_bytes(key)?, from_bytes(value)?)
+            .map_err(|e| DecodeError::DataIntegrityError(e.to_string()))?;
+        Ok(())
+    }
+
+    fn remove_row(&mut self, key: &[u8]) -> Result<(), DecodeError> {
+        self.remove(&from_bytes(key)?)
+            .map_err(|e| DecodeError::DataIntegrityError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+impl<T, const MIN: usize, const MAX: usize> LogTable for Confined<BTreeSet<T>, MIN, MAX>
+where T: Ord + Hash + StrictEncode + StrictDecode
+{
+    fn row_value(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.contains(&from_bytes::<T>(key).ok()?).then(Vec::new)
+    }
+
+    fn insert_row(&mut self, key: &[u8], _: &[u8]) -> Result<(), DecodeError> {
+        self.push(from_bytes(key)?)
+            .map_err(|e| DecodeError::DataIntegrityError(e.to_string()))
+    }
+
+    fn remove_row(&mut self, key: &[u8]) -> Result<(), DecodeError> {
+        self.remove(&from_bytes(key)?)
+            .map_err(|e| DecodeError::DataIntegrityError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Type system is stored as a single entry with an empty key.
+impl LogTable for TypeSystem {
+    fn row_value(&self, _: &[u8]) -> Option<Vec<u8>> { Some(to_bytes(self)) }
+
+    fn insert_row(&mut self, _: &[u8], value: &[u8]) -> Result<(), DecodeError> {
+        *self = from_bytes(value)?;
+        Ok(())
+    }
+
+    fn remove_row(&mut self, _: &[u8]) -> Result<(), DecodeError> {
+        *self = TypeSystem::default();
+        Ok(())
+    }
+}
+
+/// Change of a single table entry: a new value or, if the value is absent,
+/// entry removal.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+struct LogEntry {
+    table: u8,
+    key: LargeBlob,
+    value: Option<LargeBlob>,
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut engine = Sha256::default();
+    engine.input_raw(data);
+    engine.finish()
+}
+
+fn to_bytes(val: &impl StrictEncode) -> Vec<u8> {
+    val.strict_encode(StrictWriter::in_memory::<U32MAX>())
+        .expect("in-memory encoding")
+        .unbox()
+        .unconfine()
+}
+
+fn from_bytes<T: StrictDecode>(data: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = StrictReader::in_memory::<U32MAX>(data);
+    let val = T::strict_decode(&mut reader)?;
+    if reader.into_cursor().position() != data.len() as u64 {
+        return Err(DecodeError::DataIntegrityError(s!("log entry is not entirely consumed")));
+    }
+    Ok(val)
+}
+
+fn blob(data: Vec<u8>) -> LargeBlob { LargeBlob::try_from(data).expect("entry size exceeds 4GB") }
+
+pub(super) fn log_path(path: &Path) -> PathBuf { path.with_extension("log") }
+
+fn header(base: &[u8; 32]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend(FS_LOG_MAGIC);
+    header.extend(FS_LOG_VERSION.to_le_bytes());
+    header.extend(base);
+    header
+}
+
+/// Computes hash of a data file, which identifies the file in the header of
+/// its log.
+pub(super) fn file_digest(path: &Path) -> io::Result<[u8; 32]> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut engine = Sha256::default();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            len => engine.input_raw(&buf[..len]),
+        }
+    }
+    Ok(engine.finish())
+}
+
+/// Writes a log file containing just a header with the given data file hash.
+pub(super) fn write_header(path: &Path, base: &[u8; 32]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&header(base))?;
+    file.sync_all()
+}
+
+fn frame(entries: Vec<LogEntry>) -> Vec<u8> {
+    let entries = LargeVec::try_from(entries).expect("too many changes");
+    let payload = to_bytes(&entries);
+    let len = u32::try_from(payload.len()).expect("batch size exceeds 4GB");
+    let mut batch = Vec::with_capacity(payload.len() + 36);
+    batch.extend(len.to_le_bytes());
+    batch.extend(&payload);
+    batch.extend(sha256(&payload));
+    batch
+}
+
+/// Reads batches from the log, stopping at the first one which is not
+/// completely written. Returns the batches together with the length of the
+/// valid part of the file, or `None` if the log is absent or doesn't match
+/// the provided hash of the data file.
+fn read_batches(
+    path: &Path,
+    base: Option<&[u8; 32]>,
+) -> io::Result<Option<(Vec<Vec<LogEntry>>, u64)>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if data.len() < HEADER_LEN as usize || !data.starts_with(&FS_LOG_MAGIC) {
+        return Ok(None);
+    }
+    let (head, mut rest) = data.split_at(HEADER_LEN as usize);
+    if head[8..10] != FS_LOG_VERSION.to_le_bytes()
+        || base.is_some_and(|base| head[10..] != base[..])
+    {
+        return Ok(None);
+    }
+    let mut batches = vec![];
+    let mut valid = HEADER_LEN;
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < len + 36 {
+            break;
+        }
+        let payload = &rest[4..len + 4];
+        if rest[len + 4..len + 36] != sha256(payload) {
+            break;
+        }
+        let Ok(entries) = from_bytes::<LargeVec<LogEntry>>(payload) else {
+            break;
+        };
+        batches.push(entries.release());
+        valid += len as u64 + 36;
+        rest = &rest[len + 36..];
+    }
+    Ok(Some((batches, valid)))
+}
+
+/// Applies changes from the log to the data read from the data file at the
+/// given path. Returns the length of the valid part of the log, or zero if
+/// there is no log matching the data file.
+pub(super) fn replay<T: LogData>(
+    data: &mut T,
+    path: &Path,
+    base: &[u8; 32],
+) -> Result<u64, DeserializeError> {
+    let Some((batches, len)) = read_batches(&log_path(path), Some(base))? else {
+        return Ok(0);
+    };
+    for entry in batches.into_iter().flatten() {
+        let mut tables = data.tables_mut();
+        let table = tables.get_mut(entry.table as usize).ok_or_else(|| {
+            DecodeError::DataIntegrityError(format!("unknown log table {}", entry.table))
+        })?;
+        match entry.value {
+            Some(value) => table.insert_row(entry.key.as_slice(), value.as_slice())?,
+            None => table.remove_row(entry.key.as_slice())?,
+        }
+    }
+    Ok(len)
+}
+
+/// Removes the log if it applies to the given data file. Must be called
+/// before a data file gets replaced with a new one, such that the log doesn't
+/// get applied to the data which already contain its changes.
+pub(super) fn discard_log(path: &Path, data: &Path) -> io::Result<()> {
+    let log = log_path(path);
+    if log.exists() && read_batches(&log, Some(&file_digest(data)?))?.is_some() {
+        fs::remove_file(&log)?;
+    }
+    Ok(())
+}
+
+/// Removes the log, which is not used anymore after the data file got
+/// replaced.
+pub(super) fn remove_log(path: &Path) -> io::Result<()> {
+    let log = log_path(path);
+    if log.exists() {
+        fs::remove_file(&log)?;
+        sync_dir(&log)?;
+    }
+    Ok(())
+}
+
+/// Appends batches staged in the `.add` file to the log, discarding data
+/// from the log which were not completely written.
+pub(super) fn apply_staged(path: &Path) -> io::Result<()> {
+    let log = log_path(path);
+    let staged = with_suffix(&log, "add");
+    if !staged.exists() {
+        return Ok(());
+    }
+    if let Some((_, valid)) = read_batches(&log, None)? {
+        let mut file = OpenOptions::new().write(true).open(&log)?;
+        file.set_len(valid)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&fs::read(&staged)?)?;
+        file.sync_all()?;
+    }
+    fs::remove_file(&staged)
+}
+
+/// Information about the data files and their logs known to [`FsLogStore`].
+#[derive(Clone, Debug)]
+struct LogState {
+    /// Hash of the data file.
+    base: [u8; 32],
+    snapshot_len: u64,
+    /// Length of the valid part of the log, or zero if there is no log file
+    /// matching the data file.
+    log_len: u64,
+}
+
+enum Update {
+    None,
+    Append(Vec<u8>),
+    Compact,
+}
+
+/// File-based storage for in-memory stash, state and index, which appends
+/// changes to a log instead of re-writing the whole data on each store
+/// operation.
+///
+/// The in-memory providers track which table entries were added, changed or
+/// removed since the data were loaded or stored by the store (see
+/// [`DirtyRows`]), and on each store operation only these entries are
+/// appended to the log. Once the log grows larger than the data
+/// file (and at least 1 MiB), the data file is re-written with the current
+/// data and the log is emptied. Thus, the amount of data written to disk is
+/// proportional to the changes made, and the disk space used by the log does
+/// not exceed the size of the data.
+///
+/// The data files use the same format as [`FsBinStore`], and the same
+/// directory may be opened with either of the stores; [`FsBinStore`] applies
+/// the logs on load but always re-writes the whole data files.
+#[derive(Clone, Debug)]
+pub struct FsLogStore {
+    /// Identifier of the store (shared with its clones), for which the
+    /// providers track their changes.
+    id: u64,
+    files: FsBinStore,
+    known: Arc<Mutex<BTreeMap<PathBuf, LogState>>>,
+}
+
+static NEXT_STORE_ID: AtomicU64 = AtomicU64::new(0);
+
+impl FsLogStore {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        Ok(Self {
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+            files: FsBinStore::new(path)?,
+            known: default!(),
+        })
+    }
+
+    /// Returns paths of the data files.
+    pub fn files(&self) -> &FsBinStore { &self.files }
+
+    /// Brings the files to a consistent state after an interrupted store
+    /// operation.
+    pub fn recover(&self) -> io::Result<()> { self.files.recover() }
+
+    fn load_file<T: FsData + LogData>(&self, path: &Path) -> Result<T, PersistenceError> {
+        let _lock = self.files.lock(false)?;
+        self.files.recover_for_load()?;
+        let mut data = read_file::<T>(path).map_err(PersistenceError::with)?;
+        let base = file_digest(path).map_err(PersistenceError::with)?;
+        let log_len = replay(&mut data, path, &base).map_err(PersistenceError::with)?;
+        let state = LogState {
+            base,
+            snapshot_len: fs::metadata(path).map_err(PersistenceError::with)?.len(),
+            log_len,
+        };
+        self.known
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_owned(), state);
+        data.dirty_rows().track(self.id);
+        Ok(data)
+    }
+
+    fn plan(&self, data: &impl LogData, path: &Path) -> Update {
+        let known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        let (Some(state), Some(rows)) = (known.get(path), data.dirty_rows().rows(self.id)) else {
+            return Update::Compact;
+        };
+        if rows.is_empty() {
+            return Update::None;
+        }
+
+        let tables = data.tables();
+        let entries = rows
+            .into_iter()
+            .map(|(table, key)| LogEntry {
+                table,
+                value: tables[table as usize].row_value(&key).map(blob),
+                key: blob(key),
+            })
+            .collect();
+        let batch = frame(entries);
+        let log_len = state.log_len.max(HEADER_LEN) + batch.len() as u64;
+        if log_len > state.snapshot_len.max(MIN_COMPACT_LEN) {
+            return Update::Compact;
+        }
+        Update::Append(batch)
+    }
+
+    /// Writes a staged update for the data file into files with the given
+    /// suffix. Returns state of the files after the staged files replace the
+    /// original ones.
+    fn stage(
+        &self,
+        data: &(impl StrictSerialize + LogData),
+        path: &Path,
+        suffix: &str,
+    ) -> io::Result<Option<LogState>> {
+        let update = self.plan(data, path);
+        let known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        let log = log_path(path);
+        match (update, known.get(path)) {
+            (Update::None, _) => Ok(None),
+            (Update::Append(batch), Some(state)) if state.log_len > 0 => {
+                let mut file = File::create(with_suffix(&log, "add"))?;
+                file.write_all(&batch)?;
+                file.sync_all()?;
+                Ok(Some(LogState {
+                    log_len: state.log_len + batch.len() as u64,
+                    ..state.clone()
+                }))
+            }
+            (Update::Append(batch), Some(state)) => {
+                let mut file = File::create(with_suffix(&log, suffix))?;
+                file.write_all(&header(&state.base))?;
+                file.write_all(&batch)?;
+                file.sync_all()?;
+                Ok(Some(LogState {
+                    log_len: HEADER_LEN + batch.len() as u64,
+                    ..state.clone()
+                }))
+            }
+            (Update::Compact, _) | (Update::Append(_), None) => {
+                let staged = with_suffix(path, suffix);
+                write_synced(data, &staged)?;
+                let base = file_digest(&staged)?;
+                write_header(&with_suffix(&log, suffix), &base)?;
+                Ok(Some(LogState {
+                    base,
+                    snapshot_len: fs::metadata(&staged)?.len(),
+                    log_len: HEADER_LEN,
+                }))
+            }
+        }
+    }
+
+    fn store_file(&self, data: &(impl StrictSerialize + LogData), path: &Path) -> io::Result<()> {
+        self.recover()?;
+        let Some(state) = self.stage(data, path, "tmp")? else {
+            data.dirty_rows().track(self.id);
+            return Ok(());
+        };
+        let log = log_path(path);
+        // If a new data file is written, the old log gets invalidated once the data file is
+        // replaced, since it doesn't match the data file anymore. The only exception is when the
+        // data file content is the same, in which case the log must be replaced first.
+        let staged = with_suffix(path, "tmp");
+        if staged.exists() {
+            let replace_log_first = self
+                .known
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(path)
+                .is_some_and(|known| known.base == state.base);
+            if replace_log_first {
+                fs::rename(with_suffix(&log, "tmp"), &log)?;
+                fs::remove_file(&staged)?;
+            } else {
+                fs::rename(&staged, path)?;
+                sync_dir(path)?;
+                fs::rename(with_suffix(&log, "tmp"), &log)?;
+            }
+        } else if with_suffix(&log, "tmp").exists() {
+            fs::rename(with_suffix(&log, "tmp"), &log)?;
+        }
+        apply_staged(path)?;
+        sync_dir(path)?;
+        self.known
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_owned(), state);
+        data.dirty_rows().track(self.id);
+        Ok(())
+    }
+
+    fn store_all_files(
+        &self,
+        stash: &MemStash,
+        state: &MemState,
+        index: &MemIndex,
+    ) -> io::Result<()> {
+        self.recover()?;
+
+        // Phase 1: prepare new data and log batches without touching the existing files
+        let states = [
+            self.stage(stash, &self.files.stash, "new")?,
+            self.stage(state, &self.files.state, "new")?,
+            self.stage(index, &self.files.index, "new")?,
+        ];
+
+        // Phase 2: commit
+        self.files.commit()?;
+
+        let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        for (path, state) in self.files.paths().into_iter().zip(states) {
+            if let Some(state) = state {
+                known.insert(path.to_owned(), state);
+            }
+        }
+        stash.dirty_rows().track(self.id);
+        state.dirty_rows().track(self.id);
+        index.dirty_rows().track(self.id);
+        Ok(())
+    }
+}
+
+impl PersistenceProvider<MemStash> for FsLogStore {
+    fn load(&self) -> Result<MemStash, PersistenceError> { self.load_file(&self.files.stash) }
+
+    fn store(&self, object: &MemStash) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_file(object, &self.files.stash)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl PersistenceProvider<MemState> for FsLogStore {
+    fn load(&self) -> Result<MemState, PersistenceError> { self.load_file(&self.files.state) }
+
+    fn store(&self, object: &MemState) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_file(object, &self.files.state)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl PersistenceProvider<MemIndex> for FsLogStore {
+    fn load(&self) -> Result<MemIndex, PersistenceError> { self.load_file(&self.files.index) }
+
+    fn store(&self, object: &MemIndex) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_file(object, &self.files.index)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl StockPersistence<MemStash, MemState, MemIndex> for FsLogStore {
+    fn store_all(
+        &self,
+        stash: &MemStash,
+        state: &MemState,
+        index: &MemIndex,
+    ) -> Result<(), PersistenceError> {
+        let _lock = self.files.lock(true)?;
+        self.store_all_files(stash, state, index)
+            .map_err(PersistenceError::with)
+    }
+
+    fn into_read_only(self) -> Self {
+        Self {
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+            files: self.files.into_read_only(),
+            known: default!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::Vout;
+    use rgb::{GraphSeal, XChain};
+
+    use super::*;
+    use crate::persistence::fixture::{Fixture, Transfers};
+    use crate::persistence::{StashReadProvider, Stock};
+
+    fn serialized(stock: &Stock) -> [Vec<u8>; 3] {
+        [
+            to_bytes(stock.as_stash_provider()),
+            to_bytes(stock.as_state_provider()),
+            to_bytes(stock.as_index_provider()),
+        ]
+    }
+
+    #[test]
+    fn changes_are_appended() {
+        let path = std::env::temp_dir().join(format!("rgb-log-{}", rand::random::<u64>()));
+        let store = FsLogStore::new(path.clone()).unwrap();
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let stash = fs::read(&store.files().stash).unwrap();
+
+        let seal = XChain::with(
+            rgb::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        stock.store_secret_seal(seal).unwrap();
+        stock.store().unwrap();
+        let log = log_path(&store.files().stash);
+        let log_len = fs::metadata(&log).unwrap().len();
+        assert!(log_len > HEADER_LEN);
+        assert_eq!(fs::read(&store.files().stash).unwrap(), stash);
+
+        // Batch which was not completely written is ignored
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(&[0xFF; 10]).unwrap();
+        drop(file);
+
+        let stock =
+            Stock::<MemStash, MemState, MemIndex>::load_atomic(store.clone(), false).unwrap();
+        assert_eq!(
+            stock
+                .as_stash_provider()
+                .secret_seals()
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![seal]
+        );
+        let stock =
+            Stock::<MemStash, MemState, MemIndex>::load_atomic(store.files().clone(), false)
+                .unwrap();
+        assert_eq!(stock.as_stash_provider().secret_seals().unwrap().count(), 1);
+
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn changed_rows_are_replayed() {
+        let path = std::env::temp_dir().join(format!("rgb-log-{}", rand::random::<u64>()));
+        let store = FsLogStore::new(path.clone()).unwrap();
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), false).unwrap();
+        let files = store.files().paths().map(|path| fs::read(path).unwrap());
+
+        Transfers::consume(&mut stock);
+        stock.store().unwrap();
+        assert_eq!(store.files().paths().map(|path| fs::read(path).unwrap()), files);
+
+        let mut loaded = Stock::load_atomic(store.clone(), false).unwrap();
+        assert_eq!(serialized(&loaded), serialized(&stock));
+
+        // Changes made after the load are appended to the same logs
+        let fixture = Fixture::issue(1);
+        loaded
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+        assert_eq!(loaded.contracts().unwrap().count(), 2);
+        let seal = XChain::with(
+            rgb::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        loaded.store_secret_seal(seal).unwrap();
+        loaded.store().unwrap();
+        assert_eq!(store.files().paths().map(|path| fs::read(path).unwrap()), files);
+
+        let reloaded = Stock::load_atomic(store.files().clone(), false).unwrap();
+        assert_eq!(serialized(&reloaded), serialized(&loaded));
+
+        fs::remove_dir_all(path).ok();
+    }
+}
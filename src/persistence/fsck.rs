@@ -0,0 +1,346 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stock consistency checks.
+
+use std::collections::BTreeSet;
+
+use commit_verify::Conceal;
+use rgb::validation::ResolveWitness;
+use rgb::{GraphSeal, Operation, XChain};
+
+use super::{
+    ContractStateRead, IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider,
+    StashError, StashInconsistency, StashProvider, StashProviderError, StashReadProvider,
+    StateInconsistency, StateProvider, StateReadError, StateReadProvider, Stock, StockError,
+};
+
+/// Report on the consistency of the stash, contract state and index data
+/// within a [`Stock`].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ConsistencyReport {
+    /// Stash data missed or not matching the index and contract state.
+    pub stash: Vec<StashInconsistency>,
+    /// Contract state data missed or not matching the stash.
+    pub state: Vec<StateInconsistency>,
+    /// Index data missed or not matching the stash.
+    pub index: Vec<IndexInconsistency>,
+    /// Secret seals known to the stash which are not used by any of the
+    /// known contract operations.
+    ///
+    /// These are not necessarily an error, since a seal may belong to an
+    /// invoice which is not paid yet.
+    pub orphaned_seals: Vec<XChain<GraphSeal>>,
+}
+
+impl ConsistencyReport {
+    /// Detects whether no inconsistencies were found (orphaned seals are not
+    /// considered as inconsistencies).
+    pub fn is_consistent(&self) -> bool {
+        self.stash.is_empty() && self.state.is_empty() && self.index.is_empty()
+    }
+
+    fn stash_res<T, S: StashProvider, H: StateProvider, P: IndexProvider>(
+        &mut self,
+        res: Result<T, StashProviderError<<S as StashReadProvider>::Error>>,
+    ) -> Result<Option<T>, StockError<S, H, P>> {
+        match res {
+            Ok(val) => Ok(Some(val)),
+            Err(StashProviderError::Inconsistency(e)) => {
+                self.stash.push(e);
+                Ok(None)
+            }
+            Err(err) => Err(StashError::<S>::from(err).into()),
+        }
+    }
+
+    fn index_res<T, S: StashProvider, H: StateProvider, P: IndexProvider>(
+        &mut self,
+        res: Result<T, IndexReadError<<P as IndexReadProvider>::Error>>,
+    ) -> Result<Option<T>, StockError<S, H, P>> {
+        match res {
+            Ok(val) => Ok(Some(val)),
+            Err(IndexReadError::Inconsistency(e)) => {
+                self.index.push(e);
+                Ok(None)
+            }
+            Err(IndexReadError::Connectivity(e)) => Err(StockError::IndexRead(e)),
+        }
+    }
+
+    fn state_res<T, S: StashProvider, H: StateProvider, P: IndexProvider>(
+        &mut self,
+        res: Result<T, <H as StateReadProvider>::Error>,
+    ) -> Result<Option<T>, StockError<S, H, P>> {
+        match res {
+            Ok(val) => Ok(Some(val)),
+            Err(err) => match err.inconsistency() {
+                Some(e) => {
+                    self.state.push(e.clone());
+                    Ok(None)
+                }
+                None => Err(StockError::StateRead(err)),
+            },
+        }
+    }
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
+    /// Cross-checks stash, contract state and index data.
+    ///
+    /// Verifies that each operation known to the index is present in the
+    /// stash, that each bundle in the stash is indexed and has a witness which
+    /// anchors it, and that each output assignment in the contract state
+    /// originates from a known operation. Also detects orphaned secret
+    /// seals.
+    ///
+    /// Errors are returned only if the data can't be accessed; all discovered
+    /// inconsistencies are returned as a part of the report.
+    pub fn check_consistency(&self) -> Result<ConsistencyReport, StockError<S, H, P>> {
+        let stash = self.as_stash_provider();
+        let state = self.as_state_provider();
+        let index = self.as_index_provider();
+        let mut report = ConsistencyReport::default();
+
+        // Each operation in the index must be present in the stash
+        for (opid, bundle_id) in index.indexed_ops().map_err(StockError::IndexRead)? {
+            let Some(bundle) = report.stash_res(stash.bundle(bundle_id))? else {
+                continue;
+            };
+            if !bundle.known_transitions.contains_key(&opid) {
+                report.stash.push(StashInconsistency::OperationAbsent(opid));
+            }
+        }
+
+        // Each bundle in the stash must be indexed and anchored by one of its witnesses
+        for bundle_id in stash.bundle_ids().map_err(StockError::StashRead)? {
+            let Some(bundle) = report.stash_res(stash.bundle(bundle_id))? else {
+                continue;
+            };
+            for opid in bundle.known_transitions.keys() {
+                match report.index_res(index.bundle_id_for_op(*opid))? {
+                    Some(present) if present != bundle_id => {
+                        report.index.push(IndexInconsistency::DistinctBundleOp {
+                            opid: *opid,
+                            present,
+                            expected: bundle_id,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+            let Some((witness_ids, contract_id)) =
+                report.index_res(index.bundle_info(bundle_id))?
+            else {
+                continue;
+            };
+            let mut anchored = false;
+            for witness_id in witness_ids.collect::<Vec<_>>() {
+                if let Some(witness) = report.stash_res(stash.witness(witness_id))? {
+                    anchored |= witness.anchors.known_bundle_ids().any(|id| id == bundle_id);
+                }
+            }
+            if !anchored {
+                report
+                    .stash
+                    .push(StashInconsistency::BundleMissedInAnchors(bundle_id, contract_id));
+            }
+        }
+
+        // Each output assignment must originate from a known operation
+        let extension_ids = stash
+            .extension_ids()
+            .map_err(StockError::StashRead)?
+            .collect::<BTreeSet<_>>();
+        for genesis in stash.geneses().map_err(StockError::StashRead)? {
+            let contract_id = genesis.contract_id();
+            report.index_res(index.public_opouts(contract_id))?;
+            let Some(contract) = report.state_res(state.contract_state(contract_id))? else {
+                continue;
+            };
+            let origins = contract
+                .rights_all()
+                .map(|a| (a.opout.op, a.witness))
+                .chain(contract.fungible_all().map(|a| (a.opout.op, a.witness)))
+                .chain(contract.data_all().map(|a| (a.opout.op, a.witness)))
+                .chain(contract.attach_all().map(|a| (a.opout.op, a.witness)))
+                .collect::<BTreeSet<_>>();
+            let pruned = stash
+                .prune_checkpoint(contract_id)
+                .map_err(StockError::StashRead)?;
+            let mut witnesses = BTreeSet::new();
+            for (opid, witness_id) in origins {
+                if let Some(witness_id) = witness_id {
+                    if witnesses.insert(witness_id) && contract.witness_ord(witness_id).is_none() {
+                        report
+                            .state
+                            .push(StateInconsistency::AbsentWitness(witness_id));
+                    }
+                }
+                if opid == genesis.id()
+                    || extension_ids.contains(&opid)
+                    || pruned.is_some_and(|checkpoint| checkpoint.ops.contains(&opid))
+                {
+                    continue;
+                }
+                // Operations missed in the stash bundles are reported as index inconsistencies
+                // already by the bundle check above
+                match index.bundle_id_for_op(opid) {
+                    Ok(_) => {}
+                    Err(IndexReadError::Inconsistency(e)) if !report.index.contains(&e) => {
+                        report.index.push(e)
+                    }
+                    Err(IndexReadError::Inconsistency(_)) => {}
+                    Err(IndexReadError::Connectivity(e)) => return Err(StockError::IndexRead(e)),
+                }
+            }
+        }
+
+        // Secret seals must be used by some of the operations
+        let mut seals = BTreeSet::new();
+        for genesis in stash.geneses().map_err(StockError::StashRead)? {
+            seals.extend(
+                genesis
+                    .assignments()
+                    .flat()
+                    .values()
+                    .flat_map(|a| a.to_confidential_seals()),
+            );
+        }
+        for opid in &extension_ids {
+            let Some(extension) = report.stash_res(stash.extension(*opid))? else {
+                continue;
+            };
+            seals.extend(
+                extension
+                    .assignments()
+                    .flat()
+                    .values()
+                    .flat_map(|a| a.to_confidential_seals()),
+            );
+        }
+        for bundle_id in stash.bundle_ids().map_err(StockError::StashRead)? {
+            let Some(bundle) = report.stash_res(stash.bundle(bundle_id))? else {
+                continue;
+            };
+            for transition in bundle.known_transitions.values() {
+                seals.extend(
+                    transition
+                        .assignments()
+                        .flat()
+                        .values()
+                        .flat_map(|a| a.to_confidential_seals()),
+                );
+            }
+        }
+        report.orphaned_seals = stash
+            .secret_seals()
+            .map_err(StockError::StashRead)?
+            .filter(|seal| !seals.contains(&seal.conceal()))
+            .collect();
+
+        Ok(report)
+    }
+
+    /// Checks stock consistency and, if inconsistencies are found, re-creates
+    /// index and contract state from the stash data using the provided empty
+    /// providers (see [`Stock::reindex`] and [`Stock::rebuild_state`]).
+    ///
+    /// Returns report of the check performed after the repair. Inconsistencies
+    /// of the stash data itself can't be repaired and remain in the report.
+    pub fn repair(
+        &mut self,
+        index: P,
+        state: H,
+        resolver: impl ResolveWitness,
+    ) -> Result<ConsistencyReport, StockError<S, H, P>> {
+        let report = self.check_consistency()?;
+        if report.is_consistent() {
+            return Ok(report);
+        }
+        self.reindex(index)?;
+        self.rebuild_state(state, resolver)?;
+        self.check_consistency()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::Vout;
+
+    use super::*;
+    use crate::persistence::fixture::transferred_stock;
+    use crate::persistence::{IndexWriteProvider, MemIndex, MemState};
+
+    #[test]
+    fn orphaned_seals() {
+        let mut stock = Stock::in_memory();
+        let seal = XChain::with(
+            rgb::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        stock.store_secret_seal(seal).unwrap();
+
+        let report = stock.check_consistency().unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.orphaned_seals, vec![seal]);
+    }
+
+    #[test]
+    fn unindexed_operation() {
+        let (mut stock, transfers) = transferred_stock();
+        let (first, sibling) = (transfers.first, transfers.sibling);
+        assert!(stock.check_consistency().unwrap().is_consistent());
+
+        let bundle_id = stock
+            .as_index_provider()
+            .bundle_id_for_op(first.id())
+            .unwrap();
+        stock
+            .as_index_provider_mut()
+            .remove_bundle(bundle_id)
+            .unwrap();
+
+        let report = stock.check_consistency().unwrap();
+        assert!(!report.is_consistent());
+        assert!(report.stash.is_empty());
+        assert!(report.state.is_empty());
+        let mut opids = [first.id(), sibling.id()];
+        opids.sort();
+        assert_eq!(report.index, vec![
+            IndexInconsistency::BundleAbsent(opids[0]),
+            IndexInconsistency::BundleAbsent(opids[1]),
+            IndexInconsistency::BundleWitnessUnknown(bundle_id),
+        ]);
+
+        let report = stock
+            .repair(MemIndex::in_memory(), MemState::in_memory(), &transfers.fixture.chain)
+            .unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(
+            stock
+                .as_index_provider()
+                .bundle_id_for_op(first.id())
+                .unwrap(),
+            bundle_id
+        );
+    }
+}
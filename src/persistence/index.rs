@@ -150,6 +150,8 @@ where P: Default
 impl<P: IndexProvider> Index<P> {
     pub(super) fn new(provider: P) -> Self { Self { provider } }
 
+    pub(super) fn into_provider(self) -> P { self.provider }
+
     #[doc(hidden)]
     pub fn as_provider(&self) -> &P { &self.provider }
 
@@ -162,10 +164,7 @@ impl<P: IndexProvider> Index<P> {
     ) -> Result<(), IndexError<P>> {
         let contract_id = consignment.contract_id();
 
-        self.provider
-            .register_contract(contract_id)
-            .map_err(IndexError::WriteProvider)?;
-        self.index_genesis(contract_id, consignment.genesis())?;
+        self.index_contract(consignment.genesis())?;
         for extension in consignment.extensions() {
             self.index_extension(contract_id, extension)?;
         }
@@ -183,6 +182,14 @@ impl<P: IndexProvider> Index<P> {
         Ok(())
     }
 
+    pub(super) fn index_contract(&mut self, genesis: &Genesis) -> Result<(), IndexError<P>> {
+        let contract_id = genesis.contract_id();
+        self.provider
+            .register_contract(contract_id)
+            .map_err(IndexError::WriteProvider)?;
+        self.index_genesis(contract_id, genesis)
+    }
+
     fn index_genesis(&mut self, id: ContractId, genesis: &Genesis) -> Result<(), IndexError<P>> {
         let opid = genesis.id();
         for (type_id, assign) in genesis.assignments.iter() {
@@ -208,7 +215,7 @@ impl<P: IndexProvider> Index<P> {
         Ok(())
     }
 
-    fn index_extension(
+    pub(super) fn index_extension(
         &mut self,
         id: ContractId,
         extension: &Extension,
@@ -355,6 +362,18 @@ impl<P: IndexProvider> StoreTransaction for Index<P> {
             .map_err(IndexError::WriteProvider)
     }
 
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.provider
+            .precommit_transaction()
+            .map_err(IndexError::WriteProvider)
+    }
+
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.provider
+            .complete_transaction()
+            .map_err(IndexError::WriteProvider)
+    }
+
     fn rollback_transaction(&mut self) { self.provider.rollback_transaction() }
 }
 
@@ -393,6 +412,10 @@ pub trait IndexReadProvider {
         &self,
         bundle_id: BundleId,
     ) -> Result<(impl Iterator<Item = XWitnessId>, ContractId), IndexReadError<Self::Error>>;
+
+    /// Iterates over all operations known to the index, returning them
+    /// together with the ids of the bundles they are included into.
+    fn indexed_ops(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error>;
 }
 
 pub trait IndexWriteProvider: StoreTransaction<TransactionErr = Self::Error> {
@@ -429,4 +452,13 @@ pub trait IndexWriteProvider: StoreTransaction<TransactionErr = Self::Error> {
         type_id: AssignmentType,
         witness_id: XWitnessId,
     ) -> Result<(), IndexWriteError<Self::Error>>;
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error>;
+
+    /// Removes bundle from the index together with the records of all its
+    /// operations.
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error>;
+
+    /// Removes the given operations from the index of terminals.
+    fn remove_terminals(&mut self, opids: &BTreeSet<OpId>) -> Result<(), Self::Error>;
 }
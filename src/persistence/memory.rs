@@ -19,11 +19,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::any::Any;
 use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::Infallible;
 use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
 use std::num::NonZeroU32;
 use std::{iter, mem};
 
@@ -36,7 +38,6 @@ use amplify::num::u24;
 use bp::dbc::tapret::TapretCommitment;
 use commit_verify::{CommitId, Conceal};
 use nonasync::persistence::{CloneNoPersistence, Persistence, PersistenceError, Persisting};
-use rgb::validation::ResolveWitness;
 use rgb::vm::{
     ContractStateAccess, ContractStateEvolve, GlobalContractState, GlobalOrd, GlobalStateIter,
     OrdOpRef, UnknownGlobalStateType, WitnessOrd,
@@ -44,27 +45,32 @@ use rgb::vm::{
 use rgb::{
     Assign, AssignmentType, Assignments, AssignmentsRef, AttachId, AttachState, BundleId,
     ContractId, DataState, ExposedSeal, ExposedState, Extension, FungibleState, Genesis,
-    GenesisSeal, GlobalStateType, GraphSeal, Identity, OpId, Operation, Opout, RevealedAttach,
-    RevealedData, RevealedValue, Schema, SchemaId, SecretSeal, Transition, TransitionBundle,
-    TypedAssigns, VoidState, XChain, XOutpoint, XOutputSeal, XWitnessId,
+    GenesisSeal, GlobalStateType, GraphSeal, Identity, Layer1, OpId, Operation, Opout,
+    RevealedAttach, RevealedData, RevealedValue, Schema, SchemaId, SecretSeal, Transition,
+    TransitionBundle, TypedAssigns, VoidState, XChain, XOutpoint, XOutputSeal, XWitnessId,
 };
-use strict_encoding::{StrictDeserialize, StrictSerialize};
+use strict_encoding::{StrictDeserialize, StrictEncode, StrictSerialize};
 use strict_types::TypeSystem;
 
+#[cfg(feature = "fs")]
+use super::fs::{DirtyRows, LogData, LogTable};
 use super::{
     ContractIfaceError, ContractStateRead, ContractStateWrite, IndexInconsistency, IndexProvider,
-    IndexReadError, IndexReadProvider, IndexWriteError, IndexWriteProvider, SchemaIfaces,
-    StashInconsistency, StashProvider, StashProviderError, StashReadProvider, StashWriteProvider,
-    StateInconsistency, StateProvider, StateReadProvider, StateWriteProvider, StoreTransaction,
-    UpdateRes,
+    IndexReadError, IndexReadProvider, IndexWriteError, IndexWriteProvider, OrdUpdate,
+    PruneCheckpoint, SchemaIfaces, StashInconsistency, StashProvider, StashProviderError,
+    StashReadProvider, StashWriteProvider, StateInconsistency, StateProvider, StateReadProvider,
+    StateWriteProvider, StoreTransaction, UpdateRes,
 };
 use crate::containers::{
     AnchorSet, ContentId, ContentRef, ContentSigs, SealWitness, SigBlob, Supplement, TrustLevel,
 };
 use crate::contract::{GlobalOut, KnownState, OpWitness, OutputAssignment};
-use crate::interface::{Iface, IfaceClass, IfaceId, IfaceImpl, IfaceRef};
+use crate::interface::{Iface, IfaceClass, IfaceId, IfaceImpl, IfaceRef, ResolveWitnessBatch};
 use crate::LIB_NAME_RGB_STORAGE;
 
+#[cfg(feature = "fs")]
+pub(super) mod legacy;
+
 #[derive(Debug, Display, Error, From)]
 #[display(inner)]
 pub enum MemError {
@@ -75,6 +81,224 @@ pub enum MemError {
     Confinement(confinement::Error),
 }
 
+type Undo<T> = Box<dyn FnOnce(&mut T) + Send + Sync>;
+
+/// Table of an in-memory provider `T`, which is a collection of type `C`.
+///
+/// Tables are numbered in the order in which they are listed by
+/// `LogData::tables`; the number identifies the table in the log of changes.
+pub(super) struct Table<T, C> {
+    no: u8,
+    get: fn(&mut T) -> &mut C,
+}
+
+impl<T, C> Clone for Table<T, C> {
+    fn clone(&self) -> Self { *self }
+}
+impl<T, C> Copy for Table<T, C> {}
+
+impl<T, C> Table<T, C> {
+    const fn new(no: u8, get: fn(&mut T) -> &mut C) -> Self { Self { no, get } }
+}
+
+/// Log of the changes made by a transaction to an in-memory provider `T`.
+///
+/// Before each change the provider saves the previous value of the modified
+/// row into the log, such that the transaction can be reverted by applying the
+/// saved values in the reverse order. Only the first change of a row within a
+/// transaction is saved, since the later ones are reverted by it. The log is
+/// kept until the transaction is completed, allowing to revert it even after it
+/// was committed.
+///
+/// Independently from the transactions, the log also tracks rows changed
+/// since the provider was last loaded or stored by [`super::FsLogStore`].
+pub(super) struct UndoLog<T> {
+    changes: Option<Vec<Undo<T>>>,
+    /// Keys of the rows saved by the current transaction, per table.
+    touched: BTreeMap<u8, Box<dyn Any + Send + Sync>>,
+    depth: usize,
+    #[cfg(feature = "fs")]
+    dirty: DirtyRows,
+}
+
+impl<T> Default for UndoLog<T> {
+    fn default() -> Self {
+        Self {
+            changes: None,
+            touched: empty!(),
+            depth: 0,
+            #[cfg(feature = "fs")]
+            dirty: default!(),
+        }
+    }
+}
+
+impl<T> Debug for UndoLog<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.changes {
+            None => f.write_str("UndoLog(inactive)"),
+            Some(log) => write!(f, "UndoLog({} changes)", log.len()),
+        }
+    }
+}
+
+impl<T: 'static> UndoLog<T> {
+    /// Starts logging the changes. A transaction started while another one is
+    /// in progress is nested into it and shares its log.
+    fn begin(&mut self) {
+        if self.changes.is_none() {
+            self.changes = Some(vec![]);
+        }
+        self.depth += 1;
+    }
+
+    /// Whether the current transaction is nested into another one, and thus
+    /// must be stored and completed together with it.
+    fn is_nested(&self) -> bool { self.depth > 1 }
+
+    /// Completes the current transaction. Once the outermost transaction is
+    /// completed, stops logging and forgets the logged changes.
+    fn complete(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            self.changes = None;
+            self.touched.clear();
+        }
+    }
+
+    /// Stops logging and returns the logged changes in the order in which they
+    /// must be reverted.
+    fn take(&mut self) -> impl Iterator<Item = Undo<T>> {
+        self.depth = 0;
+        self.touched.clear();
+        let changes = self.changes.take().unwrap_or_default();
+        // Reverted rows may be already stored, so we can't track them as changed since then
+        #[cfg(feature = "fs")]
+        if !changes.is_empty() {
+            self.dirty.invalidate();
+        }
+        changes.into_iter().rev()
+    }
+
+    fn push(&mut self, undo: impl FnOnce(&mut T) + Send + Sync + 'static) {
+        if let Some(log) = &mut self.changes {
+            log.push(Box::new(undo));
+        }
+    }
+
+    /// Saves `prev` value of the `key` row in the `table`, which is about to be
+    /// changed.
+    fn save_row<K, V, const MIN: usize, const MAX: usize>(
+        &mut self,
+        table: Table<T, Confined<BTreeMap<K, V>, MIN, MAX>>,
+        key: K,
+        prev: Option<&V>,
+    ) where
+        K: Ord + Hash + Clone + StrictEncode + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        #[cfg(feature = "fs")]
+        self.dirty.mark(table.no, &key);
+        if self.changes.is_none() {
+            return;
+        }
+        let touched = self
+            .touched
+            .entry(table.no)
+            .or_insert_with(|| Box::new(BTreeSet::<K>::new()))
+            .downcast_mut::<BTreeSet<K>>()
+            .expect("table keys are of the same type");
+        if !touched.insert(key.clone()) {
+            return;
+        }
+        let prev = prev.cloned();
+        self.push(move |provider| {
+            let table = (table.get)(provider);
+            match prev {
+                Some(prev) => {
+                    table.insert(key, prev).expect("value was present before");
+                }
+                None => {
+                    table.remove(&key).expect("collections have no lower bound");
+                }
+            }
+        });
+    }
+
+    /// Saves presence of the `item` in the `set`, which is about to be changed.
+    fn save_member<K, const MIN: usize, const MAX: usize>(
+        &mut self,
+        set: Table<T, Confined<BTreeSet<K>, MIN, MAX>>,
+        item: K,
+        present: bool,
+    ) where
+        K: Ord + StrictEncode + Send + Sync + 'static,
+    {
+        if present {
+            return;
+        }
+        #[cfg(feature = "fs")]
+        self.dirty.mark(set.no, &item);
+        self.push(move |provider| {
+            (set.get)(provider)
+                .remove(&item)
+                .expect("collections have no lower bound");
+        });
+    }
+
+    /// Saves presence of the `item` in the `set`, which is about to be
+    /// removed from it.
+    fn save_removed_member<K, const MIN: usize, const MAX: usize>(
+        &mut self,
+        set: Table<T, Confined<BTreeSet<K>, MIN, MAX>>,
+        item: K,
+        present: bool,
+    ) where
+        K: Ord + StrictEncode + Send + Sync + 'static,
+    {
+        if !present {
+            return;
+        }
+        #[cfg(feature = "fs")]
+        self.dirty.mark(set.no, &item);
+        self.push(move |provider| {
+            (set.get)(provider)
+                .push(item)
+                .expect("the set had this item before");
+        });
+    }
+
+    /// Saves `prev` value of the `field`, which is about to be changed.
+    fn save_field<F: Clone + Send + Sync + 'static>(&mut self, field: Table<T, F>, prev: &F) {
+        #[cfg(feature = "fs")]
+        self.dirty.mark_table(field.no);
+        if self.changes.is_none() {
+            return;
+        }
+        let prev = prev.clone();
+        self.push(move |provider| *(field.get)(provider) = prev);
+    }
+}
+
+/// In-memory providers log the changes made within a transaction with
+/// [`UndoLog`], which get reverted if the transaction is rolled back.
+trait MemUndo: Persisting + Sized + 'static {
+    fn undo_log(&mut self) -> &mut UndoLog<Self>;
+
+    fn begin_undo(&mut self) {
+        self.undo_log().begin();
+        self.mark_dirty();
+    }
+
+    fn complete_undo(&mut self) { self.undo_log().complete() }
+
+    fn rollback_undo(&mut self) {
+        for undo in self.undo_log().take() {
+            undo(self);
+        }
+    }
+}
+
 //////////
 // STASH
 //////////
@@ -89,10 +313,14 @@ pub struct MemStash {
     #[strict_type(skip)]
     persistence: Option<Persistence<Self>>,
 
-    schemata: TinyOrdMap<SchemaId, SchemaIfaces>,
-    ifaces: TinyOrdMap<IfaceId, Iface>,
-    geneses: TinyOrdMap<ContractId, Genesis>,
-    suppl: TinyOrdMap<ContentRef, TinyOrdSet<Supplement>>,
+    #[getter(skip)]
+    #[strict_type(skip)]
+    undo: UndoLog<Self>,
+
+    schemata: SmallOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: SmallOrdMap<IfaceId, Iface>,
+    geneses: MediumOrdMap<ContractId, Genesis>,
+    suppl: MediumOrdMap<ContentRef, TinyOrdSet<Supplement>>,
     bundles: LargeOrdMap<BundleId, TransitionBundle>,
     extensions: LargeOrdMap<OpId, Extension>,
     witnesses: LargeOrdMap<XWitnessId, SealWitness>,
@@ -102,15 +330,92 @@ pub struct MemStash {
     identities: SmallOrdMap<Identity, TrustLevel>,
     libs: SmallOrdMap<LibId, Lib>,
     sigs: SmallOrdMap<ContentId, ContentSigs>,
+    pruned: MediumOrdMap<ContractId, PruneCheckpoint>,
+    replaced: LargeOrdMap<XWitnessId, XWitnessId>,
 }
 
 impl StrictSerialize for MemStash {}
 impl StrictDeserialize for MemStash {}
 
+impl MemStash {
+    const SCHEMATA: Table<Self, SmallOrdMap<SchemaId, SchemaIfaces>> =
+        Table::new(0, |s| &mut s.schemata);
+    const IFACES: Table<Self, SmallOrdMap<IfaceId, Iface>> = Table::new(1, |s| &mut s.ifaces);
+    const GENESES: Table<Self, MediumOrdMap<ContractId, Genesis>> =
+        Table::new(2, |s| &mut s.geneses);
+    const SUPPL: Table<Self, MediumOrdMap<ContentRef, TinyOrdSet<Supplement>>> =
+        Table::new(3, |s| &mut s.suppl);
+    const BUNDLES: Table<Self, LargeOrdMap<BundleId, TransitionBundle>> =
+        Table::new(4, |s| &mut s.bundles);
+    const EXTENSIONS: Table<Self, LargeOrdMap<OpId, Extension>> =
+        Table::new(5, |s| &mut s.extensions);
+    const WITNESSES: Table<Self, LargeOrdMap<XWitnessId, SealWitness>> =
+        Table::new(6, |s| &mut s.witnesses);
+    const ATTACHMENTS: Table<Self, SmallOrdMap<AttachId, MediumBlob>> =
+        Table::new(7, |s| &mut s.attachments);
+    const SECRET_SEALS: Table<Self, MediumOrdSet<XChain<GraphSeal>>> =
+        Table::new(8, |s| &mut s.secret_seals);
+    const TYPE_SYSTEM: Table<Self, TypeSystem> = Table::new(9, |s| &mut s.type_system);
+    const IDENTITIES: Table<Self, SmallOrdMap<Identity, TrustLevel>> =
+        Table::new(10, |s| &mut s.identities);
+    const LIBS: Table<Self, SmallOrdMap<LibId, Lib>> = Table::new(11, |s| &mut s.libs);
+    const SIGS: Table<Self, SmallOrdMap<ContentId, ContentSigs>> = Table::new(12, |s| &mut s.sigs);
+    const PRUNED: Table<Self, MediumOrdMap<ContractId, PruneCheckpoint>> =
+        Table::new(13, |s| &mut s.pruned);
+    const REPLACED: Table<Self, LargeOrdMap<XWitnessId, XWitnessId>> =
+        Table::new(14, |s| &mut s.replaced);
+}
+
+#[cfg(feature = "fs")]
+impl LogData for MemStash {
+    fn tables(&self) -> Vec<&dyn LogTable> {
+        vec![
+            &self.schemata,
+            &self.ifaces,
+            &self.geneses,
+            &self.suppl,
+            &self.bundles,
+            &self.extensions,
+            &self.witnesses,
+            &self.attachments,
+            &self.secret_seals,
+            &self.type_system,
+            &self.identities,
+            &self.libs,
+            &self.sigs,
+            &self.pruned,
+            &self.replaced,
+        ]
+    }
+
+    fn tables_mut(&mut self) -> Vec<&mut dyn LogTable> {
+        vec![
+            &mut self.schemata,
+            &mut self.ifaces,
+            &mut self.geneses,
+            &mut self.suppl,
+            &mut self.bundles,
+            &mut self.extensions,
+            &mut self.witnesses,
+            &mut self.attachments,
+            &mut self.secret_seals,
+            &mut self.type_system,
+            &mut self.identities,
+            &mut self.libs,
+            &mut self.sigs,
+            &mut self.pruned,
+            &mut self.replaced,
+        ]
+    }
+
+    fn dirty_rows(&self) -> &DirtyRows { &self.undo.dirty }
+}
+
 impl MemStash {
     pub fn in_memory() -> Self {
         Self {
             persistence: none!(),
+            undo: default!(),
             schemata: empty!(),
             ifaces: empty!(),
             geneses: empty!(),
@@ -124,6 +429,34 @@ impl MemStash {
             identities: empty!(),
             libs: empty!(),
             sigs: empty!(),
+            pruned: empty!(),
+            replaced: empty!(),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl MemStash {
+    /// Removes geneses, extensions, bundles and witnesses with the given ids.
+    pub(super) fn remove_operations(
+        &mut self,
+        geneses: &BTreeSet<ContractId>,
+        extensions: &BTreeSet<OpId>,
+        bundles: &BTreeSet<BundleId>,
+        witnesses: &BTreeSet<XWitnessId>,
+    ) {
+        const ERR: &str = "collections have no lower bound";
+        for id in geneses {
+            self.geneses.remove(id).expect(ERR);
+        }
+        for id in extensions {
+            self.extensions.remove(id).expect(ERR);
+        }
+        for id in bundles {
+            self.bundles.remove(id).expect(ERR);
+        }
+        for id in witnesses {
+            self.witnesses.remove(id).expect(ERR);
         }
     }
 }
@@ -132,6 +465,7 @@ impl CloneNoPersistence for MemStash {
     fn clone_no_persistence(&self) -> Self {
         Self {
             persistence: None,
+            undo: default!(),
             schemata: self.schemata.clone(),
             ifaces: self.ifaces.clone(),
             geneses: self.geneses.clone(),
@@ -145,6 +479,8 @@ impl CloneNoPersistence for MemStash {
             identities: self.identities.clone(),
             libs: self.libs.clone(),
             sigs: self.sigs.clone(),
+            pruned: self.pruned.clone(),
+            replaced: self.replaced.clone(),
         }
     }
 }
@@ -158,17 +494,34 @@ impl Persisting for MemStash {
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
 }
 
+impl MemUndo for MemStash {
+    #[inline]
+    fn undo_log(&mut self) -> &mut UndoLog<Self> { &mut self.undo }
+}
+
 impl StoreTransaction for MemStash {
     type TransactionErr = MemError;
     #[inline]
     fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> {
-        self.mark_dirty();
+        self.begin_undo();
         Ok(())
     }
     #[inline]
-    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(self.store()?) }
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        if !self.undo.is_nested() {
+            self.precommit_transaction()?;
+        }
+        self.complete_transaction()
+    }
+    #[inline]
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(self.store()?) }
     #[inline]
-    fn rollback_transaction(&mut self) { unreachable!() }
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.complete_undo();
+        Ok(())
+    }
+    #[inline]
+    fn rollback_transaction(&mut self) { self.rollback_undo() }
 }
 
 impl StashProvider for MemStash {}
@@ -246,6 +599,13 @@ impl StashReadProvider for MemStash {
         Ok(self.identities.get(identity).copied().unwrap_or_default())
     }
 
+    fn identities(&self) -> Result<impl Iterator<Item = (Identity, TrustLevel)>, Self::Error> {
+        Ok(self
+            .identities
+            .iter()
+            .map(|(identity, trust)| (identity.clone(), *trust)))
+    }
+
     fn supplement(&self, content_ref: ContentRef) -> Result<Option<&Supplement>, Self::Error> {
         Ok(self.suppl.get(&content_ref).and_then(|s| s.first()))
     }
@@ -314,6 +674,14 @@ impl StashReadProvider for MemStash {
             .ok_or(StashInconsistency::OperationAbsent(op_id).into())
     }
 
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        Ok(self.attachments.keys().copied())
+    }
+
+    fn attachment(&self, id: AttachId) -> Result<Option<&MediumBlob>, Self::Error> {
+        Ok(self.attachments.get(&id))
+    }
+
     fn witness(
         &self,
         witness_id: XWitnessId,
@@ -324,20 +692,9 @@ impl StashReadProvider for MemStash {
     }
 
     fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
-        Ok(self
-            .witnesses
-            .iter()
-            .filter_map(|(witness_id, witness)| match &witness.anchors {
-                AnchorSet::Tapret(anchor)
-                | AnchorSet::Double {
-                    tapret: anchor,
-                    opret: _,
-                } => Some((*witness_id, TapretCommitment {
-                    mpc: anchor.mpc_proof.commit_id(),
-                    nonce: anchor.dbc_proof.path_proof.nonce(),
-                })),
-                _ => None,
-            }))
+        Ok(self.witnesses.iter().filter_map(|(witness_id, witness)| {
+            witness_tapret(witness).map(|tapret| (*witness_id, tapret))
+        }))
     }
 
     fn seal_secret(
@@ -354,6 +711,34 @@ impl StashReadProvider for MemStash {
     fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
         Ok(self.secret_seals.iter().copied())
     }
+
+    fn prune_checkpoint(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&PruneCheckpoint>, Self::Error> {
+        Ok(self.pruned.get(&contract_id))
+    }
+
+    fn witness_replacements(
+        &self,
+    ) -> Result<impl Iterator<Item = (XWitnessId, XWitnessId)>, Self::Error> {
+        Ok(self.replaced.iter().map(|(replaced, by)| (*replaced, *by)))
+    }
+}
+
+/// Returns tapret commitment of the `witness`, if it is anchored with tapret.
+pub(super) fn witness_tapret(witness: &SealWitness) -> Option<TapretCommitment> {
+    match &witness.anchors {
+        AnchorSet::Tapret(anchor)
+        | AnchorSet::Double {
+            tapret: anchor,
+            opret: _,
+        } => Some(TapretCommitment {
+            mpc: anchor.mpc_proof.commit_id(),
+            nonce: anchor.dbc_proof.path_proof.nonce(),
+        }),
+        _ => None,
+    }
 }
 
 impl StashWriteProvider for MemStash {
@@ -362,6 +747,7 @@ impl StashWriteProvider for MemStash {
     fn replace_schema(&mut self, schema: Schema) -> Result<bool, Self::Error> {
         let schema_id = schema.schema_id();
         if !self.schemata.contains_key(&schema_id) {
+            self.undo.save_row(Self::SCHEMATA, schema_id, None);
             self.schemata.insert(schema_id, SchemaIfaces::new(schema))?;
             return Ok(true);
         }
@@ -371,6 +757,7 @@ impl StashWriteProvider for MemStash {
     fn replace_iface(&mut self, iface: Iface) -> Result<bool, Self::Error> {
         let iface_id = iface.iface_id();
         if !self.ifaces.contains_key(&iface_id) {
+            self.undo.save_row(Self::IFACES, iface_id, None);
             self.ifaces.insert(iface_id, iface)?;
             return Ok(true);
         }
@@ -378,6 +765,8 @@ impl StashWriteProvider for MemStash {
     }
 
     fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::SCHEMATA, iimpl.schema_id, self.schemata.get(&iimpl.schema_id));
         let schema_ifaces = self
             .schemata
             .get_mut(&iimpl.schema_id)
@@ -389,16 +778,16 @@ impl StashWriteProvider for MemStash {
         Ok(!present)
     }
 
-    fn set_trust(
-        &mut self,
-        identity: Identity,
-        trust: TrustLevel,
-    ) -> Result<(), confinement::Error> {
+    fn set_trust(&mut self, identity: Identity, trust: TrustLevel) -> Result<(), Self::Error> {
+        self.undo
+            .save_row(Self::IDENTITIES, identity.clone(), self.identities.get(&identity));
         self.identities.insert(identity, trust)?;
         Ok(())
     }
 
     fn add_supplement(&mut self, suppl: Supplement) -> Result<(), Self::Error> {
+        self.undo
+            .save_row(Self::SUPPL, suppl.content_id, self.suppl.get(&suppl.content_id));
         match self.suppl.get_mut(&suppl.content_id) {
             None => {
                 self.suppl.insert(suppl.content_id, tiny_bset![suppl])?;
@@ -410,24 +799,32 @@ impl StashWriteProvider for MemStash {
 
     fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, Self::Error> {
         let contract_id = genesis.contract_id();
+        self.undo
+            .save_row(Self::GENESES, contract_id, self.geneses.get(&contract_id));
         let present = self.geneses.insert(contract_id, genesis)?.is_some();
         Ok(!present)
     }
 
     fn replace_extension(&mut self, extension: Extension) -> Result<bool, Self::Error> {
         let opid = extension.id();
+        self.undo
+            .save_row(Self::EXTENSIONS, opid, self.extensions.get(&opid));
         let present = self.extensions.insert(opid, extension)?.is_some();
         Ok(!present)
     }
 
     fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, Self::Error> {
         let bundle_id = bundle.bundle_id();
+        self.undo
+            .save_row(Self::BUNDLES, bundle_id, self.bundles.get(&bundle_id));
         let present = self.bundles.insert(bundle_id, bundle)?.is_some();
         Ok(!present)
     }
 
     fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, Self::Error> {
         let witness_id = witness.witness_id();
+        self.undo
+            .save_row(Self::WITNESSES, witness_id, self.witnesses.get(&witness_id));
         let present = self.witnesses.insert(witness_id, witness)?.is_some();
         Ok(!present)
     }
@@ -437,21 +834,34 @@ impl StashWriteProvider for MemStash {
         id: AttachId,
         attach: MediumBlob,
     ) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::ATTACHMENTS, id, self.attachments.get(&id));
         let present = self.attachments.insert(id, attach)?.is_some();
         Ok(!present)
     }
 
     fn consume_types(&mut self, types: TypeSystem) -> Result<(), Self::Error> {
+        self.undo.save_field(Self::TYPE_SYSTEM, &self.type_system);
         Ok(self.type_system.extend(types)?)
     }
 
     fn replace_lib(&mut self, lib: Lib) -> Result<bool, Self::Error> {
-        let present = self.libs.insert(lib.id(), lib)?.is_some();
+        let id = lib.id();
+        self.undo.save_row(Self::LIBS, id, self.libs.get(&id));
+        let present = self.libs.insert(id, lib)?.is_some();
         Ok(!present)
     }
 
     fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), Self::Error>
     where I: IntoIterator<Item = (Identity, SigBlob)> {
+        let sigs = sigs.into_iter().collect::<Vec<_>>();
+        for (id, _) in &sigs {
+            if !self.identities.contains_key(id) {
+                self.undo.save_row(Self::IDENTITIES, id.clone(), None);
+            }
+        }
+        self.undo
+            .save_row(Self::SIGS, content_id, self.sigs.get(&content_id));
         let sigs = sigs.into_iter().filter(|(id, _)| {
             match self.identities.get(id) {
                 Some(level) => *level,
@@ -475,9 +885,87 @@ impl StashWriteProvider for MemStash {
 
     fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
         let present = self.secret_seals.contains(&seal);
+        self.undo.save_member(Self::SECRET_SEALS, seal, present);
         self.secret_seals.push(seal)?;
         Ok(!present)
     }
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::PRUNED, contract_id, self.pruned.get(&contract_id));
+        self.undo
+            .save_row(Self::GENESES, contract_id, self.geneses.get(&contract_id));
+        self.pruned.remove(&contract_id)?;
+        Ok(self.geneses.remove(&contract_id)?.is_some())
+    }
+
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::EXTENSIONS, opid, self.extensions.get(&opid));
+        Ok(self.extensions.remove(&opid)?.is_some())
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::BUNDLES, bundle_id, self.bundles.get(&bundle_id));
+        Ok(self.bundles.remove(&bundle_id)?.is_some())
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::REPLACED, witness_id, self.replaced.get(&witness_id));
+        self.undo
+            .save_row(Self::WITNESSES, witness_id, self.witnesses.get(&witness_id));
+        self.replaced.remove(&witness_id)?;
+        Ok(self.witnesses.remove(&witness_id)?.is_some())
+    }
+
+    fn remove_sigs(&mut self, content_id: ContentId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::SIGS, content_id, self.sigs.get(&content_id));
+        Ok(self.sigs.remove(&content_id)?.is_some())
+    }
+
+    fn remove_supplements(&mut self, content_ref: ContentRef) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::SUPPL, content_ref, self.suppl.get(&content_ref));
+        Ok(self.suppl.remove(&content_ref)?.is_some())
+    }
+
+    fn remove_attachment(&mut self, id: AttachId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::ATTACHMENTS, id, self.attachments.get(&id));
+        Ok(self.attachments.remove(&id)?.is_some())
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        let present = self.secret_seals.contains(&seal);
+        self.undo
+            .save_removed_member(Self::SECRET_SEALS, seal, present);
+        Ok(self.secret_seals.remove(&seal)?)
+    }
+
+    fn set_prune_checkpoint(
+        &mut self,
+        contract_id: ContractId,
+        checkpoint: PruneCheckpoint,
+    ) -> Result<(), Self::Error> {
+        self.undo
+            .save_row(Self::PRUNED, contract_id, self.pruned.get(&contract_id));
+        self.pruned.insert(contract_id, checkpoint)?;
+        Ok(())
+    }
+
+    fn set_witness_replacement(
+        &mut self,
+        replaced: XWitnessId,
+        replacement: XWitnessId,
+    ) -> Result<(), Self::Error> {
+        self.undo
+            .save_row(Self::REPLACED, replaced, self.replaced.get(&replaced));
+        self.replaced.insert(replaced, replacement)?;
+        Ok(())
+    }
 }
 
 //////////
@@ -493,27 +981,76 @@ pub struct MemState {
     #[strict_type(skip)]
     persistence: Option<Persistence<Self>>,
 
+    #[getter(skip)]
+    #[strict_type(skip)]
+    undo: UndoLog<Self>,
+
     witnesses: LargeOrdMap<XWitnessId, WitnessOrd>,
-    contracts: TinyOrdMap<ContractId, MemContractState>,
+    contracts: MediumOrdMap<ContractId, MemContractState>,
 }
 
 impl StrictSerialize for MemState {}
 impl StrictDeserialize for MemState {}
 
+impl MemState {
+    const WITNESSES: Table<Self, LargeOrdMap<XWitnessId, WitnessOrd>> =
+        Table::new(0, |s| &mut s.witnesses);
+    const CONTRACTS: Table<Self, MediumOrdMap<ContractId, MemContractState>> =
+        Table::new(1, |s| &mut s.contracts);
+}
+
+#[cfg(feature = "fs")]
+impl LogData for MemState {
+    fn tables(&self) -> Vec<&dyn LogTable> { vec![&self.witnesses, &self.contracts] }
+
+    fn tables_mut(&mut self) -> Vec<&mut dyn LogTable> {
+        vec![&mut self.witnesses, &mut self.contracts]
+    }
+
+    fn dirty_rows(&self) -> &DirtyRows { &self.undo.dirty }
+}
+
 impl MemState {
     pub fn in_memory() -> Self {
         Self {
             persistence: none!(),
+            undo: default!(),
             witnesses: empty!(),
             contracts: empty!(),
         }
     }
 }
 
+#[cfg(feature = "fs")]
+impl MemState {
+    pub(super) fn contract(&self, contract_id: ContractId) -> Option<&MemContractState> {
+        self.contracts.get(&contract_id)
+    }
+
+    pub(super) fn insert_contract(
+        &mut self,
+        contract: MemContractState,
+    ) -> Result<(), confinement::Error> {
+        self.contracts.insert(contract.contract_id, contract)?;
+        Ok(())
+    }
+
+    /// Clones the state leaving out per-contract data.
+    pub(super) fn clone_without_contracts(&self) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            witnesses: self.witnesses.clone(),
+            contracts: empty!(),
+        }
+    }
+}
+
 impl CloneNoPersistence for MemState {
     fn clone_no_persistence(&self) -> Self {
         Self {
             persistence: None,
+            undo: default!(),
             witnesses: self.witnesses.clone(),
             contracts: self.contracts.clone(),
         }
@@ -529,17 +1066,34 @@ impl Persisting for MemState {
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
 }
 
+impl MemUndo for MemState {
+    #[inline]
+    fn undo_log(&mut self) -> &mut UndoLog<Self> { &mut self.undo }
+}
+
 impl StoreTransaction for MemState {
     type TransactionErr = MemError;
     #[inline]
     fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> {
-        self.mark_dirty();
+        self.begin_undo();
         Ok(())
     }
     #[inline]
-    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(self.store()?) }
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        if !self.undo.is_nested() {
+            self.precommit_transaction()?;
+        }
+        self.complete_transaction()
+    }
+    #[inline]
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(self.store()?) }
+    #[inline]
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.complete_undo();
+        Ok(())
+    }
     #[inline]
-    fn rollback_transaction(&mut self) { unreachable!() }
+    fn rollback_transaction(&mut self) { self.rollback_undo() }
 }
 
 impl StateProvider for MemState {}
@@ -556,9 +1110,72 @@ impl StateReadProvider for MemState {
             .contracts
             .get(&contract_id)
             .ok_or(StateInconsistency::UnknownContract(contract_id))?;
+        Ok(self.contract_read(unfiltered))
+    }
+
+    fn contract_state_at(
+        &self,
+        contract_id: ContractId,
+        layer1: Layer1,
+        height: u32,
+    ) -> Result<Self::ContractRead<'_>, Self::Error> {
+        let unfiltered = self
+            .contracts
+            .get(&contract_id)
+            .ok_or(StateInconsistency::UnknownContract(contract_id))?;
+        Ok(self.contract_read_at(unfiltered, layer1, height))
+    }
+
+    fn is_valid_witness(&self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        let ord = self
+            .witnesses
+            .get(&witness_id)
+            .ok_or(StateInconsistency::AbsentWitness(witness_id))?;
+        Ok(ord.is_valid())
+    }
+
+    fn witness_ord(&self, witness_id: XWitnessId) -> Result<WitnessOrd, Self::Error> {
+        self.witnesses
+            .get(&witness_id)
+            .copied()
+            .ok_or(StateInconsistency::AbsentWitness(witness_id))
+    }
+}
+
+impl MemState {
+    /// Constructs contract state filtered by validity of the witnesses known
+    /// to this state object.
+    pub(super) fn contract_read<'a>(
+        &self,
+        unfiltered: &'a MemContractState,
+    ) -> MemContract<&'a MemContractState> {
+        self.contract_read_where(unfiltered, |_| true)
+    }
+
+    /// Constructs contract state as of the given block height of the `layer1`
+    /// blockchain, including only operations with witnesses mined on that
+    /// blockchain at or below that height.
+    pub(super) fn contract_read_at<'a>(
+        &self,
+        unfiltered: &'a MemContractState,
+        layer1: Layer1,
+        height: u32,
+    ) -> MemContract<&'a MemContractState> {
+        self.contract_read_where(unfiltered, |ord| {
+            matches!(ord, WitnessOrd::Mined(pos)
+                if pos.layer1() == layer1 && pos.height().get() <= height)
+        })
+    }
+
+    fn contract_read_where<'a>(
+        &self,
+        unfiltered: &'a MemContractState,
+        predicate: impl Fn(WitnessOrd) -> bool,
+    ) -> MemContract<&'a MemContractState> {
         let filter = self
             .witnesses
             .iter()
+            .filter(|(_, ord)| predicate(**ord))
             .filter(|(id, _)| {
                 let id = Some(**id);
                 unfiltered
@@ -573,15 +1190,7 @@ impl StateReadProvider for MemState {
             })
             .map(|(id, ord)| (*id, *ord))
             .collect();
-        Ok(MemContract { filter, unfiltered })
-    }
-
-    fn is_valid_witness(&self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
-        let ord = self
-            .witnesses
-            .get(&witness_id)
-            .ok_or(StateInconsistency::AbsentWitness(witness_id))?;
-        Ok(ord.is_valid())
+        MemContract { filter, unfiltered }
     }
 }
 
@@ -594,8 +1203,9 @@ impl StateWriteProvider for MemState {
         schema: &Schema,
         genesis: &Genesis,
     ) -> Result<Self::ContractWrite<'_>, Self::Error> {
-        // TODO: Add begin/commit transaction
         let contract_id = genesis.contract_id();
+        self.undo
+            .save_row(Self::CONTRACTS, contract_id, self.contracts.get(&contract_id));
         // This crazy construction is caused by a stupidity of rust borrow checker
         let contract = if self.contracts.contains_key(&contract_id) {
             if let Some(contract) = self.contracts.get_mut(&contract_id) {
@@ -613,6 +1223,11 @@ impl StateWriteProvider for MemState {
                 |witness_id: XWitnessId, ord: WitnessOrd| -> Result<(), confinement::Error> {
                     // NB: We do not check the existence of the witness since we have a newer
                     // version anyway and even if it is known we have to replace it
+                    self.undo.save_row(
+                        Self::WITNESSES,
+                        witness_id,
+                        self.witnesses.get(&witness_id),
+                    );
                     self.witnesses.insert(witness_id, ord)?;
                     Ok(())
                 },
@@ -627,7 +1242,8 @@ impl StateWriteProvider for MemState {
         &mut self,
         contract_id: ContractId,
     ) -> Result<Option<Self::ContractWrite<'_>>, Self::Error> {
-        // TODO: Add begin/commit transaction
+        self.undo
+            .save_row(Self::CONTRACTS, contract_id, self.contracts.get(&contract_id));
         Ok(self
             .contracts
             .get_mut(&contract_id)
@@ -639,6 +1255,11 @@ impl StateWriteProvider for MemState {
                         // NB: We do not check the existence of the witness since we have a newer
                         // version anyway and even if it is known we have to replace
                         // it
+                        self.undo.save_row(
+                            Self::WITNESSES,
+                            witness_id,
+                            self.witnesses.get(&witness_id),
+                        );
                         self.witnesses.insert(witness_id, ord)?;
                         Ok(())
                     },
@@ -649,33 +1270,88 @@ impl StateWriteProvider for MemState {
 
     fn update_witnesses(
         &mut self,
-        resolver: impl ResolveWitness,
+        resolver: impl ResolveWitnessBatch,
         after_height: u32,
+        progress: impl FnMut(usize, usize),
     ) -> Result<UpdateRes, Self::Error> {
-        let after_height = NonZeroU32::new(after_height).unwrap_or(NonZeroU32::MIN);
-        let mut succeeded = 0;
-        let mut failed = map![];
+        let res = resolve_witness_ords(
+            self.witnesses.iter().map(|(id, ord)| (*id, *ord)),
+            resolver,
+            after_height,
+            progress,
+        );
         self.begin_transaction()?;
-        let mut witnesses = LargeOrdMap::new();
-        mem::swap(&mut self.witnesses, &mut witnesses);
-        let mut witnesses = witnesses.release();
-        for (id, ord) in &mut witnesses {
-            if matches!(ord, WitnessOrd::Mined(pos) if pos.height() < after_height) {
-                continue;
-            }
-            match resolver.resolve_pub_witness_ord(*id) {
-                Ok(new) => *ord = new,
-                Err(err) => {
+        for (id, update) in &res.updated {
+            self.undo
+                .save_row(Self::WITNESSES, *id, self.witnesses.get(id));
+            self.witnesses
+                .insert(*id, update.to)
+                .inspect_err(|_| self.rollback_transaction())?;
+        }
+        self.commit_transaction()?;
+        Ok(res)
+    }
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::CONTRACTS, contract_id, self.contracts.get(&contract_id));
+        Ok(self.contracts.remove(&contract_id)?.is_some())
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        self.undo
+            .save_row(Self::WITNESSES, witness_id, self.witnesses.get(&witness_id));
+        Ok(self.witnesses.remove(&witness_id)?.is_some())
+    }
+}
+
+/// Resolves ordering of the `witnesses` which are not mined below
+/// `after_height`, returning the witnesses whose ordering has changed in
+/// [`UpdateRes::updated`].
+pub(super) fn resolve_witness_ords(
+    witnesses: impl IntoIterator<Item = (XWitnessId, WitnessOrd)>,
+    resolver: impl ResolveWitnessBatch,
+    after_height: u32,
+    mut progress: impl FnMut(usize, usize),
+) -> UpdateRes {
+    let after_height = NonZeroU32::new(after_height).unwrap_or(NonZeroU32::MIN);
+    let mut succeeded = 0;
+    let mut failed = map![];
+    let mut updated = bmap![];
+    let pending = witnesses
+        .into_iter()
+        .filter(|(_, ord)| !matches!(ord, WitnessOrd::Mined(pos) if pos.height() < after_height))
+        .collect::<Vec<_>>();
+    let total = pending.len();
+    let mut done = 0;
+    for batch in pending.chunks(resolver.batch_size().max(1)) {
+        let ids = batch.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        let mut ords = resolver.resolve_pub_witness_ords(&ids).into_iter();
+        for (id, from) in batch {
+            match ords.next() {
+                Some(Ok(to)) => {
+                    if to != *from {
+                        updated.insert(*id, OrdUpdate { from: *from, to });
+                    }
+                    succeeded += 1;
+                }
+                Some(Err(err)) => {
                     failed.insert(*id, err.to_string());
                 }
+                None => {
+                    failed.insert(*id, s!("witness is not resolved by the batch resolver"));
+                }
             }
-            succeeded += 1;
         }
-        let mut witnesses =
-            LargeOrdMap::try_from(witnesses).inspect_err(|_| self.rollback_transaction())?;
-        mem::swap(&mut self.witnesses, &mut witnesses);
-        self.commit_transaction()?;
-        Ok(UpdateRes { succeeded, failed })
+        done += batch.len();
+        progress(done, total);
+    }
+
+    UpdateRes {
+        succeeded,
+        failed,
+        updated,
+        ..default!()
     }
 }
 
@@ -727,6 +1403,23 @@ pub struct MemContractState {
     attach: LargeOrdSet<OutputAssignment<RevealedAttach>>,
 }
 
+#[cfg(any(feature = "fs", feature = "sqlite"))]
+impl MemContractState {
+    /// Returns ids of the witnesses of the operations contributing to the
+    /// contract state.
+    pub(super) fn witness_ids(&self) -> BTreeSet<XWitnessId> {
+        self.global
+            .values()
+            .flat_map(|state| state.known.keys())
+            .filter_map(GlobalOut::witness_id)
+            .chain(self.rights.iter().filter_map(|a| a.witness))
+            .chain(self.fungibles.iter().filter_map(|a| a.witness))
+            .chain(self.data.iter().filter_map(|a| a.witness))
+            .chain(self.attach.iter().filter_map(|a| a.witness))
+            .collect()
+    }
+}
+
 impl MemContractState {
     pub fn new(schema: &Schema, contract_id: ContractId) -> Self {
         let global = TinyOrdMap::from_iter_checked(
@@ -865,6 +1558,50 @@ pub struct MemContract<M: Borrow<MemContractState> = MemContractState> {
     unfiltered: M,
 }
 
+#[cfg(feature = "sqlite")]
+impl<'a> MemContract<&'a MemContractState> {
+    /// Constructs contract state filtered by the ordering of its witnesses
+    /// returned by `witness_ord`, including only the witnesses with ordering
+    /// matching the `predicate`.
+    pub(super) fn filtered<E>(
+        unfiltered: &'a MemContractState,
+        witness_ord: impl Fn(XWitnessId) -> Result<Option<WitnessOrd>, E>,
+        predicate: impl Fn(WitnessOrd) -> bool,
+    ) -> Result<Self, E> {
+        let mut filter = HashMap::new();
+        for id in unfiltered.witness_ids() {
+            match witness_ord(id)? {
+                Some(ord) if predicate(ord) => {
+                    filter.insert(id, ord);
+                }
+                _ => {}
+            }
+        }
+        Ok(MemContract { filter, unfiltered })
+    }
+}
+
+impl MemContract {
+    /// Constructs contract state defined only by the `transitions`, without
+    /// the genesis and the rest of the contract history. Transitions with an
+    /// archived witness don't contribute to the state.
+    pub(super) fn with_transitions<'op>(
+        schema: &Schema,
+        contract_id: ContractId,
+        transitions: impl IntoIterator<Item = (&'op Transition, XWitnessId, WitnessOrd)>,
+    ) -> Self {
+        let mut unfiltered = MemContractState::new(schema, contract_id);
+        let mut filter = HashMap::new();
+        for (transition, witness_id, ord) in transitions {
+            if ord.is_valid() {
+                filter.insert(witness_id, ord);
+                unfiltered.add_operation(OrdOpRef::Transition(transition, witness_id, ord));
+            }
+        }
+        MemContract { filter, unfiltered }
+    }
+}
+
 impl<M: Borrow<MemContractState>> Debug for MemContract<M> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("MemContractFiltered { .. }")
@@ -1036,7 +1773,7 @@ impl ContractStateEvolve for MemContract<MemContractState> {
     }
 
     fn evolve_state(&mut self, op: OrdOpRef) -> Result<(), confinement::Error> {
-        fn writer(me: &mut MemContract<MemContractState>) -> MemContractWriter {
+        fn writer(me: &mut MemContract<MemContractState>) -> MemContractWriter<'_> {
             MemContractWriter {
                 writer: Box::new(
                     |witness_id: XWitnessId, ord: WitnessOrd| -> Result<(), confinement::Error> {
@@ -1129,6 +1866,21 @@ pub struct MemContractWriter<'mem> {
     contract: &'mem mut MemContractState,
 }
 
+#[cfg(feature = "sqlite")]
+impl<'mem> MemContractWriter<'mem> {
+    /// Constructs writer of the `contract` state, which calls `writer` for
+    /// each witness of the added operations.
+    pub(super) fn new(
+        writer: impl FnMut(XWitnessId, WitnessOrd) -> Result<(), confinement::Error> + 'mem,
+        contract: &'mem mut MemContractState,
+    ) -> Self {
+        Self {
+            writer: Box::new(writer),
+            contract,
+        }
+    }
+}
+
 impl ContractStateWrite for MemContractWriter<'_> {
     type Error = MemError;
 
@@ -1141,6 +1893,9 @@ impl ContractStateWrite for MemContractWriter<'_> {
         Ok(())
     }
 
+    /// Operations with an archived witness are registered only by their
+    /// witness, without contributing to the contract state.
+    ///
     /// # Panics
     ///
     /// If state transition violates RGB consensus rules and wasn't checked
@@ -1152,11 +1907,16 @@ impl ContractStateWrite for MemContractWriter<'_> {
         ord: WitnessOrd,
     ) -> Result<(), Self::Error> {
         (self.writer)(witness_id, ord)?;
-        self.contract
-            .add_operation(OrdOpRef::Transition(transition, witness_id, ord));
+        if ord.is_valid() {
+            self.contract
+                .add_operation(OrdOpRef::Transition(transition, witness_id, ord));
+        }
         Ok(())
     }
 
+    /// Operations with an archived witness are registered only by their
+    /// witness, without contributing to the contract state.
+    ///
     /// # Panics
     ///
     /// If state extension violates RGB consensus rules and wasn't checked
@@ -1168,8 +1928,10 @@ impl ContractStateWrite for MemContractWriter<'_> {
         ord: WitnessOrd,
     ) -> Result<(), Self::Error> {
         (self.writer)(witness_id, ord)?;
-        self.contract
-            .add_operation(OrdOpRef::Extension(extension, witness_id, ord));
+        if ord.is_valid() {
+            self.contract
+                .add_operation(OrdOpRef::Extension(extension, witness_id, ord));
+        }
         Ok(())
     }
 }
@@ -1186,7 +1948,7 @@ impl From<confinement::Error> for IndexWriteError<confinement::Error> {
     fn from(err: confinement::Error) -> Self { IndexWriteError::Connectivity(err) }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
 #[derive(StrictType, StrictEncode, StrictDecode)]
 #[strict_type(lib = LIB_NAME_RGB_STORAGE)]
 #[cfg_attr(
@@ -1199,6 +1961,34 @@ pub struct ContractIndex {
     outpoint_opouts: MediumOrdMap<XOutputSeal, MediumOrdSet<Opout>>,
 }
 
+impl ContractIndex {
+    pub(super) fn assigns(&self, outpoint: XOutpoint) -> bool {
+        self.outpoint_opouts
+            .keys()
+            .any(|seal| seal.to_outpoint() == outpoint)
+    }
+
+    pub(super) fn public_opouts(&self) -> BTreeSet<Opout> { self.public_opouts.to_unconfined() }
+
+    pub(super) fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outpoints: impl IntoIterator<Item = impl Into<XOutpoint>>,
+    ) -> Result<BTreeSet<Opout>, IndexInconsistency> {
+        let mut opouts = BTreeSet::new();
+        for output in outpoints.into_iter().map(|o| o.into()) {
+            let set = self
+                .outpoint_opouts
+                .iter()
+                .find(|(seal, _)| seal.to_outpoint() == output)
+                .map(|(_, set)| set.to_unconfined())
+                .ok_or(IndexInconsistency::OutpointUnknown(output, contract_id))?;
+            opouts.extend(set)
+        }
+        Ok(opouts)
+    }
+}
+
 #[derive(Getters, Debug)]
 #[getter(prefix = "debug_")]
 #[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
@@ -1208,20 +1998,63 @@ pub struct MemIndex {
     #[strict_type(skip)]
     persistence: Option<Persistence<Self>>,
 
+    #[getter(skip)]
+    #[strict_type(skip)]
+    undo: UndoLog<Self>,
+
     op_bundle_index: MediumOrdMap<OpId, BundleId>,
     bundle_contract_index: MediumOrdMap<BundleId, ContractId>,
     bundle_witness_index: MediumOrdMap<BundleId, TinyOrdSet<XWitnessId>>,
-    contract_index: TinyOrdMap<ContractId, ContractIndex>,
+    contract_index: MediumOrdMap<ContractId, ContractIndex>,
     terminal_index: MediumOrdMap<XChain<SecretSeal>, TinyOrdSet<Opout>>,
 }
 
 impl StrictSerialize for MemIndex {}
 impl StrictDeserialize for MemIndex {}
 
+impl MemIndex {
+    const OP_BUNDLE_INDEX: Table<Self, MediumOrdMap<OpId, BundleId>> =
+        Table::new(0, |s| &mut s.op_bundle_index);
+    const BUNDLE_CONTRACT_INDEX: Table<Self, MediumOrdMap<BundleId, ContractId>> =
+        Table::new(1, |s| &mut s.bundle_contract_index);
+    const BUNDLE_WITNESS_INDEX: Table<Self, MediumOrdMap<BundleId, TinyOrdSet<XWitnessId>>> =
+        Table::new(2, |s| &mut s.bundle_witness_index);
+    const CONTRACT_INDEX: Table<Self, MediumOrdMap<ContractId, ContractIndex>> =
+        Table::new(3, |s| &mut s.contract_index);
+    const TERMINAL_INDEX: Table<Self, MediumOrdMap<XChain<SecretSeal>, TinyOrdSet<Opout>>> =
+        Table::new(4, |s| &mut s.terminal_index);
+}
+
+#[cfg(feature = "fs")]
+impl LogData for MemIndex {
+    fn tables(&self) -> Vec<&dyn LogTable> {
+        vec![
+            &self.op_bundle_index,
+            &self.bundle_contract_index,
+            &self.bundle_witness_index,
+            &self.contract_index,
+            &self.terminal_index,
+        ]
+    }
+
+    fn tables_mut(&mut self) -> Vec<&mut dyn LogTable> {
+        vec![
+            &mut self.op_bundle_index,
+            &mut self.bundle_contract_index,
+            &mut self.bundle_witness_index,
+            &mut self.contract_index,
+            &mut self.terminal_index,
+        ]
+    }
+
+    fn dirty_rows(&self) -> &DirtyRows { &self.undo.dirty }
+}
+
 impl MemIndex {
     pub fn in_memory() -> Self {
         Self {
             persistence: None,
+            undo: default!(),
             op_bundle_index: empty!(),
             bundle_contract_index: empty!(),
             bundle_witness_index: empty!(),
@@ -1231,10 +2064,40 @@ impl MemIndex {
     }
 }
 
+#[cfg(feature = "fs")]
+impl MemIndex {
+    pub(super) fn contract_index(&self, contract_id: ContractId) -> Option<&ContractIndex> {
+        self.contract_index.get(&contract_id)
+    }
+
+    pub(super) fn insert_contract_index(
+        &mut self,
+        contract_id: ContractId,
+        index: ContractIndex,
+    ) -> Result<(), confinement::Error> {
+        self.contract_index.insert(contract_id, index)?;
+        Ok(())
+    }
+
+    /// Clones the index leaving out per-contract data.
+    pub(super) fn clone_without_contracts(&self) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            op_bundle_index: self.op_bundle_index.clone(),
+            bundle_contract_index: self.bundle_contract_index.clone(),
+            bundle_witness_index: self.bundle_witness_index.clone(),
+            contract_index: empty!(),
+            terminal_index: self.terminal_index.clone(),
+        }
+    }
+}
+
 impl CloneNoPersistence for MemIndex {
     fn clone_no_persistence(&self) -> Self {
         Self {
             persistence: None,
+            undo: default!(),
             op_bundle_index: self.op_bundle_index.clone(),
             bundle_contract_index: self.bundle_contract_index.clone(),
             bundle_witness_index: self.bundle_witness_index.clone(),
@@ -1253,17 +2116,34 @@ impl Persisting for MemIndex {
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
 }
 
+impl MemUndo for MemIndex {
+    #[inline]
+    fn undo_log(&mut self) -> &mut UndoLog<Self> { &mut self.undo }
+}
+
 impl StoreTransaction for MemIndex {
     type TransactionErr = MemError;
     #[inline]
     fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> {
-        self.mark_dirty();
+        self.begin_undo();
         Ok(())
     }
     #[inline]
-    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(self.store()?) }
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        if !self.undo.is_nested() {
+            self.precommit_transaction()?;
+        }
+        self.complete_transaction()
+    }
+    #[inline]
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(self.store()?) }
+    #[inline]
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.complete_undo();
+        Ok(())
+    }
     #[inline]
-    fn rollback_transaction(&mut self) { unreachable!() }
+    fn rollback_transaction(&mut self) { self.rollback_undo() }
 }
 
 impl IndexProvider for MemIndex {}
@@ -1280,11 +2160,7 @@ impl IndexReadProvider for MemIndex {
             .iter()
             .flat_map(move |(contract_id, index)| {
                 outpoints.clone().into_iter().filter_map(|outpoint| {
-                    if index
-                        .outpoint_opouts
-                        .keys()
-                        .any(|seal| seal.to_outpoint() == outpoint)
-                    {
+                    if index.assigns(outpoint) {
                         Some(*contract_id)
                     } else {
                         None
@@ -1301,7 +2177,7 @@ impl IndexReadProvider for MemIndex {
             .contract_index
             .get(&contract_id)
             .ok_or(IndexInconsistency::ContractAbsent(contract_id))?;
-        Ok(index.public_opouts.to_unconfined())
+        Ok(index.public_opouts())
     }
 
     fn opouts_by_outputs(
@@ -1313,17 +2189,7 @@ impl IndexReadProvider for MemIndex {
             .contract_index
             .get(&contract_id)
             .ok_or(IndexInconsistency::ContractAbsent(contract_id))?;
-        let mut opouts = BTreeSet::new();
-        for output in outpoints.into_iter().map(|o| o.into()) {
-            let set = index
-                .outpoint_opouts
-                .iter()
-                .find(|(seal, _)| seal.to_outpoint() == output)
-                .map(|(_, set)| set.to_unconfined())
-                .ok_or(IndexInconsistency::OutpointUnknown(output, contract_id))?;
-            opouts.extend(set)
-        }
-        Ok(opouts)
+        Ok(index.opouts_by_outputs(contract_id, outpoints)?)
     }
 
     fn opouts_by_terminals(
@@ -1361,6 +2227,13 @@ impl IndexReadProvider for MemIndex {
             .ok_or(IndexInconsistency::BundleContractUnknown(bundle_id))?;
         Ok((witness_id.iter().cloned(), *contract_id))
     }
+
+    fn indexed_ops(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        Ok(self
+            .op_bundle_index
+            .iter()
+            .map(|(opid, bundle_id)| (*opid, *bundle_id)))
+    }
 }
 
 impl IndexWriteProvider for MemIndex {
@@ -1368,6 +2241,7 @@ impl IndexWriteProvider for MemIndex {
 
     fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
         if !self.contract_index.contains_key(&contract_id) {
+            self.undo.save_row(Self::CONTRACT_INDEX, contract_id, None);
             self.contract_index.insert(contract_id, empty!())?;
             Ok(true)
         } else {
@@ -1393,6 +2267,16 @@ impl IndexWriteProvider for MemIndex {
             }
             .into());
         }
+        self.undo.save_row(
+            Self::BUNDLE_WITNESS_INDEX,
+            bundle_id,
+            self.bundle_witness_index.get(&bundle_id),
+        );
+        self.undo.save_row(
+            Self::BUNDLE_CONTRACT_INDEX,
+            bundle_id,
+            self.bundle_contract_index.get(&bundle_id),
+        );
         self.bundle_witness_index
             .entry(bundle_id)?
             .or_default()
@@ -1421,6 +2305,8 @@ impl IndexWriteProvider for MemIndex {
             }
             .into());
         }
+        self.undo
+            .save_row(Self::OP_BUNDLE_INDEX, opid, self.op_bundle_index.get(&opid));
         let present = self.op_bundle_index.insert(opid, bundle_id)?.is_some();
         Ok(!present)
     }
@@ -1432,6 +2318,11 @@ impl IndexWriteProvider for MemIndex {
         opid: OpId,
         type_id: AssignmentType,
     ) -> Result<(), IndexWriteError<Self::Error>> {
+        self.undo.save_row(
+            Self::CONTRACT_INDEX,
+            contract_id,
+            self.contract_index.get(&contract_id),
+        );
         let index = self
             .contract_index
             .get_mut(&contract_id)
@@ -1466,6 +2357,11 @@ impl IndexWriteProvider for MemIndex {
         type_id: AssignmentType,
         witness_id: XWitnessId,
     ) -> Result<(), IndexWriteError<Self::Error>> {
+        self.undo.save_row(
+            Self::CONTRACT_INDEX,
+            contract_id,
+            self.contract_index.get(&contract_id),
+        );
         let index = self
             .contract_index
             .get_mut(&contract_id)
@@ -1495,6 +2391,64 @@ impl IndexWriteProvider for MemIndex {
         // We need two cycles due to the borrow checker
         self.extend_terminals(vec, opid, type_id)
     }
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        self.undo.save_row(
+            Self::CONTRACT_INDEX,
+            contract_id,
+            self.contract_index.get(&contract_id),
+        );
+        Ok(self.contract_index.remove(&contract_id)?.is_some())
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        let opids = self
+            .op_bundle_index
+            .iter()
+            .filter(|(_, id)| **id == bundle_id)
+            .map(|(opid, _)| *opid)
+            .collect::<Vec<_>>();
+        for opid in opids {
+            self.undo
+                .save_row(Self::OP_BUNDLE_INDEX, opid, self.op_bundle_index.get(&opid));
+            self.op_bundle_index.remove(&opid)?;
+        }
+        self.undo.save_row(
+            Self::BUNDLE_WITNESS_INDEX,
+            bundle_id,
+            self.bundle_witness_index.get(&bundle_id),
+        );
+        self.undo.save_row(
+            Self::BUNDLE_CONTRACT_INDEX,
+            bundle_id,
+            self.bundle_contract_index.get(&bundle_id),
+        );
+        self.bundle_witness_index.remove(&bundle_id)?;
+        Ok(self.bundle_contract_index.remove(&bundle_id)?.is_some())
+    }
+
+    fn remove_terminals(&mut self, opids: &BTreeSet<OpId>) -> Result<(), Self::Error> {
+        let seals = self
+            .terminal_index
+            .iter()
+            .filter(|(_, opouts)| opouts.iter().any(|opout| opids.contains(&opout.op)))
+            .map(|(seal, _)| *seal)
+            .collect::<Vec<_>>();
+        for seal in seals {
+            self.undo
+                .save_row(Self::TERMINAL_INDEX, seal, self.terminal_index.get(&seal));
+            let opouts = self.terminal_index.remove(&seal)?.unwrap_or_default();
+            let opouts = opouts
+                .into_iter()
+                .filter(|opout| !opids.contains(&opout.op))
+                .collect::<BTreeSet<_>>();
+            if !opouts.is_empty() {
+                self.terminal_index
+                    .insert(seal, TinyOrdSet::from_checked(opouts))?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl MemIndex {
@@ -1520,6 +2474,8 @@ impl MemIndex {
         seal: XChain<SecretSeal>,
         opout: Opout,
     ) -> Result<(), IndexWriteError<MemError>> {
+        self.undo
+            .save_row(Self::TERMINAL_INDEX, seal, self.terminal_index.get(&seal));
         match self
             .terminal_index
             .remove(&seal)
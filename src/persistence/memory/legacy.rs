@@ -0,0 +1,232 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Data layout of in-memory providers used up to v0.11.0-beta.9, which were limited
+//! to 255 contracts, schemata and interfaces, and of the stash lacking pruning
+//! checkpoints and witness replacements. Kept for migration of the data stored
+//! with the previous versions.
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{
+    Collection, Confined, LargeOrdMap, MediumBlob, MediumOrdMap, MediumOrdSet, SmallOrdMap,
+    TinyOrdMap, TinyOrdSet,
+};
+use rgb::vm::WitnessOrd;
+use rgb::{
+    AttachId, BundleId, ContractId, Extension, Genesis, GraphSeal, Identity, OpId, Opout, SchemaId,
+    SecretSeal, TransitionBundle, XChain, XWitnessId,
+};
+use strict_encoding::StrictDeserialize;
+use strict_types::TypeSystem;
+
+use super::{ContractIndex, MemContractState, MemIndex, MemStash, MemState};
+use crate::containers::{ContentId, ContentRef, ContentSigs, SealWitness, Supplement, TrustLevel};
+use crate::interface::{Iface, IfaceId};
+use crate::persistence::{PruneCheckpoint, SchemaIfaces};
+use crate::LIB_NAME_RGB_STORAGE;
+
+fn widen<C: Collection, const MAX: usize>(col: Confined<C, 0, 0xFF>) -> Confined<C, 0, MAX> {
+    Confined::from_checked(col.release())
+}
+
+#[derive(Debug)]
+#[derive(StrictType, StrictDumb, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct MemStashV0 {
+    schemata: TinyOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: TinyOrdMap<IfaceId, Iface>,
+    geneses: TinyOrdMap<ContractId, Genesis>,
+    suppl: TinyOrdMap<ContentRef, TinyOrdSet<Supplement>>,
+    bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    extensions: LargeOrdMap<OpId, Extension>,
+    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    attachments: SmallOrdMap<AttachId, MediumBlob>,
+    secret_seals: MediumOrdSet<XChain<GraphSeal>>,
+    type_system: TypeSystem,
+    identities: SmallOrdMap<Identity, TrustLevel>,
+    libs: SmallOrdMap<LibId, Lib>,
+    sigs: SmallOrdMap<ContentId, ContentSigs>,
+}
+
+impl StrictDeserialize for MemStashV0 {}
+
+impl From<MemStashV0> for MemStash {
+    fn from(old: MemStashV0) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            schemata: widen(old.schemata),
+            ifaces: widen(old.ifaces),
+            geneses: widen(old.geneses),
+            suppl: widen(old.suppl),
+            bundles: old.bundles,
+            extensions: old.extensions,
+            witnesses: old.witnesses,
+            attachments: old.attachments,
+            secret_seals: old.secret_seals,
+            type_system: old.type_system,
+            identities: old.identities,
+            libs: old.libs,
+            sigs: old.sigs,
+            pruned: empty!(),
+            replaced: empty!(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(StrictType, StrictDumb, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct MemStashV1 {
+    schemata: SmallOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: SmallOrdMap<IfaceId, Iface>,
+    geneses: MediumOrdMap<ContractId, Genesis>,
+    suppl: MediumOrdMap<ContentRef, TinyOrdSet<Supplement>>,
+    bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    extensions: LargeOrdMap<OpId, Extension>,
+    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    attachments: SmallOrdMap<AttachId, MediumBlob>,
+    secret_seals: MediumOrdSet<XChain<GraphSeal>>,
+    type_system: TypeSystem,
+    identities: SmallOrdMap<Identity, TrustLevel>,
+    libs: SmallOrdMap<LibId, Lib>,
+    sigs: SmallOrdMap<ContentId, ContentSigs>,
+}
+
+impl StrictDeserialize for MemStashV1 {}
+
+impl From<MemStashV1> for MemStash {
+    fn from(old: MemStashV1) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            schemata: old.schemata,
+            ifaces: old.ifaces,
+            geneses: old.geneses,
+            suppl: old.suppl,
+            bundles: old.bundles,
+            extensions: old.extensions,
+            witnesses: old.witnesses,
+            attachments: old.attachments,
+            secret_seals: old.secret_seals,
+            type_system: old.type_system,
+            identities: old.identities,
+            libs: old.libs,
+            sigs: old.sigs,
+            pruned: empty!(),
+            replaced: empty!(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(StrictType, StrictDumb, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct MemStashV2 {
+    schemata: SmallOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: SmallOrdMap<IfaceId, Iface>,
+    geneses: MediumOrdMap<ContractId, Genesis>,
+    suppl: MediumOrdMap<ContentRef, TinyOrdSet<Supplement>>,
+    bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    extensions: LargeOrdMap<OpId, Extension>,
+    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    attachments: SmallOrdMap<AttachId, MediumBlob>,
+    secret_seals: MediumOrdSet<XChain<GraphSeal>>,
+    type_system: TypeSystem,
+    identities: SmallOrdMap<Identity, TrustLevel>,
+    libs: SmallOrdMap<LibId, Lib>,
+    sigs: SmallOrdMap<ContentId, ContentSigs>,
+    pruned: MediumOrdMap<ContractId, PruneCheckpoint>,
+}
+
+impl StrictDeserialize for MemStashV2 {}
+
+impl From<MemStashV2> for MemStash {
+    fn from(old: MemStashV2) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            schemata: old.schemata,
+            ifaces: old.ifaces,
+            geneses: old.geneses,
+            suppl: old.suppl,
+            bundles: old.bundles,
+            extensions: old.extensions,
+            witnesses: old.witnesses,
+            attachments: old.attachments,
+            secret_seals: old.secret_seals,
+            type_system: old.type_system,
+            identities: old.identities,
+            libs: old.libs,
+            sigs: old.sigs,
+            pruned: old.pruned,
+            replaced: empty!(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(StrictType, StrictDumb, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct MemStateV0 {
+    witnesses: LargeOrdMap<XWitnessId, WitnessOrd>,
+    contracts: TinyOrdMap<ContractId, MemContractState>,
+}
+
+impl StrictDeserialize for MemStateV0 {}
+
+impl From<MemStateV0> for MemState {
+    fn from(old: MemStateV0) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            witnesses: old.witnesses,
+            contracts: widen(old.contracts),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(StrictType, StrictDumb, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct MemIndexV0 {
+    op_bundle_index: MediumOrdMap<OpId, BundleId>,
+    bundle_contract_index: MediumOrdMap<BundleId, ContractId>,
+    bundle_witness_index: MediumOrdMap<BundleId, TinyOrdSet<XWitnessId>>,
+    contract_index: TinyOrdMap<ContractId, ContractIndex>,
+    terminal_index: MediumOrdMap<XChain<SecretSeal>, TinyOrdSet<Opout>>,
+}
+
+impl StrictDeserialize for MemIndexV0 {}
+
+impl From<MemIndexV0> for MemIndex {
+    fn from(old: MemIndexV0) -> Self {
+        Self {
+            persistence: None,
+            undo: default!(),
+            op_bundle_index: old.op_bundle_index,
+            bundle_contract_index: old.bundle_contract_index,
+            bundle_witness_index: old.bundle_witness_index,
+            contract_index: widen(old.contract_index),
+            terminal_index: old.terminal_index,
+        }
+    }
+}
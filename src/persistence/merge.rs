@@ -0,0 +1,502 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merging data of independently updated stocks.
+
+use std::collections::BTreeSet;
+
+use bp::dbc::anchor::MergeError;
+use rgb::validation::ResolveWitness;
+use rgb::{BundleId, ContractId, Identity, OpId, Operation, SchemaId, XWitnessId};
+use strict_encoding::TypeName;
+
+use super::{
+    IndexProvider, StashError, StashProvider, StateProvider, Stock, StockError, TrustError,
+};
+use crate::containers::{ContentId, ContentRef, SealWitness};
+use crate::contract::{MergeReveal, MergeRevealError};
+use crate::interface::resolver::ReplacedResolver;
+use crate::interface::ImplId;
+
+/// Data which can't be merged from another stock. The stock keeps its own
+/// version of such data.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(doc_comments)]
+pub enum MergeConflict {
+    /// genesis of contract {0} can't be merged: {1}
+    Genesis(ContractId, MergeRevealError),
+
+    /// state extension {0} can't be merged: {1}
+    Extension(OpId, MergeRevealError),
+
+    /// transition bundle {0} can't be merged: {1}
+    Bundle(BundleId, MergeRevealError),
+
+    /// witness {0} can't be merged: {1}
+    Witness(XWitnessId, MergeRevealError),
+
+    /// anchors of witness {0} can't be merged: {1}
+    Anchors(XWitnessId, MergeError),
+
+    /// schema {schema_id} uses implementation {present} for interface
+    /// {iface}, while the merged stock uses implementation {other}.
+    IfaceImpl {
+        schema_id: SchemaId,
+        iface: TypeName,
+        present: ImplId,
+        other: ImplId,
+    },
+
+    /// signature of {identity} over {content_id:?} differs from the one known
+    /// to the stock.
+    Signature {
+        content_id: ContentId,
+        identity: Identity,
+    },
+}
+
+/// Report on merging data from another stock, produced by [`Stock::merge`].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct MergeReport {
+    /// Contracts which stash data were updated during the merge.
+    pub contracts: BTreeSet<ContractId>,
+    /// Data which were not merged due to conflicts.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeReport {
+    pub fn has_conflicts(&self) -> bool { !self.conflicts.is_empty() }
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
+    /// Merges stash data from another stock into this one, updating index and
+    /// contract state of the affected contracts.
+    ///
+    /// Known schemata, interfaces, implementations, geneses, state extensions,
+    /// bundles, witnesses, secret seals, supplements and signatures are
+    /// united, merge-revealing operations and witnesses known to both stocks.
+    /// Data which can't be merged are not overwritten and are reported as
+    /// conflicts. The merge is performed as a single transaction.
+    ///
+    /// Fails if some of the schemata, interfaces or interface implementations
+    /// of the other stock are rejected by the stock [`super::TrustPolicy`].
+    pub fn merge(
+        &mut self,
+        other: &Self,
+        resolver: impl ResolveWitness,
+    ) -> Result<MergeReport, StockError<S, H, P, TrustError>> {
+        let theirs = other.as_stash_provider();
+        let mut report = MergeReport::default();
+        self.check_stash_trust(theirs)?;
+
+        self.store_transaction(|stash, state, index| {
+            let ours = stash.as_provider_mut();
+            let mut content_ids = BTreeSet::new();
+            let mut content_refs = BTreeSet::new();
+
+            let types = theirs.type_system().map_err(StockError::StashRead)?;
+            ours.consume_types(types.clone())
+                .map_err(StockError::StashWrite)?;
+
+            for iface in theirs.ifaces().map_err(StockError::StashRead)? {
+                let id = iface.iface_id();
+                if ours.iface(id).is_err() {
+                    ours.replace_iface(iface.clone())
+                        .map_err(StockError::StashWrite)?;
+                }
+                content_ids.insert(ContentId::Iface(id));
+                content_refs.insert(ContentRef::Iface(id));
+            }
+
+            for schema_ifaces in theirs.schemata().map_err(StockError::StashRead)? {
+                let schema = &schema_ifaces.schema;
+                let schema_id = schema.schema_id();
+                for lib_id in schema.libs() {
+                    if ours.lib(lib_id).is_err() {
+                        let lib = theirs.lib(lib_id).map_err(StashError::from)?;
+                        ours.replace_lib(lib.clone())
+                            .map_err(StockError::StashWrite)?;
+                    }
+                }
+                if ours.schema(schema_id).is_err() {
+                    ours.replace_schema(schema.clone())
+                        .map_err(StockError::StashWrite)?;
+                }
+                content_ids.insert(ContentId::Schema(schema_id));
+                content_refs.insert(ContentRef::Schema(schema_id));
+
+                for (name, iimpl) in &schema_ifaces.iimpls {
+                    let impl_id = iimpl.impl_id();
+                    let present = ours
+                        .schema(schema_id)
+                        .map_err(StashError::from)?
+                        .iimpls
+                        .get(name)
+                        .map(|iimpl| iimpl.impl_id());
+                    match present {
+                        None => {
+                            ours.replace_iimpl(iimpl.clone())
+                                .map_err(StockError::StashWrite)?;
+                        }
+                        Some(present) if present != impl_id => {
+                            report.conflicts.push(MergeConflict::IfaceImpl {
+                                schema_id,
+                                iface: name.clone(),
+                                present,
+                                other: impl_id,
+                            });
+                            continue;
+                        }
+                        Some(_) => {}
+                    }
+                    content_ids.insert(ContentId::IfaceImpl(impl_id));
+                    content_refs.insert(ContentRef::IfaceImpl(impl_id));
+                }
+            }
+
+            let mut new_geneses = BTreeSet::new();
+            for genesis in theirs.geneses().map_err(StockError::StashRead)? {
+                let contract_id = genesis.contract_id();
+                content_ids.insert(ContentId::Genesis(contract_id));
+                content_refs.insert(ContentRef::Genesis(contract_id));
+                let merged = match ours.genesis(contract_id) {
+                    Ok(present) => match present.clone().merge_reveal(genesis.clone()) {
+                        Ok(merged) if merged == *present => continue,
+                        Ok(merged) => merged,
+                        Err(err) => {
+                            report
+                                .conflicts
+                                .push(MergeConflict::Genesis(contract_id, err));
+                            continue;
+                        }
+                    },
+                    Err(_) => genesis.clone(),
+                };
+                ours.replace_genesis(merged)
+                    .map_err(StockError::StashWrite)?;
+                new_geneses.insert(contract_id);
+                report.contracts.insert(contract_id);
+            }
+
+            let mut new_extensions = BTreeSet::new();
+            for opid in theirs.extension_ids().map_err(StockError::StashRead)? {
+                let extension = theirs.extension(opid).map_err(StashError::from)?;
+                let merged = match ours.extension(opid) {
+                    Ok(present) => match present.clone().merge_reveal(extension.clone()) {
+                        Ok(merged) if merged == *present => continue,
+                        Ok(merged) => merged,
+                        Err(err) => {
+                            report.conflicts.push(MergeConflict::Extension(opid, err));
+                            continue;
+                        }
+                    },
+                    Err(_) => extension.clone(),
+                };
+                ours.replace_extension(merged)
+                    .map_err(StockError::StashWrite)?;
+                new_extensions.insert(opid);
+                report.contracts.insert(extension.contract_id);
+            }
+
+            let mut new_bundles = BTreeSet::new();
+            for bundle_id in theirs.bundle_ids().map_err(StockError::StashRead)? {
+                let bundle = theirs.bundle(bundle_id).map_err(StashError::from)?;
+                let merged = match ours.bundle(bundle_id) {
+                    Ok(present) => match present.clone().merge_reveal(bundle.clone()) {
+                        Ok(merged) if merged == *present => continue,
+                        Ok(merged) => merged,
+                        Err(err) => {
+                            report.conflicts.push(MergeConflict::Bundle(bundle_id, err));
+                            continue;
+                        }
+                    },
+                    Err(_) => bundle.clone(),
+                };
+                ours.replace_bundle(merged)
+                    .map_err(StockError::StashWrite)?;
+                new_bundles.insert(bundle_id);
+            }
+
+            for witness_id in theirs.witness_ids().map_err(StockError::StashRead)? {
+                let witness = theirs.witness(witness_id).map_err(StashError::from)?;
+                let merged = match ours.witness(witness_id) {
+                    Ok(present) => {
+                        let public =
+                            match present.public.clone().merge_reveal(witness.public.clone()) {
+                                Ok(public) => public,
+                                Err(err) => {
+                                    report
+                                        .conflicts
+                                        .push(MergeConflict::Witness(witness_id, err));
+                                    continue;
+                                }
+                            };
+                        let anchors = match present
+                            .anchors
+                            .clone()
+                            .merge_reveal(witness.anchors.clone())
+                        {
+                            Ok(anchors) => anchors,
+                            Err(err) => {
+                                report
+                                    .conflicts
+                                    .push(MergeConflict::Anchors(witness_id, err));
+                                continue;
+                            }
+                        };
+                        let merged = SealWitness { public, anchors };
+                        if merged == *present {
+                            continue;
+                        }
+                        merged
+                    }
+                    Err(_) => witness.clone(),
+                };
+                new_bundles.extend(merged.anchors.known_bundle_ids());
+                ours.replace_witness(merged)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for (replaced, replacement) in theirs
+                .witness_replacements()
+                .map_err(StockError::StashRead)?
+            {
+                ours.set_witness_replacement(replaced, replacement)
+                    .map_err(StockError::StashWrite)?;
+            }
+
+            for seal in theirs.secret_seals().map_err(StockError::StashRead)? {
+                ours.add_secret_seal(seal).map_err(StockError::StashWrite)?;
+            }
+
+            for content_ref in content_refs {
+                let present = ours
+                    .supplements(content_ref)
+                    .map_err(StockError::StashRead)?
+                    .map(|suppl| suppl.suppl_id())
+                    .collect::<BTreeSet<_>>();
+                for suppl in theirs
+                    .supplements(content_ref)
+                    .map_err(StockError::StashRead)?
+                {
+                    let suppl_id = suppl.suppl_id();
+                    content_ids.insert(ContentId::Suppl(suppl_id));
+                    if !present.contains(&suppl_id) {
+                        ours.add_supplement(suppl).map_err(StockError::StashWrite)?;
+                    }
+                }
+            }
+
+            for content_id in content_ids {
+                let Some(sigs) = theirs
+                    .sigs_for(&content_id)
+                    .map_err(StockError::StashRead)?
+                else {
+                    continue;
+                };
+                let present = ours
+                    .sigs_for(&content_id)
+                    .map_err(StockError::StashRead)?
+                    .cloned();
+                let mut import = Vec::with_capacity(sigs.len());
+                for (identity, sig) in sigs.iter() {
+                    match present.as_ref().and_then(|present| present.get(identity)) {
+                        Some(known) if known == sig => {}
+                        Some(_) => report.conflicts.push(MergeConflict::Signature {
+                            content_id,
+                            identity: identity.clone(),
+                        }),
+                        None => import.push((identity.clone(), sig.clone())),
+                    }
+                }
+                if !import.is_empty() {
+                    ours.import_sigs(content_id, import)
+                        .map_err(StockError::StashWrite)?;
+                }
+            }
+
+            // Index the merged operations
+            for contract_id in &new_geneses {
+                index.index_contract(stash.genesis(*contract_id)?)?;
+            }
+            for opid in new_extensions {
+                let extension = stash.extension(opid)?;
+                index.index_extension(extension.contract_id, extension)?;
+            }
+            let bundle_witnesses = Self::stash_bundle_witnesses(stash)?;
+            for bundle_id in new_bundles {
+                let Ok(bundle) = stash.bundle(bundle_id) else {
+                    continue;
+                };
+                let Some(contract_id) = bundle
+                    .known_transitions
+                    .values()
+                    .map(|transition| transition.contract_id)
+                    .next()
+                else {
+                    continue;
+                };
+                for witness_id in bundle_witnesses.get(&bundle_id).into_iter().flatten() {
+                    index.index_bundle(contract_id, bundle, *witness_id)?;
+                }
+                report.contracts.insert(contract_id);
+            }
+
+            // Update the state of the affected contracts
+            let contracts = &report.contracts;
+            let resolver = ReplacedResolver {
+                resolver: &resolver,
+                replaced: stash.replaced_witnesses()?,
+            };
+            Self::replay_contracts(stash, state, |id| contracts.contains(&id), &resolver)
+        })?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::Vout;
+    use rgb::{GraphSeal, Operation, Opout, XChain};
+    use strict_encoding::{StrictDumb, StrictSerialize};
+
+    use super::*;
+    use crate::interface::resolver::DumbResolver;
+    use crate::interface::{Iface, IfaceImpl};
+    use crate::persistence::fixture::{mined, Fixture, OWNED};
+    use crate::persistence::{
+        ContractStateRead, IndexReadProvider, StashReadProvider, StashWriteProvider,
+    };
+
+    fn serialize(data: &impl StrictSerialize) -> Vec<u8> {
+        data.to_strict_serialized::<{ u32::MAX as usize }>()
+            .unwrap()
+            .release()
+    }
+
+    #[test]
+    fn secret_seals_are_merged() {
+        let seal = |vout| {
+            XChain::with(
+                rgb::Layer1::Bitcoin,
+                GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(vout)),
+            )
+        };
+        let mut desktop = Stock::in_memory();
+        desktop.store_secret_seal(seal(0)).unwrap();
+        let mut mobile = Stock::in_memory();
+        mobile.store_secret_seal(seal(1)).unwrap();
+
+        let report = desktop.merge(&mobile, DumbResolver).unwrap();
+        assert!(!report.has_conflicts());
+        assert!(report.contracts.is_empty());
+        assert_eq!(desktop.as_stash_provider().secret_seals().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn transfers_are_merged() {
+        let mut fixture = Fixture::issue(2);
+        let contract_id = fixture.contract_id();
+        let genesis_id = fixture.genesis.id();
+        let mut desktop = Stock::in_memory();
+        let mut mobile = Stock::in_memory();
+        let mut combined = Stock::in_memory();
+        for stock in [&mut desktop, &mut mobile, &mut combined] {
+            stock
+                .import_contract(fixture.contract(), &fixture.chain)
+                .unwrap();
+        }
+
+        let first = fixture.transfer(&[Opout::new(genesis_id, OWNED, 0)], &[0]);
+        let fascia = fixture.witness(vec![first.clone()], mined(100));
+        desktop
+            .consume_fascia(fascia.clone(), &fixture.chain)
+            .unwrap();
+        combined.consume_fascia(fascia, &fixture.chain).unwrap();
+        let second = fixture.transfer(&[Opout::new(genesis_id, OWNED, 1)], &[0]);
+        let fascia = fixture.witness(vec![second.clone()], mined(101));
+        mobile
+            .consume_fascia(fascia.clone(), &fixture.chain)
+            .unwrap();
+        combined.consume_fascia(fascia, &fixture.chain).unwrap();
+
+        let report = desktop.merge(&mobile, &fixture.chain).unwrap();
+        assert!(!report.has_conflicts());
+        assert_eq!(report.contracts, bset![contract_id]);
+
+        let index = desktop.as_index_provider();
+        for transition in [&first, &second] {
+            let bundle_id = index.bundle_id_for_op(transition.id()).unwrap();
+            let bundle = desktop.as_stash_provider().bundle(bundle_id).unwrap();
+            assert!(bundle.known_transitions.contains_key(&transition.id()));
+        }
+        let contract = desktop.contract_state(contract_id).unwrap();
+        for transition in [&first, &second] {
+            let opout = fixture.outputs(transition.id())[0];
+            let allocation = contract.rights_all().find(|a| a.opout == opout).unwrap();
+            assert_eq!(allocation.seal, fixture.seal(opout));
+        }
+
+        // The merged stock is the same as the one which consumed both transfers
+        assert_eq!(serialize(desktop.as_stash_provider()), serialize(combined.as_stash_provider()));
+        assert_eq!(serialize(desktop.as_state_provider()), serialize(combined.as_state_provider()));
+        assert_eq!(serialize(desktop.as_index_provider()), serialize(combined.as_index_provider()));
+    }
+
+    #[test]
+    fn iface_impl_conflict() {
+        let schema = Fixture::issue(1).schema;
+        let schema_id = schema.schema_id();
+        let iface = Iface {
+            name: tn!("Test"),
+            ..Iface::strict_dumb()
+        };
+        let iimpl = IfaceImpl {
+            schema_id,
+            iface_id: iface.iface_id(),
+            ..IfaceImpl::strict_dumb()
+        };
+        let other = IfaceImpl {
+            timestamp: iimpl.timestamp + 1,
+            ..iimpl.clone()
+        };
+        let stock = |iimpl: &IfaceImpl| {
+            let mut stock = Stock::in_memory();
+            let stash = stock.as_stash_provider_mut();
+            stash.replace_schema(schema.clone()).unwrap();
+            stash.replace_iface(iface.clone()).unwrap();
+            stash.replace_iimpl(iimpl.clone()).unwrap();
+            stock
+        };
+        let mut desktop = stock(&iimpl);
+        let mobile = stock(&other);
+
+        let report = desktop.merge(&mobile, DumbResolver).unwrap();
+        assert_eq!(report.conflicts, vec![MergeConflict::IfaceImpl {
+            schema_id,
+            iface: iface.name.clone(),
+            present: iimpl.impl_id(),
+            other: other.impl_id(),
+        }]);
+        let schema_ifaces = desktop.as_stash_provider().schema(schema_id).unwrap();
+        assert_eq!(schema_ifaces.iimpls[&iface.name].impl_id(), iimpl.impl_id());
+    }
+}
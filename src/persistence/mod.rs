@@ -28,17 +28,35 @@
 //! 3. Index over stash, which simplifies construction of new consignments.
 //!
 //! Contract state and index data can be re-computed from the stash in case of
-//! loss or corruption, while stash can't be recovered unless it was backed up.
+//! loss or corruption (see [`Stock::rebuild_state`] and [`Stock::reindex`]),
+//! while stash can't be recovered unless it was backed up.
+
+use nonasync::persistence::{PersistenceError, PersistenceProvider};
 
 mod stock;
 mod stash;
 mod state;
 mod index;
+mod fsck;
+mod backup;
+mod merge;
+mod prune;
+mod events;
+mod trust;
+#[cfg(test)]
+mod fixture;
 
 mod memory;
 #[cfg(feature = "fs")]
 pub mod fs;
+#[cfg(feature = "fs")]
+pub mod dir;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
+pub use backup::{BackupError, BackupId, BackupVer, StockBackup, ASCII_ARMOR_BACKUP_CONTENT};
+pub use events::{ObserverId, StockEvent, StockObserver};
+pub use fsck::ConsistencyReport;
 pub use index::{
     Index, IndexError, IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider,
     IndexWriteError, IndexWriteProvider,
@@ -46,18 +64,22 @@ pub use index::{
 pub use memory::{
     MemContract, MemContractState, MemError, MemGlobalState, MemIndex, MemStash, MemState,
 };
+pub use merge::{MergeConflict, MergeReport};
+pub use prune::PruneCheckpoint;
 pub use stash::{
     ProviderError as StashProviderError, SchemaIfaces, Stash, StashDataError, StashError,
     StashInconsistency, StashProvider, StashReadProvider, StashWriteProvider,
 };
 pub use state::{
     ContractStateRead, ContractStateWrite, PersistedState, State, StateError, StateInconsistency,
-    StateProvider, StateReadProvider, StateWriteProvider,
+    StateProvider, StateReadError, StateReadProvider, StateWriteProvider,
 };
 pub use stock::{
-    ComposeError, ConsignError, ContractIfaceError, FasciaError, InputError as StockInputError,
-    Stock, StockError, StockErrorAll, StockErrorMem, UpdateRes,
+    Allocation, ComposeError, ConsignError, ContractIfaceError, FasciaError, ForgetError,
+    InputError as StockInputError, OrdUpdate, Stock, StockError, StockErrorAll, StockErrorMem,
+    UpdateRes,
 };
+pub use trust::{TrustError, TrustPolicy};
 
 pub trait StoreTransaction {
     type TransactionErr: std::error::Error;
@@ -66,5 +88,66 @@ pub trait StoreTransaction {
 
     fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr>;
 
+    /// Commits the transaction keeping the data required to revert it with
+    /// [`StoreTransaction::rollback_transaction`] until
+    /// [`StoreTransaction::complete_transaction`] is called.
+    ///
+    /// Used to commit several providers together, such that all of them can be
+    /// reverted if some of them fail. Providers unable to revert committed
+    /// data just commit the transaction.
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.commit_transaction()
+    }
+
+    /// Releases the data kept by [`StoreTransaction::precommit_transaction`].
+    ///
+    /// Providers sharing a single database transaction commit it once all of
+    /// them are completed, thus completion may fail, in which case the
+    /// transaction must be rolled back.
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(()) }
+
     fn rollback_transaction(&mut self);
 }
+
+/// Persistence provider able to store stash, state and index data as a single
+/// atomic operation.
+///
+/// If the operation fails or gets interrupted, the previously stored data must
+/// remain intact, such that the next load returns a consistent snapshot of all
+/// three components.
+pub trait StockPersistence<S, H, P>:
+    PersistenceProvider<S> + PersistenceProvider<H> + PersistenceProvider<P>
+{
+    fn store_all(&self, stash: &S, state: &H, index: &P) -> Result<(), PersistenceError>;
+
+    /// Converts the provider into a mode in which it is used only for loading
+    /// data, if the provider supports such a mode. Used by
+    /// [`Stock::load_read_only`].
+    fn into_read_only(self) -> Self
+    where Self: Sized {
+        self
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display("the stock is opened in read-only mode and its data can't be stored.")]
+pub struct ReadOnlyError;
+
+/// Persistence provider which loads data using the wrapped provider, but
+/// refuses to store them.
+#[derive(Clone, Debug)]
+pub struct ReadOnly<P>(pub P);
+
+impl<T, P: PersistenceProvider<T>> PersistenceProvider<T> for ReadOnly<P> {
+    fn load(&self) -> Result<T, PersistenceError> { self.0.load() }
+
+    fn store(&self, _: &T) -> Result<(), PersistenceError> {
+        Err(PersistenceError::with(ReadOnlyError))
+    }
+}
+
+impl<S, H, I, P: StockPersistence<S, H, I>> StockPersistence<S, H, I> for ReadOnly<P> {
+    fn store_all(&self, _: &S, _: &H, _: &I) -> Result<(), PersistenceError> {
+        Err(PersistenceError::with(ReadOnlyError))
+    }
+}
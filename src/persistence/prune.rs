@@ -0,0 +1,195 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pruning of the contract history which is not needed anymore.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+
+use amplify::confinement::{Confined, LargeOrdSet};
+use chrono::Utc;
+use commit_verify::Conceal;
+use rgb::{BundleId, ContractId, OpId, Operation, XOutpoint};
+
+use super::{
+    ConsignError, IndexError, IndexProvider, StashError, StashProvider, StateProvider, Stock,
+    StockError,
+};
+use crate::LIB_NAME_RGB_STORAGE;
+
+/// Record of the contract operations removed from the stash by
+/// [`Stock::prune`].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STORAGE)]
+pub struct PruneCheckpoint {
+    /// Time of the last pruning.
+    pub timestamp: i64,
+    /// Operations removed from the stash.
+    pub ops: LargeOrdSet<OpId>,
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
+    /// Removes bundles which are not needed to construct consignments for the
+    /// state assigned to the `outpoints`, which should list all outpoints
+    /// controlled by the wallet.
+    ///
+    /// The bundles required for the consignments are detected by walking the
+    /// contract history back to genesis, starting from the public state, the
+    /// state on the `outpoints` and the state assigned to the secret seals
+    /// known to the stash. Witnesses which do not anchor any of the remaining
+    /// bundles are removed as well. Removed operations are recorded in the
+    /// contract [`PruneCheckpoint`], such that consignments requiring them
+    /// fail with [`ConsignError::Pruned`].
+    ///
+    /// The contract state keeps the contribution of the removed operations,
+    /// thus it is not re-created from the stash on the witness updates, and
+    /// can't be rebuilt (see [`Stock::rebuild_state`]).
+    ///
+    /// Returns ids of the removed bundles.
+    pub fn prune(
+        &mut self,
+        outpoints: impl IntoIterator<Item = impl Into<XOutpoint>>,
+    ) -> Result<BTreeSet<BundleId>, StockError<S, H, P, ConsignError>> {
+        let outputs = outpoints
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<XOutpoint>>();
+        let stash = self.as_stash_provider();
+        let index = self.as_index_provider();
+        let terminals = stash
+            .secret_seals()
+            .map_err(StashError::ReadProvider)?
+            .map(|seal| seal.conceal())
+            .collect::<Vec<_>>();
+
+        let mut contract_bundles = BTreeMap::<ContractId, BTreeSet<BundleId>>::new();
+        for bundle_id in stash.bundle_ids().map_err(StashError::ReadProvider)? {
+            let bundle = stash.bundle(bundle_id).map_err(StashError::from)?;
+            if let Some(transition) = bundle.known_transitions.values().next() {
+                contract_bundles
+                    .entry(transition.contract_id)
+                    .or_default()
+                    .insert(bundle_id);
+            }
+        }
+
+        let mut removed = BTreeSet::new();
+        let mut pruned = BTreeMap::<ContractId, BTreeSet<OpId>>::new();
+        for (contract_id, bundles) in contract_bundles {
+            let mut opouts = index.public_opouts(contract_id).map_err(IndexError::from)?;
+            opouts.extend(
+                index
+                    .opouts_by_outputs(contract_id, outputs.iter().copied())
+                    .map_err(IndexError::from)?,
+            );
+            opouts.extend(
+                index
+                    .opouts_by_terminals(terminals.iter().copied())
+                    .map_err(IndexError::ReadProvider)?,
+            );
+            let mut transitions = vec![];
+            for opout in opouts {
+                if opout.op == contract_id {
+                    continue;
+                }
+                let transition = self.transition(contract_id, opout.op)?;
+                if transition.contract_id == contract_id {
+                    transitions.push(transition);
+                }
+            }
+            let mut required = transitions
+                .iter()
+                .map(|transition| transition.id())
+                .collect::<BTreeSet<_>>();
+            required.extend(
+                self.ancestors(contract_id, transitions, None)?
+                    .0
+                    .into_keys(),
+            );
+            let mut required_bundles = BTreeSet::new();
+            for opid in required {
+                required_bundles.insert(index.bundle_id_for_op(opid).map_err(IndexError::from)?);
+            }
+
+            for bundle_id in bundles.difference(&required_bundles) {
+                let bundle = stash.bundle(*bundle_id).map_err(StashError::from)?;
+                let ops = pruned.entry(contract_id).or_default();
+                ops.extend(bundle.input_map.values());
+                ops.extend(bundle.known_transitions.keys());
+                removed.insert(*bundle_id);
+            }
+        }
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+
+        let mut witnesses = BTreeSet::new();
+        for witness_id in stash.witness_ids().map_err(StashError::ReadProvider)? {
+            let witness = stash.witness(witness_id).map_err(StashError::from)?;
+            let mut ids = witness.anchors.known_bundle_ids();
+            if ids.all(|bundle_id| removed.contains(&bundle_id)) {
+                witnesses.insert(witness_id);
+            }
+        }
+
+        let bundles = removed.clone();
+        self.store_transaction::<Infallible>(move |stash, _, index| {
+            let index = index.as_provider_mut();
+            for (contract_id, ops) in &pruned {
+                index
+                    .remove_terminals(ops)
+                    .map_err(StockError::IndexWrite)?;
+                let mut checkpoint = stash
+                    .prune_checkpoint(*contract_id)?
+                    .cloned()
+                    .unwrap_or_default();
+                checkpoint.timestamp = Utc::now().timestamp();
+                checkpoint.ops = Confined::from_iter_checked(
+                    checkpoint.ops.into_iter().chain(ops.iter().copied()),
+                );
+                stash
+                    .as_provider_mut()
+                    .set_prune_checkpoint(*contract_id, checkpoint)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for bundle_id in &bundles {
+                index
+                    .remove_bundle(*bundle_id)
+                    .map_err(StockError::IndexWrite)?;
+            }
+
+            let stash = stash.as_provider_mut();
+            for bundle_id in bundles {
+                stash
+                    .remove_bundle(bundle_id)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for witness_id in witnesses {
+                stash
+                    .remove_witness(witness_id)
+                    .map_err(StockError::StashWrite)?;
+            }
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+}
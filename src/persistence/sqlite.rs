@@ -0,0 +1,2175 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SQLite-backed stash, state and index providers.
+//!
+//! The providers keep their data in relational tables of a single SQLite
+//! database. Providers loaded from the same [`SqlStore`] share one connection
+//! to it: the first provider beginning a transaction with
+//! [`StoreTransaction::begin_transaction`] opens a database transaction, which
+//! is committed once all the providers which have begun it are completed with
+//! [`StoreTransaction::complete_transaction`]. Thus, a stock transaction
+//! either writes the data of all three providers or none of them. Rolling back
+//! any of the providers reverts the whole database transaction.
+//!
+//! The data are read from the database on the first access and cached
+//! afterwards, except the stash definitions (types, libraries, interfaces,
+//! schemata, supplements, signatures and identities), which are read together
+//! on the first access to any of them. Changes are written to the database
+//! right away, except the contract state, which is written when the state
+//! provider is pre-committed. Rolling back a transaction drops the caches, so
+//! the data are re-read from the database on demand.
+//!
+//! Providers constructed with `in_memory` use separate in-memory databases and
+//! thus commit their transactions independently.
+//!
+//! Since the data are written by the transactions, there is no need to call
+//! `Stock::store` when these providers are used; storing them into a
+//! [`SqlStore`] pointing to a different database file creates a full copy of
+//! the data there.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::{io, mem};
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{self, Confined, MediumBlob, U32 as U32MAX};
+use amplify::Wrapper;
+use bp::dbc::tapret::TapretCommitment;
+use commit_verify::Conceal;
+use nonasync::persistence::{
+    CloneNoPersistence, Persistence, PersistenceError, PersistenceProvider, Persisting,
+};
+use rgb::vm::WitnessOrd;
+use rgb::{
+    Assign, AssignmentType, AttachId, BundleId, ContractId, ExposedSeal, ExposedState, Extension,
+    Genesis, GenesisSeal, GraphSeal, Identity, Layer1, OpId, Operation, Opout, Schema, SchemaId,
+    SecretSeal, Transition, TransitionBundle, XChain, XOutpoint, XOutputSeal, XWitnessId,
+};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Params, Row};
+use strict_encoding::{
+    DecodeError, StreamReader, StrictDecode, StrictEncode, StrictReader, StrictWriter,
+};
+use strict_types::{SemId, Ty, TypeSystem};
+
+use super::memory::{resolve_witness_ords, witness_tapret, MemContractWriter};
+use super::{
+    ContractStateWrite, IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider,
+    IndexWriteError, IndexWriteProvider, MemContract, MemContractState, MemError, MemStash,
+    PruneCheckpoint, SchemaIfaces, StashInconsistency, StashProvider, StashProviderError,
+    StashReadProvider, StashWriteProvider, StateInconsistency, StateProvider, StateReadError,
+    StateReadProvider, StateWriteProvider, StockPersistence, StoreTransaction, UpdateRes,
+};
+use crate::containers::{
+    ContentId, ContentRef, ContentSigs, SealWitness, SigBlob, Supplement, TrustLevel,
+};
+use crate::interface::{Iface, IfaceClass, IfaceId, IfaceImpl, IfaceRef, ResolveWitnessBatch};
+
+/// Error reading data from a SQLite database.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display("unable to read SQLite database: {details}")]
+pub struct SqlReadError {
+    pub details: String,
+}
+
+impl SqlReadError {
+    fn with(err: impl ToString) -> Self {
+        Self {
+            details: err.to_string(),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for SqlReadError {
+    fn from(err: rusqlite::Error) -> Self { Self::with(err) }
+}
+
+impl From<DecodeError> for SqlReadError {
+    fn from(err: DecodeError) -> Self { Self::with(err) }
+}
+
+impl From<confinement::Error> for SqlReadError {
+    fn from(err: confinement::Error) -> Self { Self::with(err) }
+}
+
+impl From<MemError> for SqlReadError {
+    fn from(err: MemError) -> Self { Self::with(err) }
+}
+
+#[derive(Debug, Display, Error, From)]
+#[display(inner)]
+pub enum SqlError {
+    #[from]
+    Sqlite(rusqlite::Error),
+
+    #[from]
+    Read(SqlReadError),
+
+    #[from]
+    Mem(MemError),
+
+    #[from]
+    #[from(confinement::Error)]
+    Encoding(DecodeError),
+}
+
+impl From<io::Error> for SqlError {
+    fn from(err: io::Error) -> Self { SqlError::Encoding(err.into()) }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum SqlStateError {
+    #[from]
+    Inconsistency(StateInconsistency),
+
+    #[from]
+    Read(SqlReadError),
+}
+
+impl StateReadError for SqlStateError {
+    fn inconsistency(&self) -> Option<&StateInconsistency> {
+        match self {
+            SqlStateError::Inconsistency(e) => Some(e),
+            SqlStateError::Read(_) => None,
+        }
+    }
+}
+
+impl From<SqlReadError> for IndexReadError<SqlReadError> {
+    fn from(err: SqlReadError) -> Self { IndexReadError::Connectivity(err) }
+}
+
+impl From<SqlError> for IndexWriteError<SqlError> {
+    fn from(err: SqlError) -> Self { IndexWriteError::Connectivity(err) }
+}
+
+impl From<SqlReadError> for IndexWriteError<SqlError> {
+    fn from(err: SqlReadError) -> Self { IndexWriteError::Connectivity(err.into()) }
+}
+
+const TYPES: &str = "types";
+const LIBS: &str = "libs";
+const IFACES: &str = "ifaces";
+const SCHEMATA: &str = "schemata";
+const GENESES: &str = "geneses";
+const EXTENSIONS: &str = "extensions";
+const BUNDLES: &str = "bundles";
+const WITNESSES: &str = "witnesses";
+const TAPRETS: &str = "taprets";
+const ATTACHMENTS: &str = "attachments";
+const SECRET_SEALS: &str = "secret_seals";
+const PRUNE_CHECKPOINTS: &str = "prune_checkpoints";
+const WITNESS_REPLACEMENTS: &str = "witness_replacements";
+const WITNESS_ORDS: &str = "witness_ords";
+const CONTRACT_STATES: &str = "contract_states";
+const INDEXED_CONTRACTS: &str = "indexed_contracts";
+
+const STASH_TABLES: [&str; 17] = [
+    TYPES,
+    LIBS,
+    IFACES,
+    SCHEMATA,
+    "iimpls",
+    "identities",
+    "sigs",
+    "supplements",
+    GENESES,
+    EXTENSIONS,
+    BUNDLES,
+    WITNESSES,
+    TAPRETS,
+    ATTACHMENTS,
+    SECRET_SEALS,
+    PRUNE_CHECKPOINTS,
+    WITNESS_REPLACEMENTS,
+];
+const STATE_TABLES: [&str; 2] = [WITNESS_ORDS, CONTRACT_STATES];
+const INDEX_TABLES: [&str; 6] = [
+    "op_bundles",
+    "bundle_contracts",
+    "bundle_witnesses",
+    INDEXED_CONTRACTS,
+    "outpoint_opouts",
+    "terminals",
+];
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = FULL;
+
+    CREATE TABLE IF NOT EXISTS types (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS libs (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS ifaces (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS schemata (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS iimpls (
+        schema_id BLOB NOT NULL, iface_name TEXT NOT NULL, iface_id BLOB NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (schema_id, iface_name)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS identities (
+        id BLOB PRIMARY KEY, trust INTEGER NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS sigs (
+        content_id BLOB NOT NULL, identity BLOB NOT NULL, sig BLOB NOT NULL,
+        PRIMARY KEY (content_id, identity)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS supplements (
+        content_ref BLOB NOT NULL, data BLOB NOT NULL,
+        PRIMARY KEY (content_ref, data)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS geneses (
+        id BLOB PRIMARY KEY, schema_id BLOB NOT NULL, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS extensions (
+        id BLOB PRIMARY KEY, contract_id BLOB NOT NULL, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS bundles (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS witnesses (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS taprets (id BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS attachments (
+        id BLOB PRIMARY KEY, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS secret_seals (
+        id BLOB PRIMARY KEY, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS prune_checkpoints (
+        id BLOB PRIMARY KEY, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS witness_replacements (
+        id BLOB PRIMARY KEY, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+
+    CREATE TABLE IF NOT EXISTS witness_ords (
+        id BLOB PRIMARY KEY, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS contract_states (
+        id BLOB PRIMARY KEY, data BLOB NOT NULL
+    ) WITHOUT ROWID;
+
+    CREATE TABLE IF NOT EXISTS op_bundles (
+        opid BLOB PRIMARY KEY, bundle_id BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS op_bundles_bundle ON op_bundles (bundle_id);
+    CREATE TABLE IF NOT EXISTS bundle_contracts (
+        bundle_id BLOB PRIMARY KEY, contract_id BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS bundle_witnesses (
+        bundle_id BLOB NOT NULL, witness_id BLOB NOT NULL,
+        PRIMARY KEY (bundle_id, witness_id)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS indexed_contracts (id BLOB PRIMARY KEY) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS outpoint_opouts (
+        contract_id BLOB NOT NULL, outpoint BLOB NOT NULL,
+        opid BLOB NOT NULL, ty INTEGER NOT NULL, no INTEGER NOT NULL,
+        PRIMARY KEY (contract_id, outpoint, opid, ty, no)
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS outpoint_opouts_outpoint ON outpoint_opouts (outpoint);
+    CREATE TABLE IF NOT EXISTS terminals (
+        seal BLOB NOT NULL, opid BLOB NOT NULL, ty INTEGER NOT NULL, no INTEGER NOT NULL,
+        PRIMARY KEY (seal, opid, ty, no)
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS terminals_opid ON terminals (opid);
+";
+
+/// Persistence provider storing stash, state and index in a single SQLite
+/// database file.
+///
+/// Providers loaded from the same store or its clones share a single
+/// connection to the database, thus their transactions are committed
+/// together.
+#[derive(Clone, Debug)]
+pub struct SqlStore {
+    pub path: PathBuf,
+    db: Arc<OnceLock<SharedDb>>,
+}
+
+impl PartialEq for SqlStore {
+    fn eq(&self, other: &Self) -> bool { self.path == other.path }
+}
+
+impl Eq for SqlStore {}
+
+impl SqlStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            db: default!(),
+        }
+    }
+
+    fn connect(&self) -> Result<SharedDb, PersistenceError> {
+        if let Some(db) = self.db.get() {
+            return Ok(db.clone());
+        }
+        let db = Db::open(Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        ))
+        .map_err(PersistenceError::with)?;
+        Ok(self.db.get_or_init(|| db).clone())
+    }
+
+    /// Checks whether the data of a provider using `db` are kept in the
+    /// database of this store.
+    fn contains(&self, db: &SharedDb) -> bool {
+        if self.db.get().is_some_and(|own| own.is(db)) {
+            return true;
+        }
+        let db = db.lock();
+        let Some(path) = db.conn.path() else {
+            return false;
+        };
+        match (self.path.canonicalize(), PathBuf::from(path).canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn copy(&self, f: impl FnOnce(&Db) -> Result<(), SqlError>) -> Result<(), PersistenceError> {
+        self.connect()?
+            .lock()
+            .in_transaction(f)
+            .map_err(PersistenceError::with)
+    }
+}
+
+impl StockPersistence<SqlStash, SqlState, SqlIndex> for SqlStore {
+    fn store_all(
+        &self,
+        stash: &SqlStash,
+        state: &SqlState,
+        index: &SqlIndex,
+    ) -> Result<(), PersistenceError> {
+        // Data of the providers sharing the database are already there, the others are
+        // copied within a single database transaction.
+        let stash = (!self.contains(&stash.db)).then_some(stash);
+        let state = (!self.contains(&state.db)).then_some(state);
+        let index = (!self.contains(&index.db)).then_some(index);
+        if stash.is_none() && state.is_none() && index.is_none() {
+            return Ok(());
+        }
+        self.copy(|db| {
+            if let Some(stash) = stash {
+                stash.copy_into(db)?;
+            }
+            if let Some(state) = state {
+                state.copy_into(db)?;
+            }
+            if let Some(index) = index {
+                index.copy_into(db)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Connection to a SQLite database shared by the providers.
+#[derive(Clone, Debug)]
+struct SharedDb(Arc<Mutex<Db>>);
+
+impl SharedDb {
+    fn lock(&self) -> MutexGuard<'_, Db> { self.0.lock().unwrap_or_else(PoisonError::into_inner) }
+
+    fn is(&self, other: &SharedDb) -> bool { Arc::ptr_eq(&self.0, &other.0) }
+}
+
+#[derive(Debug)]
+struct Db {
+    conn: Connection,
+    /// Number of the providers which have begun the current transaction and
+    /// haven't completed it yet.
+    depth: usize,
+}
+
+impl Db {
+    fn open(conn: rusqlite::Result<Connection>) -> Result<SharedDb, SqlError> {
+        let conn = conn?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SharedDb(Arc::new(Mutex::new(Db { conn, depth: 0 }))))
+    }
+
+    fn in_memory() -> SharedDb {
+        Self::open(Connection::open_in_memory())
+            .expect("unable to create in-memory SQLite database")
+    }
+
+    fn begin(&mut self) -> Result<(), SqlError> {
+        if self.depth == 0 {
+            self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Completes the transaction for one of the providers which have begun
+    /// it, committing the transaction once all of them are completed. If the
+    /// commit fails, the transaction must be rolled back.
+    fn complete(&mut self) -> Result<(), SqlError> {
+        if self.depth == 0 {
+            return Ok(());
+        }
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        self.depth = 0;
+        if !self.conn.is_autocommit() {
+            // SQLite reverts the transaction itself if it is unable to roll it back
+            self.conn.execute_batch("ROLLBACK").ok();
+        }
+    }
+
+    /// Runs `f` within a transaction, which is rolled back if `f` fails.
+    fn in_transaction(
+        &mut self,
+        f: impl FnOnce(&Db) -> Result<(), SqlError>,
+    ) -> Result<(), SqlError> {
+        self.begin()?;
+        let res = f(self).and_then(|_| self.complete());
+        if res.is_err() {
+            self.rollback();
+        }
+        res
+    }
+
+    fn query<T>(
+        &self,
+        sql: &str,
+        params: impl Params,
+        mut f: impl FnMut(&Row) -> Result<T, SqlReadError>,
+    ) -> Result<Vec<T>, SqlReadError> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let mut rows = stmt.query(params)?;
+        let mut res = vec![];
+        while let Some(row) = rows.next()? {
+            res.push(f(row)?);
+        }
+        Ok(res)
+    }
+
+    fn select<T: StrictDecode>(
+        &self,
+        sql: &str,
+        params: impl Params,
+    ) -> Result<Vec<T>, SqlReadError> {
+        self.query(sql, params, |row| column(row, 0))
+    }
+
+    fn select_pairs<A: StrictDecode, B: StrictDecode>(
+        &self,
+        sql: &str,
+        params: impl Params,
+    ) -> Result<Vec<(A, B)>, SqlReadError> {
+        self.query(sql, params, |row| Ok((column(row, 0)?, column(row, 1)?)))
+    }
+
+    fn select_opouts(&self, sql: &str, params: impl Params) -> Result<Vec<Opout>, SqlReadError> {
+        self.query(sql, params, |row| {
+            Ok(Opout::new(column(row, 0)?, AssignmentType::with(row.get(1)?), row.get(2)?))
+        })
+    }
+
+    fn exists(&self, sql: &str, params: impl Params) -> Result<bool, SqlReadError> {
+        Ok(self.conn.prepare_cached(sql)?.exists(params)?)
+    }
+
+    fn execute(&self, sql: &str, params: impl Params) -> Result<usize, SqlError> {
+        Ok(self.conn.prepare_cached(sql)?.execute(params)?)
+    }
+
+    fn ids<K: StrictDecode>(&self, table: &str) -> Result<Vec<K>, SqlReadError> {
+        self.select(&format!("SELECT id FROM {table}"), [])
+    }
+
+    fn rows<K: StrictDecode, V: StrictDecode>(
+        &self,
+        table: &str,
+    ) -> Result<Vec<(K, V)>, SqlReadError> {
+        self.select_pairs(&format!("SELECT id, data FROM {table}"), [])
+    }
+
+    fn data<V: StrictDecode>(
+        &self,
+        table: &str,
+        id: &impl StrictEncode,
+    ) -> Result<Option<V>, SqlReadError> {
+        Ok(self
+            .select(&format!("SELECT data FROM {table} WHERE id = ?1"), [key(id)?])?
+            .pop())
+    }
+
+    fn contains(&self, table: &str, id: &impl StrictEncode) -> Result<bool, SqlReadError> {
+        self.exists(&format!("SELECT 1 FROM {table} WHERE id = ?1"), [key(id)?])
+    }
+
+    fn put(
+        &self,
+        table: &str,
+        id: &impl StrictEncode,
+        data: &impl StrictEncode,
+    ) -> Result<(), SqlError> {
+        self.execute(&format!("INSERT OR REPLACE INTO {table} (id, data) VALUES (?1, ?2)"), [
+            encode(id)?,
+            encode(data)?,
+        ])?;
+        Ok(())
+    }
+
+    fn delete(&self, table: &str, id: &impl StrictEncode) -> Result<bool, SqlError> {
+        Ok(self.execute(&format!("DELETE FROM {table} WHERE id = ?1"), [encode(id)?])? > 0)
+    }
+
+    /// Replaces rows of the `tables` with the rows of the same tables of the
+    /// `source` database.
+    fn copy_tables(&self, source: &Db, tables: &[&str]) -> Result<(), SqlError> {
+        for table in tables {
+            self.conn.execute(&format!("DELETE FROM {table}"), [])?;
+            let mut select = source.conn.prepare(&format!("SELECT * FROM {table}"))?;
+            let columns = select.column_count();
+            let placeholders = vec!["?"; columns].join(", ");
+            let mut insert = self
+                .conn
+                .prepare(&format!("INSERT INTO {table} VALUES ({placeholders})"))?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let values = (0..columns)
+                    .map(|i| row.get::<_, Value>(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                insert.execute(params_from_iter(values))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode(data: &impl StrictEncode) -> io::Result<Vec<u8>> {
+    let writer = StrictWriter::in_memory::<U32MAX>();
+    Ok(data.strict_encode(writer)?.unbox().unconfine())
+}
+
+/// Encodes key of a row to be read.
+fn key(id: &impl StrictEncode) -> Result<Vec<u8>, SqlReadError> {
+    encode(id).map_err(SqlReadError::with)
+}
+
+fn decode<T: StrictDecode>(data: Vec<u8>) -> Result<T, DecodeError> {
+    let mut reader = StrictReader::with(StreamReader::new::<U32MAX>(data.as_slice()));
+    T::strict_decode(&mut reader)
+}
+
+fn column<T: StrictDecode>(row: &Row, idx: usize) -> Result<T, SqlReadError> {
+    Ok(decode(row.get::<_, Vec<u8>>(idx)?)?)
+}
+
+/// Table rows read from the database on the first access.
+#[derive(Debug)]
+struct LazyTable<K, V> {
+    name: &'static str,
+    rows: OnceLock<BTreeMap<K, OnceLock<V>>>,
+}
+
+impl<K: Copy + Ord + StrictEncode + StrictDecode, V: StrictDecode> LazyTable<K, V> {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            rows: OnceLock::new(),
+        }
+    }
+
+    fn rows(&self, db: &SharedDb) -> Result<&BTreeMap<K, OnceLock<V>>, SqlReadError> {
+        if let Some(rows) = self.rows.get() {
+            return Ok(rows);
+        }
+        let rows = db
+            .lock()
+            .ids(self.name)?
+            .into_iter()
+            .map(|id| (id, OnceLock::new()))
+            .collect();
+        Ok(self.rows.get_or_init(|| rows))
+    }
+
+    fn ids(&self, db: &SharedDb) -> Result<impl Iterator<Item = K> + '_, SqlReadError> {
+        Ok(self.rows(db)?.keys().copied())
+    }
+
+    fn get(&self, db: &SharedDb, id: K) -> Result<Option<&V>, SqlReadError> {
+        let Some(cell) = self.rows(db)?.get(&id) else {
+            return Ok(None);
+        };
+        if let Some(value) = cell.get() {
+            return Ok(Some(value));
+        }
+        let value = db.lock().data(self.name, &id)?.ok_or_else(|| {
+            SqlReadError::with(format!("row is removed from the {} table", self.name))
+        })?;
+        Ok(Some(cell.get_or_init(|| value)))
+    }
+
+    fn values(&self, db: &SharedDb) -> Result<impl Iterator<Item = &V>, SqlReadError> {
+        let rows = self.rows(db)?;
+        if rows.values().any(|cell| cell.get().is_none()) {
+            for (id, value) in db.lock().rows::<K, V>(self.name)? {
+                if let Some(cell) = rows.get(&id) {
+                    cell.get_or_init(|| value);
+                }
+            }
+        }
+        Ok(rows.values().filter_map(OnceLock::get))
+    }
+
+    fn insert(&mut self, id: K, value: V) {
+        if let Some(rows) = self.rows.get_mut() {
+            rows.insert(id, OnceLock::from(value));
+        }
+    }
+
+    fn remove(&mut self, id: K) {
+        if let Some(rows) = self.rows.get_mut() {
+            rows.remove(&id);
+        }
+    }
+
+    /// Takes the value out of the cache, reading it from the database if it
+    /// is not cached.
+    fn take(&mut self, db: &SharedDb, id: K) -> Result<Option<V>, SqlReadError> {
+        let cached = self
+            .rows
+            .get_mut()
+            .and_then(|rows| rows.get_mut(&id))
+            .and_then(OnceLock::take);
+        match cached {
+            Some(value) => Ok(Some(value)),
+            None => db.lock().data(self.name, &id),
+        }
+    }
+
+    fn reset(&mut self) { self.rows = OnceLock::new(); }
+}
+
+//////////
+// STASH
+//////////
+
+/// Stash stored in a SQLite database.
+#[derive(Debug)]
+pub struct SqlStash {
+    persistence: Option<Persistence<Self>>,
+    db: SharedDb,
+    defs: OnceLock<MemStash>,
+    geneses: LazyTable<ContractId, Genesis>,
+    extensions: LazyTable<OpId, Extension>,
+    bundles: LazyTable<BundleId, TransitionBundle>,
+    witnesses: LazyTable<XWitnessId, SealWitness>,
+    attachments: LazyTable<AttachId, MediumBlob>,
+    pruned: LazyTable<ContractId, PruneCheckpoint>,
+}
+
+impl SqlStash {
+    /// Constructs stash backed by an in-memory SQLite database.
+    pub fn in_memory() -> Self { Self::with(Db::in_memory()) }
+
+    fn with(db: SharedDb) -> Self {
+        Self {
+            persistence: None,
+            db,
+            defs: OnceLock::new(),
+            geneses: LazyTable::new(GENESES),
+            extensions: LazyTable::new(EXTENSIONS),
+            bundles: LazyTable::new(BUNDLES),
+            witnesses: LazyTable::new(WITNESSES),
+            attachments: LazyTable::new(ATTACHMENTS),
+            pruned: LazyTable::new(PRUNE_CHECKPOINTS),
+        }
+    }
+
+    /// Returns definitions kept by the stash, reading them from the database
+    /// on the first access.
+    fn defs(&self) -> Result<&MemStash, SqlReadError> {
+        if let Some(defs) = self.defs.get() {
+            return Ok(defs);
+        }
+        let defs = Self::read_defs(&self.db.lock())?;
+        Ok(self.defs.get_or_init(|| defs))
+    }
+
+    fn defs_mut(&mut self) -> Result<&mut MemStash, SqlError> {
+        self.defs()?;
+        Ok(self.defs.get_mut().expect("definitions are just read"))
+    }
+
+    fn read_defs(db: &Db) -> Result<MemStash, SqlReadError> {
+        let mut defs = MemStash::in_memory();
+        let types = db.rows::<SemId, Ty<SemId>>(TYPES)?;
+        defs.consume_types(TypeSystem::from(Confined::try_from_iter(types)?))?;
+        for (_, lib) in db.rows::<LibId, Lib>(LIBS)? {
+            defs.replace_lib(lib)?;
+        }
+        for (_, iface) in db.rows::<IfaceId, Iface>(IFACES)? {
+            defs.replace_iface(iface)?;
+        }
+        for (_, schema) in db.rows::<SchemaId, Schema>(SCHEMATA)? {
+            defs.replace_schema(schema)?;
+        }
+        for iimpl in db.select::<IfaceImpl>("SELECT data FROM iimpls", [])? {
+            if defs.schema(iimpl.schema_id).is_err() || defs.iface(iimpl.iface_id).is_err() {
+                return Err(SqlReadError::with(format!(
+                    "interface implementation {} refers to an unknown schema or interface",
+                    iimpl.impl_id()
+                )));
+            }
+            defs.replace_iimpl(iimpl)?;
+        }
+        for suppl in db.select::<Supplement>("SELECT data FROM supplements", [])? {
+            defs.add_supplement(suppl)?;
+        }
+        // Signatures are imported before the trust levels are set, so they are not filtered
+        // once again by the current trust levels
+        let mut sigs = BTreeMap::<ContentId, Vec<(Identity, SigBlob)>>::new();
+        let rows = db.query("SELECT content_id, identity, sig FROM sigs", [], |row| {
+            Ok((column(row, 0)?, column(row, 1)?, column(row, 2)?))
+        })?;
+        for (content_id, identity, sig) in rows {
+            sigs.entry(content_id).or_default().push((identity, sig));
+        }
+        for (content_id, sigs) in sigs {
+            defs.import_sigs(content_id, sigs)?;
+        }
+        let identities = db.query("SELECT id, trust FROM identities", [], |row| {
+            let trust = TrustLevel::try_from(row.get::<_, u8>(1)?).map_err(SqlReadError::with)?;
+            Ok((column(row, 0)?, trust))
+        })?;
+        for (identity, trust) in identities {
+            defs.set_trust(identity, trust)?;
+        }
+        Ok(defs)
+    }
+
+    fn copy_into(&self, target: &Db) -> Result<(), SqlError> {
+        target.copy_tables(&self.db.lock(), &STASH_TABLES)
+    }
+
+    fn reset(&mut self) {
+        self.defs = OnceLock::new();
+        self.geneses.reset();
+        self.extensions.reset();
+        self.bundles.reset();
+        self.witnesses.reset();
+        self.attachments.reset();
+        self.pruned.reset();
+    }
+}
+
+impl CloneNoPersistence for SqlStash {
+    fn clone_no_persistence(&self) -> Self {
+        let db = Db::in_memory();
+        db.lock()
+            .in_transaction(|target| self.copy_into(target))
+            .expect("unable to copy data into in-memory SQLite database");
+        Self::with(db)
+    }
+}
+
+impl Persisting for SqlStash {
+    #[inline]
+    fn persistence(&self) -> Option<&Persistence<Self>> { self.persistence.as_ref() }
+    #[inline]
+    fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
+    #[inline]
+    fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+}
+
+impl StoreTransaction for SqlStash {
+    type TransactionErr = SqlError;
+
+    #[inline]
+    fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> { self.db.lock().begin() }
+
+    #[inline]
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.complete_transaction()
+    }
+
+    // All the changes are already written within the database transaction
+    #[inline]
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(()) }
+
+    #[inline]
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.db.lock().complete()
+    }
+
+    fn rollback_transaction(&mut self) {
+        self.db.lock().rollback();
+        self.reset();
+    }
+}
+
+impl StashProvider for SqlStash {}
+
+/// Converts an error of the in-memory definitions into the provider error.
+fn lift<T>(
+    res: Result<T, StashProviderError<Infallible>>,
+) -> Result<T, StashProviderError<SqlReadError>> {
+    res.map_err(|err| match err {
+        StashProviderError::Inconsistency(e) => StashProviderError::Inconsistency(e),
+        StashProviderError::Iface(e) => StashProviderError::Iface(e),
+        StashProviderError::Connectivity(e) => match e {},
+    })
+}
+
+fn infallible<T>(res: Result<T, Infallible>) -> T { res.unwrap_or_else(|err| match err {}) }
+
+impl StashReadProvider for SqlStash {
+    type Error = SqlReadError;
+
+    #[inline]
+    fn type_system(&self) -> Result<&TypeSystem, Self::Error> {
+        Ok(infallible(self.defs()?.type_system()))
+    }
+
+    #[inline]
+    fn lib(&self, id: LibId) -> Result<&Lib, StashProviderError<Self::Error>> {
+        lift(
+            self.defs()
+                .map_err(StashProviderError::Connectivity)?
+                .lib(id),
+        )
+    }
+
+    #[inline]
+    fn ifaces(&self) -> Result<impl Iterator<Item = &Iface>, Self::Error> {
+        Ok(infallible(self.defs()?.ifaces()))
+    }
+
+    #[inline]
+    fn iface(&self, iface: impl Into<IfaceRef>) -> Result<&Iface, StashProviderError<Self::Error>> {
+        lift(
+            self.defs()
+                .map_err(StashProviderError::Connectivity)?
+                .iface(iface),
+        )
+    }
+
+    #[inline]
+    fn schemata(&self) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        Ok(infallible(self.defs()?.schemata()))
+    }
+
+    #[inline]
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> Result<&SchemaIfaces, StashProviderError<Self::Error>> {
+        lift(
+            self.defs()
+                .map_err(StashProviderError::Connectivity)?
+                .schema(schema_id),
+        )
+    }
+
+    #[inline]
+    fn schemata_by<C: IfaceClass>(
+        &self,
+    ) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        Ok(infallible(self.defs()?.schemata_by::<C>()))
+    }
+
+    #[inline]
+    fn impl_for<'a, C: IfaceClass + 'a>(
+        &'a self,
+        schema_ifaces: &'a SchemaIfaces,
+    ) -> Result<&'a IfaceImpl, StashProviderError<Self::Error>> {
+        lift(
+            self.defs()
+                .map_err(StashProviderError::Connectivity)?
+                .impl_for::<C>(schema_ifaces),
+        )
+    }
+
+    #[inline]
+    fn geneses(&self) -> Result<impl Iterator<Item = &Genesis>, Self::Error> {
+        self.geneses.values(&self.db)
+    }
+
+    fn geneses_by<C: IfaceClass>(&self) -> Result<impl Iterator<Item = &Genesis>, Self::Error> {
+        let schema_ids = infallible(self.defs()?.schemata_by::<C>())
+            .map(|schema_ifaces| schema_ifaces.schema.schema_id())
+            .collect::<BTreeSet<_>>();
+        Ok(self
+            .geneses
+            .values(&self.db)?
+            .filter(move |genesis| schema_ids.contains(&genesis.schema_id)))
+    }
+
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&Genesis, StashProviderError<Self::Error>> {
+        self.geneses
+            .get(&self.db, contract_id)
+            .map_err(StashProviderError::Connectivity)?
+            .ok_or(StashInconsistency::ContractAbsent(contract_id).into())
+    }
+
+    #[inline]
+    fn get_trust(&self, identity: &Identity) -> Result<TrustLevel, Self::Error> {
+        Ok(infallible(self.defs()?.get_trust(identity)))
+    }
+
+    #[inline]
+    fn identities(&self) -> Result<impl Iterator<Item = (Identity, TrustLevel)>, Self::Error> {
+        Ok(infallible(self.defs()?.identities()))
+    }
+
+    #[inline]
+    fn supplement(&self, content_ref: ContentRef) -> Result<Option<&Supplement>, Self::Error> {
+        Ok(infallible(self.defs()?.supplement(content_ref)))
+    }
+
+    #[inline]
+    fn supplements(
+        &self,
+        content_ref: ContentRef,
+    ) -> Result<impl Iterator<Item = Supplement>, Self::Error> {
+        Ok(infallible(self.defs()?.supplements(content_ref)))
+    }
+
+    #[inline]
+    fn sigs_for(&self, content_id: &ContentId) -> Result<Option<&ContentSigs>, Self::Error> {
+        Ok(infallible(self.defs()?.sigs_for(content_id)))
+    }
+
+    #[inline]
+    fn witness_ids(&self) -> Result<impl Iterator<Item = XWitnessId>, Self::Error> {
+        self.witnesses.ids(&self.db)
+    }
+
+    #[inline]
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId>, Self::Error> {
+        self.bundles.ids(&self.db)
+    }
+
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<&TransitionBundle, StashProviderError<Self::Error>> {
+        self.bundles
+            .get(&self.db, bundle_id)
+            .map_err(StashProviderError::Connectivity)?
+            .ok_or(StashInconsistency::BundleAbsent(bundle_id).into())
+    }
+
+    #[inline]
+    fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error> {
+        self.extensions.ids(&self.db)
+    }
+
+    fn extension(&self, op_id: OpId) -> Result<&Extension, StashProviderError<Self::Error>> {
+        self.extensions
+            .get(&self.db, op_id)
+            .map_err(StashProviderError::Connectivity)?
+            .ok_or(StashInconsistency::OperationAbsent(op_id).into())
+    }
+
+    #[inline]
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        self.attachments.ids(&self.db)
+    }
+
+    #[inline]
+    fn attachment(&self, id: AttachId) -> Result<Option<&MediumBlob>, Self::Error> {
+        self.attachments.get(&self.db, id)
+    }
+
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<&SealWitness, StashProviderError<Self::Error>> {
+        self.witnesses
+            .get(&self.db, witness_id)
+            .map_err(StashProviderError::Connectivity)?
+            .ok_or(StashInconsistency::WitnessAbsent(witness_id).into())
+    }
+
+    fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
+        Ok(self.db.lock().rows(TAPRETS)?.into_iter())
+    }
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> Result<Option<XChain<GraphSeal>>, Self::Error> {
+        self.db.lock().data(SECRET_SEALS, &secret)
+    }
+
+    fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
+        Ok(self
+            .db
+            .lock()
+            .select("SELECT data FROM secret_seals", [])?
+            .into_iter())
+    }
+
+    #[inline]
+    fn prune_checkpoint(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&PruneCheckpoint>, Self::Error> {
+        self.pruned.get(&self.db, contract_id)
+    }
+
+    fn witness_replacements(
+        &self,
+    ) -> Result<impl Iterator<Item = (XWitnessId, XWitnessId)>, Self::Error> {
+        Ok(self.db.lock().rows(WITNESS_REPLACEMENTS)?.into_iter())
+    }
+}
+
+impl StashWriteProvider for SqlStash {
+    type Error = SqlError;
+
+    fn replace_schema(&mut self, schema: Schema) -> Result<bool, Self::Error> {
+        let schema_id = schema.schema_id();
+        let added = self.defs_mut()?.replace_schema(schema.clone())?;
+        self.db.lock().put(SCHEMATA, &schema_id, &schema)?;
+        Ok(added)
+    }
+
+    fn replace_iface(&mut self, iface: Iface) -> Result<bool, Self::Error> {
+        let iface_id = iface.iface_id();
+        let added = self.defs_mut()?.replace_iface(iface.clone())?;
+        self.db.lock().put(IFACES, &iface_id, &iface)?;
+        Ok(added)
+    }
+
+    fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, Self::Error> {
+        let defs = self.defs_mut()?;
+        let added = defs.replace_iimpl(iimpl.clone())?;
+        let iface_name = defs
+            .iface(iimpl.iface_id)
+            .expect("interface presence is checked when the implementation is added")
+            .name
+            .to_string();
+        self.db.lock().execute(
+            "INSERT OR REPLACE INTO iimpls (schema_id, iface_name, iface_id, data)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                encode(&iimpl.schema_id)?,
+                iface_name,
+                encode(&iimpl.iface_id)?,
+                encode(&iimpl)?
+            ],
+        )?;
+        Ok(added)
+    }
+
+    fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, Self::Error> {
+        let contract_id = genesis.contract_id();
+        let db = self.db.lock();
+        let present = db.contains(GENESES, &contract_id)?;
+        db.execute("INSERT OR REPLACE INTO geneses (id, schema_id, data) VALUES (?1, ?2, ?3)", [
+            encode(&contract_id)?,
+            encode(&genesis.schema_id)?,
+            encode(&genesis)?,
+        ])?;
+        drop(db);
+        self.geneses.insert(contract_id, genesis);
+        Ok(!present)
+    }
+
+    fn replace_extension(&mut self, extension: Extension) -> Result<bool, Self::Error> {
+        let opid = extension.id();
+        let db = self.db.lock();
+        let present = db.contains(EXTENSIONS, &opid)?;
+        db.execute(
+            "INSERT OR REPLACE INTO extensions (id, contract_id, data) VALUES (?1, ?2, ?3)",
+            [encode(&opid)?, encode(&extension.contract_id)?, encode(&extension)?],
+        )?;
+        drop(db);
+        self.extensions.insert(opid, extension);
+        Ok(!present)
+    }
+
+    fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, Self::Error> {
+        let bundle_id = bundle.bundle_id();
+        let db = self.db.lock();
+        let present = db.contains(BUNDLES, &bundle_id)?;
+        db.put(BUNDLES, &bundle_id, &bundle)?;
+        drop(db);
+        self.bundles.insert(bundle_id, bundle);
+        Ok(!present)
+    }
+
+    fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, Self::Error> {
+        let witness_id = witness.witness_id();
+        let db = self.db.lock();
+        let present = db.contains(WITNESSES, &witness_id)?;
+        db.put(WITNESSES, &witness_id, &witness)?;
+        match witness_tapret(&witness) {
+            Some(tapret) => db.put(TAPRETS, &witness_id, &tapret)?,
+            None => {
+                db.delete(TAPRETS, &witness_id)?;
+            }
+        }
+        drop(db);
+        self.witnesses.insert(witness_id, witness);
+        Ok(!present)
+    }
+
+    fn replace_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> Result<bool, Self::Error> {
+        let db = self.db.lock();
+        let present = db.contains(ATTACHMENTS, &id)?;
+        db.put(ATTACHMENTS, &id, &attach)?;
+        drop(db);
+        self.attachments.insert(id, attach);
+        Ok(!present)
+    }
+
+    fn replace_lib(&mut self, lib: Lib) -> Result<bool, Self::Error> {
+        let lib_id = lib.id();
+        let added = self.defs_mut()?.replace_lib(lib.clone())?;
+        self.db.lock().put(LIBS, &lib_id, &lib)?;
+        Ok(added)
+    }
+
+    fn consume_types(&mut self, types: TypeSystem) -> Result<(), Self::Error> {
+        self.defs_mut()?.consume_types(types.clone())?;
+        let db = self.db.lock();
+        for (id, ty) in types.iter() {
+            db.put(TYPES, id, ty)?;
+        }
+        Ok(())
+    }
+
+    fn set_trust(&mut self, identity: Identity, trust: TrustLevel) -> Result<(), Self::Error> {
+        self.defs_mut()?.set_trust(identity.clone(), trust)?;
+        self.db.lock().execute(
+            "INSERT OR REPLACE INTO identities (id, trust) VALUES (?1, ?2)",
+            params![encode(&identity)?, trust as u8],
+        )?;
+        Ok(())
+    }
+
+    fn add_supplement(&mut self, suppl: Supplement) -> Result<(), Self::Error> {
+        self.defs_mut()?.add_supplement(suppl.clone())?;
+        self.db.lock().execute(
+            "INSERT OR IGNORE INTO supplements (content_ref, data) VALUES (?1, ?2)",
+            [encode(&suppl.content_id)?, encode(&suppl)?],
+        )?;
+        Ok(())
+    }
+
+    fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), Self::Error>
+    where I: IntoIterator<Item = (Identity, SigBlob)> {
+        let sigs = sigs.into_iter().collect::<Vec<_>>();
+        let defs = self.defs_mut()?;
+        defs.import_sigs(content_id, sigs.clone())?;
+        // The signers unknown before are added to the identities with the default trust level
+        let identities = sigs
+            .into_iter()
+            .map(|(identity, _)| {
+                let trust = infallible(defs.get_trust(&identity));
+                (identity, trust)
+            })
+            .collect::<Vec<_>>();
+        let accepted = infallible(defs.sigs_for(&content_id))
+            .cloned()
+            .map(ContentSigs::into_iter)
+            .into_iter()
+            .flatten();
+        let db = self.db.lock();
+        for (identity, trust) in identities {
+            db.execute("INSERT OR IGNORE INTO identities (id, trust) VALUES (?1, ?2)", params![
+                encode(&identity)?,
+                trust as u8
+            ])?;
+        }
+        for (identity, sig) in accepted {
+            db.execute(
+                "INSERT OR REPLACE INTO sigs (content_id, identity, sig) VALUES (?1, ?2, ?3)",
+                [encode(&content_id)?, encode(&identity)?, encode(&sig)?],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let db = self.db.lock();
+        db.delete(PRUNE_CHECKPOINTS, &contract_id)?;
+        let removed = db.delete(GENESES, &contract_id)?;
+        drop(db);
+        self.pruned.remove(contract_id);
+        self.geneses.remove(contract_id);
+        Ok(removed)
+    }
+
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, Self::Error> {
+        let removed = self.db.lock().delete(EXTENSIONS, &opid)?;
+        self.extensions.remove(opid);
+        Ok(removed)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        let removed = self.db.lock().delete(BUNDLES, &bundle_id)?;
+        self.bundles.remove(bundle_id);
+        Ok(removed)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        let db = self.db.lock();
+        db.delete(TAPRETS, &witness_id)?;
+        db.delete(WITNESS_REPLACEMENTS, &witness_id)?;
+        let removed = db.delete(WITNESSES, &witness_id)?;
+        drop(db);
+        self.witnesses.remove(witness_id);
+        Ok(removed)
+    }
+
+    fn remove_sigs(&mut self, content_id: ContentId) -> Result<bool, Self::Error> {
+        self.defs_mut()?.remove_sigs(content_id)?;
+        let removed = self
+            .db
+            .lock()
+            .execute("DELETE FROM sigs WHERE content_id = ?1", [encode(&content_id)?])?;
+        Ok(removed > 0)
+    }
+
+    fn remove_supplements(&mut self, content_ref: ContentRef) -> Result<bool, Self::Error> {
+        self.defs_mut()?.remove_supplements(content_ref)?;
+        let removed = self
+            .db
+            .lock()
+            .execute("DELETE FROM supplements WHERE content_ref = ?1", [encode(&content_ref)?])?;
+        Ok(removed > 0)
+    }
+
+    fn remove_attachment(&mut self, id: AttachId) -> Result<bool, Self::Error> {
+        let removed = self.db.lock().delete(ATTACHMENTS, &id)?;
+        self.attachments.remove(id);
+        Ok(removed)
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        self.db.lock().delete(SECRET_SEALS, &seal.conceal())
+    }
+
+    fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        let added = self.db.lock().execute(
+            "INSERT OR IGNORE INTO secret_seals (id, data) VALUES (?1, ?2)",
+            [encode(&seal.conceal())?, encode(&seal)?],
+        )?;
+        Ok(added > 0)
+    }
+
+    fn set_prune_checkpoint(
+        &mut self,
+        contract_id: ContractId,
+        checkpoint: PruneCheckpoint,
+    ) -> Result<(), Self::Error> {
+        self.db
+            .lock()
+            .put(PRUNE_CHECKPOINTS, &contract_id, &checkpoint)?;
+        self.pruned.insert(contract_id, checkpoint);
+        Ok(())
+    }
+
+    fn set_witness_replacement(
+        &mut self,
+        replaced: XWitnessId,
+        replacement: XWitnessId,
+    ) -> Result<(), Self::Error> {
+        self.db
+            .lock()
+            .put(WITNESS_REPLACEMENTS, &replaced, &replacement)
+    }
+}
+
+impl PersistenceProvider<SqlStash> for SqlStore {
+    fn load(&self) -> Result<SqlStash, PersistenceError> { Ok(SqlStash::with(self.connect()?)) }
+
+    fn store(&self, object: &SqlStash) -> Result<(), PersistenceError> {
+        if self.contains(&object.db) {
+            return Ok(());
+        }
+        self.copy(|db| object.copy_into(db))
+    }
+}
+
+//////////
+// STATE
+//////////
+
+/// Contract state stored in a SQLite database.
+#[derive(Debug)]
+pub struct SqlState {
+    persistence: Option<Persistence<Self>>,
+    db: SharedDb,
+    contracts: LazyTable<ContractId, MemContractState>,
+    /// Contracts changed since the last pre-commit, which are written into
+    /// the database by the next one.
+    updated: BTreeMap<ContractId, MemContractState>,
+}
+
+impl SqlState {
+    /// Constructs state backed by an in-memory SQLite database.
+    pub fn in_memory() -> Self { Self::with(Db::in_memory()) }
+
+    fn with(db: SharedDb) -> Self {
+        Self {
+            persistence: None,
+            db,
+            contracts: LazyTable::new(CONTRACT_STATES),
+            updated: empty!(),
+        }
+    }
+
+    fn unfiltered(&self, contract_id: ContractId) -> Result<&MemContractState, SqlStateError> {
+        if let Some(contract) = self.updated.get(&contract_id) {
+            return Ok(contract);
+        }
+        Ok(self
+            .contracts
+            .get(&self.db, contract_id)?
+            .ok_or(StateInconsistency::UnknownContract(contract_id))?)
+    }
+
+    fn contract_read_where<'a>(
+        &self,
+        unfiltered: &'a MemContractState,
+        predicate: impl Fn(WitnessOrd) -> bool,
+    ) -> Result<MemContract<&'a MemContractState>, SqlStateError> {
+        Ok(MemContract::filtered(
+            unfiltered,
+            |witness_id| self.db.lock().data(WITNESS_ORDS, &witness_id),
+            predicate,
+        )?)
+    }
+
+    /// Moves state of the contract into the updated contracts, returning
+    /// whether the contract is known.
+    fn update(&mut self, contract_id: ContractId) -> Result<bool, SqlError> {
+        if self.updated.contains_key(&contract_id) {
+            return Ok(true);
+        }
+        let Some(contract) = self.contracts.take(&self.db, contract_id)? else {
+            return Ok(false);
+        };
+        self.updated.insert(contract_id, contract);
+        Ok(true)
+    }
+
+    fn copy_into(&self, target: &Db) -> Result<(), SqlError> {
+        target.copy_tables(&self.db.lock(), &STATE_TABLES)?;
+        for (contract_id, contract) in &self.updated {
+            target.put(CONTRACT_STATES, contract_id, contract)?;
+        }
+        Ok(())
+    }
+}
+
+impl CloneNoPersistence for SqlState {
+    fn clone_no_persistence(&self) -> Self {
+        let db = Db::in_memory();
+        db.lock()
+            .in_transaction(|target| self.copy_into(target))
+            .expect("unable to copy data into in-memory SQLite database");
+        Self::with(db)
+    }
+}
+
+impl Persisting for SqlState {
+    #[inline]
+    fn persistence(&self) -> Option<&Persistence<Self>> { self.persistence.as_ref() }
+    #[inline]
+    fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
+    #[inline]
+    fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+}
+
+impl StoreTransaction for SqlState {
+    type TransactionErr = SqlError;
+
+    #[inline]
+    fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> { self.db.lock().begin() }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.precommit_transaction()?;
+        self.complete_transaction()
+    }
+
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        let db = self.db.lock();
+        for (contract_id, contract) in &self.updated {
+            db.put(CONTRACT_STATES, contract_id, contract)?;
+        }
+        drop(db);
+        for (contract_id, contract) in mem::take(&mut self.updated) {
+            self.contracts.insert(contract_id, contract);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.db.lock().complete()
+    }
+
+    fn rollback_transaction(&mut self) {
+        self.db.lock().rollback();
+        self.updated.clear();
+        self.contracts.reset();
+    }
+}
+
+impl StateProvider for SqlState {}
+
+impl StateReadProvider for SqlState {
+    type ContractRead<'a> = MemContract<&'a MemContractState>;
+    type Error = SqlStateError;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Self::ContractRead<'_>, Self::Error> {
+        self.contract_read_where(self.unfiltered(contract_id)?, |_| true)
+    }
+
+    fn contract_state_at(
+        &self,
+        contract_id: ContractId,
+        layer1: Layer1,
+        height: u32,
+    ) -> Result<Self::ContractRead<'_>, Self::Error> {
+        self.contract_read_where(self.unfiltered(contract_id)?, |ord| {
+            matches!(ord, WitnessOrd::Mined(pos)
+                if pos.layer1() == layer1 && pos.height().get() <= height)
+        })
+    }
+
+    fn is_valid_witness(&self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        Ok(self.witness_ord(witness_id)?.is_valid())
+    }
+
+    fn witness_ord(&self, witness_id: XWitnessId) -> Result<WitnessOrd, Self::Error> {
+        Ok(self
+            .db
+            .lock()
+            .data(WITNESS_ORDS, &witness_id)?
+            .ok_or(StateInconsistency::AbsentWitness(witness_id))?)
+    }
+}
+
+pub struct SqlContractWriter<'a> {
+    inner: MemContractWriter<'a>,
+    db: &'a SharedDb,
+}
+
+impl<'a> SqlContractWriter<'a> {
+    fn new(contract: &'a mut MemContractState, db: &'a SharedDb) -> Self {
+        // Witness ordering is written into the database by this writer
+        Self {
+            inner: MemContractWriter::new(|_, _| Ok(()), contract),
+            db,
+        }
+    }
+}
+
+impl ContractStateWrite for SqlContractWriter<'_> {
+    type Error = SqlError;
+
+    fn add_genesis(&mut self, genesis: &Genesis) -> Result<(), Self::Error> {
+        Ok(self.inner.add_genesis(genesis)?)
+    }
+
+    fn add_transition(
+        &mut self,
+        transition: &Transition,
+        witness_id: XWitnessId,
+        witness_ord: WitnessOrd,
+    ) -> Result<(), Self::Error> {
+        self.db
+            .lock()
+            .put(WITNESS_ORDS, &witness_id, &witness_ord)?;
+        Ok(self
+            .inner
+            .add_transition(transition, witness_id, witness_ord)?)
+    }
+
+    fn add_extension(
+        &mut self,
+        extension: &Extension,
+        witness_id: XWitnessId,
+        witness_ord: WitnessOrd,
+    ) -> Result<(), Self::Error> {
+        self.db
+            .lock()
+            .put(WITNESS_ORDS, &witness_id, &witness_ord)?;
+        Ok(self
+            .inner
+            .add_extension(extension, witness_id, witness_ord)?)
+    }
+}
+
+impl StateWriteProvider for SqlState {
+    type ContractWrite<'a> = SqlContractWriter<'a>;
+    type Error = SqlError;
+
+    fn register_contract(
+        &mut self,
+        schema: &Schema,
+        genesis: &Genesis,
+    ) -> Result<Self::ContractWrite<'_>, Self::Error> {
+        let contract_id = genesis.contract_id();
+        if !self.update(contract_id)? {
+            self.updated
+                .insert(contract_id, MemContractState::new(schema, contract_id));
+        }
+        let contract = self
+            .updated
+            .get_mut(&contract_id)
+            .expect("contract state is just updated");
+        let mut writer = SqlContractWriter::new(contract, &self.db);
+        writer.add_genesis(genesis)?;
+        Ok(writer)
+    }
+
+    fn update_contract(
+        &mut self,
+        contract_id: ContractId,
+    ) -> Result<Option<Self::ContractWrite<'_>>, Self::Error> {
+        if !self.update(contract_id)? {
+            return Ok(None);
+        }
+        Ok(self
+            .updated
+            .get_mut(&contract_id)
+            .map(|contract| SqlContractWriter::new(contract, &self.db)))
+    }
+
+    fn update_witnesses(
+        &mut self,
+        resolver: impl ResolveWitnessBatch,
+        after_height: u32,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<UpdateRes, Self::Error> {
+        let witnesses = self.db.lock().rows(WITNESS_ORDS)?;
+        let res = resolve_witness_ords(witnesses, resolver, after_height, progress);
+        self.begin_transaction()?;
+        for (id, update) in &res.updated {
+            let written = self.db.lock().put(WITNESS_ORDS, id, &update.to);
+            written.inspect_err(|_| self.rollback_transaction())?;
+        }
+        self.commit_transaction()?;
+        Ok(res)
+    }
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let removed = self.db.lock().delete(CONTRACT_STATES, &contract_id)?;
+        self.contracts.remove(contract_id);
+        Ok(self.updated.remove(&contract_id).is_some() || removed)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        self.db.lock().delete(WITNESS_ORDS, &witness_id)
+    }
+}
+
+impl PersistenceProvider<SqlState> for SqlStore {
+    fn load(&self) -> Result<SqlState, PersistenceError> { Ok(SqlState::with(self.connect()?)) }
+
+    fn store(&self, object: &SqlState) -> Result<(), PersistenceError> {
+        if self.contains(&object.db) {
+            // Contracts updated outside of a transaction are written on its pre-commit
+            return Ok(());
+        }
+        self.copy(|db| object.copy_into(db))
+    }
+}
+
+//////////
+// INDEX
+//////////
+
+/// Index stored in a SQLite database.
+#[derive(Debug)]
+pub struct SqlIndex {
+    persistence: Option<Persistence<Self>>,
+    db: SharedDb,
+}
+
+impl SqlIndex {
+    /// Constructs index backed by an in-memory SQLite database.
+    pub fn in_memory() -> Self { Self::with(Db::in_memory()) }
+
+    fn with(db: SharedDb) -> Self {
+        Self {
+            persistence: None,
+            db,
+        }
+    }
+
+    fn copy_into(&self, target: &Db) -> Result<(), SqlError> {
+        target.copy_tables(&self.db.lock(), &INDEX_TABLES)
+    }
+
+    fn index_assignments<State: ExposedState, Seal: ExposedSeal>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, Seal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        output: impl Fn(&XChain<Seal>) -> XOutputSeal,
+    ) -> Result<(), IndexWriteError<SqlError>> {
+        let db = self.db.lock();
+        if !db.contains(INDEXED_CONTRACTS, &contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+        for (no, assign) in vec.iter().enumerate() {
+            let opout = Opout::new(opid, type_id, no as u16);
+            match assign {
+                Assign::ConfidentialState { seal, .. } | Assign::Revealed { seal, .. } => {
+                    add_outpoint_opout(&db, contract_id, output(seal).to_outpoint(), opout)?;
+                }
+                Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } => {
+                    add_terminal(&db, *seal, opout)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn add_outpoint_opout(
+    db: &Db,
+    contract_id: ContractId,
+    outpoint: XOutpoint,
+    opout: Opout,
+) -> Result<(), SqlError> {
+    db.execute(
+        "INSERT OR IGNORE INTO outpoint_opouts (contract_id, outpoint, opid, ty, no)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            encode(&contract_id)?,
+            encode(&outpoint)?,
+            encode(&opout.op)?,
+            opout.ty.to_inner(),
+            opout.no
+        ],
+    )?;
+    Ok(())
+}
+
+fn add_terminal(db: &Db, seal: XChain<SecretSeal>, opout: Opout) -> Result<(), SqlError> {
+    db.execute(
+        "INSERT OR IGNORE INTO terminals (seal, opid, ty, no) VALUES (?1, ?2, ?3, ?4)",
+        params![encode(&seal)?, encode(&opout.op)?, opout.ty.to_inner(), opout.no],
+    )?;
+    Ok(())
+}
+
+impl CloneNoPersistence for SqlIndex {
+    fn clone_no_persistence(&self) -> Self {
+        let db = Db::in_memory();
+        db.lock()
+            .in_transaction(|target| self.copy_into(target))
+            .expect("unable to copy data into in-memory SQLite database");
+        Self::with(db)
+    }
+}
+
+impl Persisting for SqlIndex {
+    #[inline]
+    fn persistence(&self) -> Option<&Persistence<Self>> { self.persistence.as_ref() }
+    #[inline]
+    fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
+    #[inline]
+    fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+}
+
+impl StoreTransaction for SqlIndex {
+    type TransactionErr = SqlError;
+
+    #[inline]
+    fn begin_transaction(&mut self) -> Result<(), Self::TransactionErr> { self.db.lock().begin() }
+
+    #[inline]
+    fn commit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.complete_transaction()
+    }
+
+    // All the changes are already written within the database transaction
+    #[inline]
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> { Ok(()) }
+
+    #[inline]
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.db.lock().complete()
+    }
+
+    #[inline]
+    fn rollback_transaction(&mut self) { self.db.lock().rollback(); }
+}
+
+impl IndexProvider for SqlIndex {}
+
+impl IndexReadProvider for SqlIndex {
+    type Error = SqlReadError;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutpoint>,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, Self::Error> {
+        let db = self.db.lock();
+        let mut contracts = BTreeSet::new();
+        for outpoint in outputs {
+            contracts.extend(db.select::<ContractId>(
+                "SELECT DISTINCT contract_id FROM outpoint_opouts WHERE outpoint = ?1",
+                [key(&outpoint)?],
+            )?);
+        }
+        Ok(contracts.into_iter())
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        if !self.db.lock().contains(INDEXED_CONTRACTS, &contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+        // Like the in-memory index, we do not track public opouts
+        Ok(empty!())
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutpoint>>,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        let db = self.db.lock();
+        if !db.contains(INDEXED_CONTRACTS, &contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+        let mut opouts = BTreeSet::new();
+        for output in outputs.into_iter().map(Into::into) {
+            let found = db.select_opouts(
+                "SELECT opid, ty, no FROM outpoint_opouts WHERE contract_id = ?1 AND outpoint = ?2",
+                [key(&contract_id)?, key(&output)?],
+            )?;
+            if found.is_empty() {
+                return Err(IndexInconsistency::OutpointUnknown(output, contract_id).into());
+            }
+            opouts.extend(found);
+        }
+        Ok(opouts)
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<BTreeSet<Opout>, Self::Error> {
+        let db = self.db.lock();
+        let mut opouts = BTreeSet::new();
+        for seal in terminals {
+            opouts.extend(
+                db.select_opouts("SELECT opid, ty, no FROM terminals WHERE seal = ?1", [key(
+                    &seal,
+                )?])?,
+            );
+        }
+        Ok(opouts)
+    }
+
+    fn bundle_id_for_op(&self, opid: OpId) -> Result<BundleId, IndexReadError<Self::Error>> {
+        self.db
+            .lock()
+            .select("SELECT bundle_id FROM op_bundles WHERE opid = ?1", [key(&opid)?])?
+            .pop()
+            .ok_or(IndexInconsistency::BundleAbsent(opid).into())
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<(impl Iterator<Item = XWitnessId>, ContractId), IndexReadError<Self::Error>> {
+        let db = self.db.lock();
+        let witness_ids = db.select::<XWitnessId>(
+            "SELECT witness_id FROM bundle_witnesses WHERE bundle_id = ?1",
+            [key(&bundle_id)?],
+        )?;
+        if witness_ids.is_empty() {
+            return Err(IndexInconsistency::BundleWitnessUnknown(bundle_id).into());
+        }
+        let contract_id = db
+            .select("SELECT contract_id FROM bundle_contracts WHERE bundle_id = ?1", [key(
+                &bundle_id,
+            )?])?
+            .pop()
+            .ok_or(IndexInconsistency::BundleContractUnknown(bundle_id))?;
+        Ok((witness_ids.into_iter(), contract_id))
+    }
+
+    fn indexed_ops(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        Ok(self
+            .db
+            .lock()
+            .select_pairs("SELECT opid, bundle_id FROM op_bundles", [])?
+            .into_iter())
+    }
+}
+
+impl IndexWriteProvider for SqlIndex {
+    type Error = SqlError;
+
+    fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let added = self
+            .db
+            .lock()
+            .execute("INSERT OR IGNORE INTO indexed_contracts (id) VALUES (?1)", [encode(
+                &contract_id,
+            )?])?;
+        Ok(added > 0)
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let db = self.db.lock();
+        let present = db
+            .select::<ContractId>(
+                "SELECT contract_id FROM bundle_contracts WHERE bundle_id = ?1",
+                [key(&bundle_id)?],
+            )?
+            .pop();
+        if let Some(alt) = present.filter(|alt| *alt != contract_id) {
+            return Err(IndexInconsistency::DistinctBundleContract {
+                bundle_id,
+                present: alt,
+                expected: contract_id,
+            }
+            .into());
+        }
+        db.execute(
+            "INSERT OR IGNORE INTO bundle_witnesses (bundle_id, witness_id) VALUES (?1, ?2)",
+            [key(&bundle_id)?, key(&witness_id)?],
+        )?;
+        db.execute(
+            "INSERT OR REPLACE INTO bundle_contracts (bundle_id, contract_id) VALUES (?1, ?2)",
+            [key(&bundle_id)?, key(&contract_id)?],
+        )?;
+        Ok(present.is_none())
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let db = self.db.lock();
+        let present = db
+            .select::<BundleId>("SELECT bundle_id FROM op_bundles WHERE opid = ?1", [key(&opid)?])?
+            .pop();
+        if let Some(alt) = present.filter(|alt| *alt != bundle_id) {
+            return Err(IndexInconsistency::DistinctBundleOp {
+                opid,
+                present: alt,
+                expected: bundle_id,
+            }
+            .into());
+        }
+        db.execute("INSERT OR REPLACE INTO op_bundles (opid, bundle_id) VALUES (?1, ?2)", [
+            key(&opid)?,
+            key(&bundle_id)?,
+        ])?;
+        Ok(present.is_none())
+    }
+
+    fn index_genesis_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GenesisSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        self.index_assignments(contract_id, vec, opid, type_id, |seal| {
+            seal.to_output_seal()
+                .expect("genesis seals always have outpoint")
+        })
+    }
+
+    fn index_transition_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GraphSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        witness_id: XWitnessId,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        self.index_assignments(contract_id, vec, opid, type_id, |seal| {
+            seal.try_to_output_seal(witness_id).unwrap_or_else(|_| {
+                panic!(
+                    "chain mismatch between assignment vout seal ({}) and witness transaction ({})",
+                    seal, witness_id
+                )
+            })
+        })
+    }
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let db = self.db.lock();
+        db.execute("DELETE FROM outpoint_opouts WHERE contract_id = ?1", [encode(&contract_id)?])?;
+        db.delete(INDEXED_CONTRACTS, &contract_id)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        let db = self.db.lock();
+        let id = encode(&bundle_id)?;
+        db.execute("DELETE FROM op_bundles WHERE bundle_id = ?1", [&id])?;
+        db.execute("DELETE FROM bundle_witnesses WHERE bundle_id = ?1", [&id])?;
+        Ok(db.execute("DELETE FROM bundle_contracts WHERE bundle_id = ?1", [&id])? > 0)
+    }
+
+    fn remove_terminals(&mut self, opids: &BTreeSet<OpId>) -> Result<(), Self::Error> {
+        let db = self.db.lock();
+        for opid in opids {
+            db.execute("DELETE FROM terminals WHERE opid = ?1", [encode(opid)?])?;
+        }
+        Ok(())
+    }
+}
+
+impl PersistenceProvider<SqlIndex> for SqlStore {
+    fn load(&self) -> Result<SqlIndex, PersistenceError> { Ok(SqlIndex::with(self.connect()?)) }
+
+    fn store(&self, object: &SqlIndex) -> Result<(), PersistenceError> {
+        if self.contains(&object.db) {
+            return Ok(());
+        }
+        self.copy(|db| object.copy_into(db))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::containers::{ConsignmentExt, Contract};
+    use crate::persistence::fixture::Transfers;
+    use crate::persistence::{ContractStateRead, Stock};
+
+    type SqlStock = Stock<SqlStash, SqlState, SqlIndex>;
+
+    fn temp_store() -> (PathBuf, SqlStore) {
+        let dir = std::env::temp_dir().join(format!("rgb-sql-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = SqlStore::new(dir.join("stock.db"));
+        (dir, store)
+    }
+
+    fn secret_seal(vout: u32) -> XChain<GraphSeal> {
+        XChain::with(
+            rgb::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::TapretFirst, bp::Vout::from_u32(vout)),
+        )
+    }
+
+    #[test]
+    fn sql_secret_seal_commit_rollback() {
+        let seal = secret_seal(0);
+
+        let mut stash = SqlStash::in_memory();
+        stash.begin_transaction().unwrap();
+        stash.add_secret_seal(seal).unwrap();
+        stash.rollback_transaction();
+        assert_eq!(stash.secret_seals().unwrap().count(), 0);
+
+        stash.begin_transaction().unwrap();
+        stash.add_secret_seal(seal).unwrap();
+        stash.commit_transaction().unwrap();
+        stash.rollback_transaction();
+        assert_eq!(stash.secret_seals().unwrap().collect::<Vec<_>>(), vec![seal]);
+    }
+
+    #[test]
+    fn sql_stock_reload() {
+        let (dir, store) = temp_store();
+        let seal = secret_seal(1);
+
+        let mut stock = SqlStock::load_atomic(store.clone(), true).unwrap();
+        stock.store_secret_seal(seal).unwrap();
+        drop(stock);
+
+        let stock = SqlStock::load_atomic(store, true).unwrap();
+        assert_eq!(
+            stock
+                .as_stash_provider()
+                .secret_seals()
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![seal]
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn sql_stock_transaction_atomicity() {
+        let (dir, store) = temp_store();
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let contract_id = contract.contract_id();
+        let seal = secret_seal(0);
+        let transaction = |stock: &mut SqlStock| {
+            stock.store_transaction::<Infallible>(|stash, state, index| {
+                stash.as_provider_mut().add_secret_seal(seal).unwrap();
+                state
+                    .as_provider_mut()
+                    .register_contract(contract.schema(), contract.genesis())
+                    .unwrap();
+                index
+                    .as_provider_mut()
+                    .register_contract(contract_id)
+                    .unwrap();
+                Ok(())
+            })
+        };
+
+        // The state is written by its pre-commit, after the index rows are already
+        // written within the same database transaction
+        let mut stock = SqlStock::load_atomic(store.clone(), true).unwrap();
+        stock
+            .as_state_provider()
+            .db
+            .lock()
+            .conn
+            .execute_batch(
+                "CREATE TRIGGER failure BEFORE INSERT ON contract_states
+                 BEGIN SELECT RAISE(ABORT, 'failure'); END;",
+            )
+            .unwrap();
+        assert!(transaction(&mut stock).is_err());
+        drop(stock);
+
+        let mut stock = SqlStock::load_atomic(store.clone(), true).unwrap();
+        assert_eq!(stock.as_stash_provider().secret_seals().unwrap().count(), 0);
+        assert!(stock
+            .as_state_provider()
+            .contract_state(contract_id)
+            .is_err());
+        assert!(stock
+            .as_index_provider()
+            .public_opouts(contract_id)
+            .is_err());
+
+        stock
+            .as_state_provider()
+            .db
+            .lock()
+            .conn
+            .execute_batch("DROP TRIGGER failure")
+            .unwrap();
+        transaction(&mut stock).unwrap();
+        drop(stock);
+
+        let stock = SqlStock::load_atomic(store, true).unwrap();
+        assert_eq!(
+            stock
+                .as_stash_provider()
+                .secret_seals()
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![seal]
+        );
+        assert!(stock
+            .as_state_provider()
+            .contract_state(contract_id)
+            .is_ok());
+        assert!(stock.as_index_provider().public_opouts(contract_id).is_ok());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn sql_trust_reload() {
+        let (dir, store) = temp_store();
+        let identity = Identity::default();
+
+        let mut stock = SqlStock::load_atomic(store.clone(), true).unwrap();
+        stock
+            .as_stash_provider_mut()
+            .set_trust(identity.clone(), TrustLevel::Trusted)
+            .unwrap();
+        drop(stock);
+
+        let stock = SqlStock::load_atomic(store, true).unwrap();
+        assert_eq!(stock.as_stash_provider().get_trust(&identity).unwrap(), TrustLevel::Trusted);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn sql_stock_transfers_reload() {
+        let (dir, store) = temp_store();
+
+        let mut stock = SqlStock::load_atomic(store.clone(), true).unwrap();
+        let transfers = Transfers::consume(&mut stock);
+        let contract_id = transfers.fixture.contract_id();
+        let state = |stock: &SqlStock| {
+            let contract = stock
+                .as_state_provider()
+                .contract_state(contract_id)
+                .unwrap();
+            contract.rights_all().cloned().collect::<Vec<_>>()
+        };
+        let ops = |stock: &SqlStock| {
+            stock
+                .as_index_provider()
+                .indexed_ops()
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        let rights = state(&stock);
+        let indexed_ops = ops(&stock);
+        assert!(!rights.is_empty());
+        drop(stock);
+
+        let stock = SqlStock::load_atomic(store, true).unwrap();
+        assert_eq!(state(&stock), rights);
+        assert_eq!(ops(&stock), indexed_ops);
+        assert!(stock.check_consistency().unwrap().is_consistent());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
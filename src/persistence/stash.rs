@@ -25,7 +25,7 @@ use std::fmt::Debug;
 
 use aluvm::library::{Lib, LibId};
 use amplify::confinement::{Confined, MediumBlob, TinyOrdMap};
-use amplify::{confinement, ByteArray};
+use amplify::ByteArray;
 use bp::dbc::anchor::MergeError;
 use bp::dbc::tapret::TapretCommitment;
 use bp::dbc::Anchor;
@@ -49,7 +49,7 @@ use crate::containers::{
 use crate::interface::{
     ContractBuilder, Iface, IfaceClass, IfaceId, IfaceImpl, IfaceRef, TransitionBuilder,
 };
-use crate::persistence::{ContractIfaceError, StoreTransaction};
+use crate::persistence::{ContractIfaceError, PruneCheckpoint, StoreTransaction};
 use crate::{MergeReveal, MergeRevealError, SecretSeal, LIB_NAME_RGB_STD};
 
 #[derive(Debug, Display, Error, From)]
@@ -218,6 +218,8 @@ where P: Default
 impl<P: StashProvider> Stash<P> {
     pub(super) fn new(provider: P) -> Self { Self { provider } }
 
+    pub(super) fn into_provider(self) -> P { self.provider }
+
     #[doc(hidden)]
     pub fn as_provider(&self) -> &P { &self.provider }
 
@@ -258,12 +260,51 @@ impl<P: StashProvider> Stash<P> {
     pub(super) fn genesis(&self, contract_id: ContractId) -> Result<&Genesis, StashError<P>> {
         Ok(self.provider.genesis(contract_id)?)
     }
+    pub(super) fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, StashError<P>> {
+        self.provider.bundle_ids().map_err(StashError::ReadProvider)
+    }
     pub(super) fn bundle(&self, bundle_id: BundleId) -> Result<&TransitionBundle, StashError<P>> {
         Ok(self.provider.bundle(bundle_id)?)
     }
+    pub(super) fn extension_ids(&self) -> Result<impl Iterator<Item = OpId> + '_, StashError<P>> {
+        self.provider
+            .extension_ids()
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn extension(&self, opid: OpId) -> Result<&Extension, StashError<P>> {
+        Ok(self.provider.extension(opid)?)
+    }
+    pub(super) fn attachment(&self, id: AttachId) -> Result<Option<&MediumBlob>, StashError<P>> {
+        self.provider
+            .attachment(id)
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn witness_ids(
+        &self,
+    ) -> Result<impl Iterator<Item = XWitnessId> + '_, StashError<P>> {
+        self.provider
+            .witness_ids()
+            .map_err(StashError::ReadProvider)
+    }
     pub(super) fn witness(&self, witness_id: XWitnessId) -> Result<&SealWitness, StashError<P>> {
         Ok(self.provider.witness(witness_id)?)
     }
+    pub(super) fn prune_checkpoint(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&PruneCheckpoint>, StashError<P>> {
+        self.provider
+            .prune_checkpoint(contract_id)
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn replaced_witnesses(&self) -> Result<BTreeSet<XWitnessId>, StashError<P>> {
+        Ok(self
+            .provider
+            .witness_replacements()
+            .map_err(StashError::ReadProvider)?
+            .map(|(replaced, _)| replaced)
+            .collect())
+    }
 
     pub(super) fn supplements(
         &self,
@@ -545,7 +586,7 @@ impl<P: StashProvider> Stash<P> {
         })
     }
 
-    fn consume_witness_bundle(
+    pub(super) fn consume_witness_bundle(
         &mut self,
         contract_id: ContractId,
         witness_bundle: WitnessBundle,
@@ -676,14 +717,9 @@ impl<P: StashProvider> Stash<P> {
         &mut self,
         seal: XChain<GraphSeal>,
     ) -> Result<bool, StashError<P>> {
-        self.begin_transaction()?;
-        let seal = self
-            .provider
+        self.provider
             .add_secret_seal(seal)
-            .inspect_err(|_| self.rollback_transaction())
-            .map_err(StashError::WriteProvider)?;
-        self.commit_transaction()?;
-        Ok(seal)
+            .map_err(StashError::WriteProvider)
     }
 }
 
@@ -702,6 +738,18 @@ impl<P: StashProvider> StoreTransaction for Stash<P> {
             .map_err(StashError::WriteProvider)
     }
 
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.provider
+            .precommit_transaction()
+            .map_err(StashError::WriteProvider)
+    }
+
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.provider
+            .complete_transaction()
+            .map_err(StashError::WriteProvider)
+    }
+
     fn rollback_transaction(&mut self) { self.provider.rollback_transaction() }
 }
 
@@ -741,6 +789,9 @@ pub trait StashReadProvider {
     }
 
     fn get_trust(&self, identity: &Identity) -> Result<TrustLevel, Self::Error>;
+    /// Returns all identities known to the stash together with their trust
+    /// level.
+    fn identities(&self) -> Result<impl Iterator<Item = (Identity, TrustLevel)>, Self::Error>;
     fn supplement(&self, content_ref: ContentRef) -> Result<Option<&Supplement>, Self::Error>;
     fn supplements(
         &self,
@@ -753,6 +804,8 @@ pub trait StashReadProvider {
     fn bundle(&self, bundle_id: BundleId) -> Result<&TransitionBundle, ProviderError<Self::Error>>;
     fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error>;
     fn extension(&self, op_id: OpId) -> Result<&Extension, ProviderError<Self::Error>>;
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error>;
+    fn attachment(&self, id: AttachId) -> Result<Option<&MediumBlob>, Self::Error>;
     fn witness(&self, witness_id: XWitnessId) -> Result<&SealWitness, ProviderError<Self::Error>>;
 
     fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error>;
@@ -761,6 +814,19 @@ pub trait StashReadProvider {
         secret: XChain<SecretSeal>,
     ) -> Result<Option<XChain<GraphSeal>>, Self::Error>;
     fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error>;
+
+    /// Returns record of the contract operations removed by
+    /// [`super::Stock::prune`], if the contract history was pruned.
+    fn prune_checkpoint(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&PruneCheckpoint>, Self::Error>;
+
+    /// Returns witnesses replaced by [`super::Stock::replace_witness`] together
+    /// with their replacements.
+    fn witness_replacements(
+        &self,
+    ) -> Result<impl Iterator<Item = (XWitnessId, XWitnessId)>, Self::Error>;
 }
 
 pub trait StashWriteProvider: StoreTransaction<TransactionErr = Self::Error> {
@@ -778,14 +844,31 @@ pub trait StashWriteProvider: StoreTransaction<TransactionErr = Self::Error> {
 
     fn replace_lib(&mut self, lib: Lib) -> Result<bool, Self::Error>;
     fn consume_types(&mut self, types: TypeSystem) -> Result<(), Self::Error>;
-    fn set_trust(
-        &mut self,
-        identity: Identity,
-        trust: TrustLevel,
-    ) -> Result<(), confinement::Error>;
+    fn set_trust(&mut self, identity: Identity, trust: TrustLevel) -> Result<(), Self::Error>;
     fn add_supplement(&mut self, suppl: Supplement) -> Result<(), Self::Error>;
     fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), Self::Error>
     where I: IntoIterator<Item = (Identity, SigBlob)>;
 
     fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error>;
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, Self::Error>;
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, Self::Error>;
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error>;
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error>;
+    fn remove_sigs(&mut self, content_id: ContentId) -> Result<bool, Self::Error>;
+    fn remove_supplements(&mut self, content_ref: ContentRef) -> Result<bool, Self::Error>;
+    fn remove_attachment(&mut self, id: AttachId) -> Result<bool, Self::Error>;
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error>;
+
+    fn set_prune_checkpoint(
+        &mut self,
+        contract_id: ContractId,
+        checkpoint: PruneCheckpoint,
+    ) -> Result<(), Self::Error>;
+
+    fn set_witness_replacement(
+        &mut self,
+        replaced: XWitnessId,
+        replacement: XWitnessId,
+    ) -> Result<(), Self::Error>;
 }
@@ -20,6 +20,7 @@
 // limitations under the License.
 
 use std::borrow::Borrow;
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Debug;
@@ -30,13 +31,14 @@ use nonasync::persistence::{CloneNoPersistence, Persisting};
 use rgb::validation::{ResolveWitness, WitnessResolverError};
 use rgb::vm::{ContractStateAccess, WitnessOrd};
 use rgb::{
-    AssetTag, AttachState, BlindingFactor, ContractId, DataState, Extension, Genesis, Operation,
-    RevealedAttach, RevealedData, RevealedValue, Schema, SchemaId, Transition, TransitionBundle,
-    VoidState, XWitnessId,
+    AssetTag, AttachState, BlindingFactor, ContractId, DataState, Extension, Genesis, Layer1,
+    Operation, RevealedAttach, RevealedData, RevealedValue, Schema, SchemaId, Transition,
+    TransitionBundle, VoidState, XWitnessId,
 };
 
 use crate::containers::{ConsignmentExt, ToWitnessId};
 use crate::contract::OutputAssignment;
+use crate::interface::ResolveWitnessBatch;
 use crate::persistence::{StoreTransaction, UpdateRes};
 
 #[derive(Debug, Display, Error, From)]
@@ -74,6 +76,18 @@ pub enum StateInconsistency {
     AbsentWitness(XWitnessId),
 }
 
+/// Errors returned by [`StateReadProvider`], which distinguish inconsistency
+/// of the state data from the inability to access them.
+pub trait StateReadError: Clone + Eq + Error {
+    /// Returns the inconsistency of the state data, if it has caused the
+    /// error.
+    fn inconsistency(&self) -> Option<&StateInconsistency>;
+}
+
+impl StateReadError for StateInconsistency {
+    fn inconsistency(&self) -> Option<&StateInconsistency> { Some(self) }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum PersistedState {
     Void,
@@ -121,6 +135,8 @@ where P: Default
 impl<P: StateProvider> State<P> {
     pub(super) fn new(provider: P) -> Self { Self { provider } }
 
+    pub(super) fn into_provider(self) -> P { self.provider }
+
     #[doc(hidden)]
     pub fn as_provider(&self) -> &P { &self.provider }
 
@@ -137,21 +153,40 @@ impl<P: StateProvider> State<P> {
             .map_err(StateError::ReadProvider)
     }
 
+    #[inline]
+    pub fn contract_state_at(
+        &self,
+        contract_id: ContractId,
+        layer1: Layer1,
+        height: u32,
+    ) -> Result<P::ContractRead<'_>, StateError<P>> {
+        self.provider
+            .contract_state_at(contract_id, layer1, height)
+            .map_err(StateError::ReadProvider)
+    }
+
+    /// Selects the valid witness with the highest consensus priority: mined
+    /// witnesses are preferred over tentative ones, and the earlier mined ones
+    /// over the later. Witnesses with the same ordering are selected by their
+    /// id, such that the result doesn't depend on the order of `witness_ids`.
     pub fn select_valid_witness(
         &self,
         witness_ids: impl IntoIterator<Item = impl Borrow<XWitnessId>>,
     ) -> Result<XWitnessId, StateError<P>> {
+        let mut selected = None;
         for witness_id in witness_ids {
             let witness_id = *witness_id.borrow();
-            if self
+            let ord = self
                 .provider
-                .is_valid_witness(witness_id)
-                .map_err(StateError::ReadProvider)?
-            {
-                return Ok(witness_id);
+                .witness_ord(witness_id)
+                .map_err(StateError::ReadProvider)?;
+            if ord.is_valid() && selected.map_or(true, |best| (ord, witness_id) < best) {
+                selected = Some((ord, witness_id));
             }
         }
-        Err(StateError::AbsentValidWitness)
+        selected
+            .map(|(_, witness_id)| witness_id)
+            .ok_or(StateError::AbsentValidWitness)
     }
 
     pub fn update_from_bundle<R: ResolveWitness>(
@@ -235,13 +270,107 @@ impl<P: StateProvider> State<P> {
         Ok(())
     }
 
+    /// Re-creates state of a contract from its genesis, state extensions and
+    /// state transition bundles, processing bundles in the order of their
+    /// witnesses.
+    ///
+    /// A bundle committed by several witnesses (for instance, after an RBF
+    /// replacement) is applied only once, using the valid witness with the
+    /// highest consensus priority. Bundles lacking valid witnesses are
+    /// registered without contributing to the contract state.
+    pub(super) fn replay_contract<'a, R: ResolveWitness>(
+        &mut self,
+        schema: &Schema,
+        genesis: &Genesis,
+        extensions: impl IntoIterator<Item = &'a Extension>,
+        bundles: impl IntoIterator<Item = (XWitnessId, &'a TransitionBundle)>,
+        resolver: R,
+    ) -> Result<(), StateError<P>> {
+        let mut ords = BTreeMap::new();
+        let mut selected = BTreeMap::<_, (WitnessOrd, XWitnessId, &TransitionBundle)>::new();
+        let mut archived = Vec::new();
+        for (witness_id, bundle) in bundles {
+            let witness_ord = match ords.get(&witness_id) {
+                Some(ord) => *ord,
+                None => {
+                    let ord = resolver
+                        .resolve_pub_witness_ord(witness_id)
+                        .map_err(|e| StateError::Resolver(witness_id, e))?;
+                    ords.insert(witness_id, ord);
+                    ord
+                }
+            };
+            if !witness_ord.is_valid() {
+                archived.push((witness_ord, witness_id, bundle));
+                continue;
+            }
+            match selected.entry(bundle.bundle_id()) {
+                Entry::Vacant(entry) => {
+                    entry.insert((witness_ord, witness_id, bundle));
+                }
+                Entry::Occupied(mut entry) => {
+                    let (ord, id, _) = entry.get();
+                    if (witness_ord, witness_id) < (*ord, *id) {
+                        entry.insert((witness_ord, witness_id, bundle));
+                    }
+                }
+            }
+        }
+        let mut ordered_bundles = selected.into_values().chain(archived).collect::<Vec<_>>();
+        ordered_bundles.sort_by_key(|(ord, id, _)| (*ord, *id));
+
+        let mut state = self
+            .as_provider_mut()
+            .register_contract(schema, genesis)
+            .map_err(StateError::WriteProvider)?;
+        let extensions = extensions
+            .into_iter()
+            .map(|extension| (extension.id(), extension))
+            .collect::<BTreeMap<_, _>>();
+        let mut ordered_extensions = BTreeMap::<_, (XWitnessId, WitnessOrd)>::new();
+        for (witness_ord, witness_id, bundle) in ordered_bundles {
+            for transition in bundle.known_transitions.values() {
+                state
+                    .add_transition(transition, witness_id, witness_ord)
+                    .map_err(StateError::WriteProvider)?;
+                for input in &transition.inputs {
+                    let id = input.prev_out.op;
+                    if !extensions.contains_key(&id) {
+                        continue;
+                    }
+                    // Extension is witnessed by the first valid transition closing its seals,
+                    // such that archived witnesses are taken only if there are no valid ones
+                    let entry = ordered_extensions
+                        .entry(id)
+                        .or_insert((witness_id, witness_ord));
+                    let earlier = match (entry.1.is_valid(), witness_ord.is_valid()) {
+                        (false, true) => true,
+                        (true, true) => witness_ord < entry.1,
+                        _ => false,
+                    };
+                    if earlier {
+                        *entry = (witness_id, witness_ord);
+                    }
+                }
+            }
+        }
+        for (id, (witness_id, witness_ord)) in ordered_extensions {
+            state
+                .add_extension(extensions[&id], witness_id, witness_ord)
+                .map_err(StateError::WriteProvider)?;
+        }
+
+        Ok(())
+    }
+
     pub fn update_witnesses(
         &mut self,
-        resolver: impl ResolveWitness,
+        resolver: impl ResolveWitnessBatch,
         after_height: u32,
+        progress: impl FnMut(usize, usize),
     ) -> Result<UpdateRes, StateError<P>> {
         self.provider
-            .update_witnesses(resolver, after_height)
+            .update_witnesses(resolver, after_height, progress)
             .map_err(StateError::WriteProvider)
     }
 }
@@ -261,6 +390,18 @@ impl<P: StateProvider> StoreTransaction for State<P> {
             .map_err(StateError::WriteProvider)
     }
 
+    fn precommit_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.provider
+            .precommit_transaction()
+            .map_err(StateError::WriteProvider)
+    }
+
+    fn complete_transaction(&mut self) -> Result<(), Self::TransactionErr> {
+        self.provider
+            .complete_transaction()
+            .map_err(StateError::WriteProvider)
+    }
+
     fn rollback_transaction(&mut self) { self.provider.rollback_transaction() }
 }
 
@@ -272,14 +413,26 @@ pub trait StateProvider:
 pub trait StateReadProvider {
     type ContractRead<'a>: ContractStateRead
     where Self: 'a;
-    type Error: Clone + Eq + Error;
+    type Error: StateReadError;
 
     fn contract_state(
         &self,
         contract_id: ContractId,
     ) -> Result<Self::ContractRead<'_>, Self::Error>;
 
+    /// Returns contract state as of the given block height of the `layer1`
+    /// blockchain, which includes only operations with witnesses mined on that
+    /// blockchain at or below that height.
+    fn contract_state_at(
+        &self,
+        contract_id: ContractId,
+        layer1: Layer1,
+        height: u32,
+    ) -> Result<Self::ContractRead<'_>, Self::Error>;
+
     fn is_valid_witness(&self, witness_id: XWitnessId) -> Result<bool, Self::Error>;
+
+    fn witness_ord(&self, witness_id: XWitnessId) -> Result<WitnessOrd, Self::Error>;
 }
 
 pub trait StateWriteProvider: StoreTransaction<TransactionErr = Self::Error> {
@@ -298,11 +451,20 @@ pub trait StateWriteProvider: StoreTransaction<TransactionErr = Self::Error> {
         contract_id: ContractId,
     ) -> Result<Option<Self::ContractWrite<'_>>, Self::Error>;
 
+    /// Updates ordering of the witnesses, except the ones mined below
+    /// `after_height`, resolving them in batches. After each batch `progress`
+    /// is called with the number of already processed and the total number
+    /// of witnesses to resolve.
     fn update_witnesses(
         &mut self,
-        resolver: impl ResolveWitness,
+        resolver: impl ResolveWitnessBatch,
         after_height: u32,
+        progress: impl FnMut(usize, usize),
     ) -> Result<UpdateRes, Self::Error>;
+
+    fn remove_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error>;
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error>;
 }
 
 pub trait ContractStateRead: ContractStateAccess {
@@ -24,6 +24,8 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::Infallible;
 use std::error::Error;
 use std::fmt::Debug;
+use std::mem;
+use std::num::NonZeroU32;
 
 use amplify::confinement::{Confined, U24};
 use amplify::Wrapper;
@@ -31,36 +33,45 @@ use bp::dbc::Method;
 use bp::seals::txout::CloseMethod;
 use bp::Vout;
 use chrono::Utc;
+use commit_verify::Conceal;
 use invoice::{Amount, Beneficiary, InvoiceState, NonFungible, RgbInvoice};
-use nonasync::persistence::{CloneNoPersistence, PersistenceError, PersistenceProvider};
+use nonasync::persistence::{
+    CloneNoPersistence, Persistence, PersistenceError, PersistenceProvider,
+};
 use rgb::validation::{DbcProof, ResolveWitness, WitnessResolverError};
+use rgb::vm::WitnessOrd;
 use rgb::{
-    validation, AssignmentType, BlindingFactor, BundleId, ContractId, DataState, GraphSeal,
-    Identity, OpId, Operation, Opout, SchemaId, SecretSeal, Transition, TxoSeal, XChain, XOutpoint,
-    XOutputSeal, XWitnessId,
+    validation, AssignmentType, Assignments, AttachId, BlindingFactor, BundleId, ContractId,
+    DataState, ExposedSeal, Extension, GraphSeal, Identity, Layer1, OpId, Operation, Opout,
+    SchemaId, SecretSeal, Transition, TxoSeal, TypedAssigns, XChain, XOutpoint, XOutputSeal,
+    XWitnessId,
 };
 use strict_encoding::FieldName;
 
+use super::events::{bundle_opouts, Observers};
 use super::{
-    ContractStateRead, Index, IndexError, IndexInconsistency, IndexProvider, IndexReadProvider,
-    IndexWriteProvider, MemIndex, MemStash, MemState, PersistedState, SchemaIfaces, Stash,
-    StashDataError, StashError, StashInconsistency, StashProvider, StashReadProvider,
-    StashWriteProvider, State, StateError, StateInconsistency, StateProvider, StateReadProvider,
-    StateWriteProvider, StoreTransaction,
+    BackupError, ContractStateRead, Index, IndexError, IndexInconsistency, IndexProvider,
+    IndexReadProvider, IndexWriteProvider, MemContract, MemIndex, MemStash, MemState, ObserverId,
+    PersistedState, ReadOnly, SchemaIfaces, Stash, StashDataError, StashError, StashInconsistency,
+    StashProvider, StashReadProvider, StashWriteProvider, State, StateError, StateInconsistency,
+    StateProvider, StateReadError, StateReadProvider, StateWriteProvider, StockEvent,
+    StockObserver, StockPersistence, StoreTransaction, TrustError, TrustPolicy,
 };
 use crate::containers::{
-    AnchorSet, AnchoredBundleMismatch, Batch, BuilderSeal, ClientBundle, Consignment, ContainerVer,
-    ContentId, ContentRef, Contract, Fascia, Kit, SealWitness, SupplItem, SupplSub, Transfer,
-    TransitionDichotomy, TransitionInfo, TransitionInfoError, UnrelatedTransition,
-    ValidConsignment, ValidContract, ValidKit, ValidTransfer, VelocityHint, WitnessBundle,
-    SUPPL_ANNOT_VELOCITY,
+    AnchorSet, AnchoredBundleMismatch, Batch, BuilderSeal, ClientBundle, Consignment,
+    ConsignmentExt, ContainerVer, ContentId, ContentRef, Contract, Disclosure, DumbValidator,
+    Fascia, Kit, SealWitness, SigValidator, SupplItem, SupplSub, Transfer, TransitionDichotomy,
+    TransitionInfo, TransitionInfoError, TrustLevel, TrustedValidator, UnrelatedTransition,
+    ValidConsignment, ValidContract, ValidDisclosure, ValidKit, ValidTransfer, VelocityHint,
+    WitnessBundle, SUPPL_ANNOT_VELOCITY,
 };
 use crate::info::{ContractInfo, IfaceInfo, SchemaInfo};
+use crate::interface::resolver::{KnownOrdResolver, ReplacedResolver};
 use crate::interface::{
     BuilderError, ContractBuilder, ContractIface, Iface, IfaceClass, IfaceId, IfaceRef,
-    IfaceWrapper, TransitionBuilder,
+    IfaceWrapper, ResolveWitnessBatch, SequentialResolver, TransitionBuilder,
 };
-use crate::MergeRevealError;
+use crate::{MergeRevealError, TypedAssignsExt};
 
 pub type ContractAssignments = HashMap<XOutputSeal, HashMap<Opout, PersistedState>>;
 
@@ -114,6 +125,13 @@ pub enum StockError<
 
     /// witness {0} can't be resolved: {1}
     WitnessUnresolved(XWitnessId, WitnessResolverError),
+
+    /// history of contract {0} was pruned, thus its state can't be re-created
+    /// from the stash.
+    PrunedState(ContractId),
+
+    /// unable to store stock data: {0}
+    Persistence(PersistenceError),
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider, E: Error> From<StashError<S>>
@@ -165,10 +183,20 @@ pub enum ConsignError {
     /// unable to construct consignment: too many terminals provided.
     TooManyTerminals,
 
+    /// unable to construct consignment: too many attachments provided.
+    TooManyAttachments,
+
+    /// unable to construct consignment: history size too large, resulting in
+    /// too many state extensions.
+    TooManyExtensions,
+
     /// unable to construct consignment: history size too large, resulting in
     /// too many transitions.
     TooManyBundles,
 
+    /// unable to construct disclosure: too many operation outputs provided.
+    TooManyOpouts,
+
     #[from]
     #[display(inner)]
     MergeReveal(MergeRevealError),
@@ -183,6 +211,14 @@ pub enum ConsignError {
 
     /// the spent state from transition {1} inside bundle {0} is concealed.
     Concealed(BundleId, OpId),
+
+    /// history of contract {0} was pruned and operation {1} required for the
+    /// consignment is not available anymore.
+    Pruned(ContractId, OpId),
+
+    /// operation output {0} can't be disclosed since it is either not a part
+    /// of a state transition or its seal or state is not known.
+    Undisclosable(Opout),
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<ConsignError>
@@ -269,6 +305,12 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<BuilderError>
 pub enum FasciaError {
     /// bundle {1} for contract {0} contains invalid transition input map.
     InvalidBundle(ContractId, BundleId),
+
+    /// witness {0} can't replace itself.
+    SelfReplacement(XWitnessId),
+
+    /// bundle {1} is not anchored by the replaced witness {0}.
+    NotReplaced(XWitnessId, BundleId),
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<FasciaError>
@@ -277,6 +319,20 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<FasciaError>
     fn from(err: FasciaError) -> Self { Self::InvalidInput(err) }
 }
 
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ForgetError {
+    /// contract {0} can't be forgotten since it still has {1} allocations
+    /// owned by the wallet.
+    OwnedAllocations(ContractId, usize),
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<ForgetError>
+    for StockError<S, H, P, ForgetError>
+{
+    fn from(err: ForgetError) -> Self { Self::InvalidInput(err) }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
 #[display(doc_comments)]
 pub enum ContractIfaceError {
@@ -302,6 +358,10 @@ pub enum InputError {
     Fascia(FasciaError),
     #[from]
     ContractIface(ContractIfaceError),
+    #[from]
+    Forget(ForgetError),
+    #[from]
+    Trust(TrustError),
 }
 
 macro_rules! stock_err_conv {
@@ -325,6 +385,8 @@ macro_rules! stock_err_conv {
                     StockError::StateInconsistency(e) => StockError::StateInconsistency(e),
                     StockError::IndexInconsistency(e) => StockError::IndexInconsistency(e),
                     StockError::WitnessUnresolved(id, e) => StockError::WitnessUnresolved(id, e),
+                    StockError::PrunedState(id) => StockError::PrunedState(id),
+                    StockError::Persistence(e) => StockError::Persistence(e),
                 }
             }
         }
@@ -346,16 +408,30 @@ impl From<Infallible> for FasciaError {
 impl From<Infallible> for ContractIfaceError {
     fn from(_: Infallible) -> Self { unreachable!() }
 }
+impl From<Infallible> for ForgetError {
+    fn from(_: Infallible) -> Self { unreachable!() }
+}
+impl From<Infallible> for TrustError {
+    fn from(_: Infallible) -> Self { unreachable!() }
+}
+impl From<Infallible> for BackupError {
+    fn from(_: Infallible) -> Self { unreachable!() }
+}
 
 stock_err_conv!(Infallible, ComposeError);
 stock_err_conv!(Infallible, ConsignError);
 stock_err_conv!(Infallible, FasciaError);
 stock_err_conv!(Infallible, ContractIfaceError);
+stock_err_conv!(Infallible, ForgetError);
+stock_err_conv!(Infallible, TrustError);
+stock_err_conv!(Infallible, BackupError);
 stock_err_conv!(Infallible, InputError);
 stock_err_conv!(ComposeError, InputError);
 stock_err_conv!(ConsignError, InputError);
 stock_err_conv!(FasciaError, InputError);
 stock_err_conv!(ContractIfaceError, InputError);
+stock_err_conv!(ForgetError, InputError);
+stock_err_conv!(TrustError, InputError);
 
 pub type StockErrorMem<E = Infallible> = StockError<MemStash, MemState, MemIndex, E>;
 pub type StockErrorAll<S = MemStash, H = MemState, P = MemIndex> = StockError<S, H, P, InputError>;
@@ -369,6 +445,9 @@ pub struct Stock<
     stash: Stash<S>,
     state: State<H>,
     index: Index<P>,
+    persistence: Option<Box<dyn StockPersistence<S, H, P>>>,
+    observers: Observers,
+    trust_policy: TrustPolicy,
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider> CloneNoPersistence for Stock<S, H, P> {
@@ -377,6 +456,9 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> CloneNoPersistence fo
             stash: self.stash.clone_no_persistence(),
             state: self.state.clone_no_persistence(),
             index: self.index.clone_no_persistence(),
+            persistence: None,
+            observers: default!(),
+            trust_policy: self.trust_policy,
         }
     }
 }
@@ -392,6 +474,9 @@ where
             stash: default!(),
             state: default!(),
             index: default!(),
+            persistence: None,
+            observers: default!(),
+            trust_policy: default!(),
         }
     }
 }
@@ -404,6 +489,12 @@ impl Stock {
 }
 
 impl<S: StashProvider, H: StateProvider, I: IndexProvider> Stock<S, H, I> {
+    /// Loads the stock using a provider of stash, state and index data.
+    ///
+    /// The providers store their data separately, thus a failure may leave
+    /// the data stored partially; use [`Stock::load_atomic`] with a
+    /// [`StockPersistence`] provider to store them as a single atomic
+    /// operation.
     pub fn load<P>(provider: P, autosave: bool) -> Result<Self, PersistenceError>
     where P: Clone
             + PersistenceProvider<S>
@@ -416,6 +507,35 @@ impl<S: StashProvider, H: StateProvider, I: IndexProvider> Stock<S, H, I> {
         Ok(Self::with(stash, state, index))
     }
 
+    /// Loads the stock using a [`StockPersistence`] provider, which stores
+    /// stash, state and index data as a single atomic operation.
+    pub fn load_atomic<P>(provider: P, autosave: bool) -> Result<Self, PersistenceError>
+    where P: Clone + StockPersistence<S, H, I> + 'static {
+        let stash = S::load(provider.clone(), autosave)?;
+        let state = H::load(provider.clone(), autosave)?;
+        let index = I::load(provider.clone(), autosave)?;
+        let mut stock = Self::with(stash, state, index);
+        stock.persistence = Some(Box::new(provider));
+        Ok(stock)
+    }
+
+    /// Loads the stock in read-only mode.
+    ///
+    /// Any attempt to store the stock data fails with
+    /// [`crate::persistence::ReadOnlyError`], and
+    /// the data are never stored automatically. Providers supporting a
+    /// read-only mode (like [`crate::persistence::fs::FsBinStore`]) are
+    /// switched into it, such that the stock can be loaded while another
+    /// process is writing to it.
+    pub fn load_read_only<P>(provider: P) -> Result<Self, PersistenceError>
+    where P: Clone + StockPersistence<S, H, I> + 'static {
+        Self::load_atomic(ReadOnly(provider.into_read_only()), false)
+    }
+
+    /// Makes the stock persistent using a provider of stash, state and index
+    /// data, which store their data separately; see
+    /// [`Stock::make_persistent_atomic`] for storing them as a single atomic
+    /// operation.
     pub fn make_persistent<P>(
         &mut self,
         provider: P,
@@ -428,6 +548,7 @@ impl<S: StashProvider, H: StateProvider, I: IndexProvider> Stock<S, H, I> {
             + PersistenceProvider<I>
             + 'static,
     {
+        self.persistence = None;
         let a = self
             .as_stash_provider_mut()
             .make_persistent(provider.clone(), autosave)?;
@@ -440,13 +561,79 @@ impl<S: StashProvider, H: StateProvider, I: IndexProvider> Stock<S, H, I> {
         Ok(a && b && c)
     }
 
-    pub fn store(&mut self) -> Result<(), PersistenceError> {
-        // TODO: Revert on failure
+    /// Makes the stock persistent using a [`StockPersistence`] provider, which
+    /// stores stash, state and index data as a single atomic operation.
+    pub fn make_persistent_atomic<P>(
+        &mut self,
+        provider: P,
+        autosave: bool,
+    ) -> Result<bool, PersistenceError>
+    where
+        P: Clone + StockPersistence<S, H, I> + 'static,
+    {
+        let was_persisted = self.as_stash_provider().is_persisted()
+            && self.as_state_provider().is_persisted()
+            && self.as_index_provider().is_persisted();
+        // We store all the data atomically first, such that the providers below do not write
+        // partial data
+        provider.store_all(
+            self.as_stash_provider(),
+            self.as_state_provider(),
+            self.as_index_provider(),
+        )?;
+        self.persistence = Some(Box::new(provider.clone()));
+        self.as_stash_provider_mut()
+            .as_mut_persistence()
+            .replace(Persistence {
+                dirty: false,
+                autosave,
+                provider: Box::new(provider.clone()),
+            });
+        self.as_state_provider_mut()
+            .as_mut_persistence()
+            .replace(Persistence {
+                dirty: false,
+                autosave,
+                provider: Box::new(provider.clone()),
+            });
+        self.as_index_provider_mut()
+            .as_mut_persistence()
+            .replace(Persistence {
+                dirty: false,
+                autosave,
+                provider: Box::new(provider),
+            });
+        Ok(was_persisted)
+    }
 
-        self.as_stash_provider_mut().store()?;
-        self.as_state_provider_mut().store()?;
-        self.as_index_provider_mut().store()?;
+    /// Stores stash, state and index data.
+    ///
+    /// If the stock was loaded with [`Stock::load_atomic`] or made persistent
+    /// with [`Stock::make_persistent_atomic`], all three components are stored
+    /// as a single atomic operation:
+    /// if the operation fails, the previously stored data remain intact.
+    pub fn store(&mut self) -> Result<(), PersistenceError> {
+        let Some(persistence) = &self.persistence else {
+            self.as_stash_provider_mut().store()?;
+            self.as_state_provider_mut().store()?;
+            self.as_index_provider_mut().store()?;
+            return Ok(());
+        };
 
+        persistence.store_all(
+            self.stash.as_provider(),
+            self.state.as_provider(),
+            self.index.as_provider(),
+        )?;
+        if let Some(p) = self.as_stash_provider_mut().persistence_mut() {
+            p.dirty = false;
+        }
+        if let Some(p) = self.as_state_provider_mut().persistence_mut() {
+            p.dirty = false;
+        }
+        if let Some(p) = self.as_index_provider_mut().persistence_mut() {
+            p.dirty = false;
+        }
         Ok(())
     }
 }
@@ -457,6 +644,44 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             stash: Stash::new(stash_provider),
             state: State::new(state_provider),
             index: Index::new(index_provider),
+            persistence: None,
+            observers: default!(),
+            trust_policy: default!(),
+        }
+    }
+
+    /// Registers `observer` receiving [`StockEvent`]s after each committed
+    /// change of the stock data.
+    pub fn subscribe(&mut self, observer: impl StockObserver + 'static) -> ObserverId {
+        self.observers.subscribe(observer)
+    }
+
+    /// Removes observer registered with [`Stock::subscribe`]. Returns `false`
+    /// if there was no such observer.
+    pub fn unsubscribe(&mut self, id: ObserverId) -> bool { self.observers.unsubscribe(id) }
+
+    /// Returns [`TrustPolicy`] applied when importing kits and contracts.
+    pub fn trust_policy(&self) -> TrustPolicy { self.trust_policy }
+
+    /// Sets [`TrustPolicy`] applied when importing kits and contracts.
+    pub fn set_trust_policy(&mut self, policy: TrustPolicy) { self.trust_policy = policy; }
+
+    /// Constructs signature validator accepting only signatures which are valid
+    /// according to the `validator` and made by identities which should be
+    /// accepted according to their [`TrustLevel`] known to the stash.
+    /// Identities unknown to the stash have the default trust level.
+    pub fn sig_validator<V: SigValidator>(
+        &self,
+        validator: V,
+    ) -> TrustedValidator<V, impl Fn(&Identity) -> TrustLevel + '_> {
+        TrustedValidator {
+            validator,
+            trust: |identity| {
+                self.stash
+                    .as_provider()
+                    .get_trust(identity)
+                    .unwrap_or_default()
+            },
         }
     }
 
@@ -562,6 +787,22 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             .map_err(StockError::from)
     }
 
+    /// Returns contract state as of the given block `height` of the `layer1`
+    /// blockchain: allocations and global state created by operations with
+    /// witnesses mined on that blockchain at or below that height. Operations
+    /// with tentative witnesses or witnesses on other blockchains are not
+    /// included.
+    pub fn contract_state_at(
+        &self,
+        contract_id: ContractId,
+        layer1: Layer1,
+        height: u32,
+    ) -> Result<H::ContractRead<'_>, StockError<S, H, P>> {
+        self.state
+            .contract_state_at(contract_id, layer1, height)
+            .map_err(StockError::from)
+    }
+
     #[allow(clippy::multiple_bound_locations, clippy::type_complexity)]
     pub fn contract_iface_class<C: IfaceClass>(
         &self,
@@ -713,7 +954,9 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             .extend(scripts.into_values())
             .expect("type guarantees");
         kit.types = types;
-        Ok(kit.validate().expect("stock produced invalid kit"))
+        Ok(kit
+            .validate(&DumbValidator)
+            .expect("stock produced invalid kit"))
     }
 
     pub fn export_contract(
@@ -724,6 +967,16 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         Ok(consignment)
     }
 
+    /// Creates a transfer consignment for the state assigned to the `outputs`
+    /// and to the `secret_seal`.
+    ///
+    /// The consignment discloses only the data required for its validation:
+    /// the outputs which are neither transferred, nor public, nor spent by the
+    /// transitions inside the consignment are concealed. Fungible and
+    /// structured state of such outputs remains revealed, since it is required
+    /// by the schema validation scripts, thus only their seals are concealed.
+    /// All transitions of the included bundles are retained, as required to
+    /// validate bundle commitments, together with their ancestors.
     pub fn transfer(
         &self,
         contract_id: ContractId,
@@ -765,18 +1018,24 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             self.index
                 .opouts_by_outputs(contract_id, outputs.iter().copied())?,
         );
-        opouts.extend(self.index.opouts_by_terminals(secret_seal.into_iter())?);
+        opouts.extend(self.index.opouts_by_terminals(secret_seal)?);
 
         // 1.3. Collect all state transitions assigning state to the provided outpoints
         let mut anchored_bundles = BTreeMap::<BundleId, ClientBundle>::new();
         let mut transitions = BTreeMap::<OpId, Transition>::new();
+        let mut extensions = BTreeMap::<OpId, Extension>::new();
         let mut terminals = BTreeMap::<BundleId, XChain<SecretSeal>>::new();
+        let mut required = opouts.clone();
         for opout in opouts {
             if opout.op == contract_id {
                 continue; // we skip genesis since it will be present anywhere
             }
+            if let Some(extension) = self.known_extension(opout.op)? {
+                extensions.insert(opout.op, extension.clone());
+                continue;
+            }
 
-            let transition = self.transition(opout.op)?;
+            let transition = self.transition(contract_id, opout.op)?;
             transitions.insert(opout.op, transition.clone());
 
             let bundle_id = self.index.bundle_id_for_op(transition.id())?;
@@ -796,26 +1055,81 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             }
         }
 
-        // 2. Collect all state transitions between terminals and genesis
-        let mut ids = vec![];
-        for transition in transitions.values() {
-            ids.extend(transition.inputs().iter().map(|input| input.prev_out.op));
+        // 2. Collect all state transitions and extensions between terminals and genesis. All known
+        //    transitions of the collected bundles are validated, thus the ancestors of the other
+        //    transitions of these bundles are collected as well.
+        let mut pending = transitions.values().cloned().collect::<Vec<_>>();
+        let mut pending_extensions = extensions.values().cloned().collect::<Vec<_>>();
+        while !pending.is_empty() || !pending_extensions.is_empty() {
+            let (ancestors, ancestor_extensions) =
+                self.ancestors(contract_id, &pending, &pending_extensions)?;
+            extensions.extend(
+                ancestor_extensions
+                    .into_iter()
+                    .map(|(id, extension)| (id, extension.clone())),
+            );
+            for (id, transition) in ancestors {
+                transitions.insert(id, transition.clone());
+                let bundle_id = self.index.bundle_id_for_op(transition.id())?;
+                anchored_bundles
+                    .entry(bundle_id)
+                    .or_insert(self.client_bundle(bundle_id)?.clone())
+                    .reveal_transition(transition.clone())?;
+            }
+            pending = anchored_bundles
+                .values()
+                .flat_map(ClientBundle::known_transitions)
+                .filter(|transition| !transitions.contains_key(&transition.id()))
+                .cloned()
+                .collect();
+            pending_extensions.clear();
+            for transition in &pending {
+                transitions.insert(transition.id(), transition.clone());
+            }
         }
-        while let Some(id) = ids.pop() {
-            if id == contract_id {
-                continue; // we skip genesis since it will be present anywhere
+
+        let mut genesis = self.stash.genesis(contract_id)?.clone();
+
+        // 3. Collect attachments for the attachment state being transferred
+        let mut attachments = BTreeMap::new();
+        for opout in &required {
+            let attach_id = if opout.op == contract_id {
+                attach_id(&genesis.assignments, *opout)
+            } else if let Some(extension) = extensions.get(&opout.op) {
+                attach_id(&extension.assignments, *opout)
+            } else {
+                transitions
+                    .get(&opout.op)
+                    .and_then(|transition| attach_id(&transition.assignments, *opout))
+            };
+            if let Some(id) = attach_id {
+                if let Some(attach) = self.stash.attachment(id)? {
+                    attachments.insert(id, attach.clone());
+                }
             }
-            let transition = self.transition(id)?;
-            ids.extend(transition.inputs().iter().map(|input| input.prev_out.op));
-            transitions.insert(id, transition.clone());
-            let bundle_id = self.index.bundle_id_for_op(transition.id())?;
-            anchored_bundles
-                .entry(bundle_id)
-                .or_insert(self.client_bundle(bundle_id)?.clone())
-                .reveal_transition(transition.clone())?;
+        }
+        let attachments =
+            Confined::try_from(attachments).map_err(|_| ConsignError::TooManyAttachments)?;
+
+        // 4. Conceal outputs which are not required to validate the transfer
+        if TRANSFER {
+            required.extend(
+                transitions
+                    .values()
+                    .flat_map(|transition| transition.inputs.iter().map(|input| input.prev_out)),
+            );
+            for client_bundle in anchored_bundles.values_mut() {
+                client_bundle.retain_transitions(|opid, transition| {
+                    conceal_outputs(opid, &mut transition.assignments, &required);
+                    true
+                });
+            }
+            for (opid, extension) in &mut extensions {
+                conceal_outputs(*opid, &mut extension.assignments, &required);
+            }
+            conceal_outputs(genesis.id(), &mut genesis.assignments, &required);
         }
 
-        let genesis = self.stash.genesis(contract_id)?.clone();
         // Get schema signature by schema id
         self.stash
             .sigs_for(&ContentId::Schema(genesis.schema_id))?
@@ -872,6 +1186,8 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             .map_err(|_| ConsignError::TooManyBundles)?;
         let terminals =
             Confined::try_from(terminals).map_err(|_| ConsignError::TooManyTerminals)?;
+        let extensions = Confined::try_from_iter(extensions.into_values())
+            .map_err(|_| ConsignError::TooManyExtensions)?;
 
         let (types, scripts) = self.stash.extract(&schema_ifaces.schema, ifaces.keys())?;
         let scripts = Confined::from_iter_checked(scripts.into_values());
@@ -879,7 +1195,6 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             Confined::try_from(supplements).map_err(|_| ConsignError::TooManySupplements)?;
         let signatures =
             Confined::try_from(signatures).map_err(|_| ConsignError::TooManySignatures)?;
-        // TODO: Conceal everything we do not need
         // TODO: Add known sigs to the consignment
 
         Ok(Consignment {
@@ -891,8 +1206,8 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             genesis,
             terminals,
             bundles,
-            extensions: none!(),
-            attachments: none!(),
+            extensions,
+            attachments,
 
             signatures,
             supplements,
@@ -901,6 +1216,79 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         })
     }
 
+    /// Creates a disclosure revealing the seals and the state of the contract
+    /// `opouts`, together with the state transitions defining them and their
+    /// anchors.
+    ///
+    /// Transitions which are not required for the disclosure are removed from
+    /// their bundles, and assignments of the disclosed transitions which are
+    /// not listed in `opouts` are concealed.
+    pub fn disclose(
+        &self,
+        contract_id: ContractId,
+        opouts: impl IntoIterator<Item = Opout>,
+    ) -> Result<Disclosure, StockError<S, H, P, ConsignError>> {
+        let opouts = Confined::<BTreeSet<_>, 0, U24>::try_from_iter(opouts)
+            .map_err(|_| ConsignError::TooManyOpouts)?;
+
+        let mut selected =
+            BTreeMap::<BundleId, BTreeMap<OpId, BTreeSet<(AssignmentType, u16)>>>::new();
+        for opout in &opouts {
+            if opout.op == contract_id {
+                return Err(ConsignError::Undisclosable(*opout).into());
+            }
+            let transition = self.transition(contract_id, opout.op)?;
+            if transition.contract_id != contract_id
+                || !transition
+                    .assignments
+                    .get(&opout.ty)
+                    .is_some_and(|assigns| assigns.is_revealed_at(opout.no))
+            {
+                return Err(ConsignError::Undisclosable(*opout).into());
+            }
+            let bundle_id = self.index.bundle_id_for_op(opout.op)?;
+            selected
+                .entry(bundle_id)
+                .or_default()
+                .entry(opout.op)
+                .or_default()
+                .insert((opout.ty, opout.no));
+        }
+
+        let mut bundles = BTreeMap::<XWitnessId, WitnessBundle>::new();
+        for (bundle_id, ops) in selected {
+            let mut client_bundle = self.client_bundle(bundle_id)?;
+            let retained = client_bundle.retain_transitions(|opid, transition| {
+                let Some(outputs) = ops.get(&opid) else {
+                    return false;
+                };
+                for (ty, assigns) in transition.assignments.keyed_values_mut() {
+                    assigns.conceal_except(|no| outputs.contains(&(*ty, no)));
+                }
+                true
+            });
+            debug_assert!(retained, "bundle contains disclosed transitions");
+
+            let witness_ids = self.index.bundle_info(bundle_id)?.0;
+            let witness_id = self.state.select_valid_witness(witness_ids)?;
+            let pub_witness = self.stash.witness(witness_id)?.public.clone();
+            let wb = match bundles.remove(&witness_id) {
+                Some(bundle) => bundle.into_double(client_bundle)?,
+                None => WitnessBundle::with(pub_witness, client_bundle),
+            };
+            bundles.insert(witness_id, wb);
+        }
+
+        Ok(Disclosure {
+            version: ContainerVer::V2,
+            contract_id,
+            bundles: Confined::try_from_iter(bundles.into_values())
+                .map_err(|_| ConsignError::TooManyBundles)?,
+            opouts,
+            signatures: none!(),
+        })
+    }
+
     /// Composes a batch of state transitions updating state for the provided
     /// set of previous outputs, satisfying requirements of the invoice, paying
     /// the change back and including the necessary blank state transitions.
@@ -1246,7 +1634,7 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         Ok(batch)
     }
 
-    fn store_transaction<E: Error>(
+    pub(super) fn store_transaction<E: Error>(
         &mut self,
         f: impl FnOnce(
             &mut Stash<S>,
@@ -1254,49 +1642,181 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             &mut Index<P>,
         ) -> Result<(), StockError<S, H, P, E>>,
     ) -> Result<(), StockError<S, H, P, E>> {
-        self.state.begin_transaction()?;
-        self.stash
-            .begin_transaction()
-            .inspect_err(|_| self.stash.rollback_transaction())?;
-        self.index.begin_transaction().inspect_err(|_| {
+        // Providers must not store their data on their own, since they are stored
+        // together with `StockPersistence::store_all` once all of them are committed.
+        let autosave = self.suspend_autosave();
+        let res = self.run_transaction(f).and_then(|_| {
+            if autosave {
+                self.store().map_err(StockError::Persistence)?;
+            }
+            self.complete_transaction()
+        });
+        self.resume_autosave(autosave);
+        if res.is_err() {
             self.state.rollback_transaction();
             self.stash.rollback_transaction();
-        })?;
+            self.index.rollback_transaction();
+        }
+        res
+    }
+
+    /// Runs `f` within a transaction and pre-commits all the providers, which
+    /// must be either completed or rolled back afterwards.
+    fn run_transaction<E: Error>(
+        &mut self,
+        f: impl FnOnce(
+            &mut Stash<S>,
+            &mut State<H>,
+            &mut Index<P>,
+        ) -> Result<(), StockError<S, H, P, E>>,
+    ) -> Result<(), StockError<S, H, P, E>> {
+        self.state.begin_transaction()?;
+        self.stash.begin_transaction()?;
+        self.index.begin_transaction()?;
         f(&mut self.stash, &mut self.state, &mut self.index)?;
-        self.index
-            .commit_transaction()
-            .map_err(StockError::from)
-            .and_then(|_| self.state.commit_transaction().map_err(StockError::from))
-            .and_then(|_| self.stash.commit_transaction().map_err(StockError::from))
-            .inspect_err(|_| {
-                self.state.rollback_transaction();
-                self.stash.rollback_transaction();
-                self.index.rollback_transaction();
-            })
+        // Providers keep the data required for the rollback until all of them are committed
+        self.index.precommit_transaction()?;
+        self.state.precommit_transaction()?;
+        self.stash.precommit_transaction()?;
+        Ok(())
+    }
+
+    /// Completes transaction of all the providers pre-committed by
+    /// [`Self::run_transaction`].
+    fn complete_transaction<E: Error>(&mut self) -> Result<(), StockError<S, H, P, E>> {
+        self.state.complete_transaction()?;
+        self.stash.complete_transaction()?;
+        self.index.complete_transaction()?;
+        Ok(())
+    }
+
+    /// Disables autosave of the stash, state and index providers if the stock
+    /// has a [`StockPersistence`] provider. Returns whether the autosave was
+    /// enabled.
+    fn suspend_autosave(&mut self) -> bool {
+        if self.persistence.is_none() {
+            return false;
+        }
+        let mut autosave = false;
+        if let Some(p) = self.as_stash_provider_mut().persistence_mut() {
+            autosave |= mem::replace(&mut p.autosave, false);
+        }
+        if let Some(p) = self.as_state_provider_mut().persistence_mut() {
+            autosave |= mem::replace(&mut p.autosave, false);
+        }
+        if let Some(p) = self.as_index_provider_mut().persistence_mut() {
+            autosave |= mem::replace(&mut p.autosave, false);
+        }
+        autosave
+    }
+
+    fn resume_autosave(&mut self, autosave: bool) {
+        if !autosave {
+            return;
+        }
+        if let Some(p) = self.as_stash_provider_mut().persistence_mut() {
+            p.autosave = true;
+        }
+        if let Some(p) = self.as_state_provider_mut().persistence_mut() {
+            p.autosave = true;
+        }
+        if let Some(p) = self.as_index_provider_mut().persistence_mut() {
+            p.autosave = true;
+        }
     }
 
-    pub fn import_kit(&mut self, kit: ValidKit) -> Result<validation::Status, StockError<S, H, P>> {
+    /// Imports kit into the stash.
+    ///
+    /// Fails if some of the kit schemata, interfaces or interface
+    /// implementations are rejected by the stock [`TrustPolicy`].
+    pub fn import_kit(
+        &mut self,
+        kit: ValidKit,
+    ) -> Result<validation::Status, StockError<S, H, P, TrustError>> {
         let (kit, status) = kit.split();
-        self.stash.begin_transaction()?;
-        self.stash.consume_kit(kit)?;
-        self.stash.commit_transaction()?;
+        let content_ids = kit
+            .schemata
+            .iter()
+            .map(|schema| ContentId::Schema(schema.schema_id()))
+            .chain(
+                kit.ifaces
+                    .iter()
+                    .map(|iface| ContentId::Iface(iface.iface_id())),
+            )
+            .chain(
+                kit.iimpls
+                    .iter()
+                    .map(|iimpl| ContentId::IfaceImpl(iimpl.impl_id())),
+            );
+        for content_id in content_ids {
+            self.check_trust(content_id, kit.signatures.get(&content_id), false)?;
+        }
+        self.store_transaction::<TrustError>(move |stash, _, _| Ok(stash.consume_kit(kit)?))?;
         Ok(status)
     }
 
+    /// Imports contract into the stash, index and contract state.
+    ///
+    /// Fails if the contract schema, interfaces or interface implementations
+    /// are rejected by the stock [`TrustPolicy`]. Contracts which are not
+    /// issued on testnets are treated as production network contracts.
     pub fn import_contract<R: ResolveWitness>(
         &mut self,
         contract: ValidContract,
         resolver: R,
-    ) -> Result<validation::Status, StockError<S, H, P>> {
-        self.consume_consignment(contract, resolver)
+    ) -> Result<validation::Status, StockError<S, H, P, TrustError>> {
+        self.check_consignment_trust(&contract)?;
+        Ok(self.consume_consignment(contract, resolver)?)
     }
 
+    /// Accepts transfer into the stash, index and contract state.
+    ///
+    /// Fails if the contract schema, interfaces or interface implementations
+    /// are rejected by the stock [`TrustPolicy`], like in
+    /// [`Stock::import_contract`].
     pub fn accept_transfer<R: ResolveWitness>(
         &mut self,
         contract: ValidTransfer,
         resolver: R,
-    ) -> Result<validation::Status, StockError<S, H, P>> {
-        self.consume_consignment(contract, resolver)
+    ) -> Result<validation::Status, StockError<S, H, P, TrustError>> {
+        self.check_consignment_trust(&contract)?;
+        Ok(self.consume_consignment(contract, resolver)?)
+    }
+
+    /// Inspects the state revealed by a disclosure of a contract known to the
+    /// stock.
+    ///
+    /// Disclosure validation checks only that the disclosed transitions are
+    /// anchored, and not that they are valid against the contract history.
+    /// Thus, the disclosed state is returned as a separate view and is never
+    /// merged into the stock. Transitions with an archived witness don't
+    /// contribute to the returned state.
+    ///
+    /// Fails if the contract schema is rejected by the stock [`TrustPolicy`],
+    /// like in [`Stock::import_contract`].
+    pub fn inspect_disclosure<R: ResolveWitness>(
+        &self,
+        disclosure: &ValidDisclosure,
+        resolver: R,
+    ) -> Result<MemContract, StockError<S, H, P, TrustError>> {
+        let contract_id = disclosure.contract_id;
+        let genesis = self.stash.genesis(contract_id)?;
+        let schema_id = genesis.schema_id;
+        let content_id = ContentId::Schema(schema_id);
+        self.check_trust(content_id, disclosure.signatures.get(&content_id), !genesis.testnet)?;
+        let schema = &self.stash.schema(schema_id)?.schema;
+
+        let mut transitions = Vec::new();
+        for witness_bundle in &disclosure.bundles {
+            let witness_id = witness_bundle.witness_id();
+            let ord = resolver
+                .resolve_pub_witness_ord(witness_id)
+                .map_err(|e| StockError::<S, H, P, TrustError>::WitnessUnresolved(witness_id, e))?;
+            for transition in witness_bundle.known_transitions() {
+                transitions.push((transition, witness_id, ord));
+            }
+        }
+        Ok(MemContract::with_transitions(schema, contract_id, transitions))
     }
 
     fn consume_consignment<R: ResolveWitness, const TRANSFER: bool>(
@@ -1307,12 +1827,31 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         let (mut consignment, status) = consignment.split();
 
         consignment = self.stash.resolve_secrets(consignment)?;
+        let contract_id = consignment.contract_id();
+        let event = if TRANSFER {
+            let mut opouts = BTreeSet::new();
+            let mut witness_ids = BTreeSet::new();
+            for bw in consignment.bundled_witnesses() {
+                witness_ids.insert(bw.witness_id());
+                for bundle in bw.anchored_bundles.bundles() {
+                    opouts.extend(bundle_opouts(bundle));
+                }
+            }
+            StockEvent::TransferAccepted {
+                contract_id,
+                opouts,
+                witness_ids,
+            }
+        } else {
+            StockEvent::ContractImported { contract_id }
+        };
         self.store_transaction(move |stash, state, index| {
             state.update_from_consignment(&consignment, &resolver)?;
             index.index_consignment(&consignment)?;
             stash.consume_consignment(consignment)?;
             Ok(())
         })?;
+        self.observers.emit([event]);
 
         Ok(status)
     }
@@ -1330,8 +1869,10 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         fascia: Fascia,
         resolver: R,
     ) -> Result<(), StockError<S, H, P, FasciaError>> {
-        self.store_transaction(move |stash, state, index| {
-            let witness_id = fascia.witness_id();
+        let witness_id = fascia.witness_id();
+        let mut contract_ids = BTreeSet::new();
+        let mut opouts = BTreeSet::new();
+        self.store_transaction(|stash, state, index| {
             stash
                 .consume_witness(SealWitness::new(fascia.witness.clone(), fascia.anchor.clone()))?;
 
@@ -1348,13 +1889,172 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
 
                 index.index_bundle(contract_id, &bundle, witness_id)?;
                 state.update_from_bundle(contract_id, &bundle, witness_id, &resolver)?;
+                contract_ids.insert(contract_id);
+                opouts.extend(bundle_opouts(&bundle));
                 stash.consume_bundle(bundle)?;
             }
             Ok(())
-        })
+        })?;
+        self.observers.emit([StockEvent::FasciaConsumed {
+            witness_id,
+            contract_ids,
+            opouts,
+        }]);
+        Ok(())
+    }
+
+    /// Re-anchors bundles of an already consumed fascia into a replacement
+    /// witness transaction, for instance after the `replaced` witness was
+    /// bumped with RBF.
+    ///
+    /// All bundles of the `fascia` must be anchored by the `replaced` witness,
+    /// which gets archived in the contract state. The state of the affected
+    /// contracts is re-derived, such that their allocations are assigned to
+    /// the outputs of the replacement witness. The replacement is recorded in
+    /// the stash, such that the replaced witness stays archived on subsequent
+    /// witness updates (see [`Stock::update_witnesses`]) and state rebuilds.
+    /// Both witnesses also stay linked through the bundles they anchor (see
+    /// [`Stock::conflicting_witnesses`]); consignments use the valid witness
+    /// with the highest priority (see [`State::select_valid_witness`]).
+    pub fn replace_witness<R: ResolveWitness>(
+        &mut self,
+        replaced: XWitnessId,
+        fascia: Fascia,
+        resolver: R,
+    ) -> Result<(), StockError<S, H, P, FasciaError>> {
+        let witness_id = fascia.witness_id();
+        if witness_id == replaced {
+            return Err(FasciaError::SelfReplacement(witness_id).into());
+        }
+        let mut replaced_ids = self.stash.replaced_witnesses()?;
+        replaced_ids.insert(replaced);
+        let resolver = ReplacedResolver {
+            resolver,
+            replaced: replaced_ids,
+        };
+        let mut contracts = BTreeSet::new();
+        let mut opouts = BTreeSet::new();
+        self.store_transaction(|stash, state, index| {
+            stash.witness(replaced)?;
+            stash
+                .consume_witness(SealWitness::new(fascia.witness.clone(), fascia.anchor.clone()))?;
+            stash
+                .as_provider_mut()
+                .set_witness_replacement(replaced, witness_id)
+                .map_err(StashError::WriteProvider)?;
+
+            for (contract_id, bundle) in fascia.into_bundles() {
+                let bundle_id = bundle.bundle_id();
+                if !index.bundle_info(bundle_id)?.0.any(|id| id == replaced) {
+                    return Err(FasciaError::NotReplaced(replaced, bundle_id).into());
+                }
+                index.index_bundle(contract_id, &bundle, witness_id)?;
+                opouts.extend(bundle_opouts(&bundle));
+                stash.consume_bundle(bundle)?;
+                contracts.insert(contract_id);
+            }
+
+            Self::refresh_contracts(stash, state, &contracts, &resolver)?;
+            Ok(())
+        })?;
+        self.observers.emit([
+            StockEvent::WitnessOrdChanged {
+                witness_id: replaced,
+                ord: WitnessOrd::Archived,
+                contract_ids: contracts.clone(),
+                opouts: opouts.clone(),
+            },
+            StockEvent::FasciaConsumed {
+                witness_id,
+                contract_ids: contracts,
+                opouts,
+            },
+        ]);
+        Ok(())
+    }
+
+    /// Returns witnesses anchoring any of the bundles anchored by the
+    /// `witness_id`, i.e. its RBF replacements or the witnesses it has
+    /// replaced.
+    pub fn conflicting_witnesses(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<BTreeSet<XWitnessId>, StockError<S, H, P>> {
+        let mut witness_ids = BTreeSet::new();
+        for bundle_id in self.stash.witness(witness_id)?.anchors.known_bundle_ids() {
+            witness_ids.extend(self.index.bundle_info(bundle_id)?.0);
+        }
+        witness_ids.remove(&witness_id);
+        Ok(witness_ids)
+    }
+
+    /// Collects all state transitions and state extensions between the
+    /// provided operations and the contract genesis, which are required to
+    /// construct a consignment.
+    #[allow(clippy::type_complexity)]
+    pub(super) fn ancestors<'a>(
+        &self,
+        contract_id: ContractId,
+        transitions: impl IntoIterator<Item = &'a Transition>,
+        extensions: impl IntoIterator<Item = &'a Extension>,
+    ) -> Result<
+        (BTreeMap<OpId, &Transition>, BTreeMap<OpId, &Extension>),
+        StockError<S, H, P, ConsignError>,
+    > {
+        let mut ancestors = BTreeMap::new();
+        let mut ancestor_extensions = BTreeMap::new();
+        let mut ids = vec![];
+        for transition in transitions {
+            ids.extend(transition.inputs.iter().map(|input| input.prev_out.op));
+        }
+        for extension in extensions {
+            ids.extend(extension.redeemed.values().copied());
+        }
+        while let Some(id) = ids.pop() {
+            if id == contract_id
+                || ancestors.contains_key(&id)
+                || ancestor_extensions.contains_key(&id)
+            {
+                continue; // we skip genesis since it will be present anywhere
+            }
+            if let Some(extension) = self.known_extension(id)? {
+                ids.extend(extension.redeemed.values().copied());
+                ancestor_extensions.insert(id, extension);
+                continue;
+            }
+            let transition = self.transition(contract_id, id)?;
+            ids.extend(transition.inputs.iter().map(|input| input.prev_out.op));
+            ancestors.insert(id, transition);
+        }
+        Ok((ancestors, ancestor_extensions))
+    }
+
+    /// Returns state extension with the given id, or `None` if the stash
+    /// doesn't know such state extension.
+    fn known_extension(&self, opid: OpId) -> Result<Option<&Extension>, StashError<S>> {
+        match self.stash.extension(opid) {
+            Ok(extension) => Ok(Some(extension)),
+            Err(StashError::Inconsistency(StashInconsistency::OperationAbsent(id)))
+                if id == opid =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
     }
 
-    fn transition(&self, opid: OpId) -> Result<&Transition, StockError<S, H, P, ConsignError>> {
+    pub(super) fn transition(
+        &self,
+        contract_id: ContractId,
+        opid: OpId,
+    ) -> Result<&Transition, StockError<S, H, P, ConsignError>> {
+        if self
+            .stash
+            .prune_checkpoint(contract_id)?
+            .is_some_and(|checkpoint| checkpoint.ops.contains(&opid))
+        {
+            return Err(ConsignError::Pruned(contract_id, opid).into());
+        }
         let bundle_id = self.index.bundle_id_for_op(opid)?;
         let bundle = self.stash.bundle(bundle_id)?;
         bundle
@@ -1363,6 +2063,9 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             .ok_or(ConsignError::Concealed(bundle_id, opid).into())
     }
 
+    /// Constructs client-side bundle with all known transitions of the bundle.
+    /// Disclosures retain only the transitions they need using
+    /// [`ClientBundle::retain_transitions`].
     fn client_bundle(&self, bundle_id: BundleId) -> Result<ClientBundle, StockError<S, H, P>> {
         let (witness_ids, contract_id) = self.index.bundle_info(bundle_id)?;
 
@@ -1394,8 +2097,6 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             .into());
         };
 
-        // TODO: Conceal all transitions except the one we need
-
         Ok(ClientBundle::new(mpc_proof, dbc, bundle))
     }
 
@@ -1403,34 +2104,632 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         &mut self,
         seal: XChain<GraphSeal>,
     ) -> Result<bool, StockError<S, H, P>> {
-        Ok(self.stash.store_secret_seal(seal)?)
+        let mut stored = false;
+        self.store_transaction(|stash, _, _| {
+            stored = stash.store_secret_seal(seal)?;
+            Ok(())
+        })?;
+        if stored {
+            self.observers.emit([StockEvent::SecretSealStored {
+                seal: seal.conceal(),
+            }]);
+        }
+        Ok(stored)
     }
 
+    /// Updates ordering of the witnesses known to the contract state, starting
+    /// from `after_height`, and re-derives state of the contracts affected by
+    /// the changed witnesses (for instance, due to blockchain re-orgs or RBF
+    /// replacements).
+    ///
+    /// Operations whose witnesses became archived are unwound, and bundles
+    /// committed by several witnesses are re-applied using the valid witness
+    /// with the highest priority. The returned [`UpdateRes`] lists the
+    /// allocations which appeared or disappeared as the result.
+    ///
+    /// Witnesses are resolved one by one; use [`Stock::update_witnesses_batched`]
+    /// for batch or parallel resolution.
     pub fn update_witnesses(
         &mut self,
         resolver: impl ResolveWitness,
         after_height: u32,
     ) -> Result<UpdateRes, StockError<S, H, P>> {
-        Ok(self.state.update_witnesses(resolver, after_height)?)
+        self.update_witnesses_batched(SequentialResolver(resolver), after_height, |_, _| {})
     }
-}
 
-#[derive(Clone, Eq, PartialEq, Debug)]
-pub struct UpdateRes {
-    pub succeeded: usize,
-    pub failed: HashMap<XWitnessId, String>,
-}
+    /// Updates witnesses like [`Stock::update_witnesses`], resolving them in
+    /// batches with the `resolver` (see [`SequentialResolver`] and
+    /// [`crate::interface::ParallelResolver`]). After each batch `progress`
+    /// is called with the number of already processed and the total number of
+    /// witnesses to resolve.
+    pub fn update_witnesses_batched(
+        &mut self,
+        resolver: impl ResolveWitnessBatch,
+        after_height: u32,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<UpdateRes, StockError<S, H, P>> {
+        let bundle_witnesses = Self::stash_bundle_witnesses(&self.stash)?;
+        let mut ords = BTreeMap::new();
+        for witness_ids in bundle_witnesses.values() {
+            for witness_id in witness_ids {
+                if let Ok(ord) = self.state.as_provider().witness_ord(*witness_id) {
+                    ords.insert(*witness_id, ord);
+                }
+            }
+        }
 
-#[cfg(test)]
-mod test {
-    use std::str::FromStr;
+        // Only contracts with bundles anchored by the witnesses which are going to
+        // be resolved may be affected by the update
+        let min_height = NonZeroU32::new(after_height).unwrap_or(NonZeroU32::MIN);
+        let mut before = BTreeSet::new();
+        let mut pending = BTreeSet::new();
+        for (bundle_id, witness_ids) in &bundle_witnesses {
+            let is_pending = witness_ids.iter().any(|id| {
+                ords.get(id).is_some_and(
+                    |ord| !matches!(ord, WitnessOrd::Mined(pos) if pos.height() < min_height),
+                )
+            });
+            if is_pending {
+                let bundle = self.stash.bundle(*bundle_id)?;
+                pending.extend(
+                    bundle
+                        .known_transitions
+                        .values()
+                        .map(|transition| transition.contract_id),
+                );
+            }
+        }
+        for contract_id in pending {
+            before.extend(self.contract_allocations(contract_id)?);
+        }
 
-    use baid64::FromBaid64Str;
+        let resolver = ReplacedResolver {
+            resolver,
+            replaced: self.stash.replaced_witnesses()?,
+        };
+        let mut res = UpdateRes::default();
+        let mut affected = BTreeSet::new();
+        let mut changes = BTreeMap::<XWitnessId, (BTreeSet<ContractId>, BTreeSet<Opout>)>::new();
+        self.store_transaction(|stash, state, _| {
+            res = state.update_witnesses(resolver, after_height, progress)?;
+            for (bundle_id, witness_ids) in &bundle_witnesses {
+                let changed = witness_ids
+                    .iter()
+                    .filter(|id| res.updated.contains_key(id))
+                    .collect::<Vec<_>>();
+                if changed.is_empty() {
+                    continue;
+                }
+                let bundle = stash.bundle(*bundle_id)?;
+                let contract_ids = bundle
+                    .known_transitions
+                    .values()
+                    .map(|transition| transition.contract_id)
+                    .collect::<BTreeSet<_>>();
+                for witness_id in changed {
+                    let (ids, opouts) = changes.entry(*witness_id).or_default();
+                    ids.extend(&contract_ids);
+                    opouts.extend(bundle_opouts(bundle));
+                }
+                affected.extend(contract_ids);
+            }
+
+            // Witness ordering is already updated, so we replay contracts with it
+            // instead of resolving the witnesses once more
+            ords.extend(
+                res.updated
+                    .iter()
+                    .map(|(witness_id, update)| (*witness_id, update.to)),
+            );
+            Self::refresh_contracts(stash, state, &affected, KnownOrdResolver(ords))
+        })?;
+        if res.updated.is_empty() {
+            return Ok(res);
+        }
+
+        let mut after = BTreeSet::new();
+        for contract_id in &affected {
+            after.extend(self.contract_allocations(*contract_id)?);
+        }
+        before.retain(|allocation| affected.contains(&allocation.contract_id));
+        res.appeared = after.difference(&before).copied().collect();
+        res.disappeared = before.difference(&after).copied().collect();
+
+        self.observers
+            .emit(res.updated.iter().map(|(witness_id, update)| {
+                let (contract_ids, opouts) = changes.remove(witness_id).unwrap_or_default();
+                StockEvent::WitnessOrdChanged {
+                    witness_id: *witness_id,
+                    ord: update.to,
+                    contract_ids,
+                    opouts,
+                }
+            }));
+        Ok(res)
+    }
+
+    fn contract_allocations(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Allocation>, StockError<S, H, P>> {
+        let contract = self.state.contract_state(contract_id)?;
+        let allocation = |opout: Opout, seal: XOutputSeal| Allocation {
+            contract_id,
+            opout,
+            seal,
+        };
+        Ok(contract
+            .rights_all()
+            .map(|a| allocation(a.opout, a.seal))
+            .chain(contract.fungible_all().map(|a| allocation(a.opout, a.seal)))
+            .chain(contract.data_all().map(|a| allocation(a.opout, a.seal)))
+            .chain(contract.attach_all().map(|a| allocation(a.opout, a.seal)))
+            .collect())
+    }
+
+    /// Removes all data of the contract from the stash, contract state and
+    /// index, including signatures and supplements of the contract genesis.
+    ///
+    /// Unless `force` is set, the contract is kept if it has allocations on
+    /// any of the `outpoints`, which should list outpoints controlled by the
+    /// wallet. Witnesses anchoring bundles of other contracts, as well as
+    /// attachments and secret seals used by other contracts, are kept as well.
+    pub fn forget_contract(
+        &mut self,
+        contract_id: ContractId,
+        outpoints: impl IntoIterator<Item = impl Into<XOutpoint>>,
+        force: bool,
+    ) -> Result<(), StockError<S, H, P, ForgetError>> {
+        self.stash.genesis(contract_id)?;
+        if !force {
+            let allocations = self
+                .contract_assignments_for(contract_id, outpoints)?
+                .values()
+                .map(HashMap::len)
+                .sum::<usize>();
+            if allocations > 0 {
+                return Err(ForgetError::OwnedAllocations(contract_id, allocations).into());
+            }
+        }
+
+        self.store_transaction::<Infallible>(move |stash, state, index| {
+            // Attachments and secret seals are removed only if no other contract uses them
+            let mut refs = OpRefs::default();
+            let mut other_refs = OpRefs::default();
+            for genesis in stash.geneses()? {
+                if genesis.contract_id() == contract_id {
+                    refs.add(genesis);
+                } else {
+                    other_refs.add(genesis);
+                }
+            }
+            let mut opids = bset![stash.genesis(contract_id)?.id()];
+            let mut extensions = bset![];
+            for opid in stash.extension_ids()? {
+                let extension = stash.extension(opid)?;
+                if extension.contract_id == contract_id {
+                    refs.add(extension);
+                    extensions.insert(opid);
+                } else {
+                    other_refs.add(extension);
+                }
+            }
+            opids.extend(&extensions);
+            let mut bundles = bset![];
+            for bundle_id in stash.bundle_ids()? {
+                let bundle = stash.bundle(bundle_id)?;
+                if bundle
+                    .known_transitions
+                    .values()
+                    .any(|transition| transition.contract_id == contract_id)
+                {
+                    bundle.known_transitions.values().for_each(|t| refs.add(t));
+                    opids.extend(bundle.input_map.values());
+                    opids.extend(bundle.known_transitions.keys());
+                    bundles.insert(bundle_id);
+                } else {
+                    bundle
+                        .known_transitions
+                        .values()
+                        .for_each(|t| other_refs.add(t));
+                }
+            }
+            let attachments = refs.attachments.difference(&other_refs.attachments);
+            let attachments = attachments.copied().collect::<Vec<_>>();
+            let secret_seals = stash
+                .as_provider()
+                .secret_seals()
+                .map_err(StockError::StashRead)?
+                .filter(|seal| {
+                    let secret = seal.conceal();
+                    refs.seals.contains(&secret) && !other_refs.seals.contains(&secret)
+                })
+                .collect::<Vec<_>>();
+            let genesis_ref = ContentRef::Genesis(contract_id);
+            let mut content_ids = bset![ContentId::Genesis(contract_id)];
+            content_ids.extend(
+                stash
+                    .supplements(genesis_ref)?
+                    .map(|suppl| ContentId::Suppl(suppl.suppl_id())),
+            );
+            let mut witnesses = bset![];
+            for witness_id in stash.witness_ids()? {
+                let mut ids = stash.witness(witness_id)?.anchors.known_bundle_ids();
+                if ids.all(|bundle_id| bundles.contains(&bundle_id)) {
+                    witnesses.insert(witness_id);
+                }
+            }
+
+            let index = index.as_provider_mut();
+            index
+                .remove_terminals(&opids)
+                .map_err(StockError::IndexWrite)?;
+            for bundle_id in &bundles {
+                index
+                    .remove_bundle(*bundle_id)
+                    .map_err(StockError::IndexWrite)?;
+            }
+            index
+                .remove_contract(contract_id)
+                .map_err(StockError::IndexWrite)?;
+
+            let state = state.as_provider_mut();
+            state
+                .remove_contract(contract_id)
+                .map_err(StockError::StateWrite)?;
+            for witness_id in &witnesses {
+                state
+                    .remove_witness(*witness_id)
+                    .map_err(StockError::StateWrite)?;
+            }
+
+            let stash = stash.as_provider_mut();
+            for opid in extensions {
+                stash
+                    .remove_extension(opid)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for bundle_id in bundles {
+                stash
+                    .remove_bundle(bundle_id)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for witness_id in witnesses {
+                stash
+                    .remove_witness(witness_id)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for id in attachments {
+                stash
+                    .remove_attachment(id)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for seal in secret_seals {
+                stash
+                    .remove_secret_seal(seal)
+                    .map_err(StockError::StashWrite)?;
+            }
+            for content_id in content_ids {
+                stash
+                    .remove_sigs(content_id)
+                    .map_err(StockError::StashWrite)?;
+            }
+            stash
+                .remove_supplements(genesis_ref)
+                .map_err(StockError::StashWrite)?;
+            stash
+                .remove_genesis(contract_id)
+                .map_err(StockError::StashWrite)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Collects information about witnesses for each of the bundles known to
+    /// the stash, using their anchors.
+    pub(super) fn stash_bundle_witnesses(
+        stash: &Stash<S>,
+    ) -> Result<BTreeMap<BundleId, BTreeSet<XWitnessId>>, StockError<S, H, P>> {
+        let mut bundle_witnesses = BTreeMap::<_, BTreeSet<_>>::new();
+        for witness_id in stash.witness_ids()? {
+            let witness = stash.witness(witness_id)?;
+            for bundle_id in witness.anchors.known_bundle_ids() {
+                bundle_witnesses
+                    .entry(bundle_id)
+                    .or_default()
+                    .insert(witness_id);
+            }
+        }
+        Ok(bundle_witnesses)
+    }
+
+    /// Re-derives the state of the `contracts` by replaying their operations
+    /// known to the stash.
+    ///
+    /// The state of the contracts having a [`super::PruneCheckpoint`] is not removed
+    /// before the replay, such that it keeps the contribution of the pruned
+    /// operations, while the remaining operations are added to it once more.
+    fn refresh_contracts(
+        stash: &Stash<S>,
+        state: &mut State<H>,
+        contracts: &BTreeSet<ContractId>,
+        resolver: impl ResolveWitness,
+    ) -> Result<(), StockError<S, H, P>> {
+        for contract_id in contracts {
+            if stash.prune_checkpoint(*contract_id)?.is_none() {
+                state
+                    .as_provider_mut()
+                    .remove_contract(*contract_id)
+                    .map_err(StockError::StateWrite)?;
+            }
+        }
+        Self::replay_contracts(stash, state, |id| contracts.contains(&id), resolver)
+    }
+
+    /// Replays all operations known to the stash for the contracts matching
+    /// `filter` into the contract state.
+    ///
+    /// Since the history of the contracts having a [`super::PruneCheckpoint`] is
+    /// incomplete, their operations are replayed only on top of their existing
+    /// state; fails with [`StockError::PrunedState`] if the state of such a
+    /// contract is absent.
+    pub(super) fn replay_contracts(
+        stash: &Stash<S>,
+        state: &mut State<H>,
+        filter: impl Fn(ContractId) -> bool,
+        resolver: impl ResolveWitness,
+    ) -> Result<(), StockError<S, H, P>> {
+        let bundle_witnesses = Self::stash_bundle_witnesses(stash)?;
+
+        let mut contract_bundles = BTreeMap::<_, Vec<_>>::new();
+        for bundle_id in stash.bundle_ids()? {
+            let bundle = stash.bundle(bundle_id)?;
+            let Some(contract_id) = bundle
+                .known_transitions
+                .values()
+                .map(|transition| transition.contract_id)
+                .next()
+            else {
+                continue;
+            };
+            if !filter(contract_id) {
+                continue;
+            }
+            for witness_id in bundle_witnesses.get(&bundle_id).into_iter().flatten() {
+                contract_bundles
+                    .entry(contract_id)
+                    .or_default()
+                    .push((*witness_id, bundle));
+            }
+        }
+        let mut contract_extensions = BTreeMap::<_, Vec<_>>::new();
+        for opid in stash.extension_ids()? {
+            let extension = stash.extension(opid)?;
+            if filter(extension.contract_id) {
+                contract_extensions
+                    .entry(extension.contract_id)
+                    .or_default()
+                    .push(extension);
+            }
+        }
+
+        for genesis in stash.geneses()? {
+            let contract_id = genesis.contract_id();
+            if !filter(contract_id) {
+                continue;
+            }
+            if stash.prune_checkpoint(contract_id)?.is_some() {
+                match state.as_provider().contract_state(contract_id) {
+                    Ok(_) => {}
+                    Err(err)
+                        if err.inconsistency()
+                            == Some(&StateInconsistency::UnknownContract(contract_id)) =>
+                    {
+                        return Err(StockError::PrunedState(contract_id));
+                    }
+                    Err(err) => return Err(StockError::StateRead(err)),
+                }
+            }
+            let schema = &stash.schema(genesis.schema_id)?.schema;
+            state.replay_contract(
+                schema,
+                genesis,
+                contract_extensions.remove(&contract_id).unwrap_or_default(),
+                contract_bundles.remove(&contract_id).unwrap_or_default(),
+                &resolver,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Re-creates contract index from the stash data.
+    ///
+    /// The provided index provider must not contain any data. It replaces the
+    /// existing index provider, taking over its persistence, such that the
+    /// next store operation overwrites the previous index. Can be used to
+    /// recover from a lost or corrupted index, in which case the stock should
+    /// be constructed with [`Stock::with`] from a loaded stash, state and an
+    /// empty index.
+    pub fn reindex(&mut self, provider: P) -> Result<(), StockError<S, H, P>> {
+        let mut index = Index::new(provider);
+        Self::index_stash(&self.stash, &mut index)?;
+
+        let persistence = self.index.as_provider_mut().as_mut_persistence().take();
+        *index.as_provider_mut().as_mut_persistence() = persistence;
+        self.index = index;
+        self.index.as_provider_mut().mark_dirty();
+        Ok(())
+    }
+
+    /// Indexes all geneses, state extensions and bundles known to the stash.
+    pub(super) fn index_stash(
+        stash: &Stash<S>,
+        index: &mut Index<P>,
+    ) -> Result<(), StockError<S, H, P>> {
+        let bundle_witnesses = Self::stash_bundle_witnesses(stash)?;
+        for genesis in stash.geneses()? {
+            index.index_contract(genesis)?;
+        }
+        for opid in stash.extension_ids()? {
+            let extension = stash.extension(opid)?;
+            index.index_extension(extension.contract_id, extension)?;
+        }
+        for bundle_id in stash.bundle_ids()? {
+            let bundle = stash.bundle(bundle_id)?;
+            let Some(contract_id) = bundle
+                .known_transitions
+                .values()
+                .map(|transition| transition.contract_id)
+                .next()
+            else {
+                continue;
+            };
+            for witness_id in bundle_witnesses.get(&bundle_id).into_iter().flatten() {
+                index.index_bundle(contract_id, bundle, *witness_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-creates contract state from the stash data by replaying all known
+    /// contract operations in the order of their witnesses.
+    ///
+    /// The provided state provider must not contain any data. It replaces the
+    /// existing state provider, taking over its persistence, such that the
+    /// next store operation overwrites the previous state. Can be used to
+    /// recover from a lost or corrupted state, in which case the stock should
+    /// be constructed with [`Stock::with`] from a loaded stash, index and an
+    /// empty state.
+    ///
+    /// Fails with [`StockError::PrunedState`] if the history of some contract
+    /// was pruned (see [`Stock::prune`]).
+    pub fn rebuild_state(
+        &mut self,
+        provider: H,
+        resolver: impl ResolveWitness,
+    ) -> Result<(), StockError<S, H, P>> {
+        let resolver = ReplacedResolver {
+            resolver,
+            replaced: self.stash.replaced_witnesses()?,
+        };
+        let mut state = State::new(provider);
+        Self::replay_contracts(&self.stash, &mut state, |_| true, resolver)?;
+
+        let persistence = self.state.as_provider_mut().as_mut_persistence().take();
+        *state.as_provider_mut().as_mut_persistence() = persistence;
+        self.state = state;
+        self.state.as_provider_mut().mark_dirty();
+        Ok(())
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct UpdateRes {
+    pub succeeded: usize,
+    pub failed: HashMap<XWitnessId, String>,
+    /// Witnesses whose ordering was changed by the update.
+    pub updated: BTreeMap<XWitnessId, OrdUpdate>,
+    /// Allocations which became valid after the update.
+    pub appeared: BTreeSet<Allocation>,
+    /// Allocations which became invalid after the update.
+    pub disappeared: BTreeSet<Allocation>,
+}
+
+/// Change of the witness ordering reported in [`UpdateRes`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct OrdUpdate {
+    pub from: WitnessOrd,
+    pub to: WitnessOrd,
+}
+
+/// Conceals all operation outputs except the `required` ones. Fungible and
+/// structured state is kept revealed, since it is required by the validation
+/// scripts (and range proofs for the concealed fungible state are not
+/// supported), thus only seals of such outputs are concealed.
+fn conceal_outputs<Seal: ExposedSeal>(
+    opid: OpId,
+    assignments: &mut Assignments<Seal>,
+    required: &BTreeSet<Opout>,
+) {
+    for (ty, assigns) in assignments.keyed_values_mut() {
+        let keep = |no| required.contains(&Opout::new(opid, *ty, no));
+        match assigns {
+            TypedAssigns::Declarative(_) | TypedAssigns::Attachment(_) => {
+                assigns.conceal_except(keep)
+            }
+            TypedAssigns::Fungible(_) | TypedAssigns::Structured(_) => {
+                assigns.conceal_seals_except(keep)
+            }
+        }
+    }
+}
+
+/// Returns id of the revealed attachment assigned to the `opout`.
+fn attach_id<Seal: ExposedSeal>(assignments: &Assignments<Seal>, opout: Opout) -> Option<AttachId> {
+    assignments
+        .get(&opout.ty)?
+        .as_attachment()
+        .get(opout.no as usize)?
+        .as_revealed_state()
+        .map(|attach| attach.file.id)
+}
+
+/// Attachments and concealed seals referenced by contract operations.
+#[derive(Default)]
+struct OpRefs {
+    attachments: BTreeSet<AttachId>,
+    seals: BTreeSet<XChain<SecretSeal>>,
+}
+
+impl OpRefs {
+    fn add(&mut self, op: &impl Operation) {
+        for assigns in op.assignments().flat().values() {
+            self.attachments.extend(
+                assigns
+                    .as_attachment()
+                    .iter()
+                    .filter_map(|a| a.as_revealed_state())
+                    .map(|attach| attach.file.id),
+            );
+            self.seals.extend(assigns.to_confidential_seals());
+        }
+    }
+}
+
+/// Contract state assigned to a single-use seal.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct Allocation {
+    pub contract_id: ContractId,
+    pub opout: Opout,
+    pub seal: XOutputSeal,
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::{NonZeroU32, NonZeroUsize};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    use amplify::ByteArray;
+    use baid64::FromBaid64Str;
     use commit_verify::{Conceal, DigestExt, Sha256};
-    use strict_encoding::TypeName;
+    use nonasync::persistence::{PersistenceProvider, Persisting};
+    use rgb::vm::{WitnessOrd, WitnessPos, XWitnessTx};
+    use rgb::{
+        Assign, Assignments, Input, InputMap, MediaType, RevealedAttach, TransitionBundle,
+        TypedAssigns, VoidState,
+    };
+    use strict_encoding::{StrictDumb, StrictSerialize, TypeName};
 
     use super::*;
-    use crate::containers::ConsignmentExt;
+    use crate::containers::{ConsignmentExt, SigBlob, Supplement};
+    use crate::interface::ParallelResolver;
+    use crate::persistence::fixture::{
+        mined, transferred_stock, Fixture, Transfers, ATTACH, OWNED,
+    };
+    use crate::persistence::{
+        ContractStateRead, ContractStateWrite, MemContract, MemContractState, PruneCheckpoint,
+    };
 
     #[test]
     fn test_consign() {
@@ -1513,4 +2812,964 @@ mod test {
             println!("{:?}", builder.transition_type())
         }
     }
+
+    #[test]
+    fn test_failed_transaction_rollback() {
+        let mut stock = Stock::in_memory();
+        let seal = XChain::with(
+            rgbcore::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+
+        let res = stock.store_transaction::<Infallible>(|stash, _, index| {
+            stash.as_provider_mut().add_secret_seal(seal).unwrap();
+            index
+                .as_provider_mut()
+                .register_contract(ContractId::from_byte_array([1u8; 32]))
+                .unwrap();
+            Err(StockError::Resolver(s!("failure")))
+        });
+        assert!(res.is_err());
+        assert_eq!(stock.as_stash_provider().secret_seals().unwrap().count(), 0);
+        assert!(stock.as_index_provider().debug_contract_index().is_empty());
+    }
+
+    #[test]
+    fn test_transaction_stores_all() {
+        /// Counts separate and atomic stores of the stock data.
+        #[derive(Clone, Debug, Default)]
+        struct CountingStore(Arc<Mutex<(usize, usize)>>);
+        impl<T> PersistenceProvider<T> for CountingStore {
+            fn load(&self) -> Result<T, PersistenceError> { unreachable!() }
+            fn store(&self, _: &T) -> Result<(), PersistenceError> {
+                self.0.lock().unwrap().0 += 1;
+                Ok(())
+            }
+        }
+        impl StockPersistence<MemStash, MemState, MemIndex> for CountingStore {
+            fn store_all(
+                &self,
+                _: &MemStash,
+                _: &MemState,
+                _: &MemIndex,
+            ) -> Result<(), PersistenceError> {
+                self.0.lock().unwrap().1 += 1;
+                Ok(())
+            }
+        }
+
+        let store = CountingStore::default();
+        let mut stock = Stock::in_memory();
+        stock.make_persistent_atomic(store.clone(), true).unwrap();
+        assert_eq!(*store.0.lock().unwrap(), (0, 1));
+
+        let seal = XChain::with(
+            rgbcore::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        stock.store_secret_seal(seal).unwrap();
+        assert_eq!(*store.0.lock().unwrap(), (0, 2));
+        assert!(stock.as_stash_provider().persistence().unwrap().autosave);
+
+        let res = stock
+            .store_transaction::<Infallible>(|_, _, _| Err(StockError::Resolver(s!("failure"))));
+        assert!(res.is_err());
+        assert_eq!(*store.0.lock().unwrap(), (0, 2));
+    }
+
+    #[test]
+    fn test_plain_provider_stores_separately() {
+        /// Provider without atomic store of the stock data.
+        #[derive(Clone, Debug, Default)]
+        struct PlainStore(Arc<Mutex<usize>>);
+        impl<T> PersistenceProvider<T> for PlainStore {
+            fn load(&self) -> Result<T, PersistenceError> { unreachable!() }
+            fn store(&self, _: &T) -> Result<(), PersistenceError> {
+                *self.0.lock().unwrap() += 1;
+                Ok(())
+            }
+        }
+
+        let store = PlainStore::default();
+        let mut stock = Stock::in_memory();
+        stock.make_persistent(store.clone(), true).unwrap();
+        assert_eq!(*store.0.lock().unwrap(), 3);
+
+        let seal = XChain::with(
+            rgbcore::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        stock.store_secret_seal(seal).unwrap();
+        assert!(*store.0.lock().unwrap() > 3);
+        stock.store().unwrap();
+    }
+
+    #[test]
+    fn test_rollback_committed_transaction() {
+        #[derive(Clone, Debug)]
+        struct FailingStore;
+        impl<T> PersistenceProvider<T> for FailingStore {
+            fn load(&self) -> Result<T, PersistenceError> { unreachable!() }
+            fn store(&self, _: &T) -> Result<(), PersistenceError> { Ok(()) }
+        }
+        impl StockPersistence<MemStash, MemState, MemIndex> for FailingStore {
+            fn store_all(
+                &self,
+                _: &MemStash,
+                _: &MemState,
+                _: &MemIndex,
+            ) -> Result<(), PersistenceError> {
+                Err(PersistenceError::with(crate::persistence::ReadOnlyError))
+            }
+        }
+
+        let mut stock = Stock::in_memory();
+        fn persistence<T: Persisting>() -> Persistence<T>
+        where FailingStore: PersistenceProvider<T> {
+            Persistence {
+                dirty: false,
+                autosave: true,
+                provider: Box::new(FailingStore),
+            }
+        }
+        stock.persistence = Some(Box::new(FailingStore));
+        stock
+            .as_stash_provider_mut()
+            .as_mut_persistence()
+            .replace(persistence());
+        stock
+            .as_state_provider_mut()
+            .as_mut_persistence()
+            .replace(persistence());
+        stock
+            .as_index_provider_mut()
+            .as_mut_persistence()
+            .replace(persistence());
+
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let seal = XChain::with(
+            rgbcore::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+        // All the providers get committed before the data are stored
+        let res = stock.store_transaction::<Infallible>(|stash, state, index| {
+            stash.as_provider_mut().add_secret_seal(seal).unwrap();
+            state
+                .as_provider_mut()
+                .register_contract(contract.schema(), contract.genesis())
+                .unwrap();
+            index
+                .as_provider_mut()
+                .register_contract(contract.contract_id())
+                .unwrap();
+            Ok(())
+        });
+        assert!(matches!(res, Err(StockError::Persistence(_))));
+        assert_eq!(stock.as_stash_provider().secret_seals().unwrap().count(), 0);
+        assert!(stock.as_state_provider().debug_contracts().is_empty());
+        assert!(stock.as_index_provider().debug_contract_index().is_empty());
+    }
+
+    #[test]
+    fn test_rollback_replayed_contract() {
+        let (mut stock, transfers) = transferred_stock();
+        let contract_id = transfers.fixture.contract_id();
+        let state = stock
+            .as_state_provider()
+            .to_strict_serialized::<{ u32::MAX as usize }>()
+            .unwrap();
+
+        // The contract state is changed many times by the replay, and the rollback
+        // must restore the value it had before the first change
+        let res = stock.store_transaction::<Infallible>(|stash, state, _| {
+            state
+                .as_provider_mut()
+                .remove_contract(contract_id)
+                .map_err(StockError::StateWrite)?;
+            Stock::replay_contracts(
+                stash,
+                state,
+                |id| id == contract_id,
+                &transfers.fixture.chain,
+            )?;
+            Err(StockError::Resolver(s!("failure")))
+        });
+        assert!(res.is_err());
+        assert_eq!(
+            stock
+                .as_state_provider()
+                .to_strict_serialized::<{ u32::MAX as usize }>()
+                .unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn test_reindex_rebuild_state() {
+        let (mut stock, transfers) = transferred_stock();
+        let index = stock
+            .as_index_provider()
+            .to_strict_serialized::<{ u32::MAX as usize }>()
+            .unwrap();
+        let state = stock
+            .as_state_provider()
+            .to_strict_serialized::<{ u32::MAX as usize }>()
+            .unwrap();
+        assert_eq!(
+            stock
+                .as_state_provider()
+                .debug_witnesses()
+                .keys()
+                .copied()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(transfers.witnesses)
+        );
+        assert!(stock
+            .as_index_provider()
+            .bundle_id_for_op(transfers.next.id())
+            .is_ok());
+
+        stock.reindex(MemIndex::in_memory()).unwrap();
+        stock
+            .rebuild_state(MemState::in_memory(), &transfers.fixture.chain)
+            .unwrap();
+        assert_eq!(
+            stock
+                .as_index_provider()
+                .to_strict_serialized::<{ u32::MAX as usize }>()
+                .unwrap(),
+            index
+        );
+        assert_eq!(
+            stock
+                .as_state_provider()
+                .to_strict_serialized::<{ u32::MAX as usize }>()
+                .unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn test_forget_unknown_contract() {
+        let mut stock = Stock::in_memory();
+        let contract_id = ContractId::from_byte_array([2u8; 32]);
+        let res = stock.forget_contract(contract_id, Vec::<XOutpoint>::new(), true);
+        assert!(matches!(res, Err(StockError::StashInconsistency(_) | StockError::StashRead(_))));
+    }
+
+    #[test]
+    fn test_forget_contract() {
+        let (mut stock, forgotten) = transferred_stock();
+        let kept = Transfers::consume(&mut stock);
+        let forgotten_id = forgotten.fixture.contract_id();
+        let kept_id = kept.fixture.contract_id();
+        let kept_bundle = stock
+            .as_index_provider()
+            .bundle_id_for_op(kept.first.id())
+            .unwrap();
+        let forgotten_bundle = stock
+            .as_index_provider()
+            .bundle_id_for_op(forgotten.first.id())
+            .unwrap();
+        let kept_state = stock.as_state_provider().debug_contracts()[&kept_id].clone();
+        let kept_index = stock.as_index_provider().debug_contract_index()[&kept_id].clone();
+        for id in [forgotten_id, kept_id] {
+            let stash = stock.as_stash_provider_mut();
+            stash
+                .import_sigs(ContentId::Genesis(id), [(Identity::default(), SigBlob::default())])
+                .unwrap();
+            stash
+                .add_supplement(Supplement::new(id, Identity::default()))
+                .unwrap();
+        }
+
+        let genesis_id = forgotten.fixture.genesis.id();
+        let owned = XChain::Bitcoin(forgotten.fixture.outpoint(Opout::new(genesis_id, OWNED, 2)));
+        assert!(matches!(
+            stock.forget_contract(forgotten_id, [owned], false),
+            Err(StockError::InvalidInput(ForgetError::OwnedAllocations(id, 1))) if id == forgotten_id
+        ));
+        stock.forget_contract(forgotten_id, [owned], true).unwrap();
+
+        let stash = stock.as_stash_provider();
+        assert!(stash.genesis(forgotten_id).is_err());
+        assert!(stash.bundle(forgotten_bundle).is_err());
+        assert!(stash
+            .sigs_for(&ContentId::Genesis(forgotten_id))
+            .unwrap()
+            .is_none());
+        assert!(stash
+            .supplement(ContentRef::Genesis(forgotten_id))
+            .unwrap()
+            .is_none());
+        for witness_id in forgotten.witnesses {
+            assert!(stash.witness(witness_id).is_err());
+            assert!(stock.as_state_provider().witness_ord(witness_id).is_err());
+        }
+        assert!(stock
+            .as_state_provider()
+            .contract_state(forgotten_id)
+            .is_err());
+        assert!(stock
+            .as_index_provider()
+            .bundle_id_for_op(forgotten.first.id())
+            .is_err());
+        assert!(stock
+            .as_index_provider()
+            .public_opouts(forgotten_id)
+            .is_err());
+
+        assert!(stash.genesis(kept_id).is_ok());
+        assert!(stash.bundle(kept_bundle).is_ok());
+        assert!(stash
+            .sigs_for(&ContentId::Genesis(kept_id))
+            .unwrap()
+            .is_some());
+        assert!(stash
+            .supplement(ContentRef::Genesis(kept_id))
+            .unwrap()
+            .is_some());
+        for witness_id in kept.witnesses {
+            assert!(stash.witness(witness_id).is_ok());
+            assert!(stock.as_state_provider().witness_ord(witness_id).is_ok());
+        }
+        assert_eq!(stock.as_state_provider().debug_contracts()[&kept_id], kept_state);
+        assert_eq!(stock.as_index_provider().debug_contract_index()[&kept_id], kept_index);
+        assert!(stock.check_consistency().unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_consign_pruned() {
+        let mut stock = Stock::in_memory();
+        assert!(stock.prune(Vec::<XOutpoint>::new()).unwrap().is_empty());
+
+        let contract_id = ContractId::from_byte_array([2u8; 32]);
+        let opid = OpId::from_byte_array([3u8; 32]);
+        let checkpoint = PruneCheckpoint {
+            timestamp: 0,
+            ops: Confined::from_iter_checked([opid]),
+        };
+        stock
+            .as_stash_provider_mut()
+            .set_prune_checkpoint(contract_id, checkpoint)
+            .unwrap();
+        let res = stock.transition(contract_id, opid);
+        assert!(matches!(
+            res,
+            Err(StockError::InvalidInput(ConsignError::Pruned(id, op))) if id == contract_id && op == opid
+        ));
+    }
+
+    #[test]
+    fn test_prune_transfers() {
+        let (mut stock, transfers) = transferred_stock();
+        let fixture = &transfers.fixture;
+        let contract_id = fixture.contract_id();
+        let kept = fixture.outputs(transfers.sibling.id())[0];
+        let pruned = fixture.outputs(transfers.next.id())[0];
+        let bundle_id = stock
+            .as_index_provider()
+            .bundle_id_for_op(transfers.next.id())
+            .unwrap();
+
+        let removed = stock
+            .prune([XChain::Bitcoin(fixture.outpoint(kept))])
+            .unwrap();
+        assert_eq!(removed, bset![bundle_id]);
+        let stash = stock.as_stash_provider();
+        assert!(stash.bundle(bundle_id).is_err());
+        assert!(stash.witness(transfers.witnesses[1]).is_err());
+        assert!(stash.witness(transfers.witnesses[0]).is_ok());
+
+        let transfer = stock
+            .transfer(contract_id, [fixture.seal(kept)], None)
+            .unwrap();
+        transfer
+            .validate(&fixture.chain, &DumbValidator, true)
+            .unwrap();
+
+        let res = stock.transfer(contract_id, [fixture.seal(pruned)], None);
+        assert!(matches!(
+            res,
+            Err(StockError::InvalidInput(ConsignError::Pruned(id, op)))
+                if id == contract_id && op == transfers.next.id()
+        ));
+    }
+
+    #[test]
+    fn test_pruned_state_survives_replay() {
+        let (mut stock, transfers) = transferred_stock();
+        let mut fixture = transfers.fixture;
+        let contract_id = fixture.contract_id();
+        let kept = fixture.outputs(transfers.sibling.id())[0];
+        let pruned = fixture.outputs(transfers.next.id())[0];
+        stock
+            .prune([XChain::Bitcoin(fixture.outpoint(kept))])
+            .unwrap();
+        let state = stock.as_state_provider().debug_contracts()[&contract_id].clone();
+
+        // Re-org of the witness of the remaining bundle makes the contract replayed
+        fixture.chain.set_ord(transfers.witnesses[0], mined(99));
+        let res = stock.update_witnesses(&fixture.chain, 0).unwrap();
+        assert!(res.updated.contains_key(&transfers.witnesses[0]));
+        assert_eq!(stock.as_state_provider().debug_contracts()[&contract_id], state);
+        assert!(stock
+            .contract_state(contract_id)
+            .unwrap()
+            .rights_all()
+            .any(|a| a.opout == pruned));
+
+        let res = stock.rebuild_state(MemState::in_memory(), &fixture.chain);
+        assert!(matches!(res, Err(StockError::PrunedState(id)) if id == contract_id));
+    }
+
+    #[test]
+    fn test_secret_seal_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut stock = Stock::in_memory();
+        let sink = events.clone();
+        let id = stock.subscribe(move |event: &StockEvent| {
+            sink.lock().unwrap().push(event.clone());
+        });
+        let seal = XChain::with(
+            rgbcore::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(0)),
+        );
+
+        assert!(stock.store_secret_seal(seal).unwrap());
+        assert!(!stock.store_secret_seal(seal).unwrap());
+        assert_eq!(*events.lock().unwrap(), vec![StockEvent::SecretSealStored {
+            seal: seal.conceal()
+        }]);
+
+        assert!(stock.unsubscribe(id));
+        assert!(!stock.unsubscribe(id));
+        let seal = XChain::with(
+            rgbcore::Layer1::Bitcoin,
+            GraphSeal::new_random_vout(bp::dbc::Method::OpretFirst, Vout::from_u32(1)),
+        );
+        assert!(stock.store_secret_seal(seal).unwrap());
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_update_witnesses_reports_changes() {
+        struct OrdResolver(WitnessOrd);
+        impl ResolveWitness for OrdResolver {
+            fn resolve_pub_witness(
+                &self,
+                witness_id: XWitnessId,
+            ) -> Result<XWitnessTx, WitnessResolverError> {
+                Err(WitnessResolverError::Unknown(witness_id))
+            }
+            fn resolve_pub_witness_ord(
+                &self,
+                _: XWitnessId,
+            ) -> Result<WitnessOrd, WitnessResolverError> {
+                Ok(self.0)
+            }
+        }
+
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let witness_id = XWitnessId::strict_dumb();
+        let mut state = MemState::in_memory();
+        state
+            .register_contract(contract.schema(), contract.genesis())
+            .unwrap()
+            .add_transition(&Transition::strict_dumb(), witness_id, WitnessOrd::Tentative)
+            .unwrap();
+
+        let res = state
+            .update_witnesses(SequentialResolver(OrdResolver(WitnessOrd::Archived)), 0, |_, _| {})
+            .unwrap();
+        assert_eq!(res.updated, bmap! {
+            witness_id => OrdUpdate { from: WitnessOrd::Tentative, to: WitnessOrd::Archived }
+        });
+        assert!(!state.is_valid_witness(witness_id).unwrap());
+        let res = state
+            .update_witnesses(SequentialResolver(OrdResolver(WitnessOrd::Archived)), 0, |_, _| {})
+            .unwrap();
+        assert!(res.updated.is_empty());
+    }
+
+    #[test]
+    fn test_update_witnesses_batched() {
+        struct EvenArchived;
+        impl ResolveWitness for EvenArchived {
+            fn resolve_pub_witness(
+                &self,
+                witness_id: XWitnessId,
+            ) -> Result<XWitnessTx, WitnessResolverError> {
+                Err(WitnessResolverError::Unknown(witness_id))
+            }
+            fn resolve_pub_witness_ord(
+                &self,
+                witness_id: XWitnessId,
+            ) -> Result<WitnessOrd, WitnessResolverError> {
+                match witness_id {
+                    XChain::Bitcoin(txid) if txid.to_byte_array()[0] % 2 == 0 => {
+                        Ok(WitnessOrd::Archived)
+                    }
+                    _ => Ok(WitnessOrd::Tentative),
+                }
+            }
+        }
+
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let witness = |no: u8| XChain::Bitcoin(bp::Txid::from_byte_array([no; 32]));
+        let mut state = MemState::in_memory();
+        let mut writer = state
+            .register_contract(contract.schema(), contract.genesis())
+            .unwrap();
+        for no in 1..=5 {
+            writer
+                .add_transition(&Transition::strict_dumb(), witness(no), WitnessOrd::Tentative)
+                .unwrap();
+        }
+        drop(writer);
+
+        let resolver = ParallelResolver {
+            resolver: EvenArchived,
+            threads: NonZeroUsize::new(2).unwrap(),
+            batch_size: 3,
+        };
+        let mut progress = vec![];
+        let res = state
+            .update_witnesses(resolver, 0, |done, total| progress.push((done, total)))
+            .unwrap();
+        assert_eq!(progress, vec![(3, 5), (5, 5)]);
+        assert_eq!(res.succeeded, 5);
+        let archived = OrdUpdate {
+            from: WitnessOrd::Tentative,
+            to: WitnessOrd::Archived,
+        };
+        assert_eq!(res.updated, bmap! { witness(2) => archived, witness(4) => archived });
+    }
+
+    #[test]
+    fn test_select_valid_witness() {
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let witness = |no: u8| XChain::Bitcoin(bp::Txid::from_byte_array([no; 32]));
+        let mined = WitnessPos::bitcoin(NonZeroU32::MIN, 1_700_000_000).unwrap();
+        let mut state = State::new(MemState::in_memory());
+        let mut writer = state
+            .as_provider_mut()
+            .register_contract(contract.schema(), contract.genesis())
+            .unwrap();
+        for (no, ord) in [
+            (1, WitnessOrd::Archived),
+            (2, WitnessOrd::Tentative),
+            (3, WitnessOrd::Mined(mined)),
+            (4, WitnessOrd::Mined(mined)),
+        ] {
+            writer
+                .add_transition(&Transition::strict_dumb(), witness(no), ord)
+                .unwrap();
+        }
+        drop(writer);
+
+        for ids in [[1, 2, 3, 4], [4, 3, 2, 1]] {
+            let selected = state.select_valid_witness(ids.map(witness)).unwrap();
+            assert_eq!(selected, witness(3));
+        }
+        let selected = state
+            .select_valid_witness([witness(2), witness(1)])
+            .unwrap();
+        assert_eq!(selected, witness(2));
+        assert!(state.select_valid_witness([witness(1)]).is_err());
+    }
+
+    #[test]
+    fn test_replay_extension_witness() {
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let contract_id = contract.contract_id();
+        let witness = |no: u8| XChain::Bitcoin(bp::Txid::from_byte_array([no; 32]));
+        let mined = |height| {
+            WitnessOrd::Mined(
+                WitnessPos::bitcoin(NonZeroU32::new(height).unwrap(), 1_700_000_000).unwrap(),
+            )
+        };
+        let ty = AssignmentType::with(1);
+        let mut extension = Extension::strict_dumb();
+        extension.contract_id = contract_id;
+        extension.assignments = Assignments::from_inner(Confined::from_checked(bmap! {
+            ty => TypedAssigns::Declarative(Confined::from_checked(vec![Assign::revealed(
+                XChain::Bitcoin(rgb::GenesisSeal::new_random(
+                    bp::dbc::Method::TapretFirst,
+                    bp::Txid::from_byte_array([9; 32]),
+                    Vout::from_u32(0),
+                )),
+                VoidState::default(),
+            )]))
+        }));
+        let ext_id = extension.id();
+
+        // Several transitions close the extension seal, the first valid one must witness it
+        let mut bundles = vec![];
+        let mut ords = BTreeMap::new();
+        for (no, ord) in [(1, WitnessOrd::Archived), (2, mined(200)), (3, mined(100))] {
+            let mut transition = Transition::strict_dumb();
+            transition.contract_id = contract_id;
+            transition.nonce = no as u64;
+            transition
+                .inputs
+                .push(Input::with(Opout::new(ext_id, ty, 0)))
+                .unwrap();
+            let opid = transition.id();
+            let bundle = TransitionBundle {
+                close_method: CloseMethod::TapretFirst,
+                input_map: InputMap::with(Vout::from_u32(0), opid),
+                known_transitions: Confined::with((opid, transition)),
+            };
+            bundles.push((witness(no), bundle));
+            ords.insert(witness(no), ord);
+        }
+
+        let mut state = State::new(MemState::in_memory());
+        state
+            .replay_contract(
+                contract.schema(),
+                contract.genesis(),
+                [&extension],
+                bundles.iter().map(|(id, bundle)| (*id, bundle)),
+                KnownOrdResolver(ords),
+            )
+            .unwrap();
+        let contract_state = state.contract_state(contract_id).unwrap();
+        let witnesses = contract_state
+            .rights_all()
+            .filter(|assignment| assignment.opout.op == ext_id)
+            .map(|assignment| assignment.witness)
+            .collect::<Vec<_>>();
+        assert_eq!(witnesses, vec![Some(witness(3))]);
+    }
+
+    #[test]
+    fn test_conceal_outputs() {
+        let seal = |vout| {
+            XChain::Bitcoin(GraphSeal::new_random_vout(
+                bp::dbc::Method::TapretFirst,
+                Vout::from_u32(vout),
+            ))
+        };
+        let ty = AssignmentType::with(1);
+        let mut transition = Transition::strict_dumb();
+        transition.assignments = Assignments::from_inner(Confined::from_checked(bmap! {
+            ty => TypedAssigns::Declarative(Confined::from_checked(vec![
+                Assign::revealed(seal(0), VoidState::default()),
+                Assign::revealed(seal(1), VoidState::default()),
+            ]))
+        }));
+        let opid = transition.id();
+
+        conceal_outputs(opid, &mut transition.assignments, &bset![Opout::new(opid, ty, 1)]);
+        assert_eq!(transition.id(), opid);
+        let assigns = transition.assignments.get(&ty).unwrap();
+        assert_eq!(assigns.revealed_seal_at(0).unwrap(), None);
+        assert!(assigns.revealed_seal_at(1).unwrap().is_some());
+        assert!(matches!(assigns.as_declarative()[0], Assign::Confidential { .. }));
+        assert!(assigns.is_revealed_at(1));
+    }
+
+    #[test]
+    fn test_attach_id() {
+        let id = AttachId::from([7u8; 32]);
+        let seal = XChain::Bitcoin(GraphSeal::new_random_vout(
+            bp::dbc::Method::TapretFirst,
+            Vout::from_u32(0),
+        ));
+        let ty = AssignmentType::with(1);
+        let mut transition = Transition::strict_dumb();
+        transition.assignments = Assignments::from_inner(Confined::from_checked(bmap! {
+            ty => TypedAssigns::Attachment(Confined::from_checked(vec![
+                Assign::revealed(seal, RevealedAttach::new_random_salt(id, MediaType::Any)),
+            ]))
+        }));
+        let opid = transition.id();
+
+        assert_eq!(attach_id(&transition.assignments, Opout::new(opid, ty, 0)), Some(id));
+        assert_eq!(attach_id(&transition.assignments, Opout::new(opid, ty, 1)), None);
+        assert_eq!(
+            attach_id(&transition.assignments, Opout::new(opid, AssignmentType::with(2), 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_consign_extension_attachment() {
+        let mut fixture = Fixture::issue(1);
+        let spent = fixture.extend(None).id();
+        let attached = fixture.extend(Some(b"attachment")).id();
+        let (attach_id, attachment) = fixture.attachments.first_key_value().unwrap();
+        let (attach_id, attachment) = (*attach_id, attachment.clone());
+        let mut stock = Stock::in_memory();
+        stock
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+
+        // The first extension is an ancestor of the transferred state, while the second one
+        // assigns the transferred attachment
+        let transition = fixture.transfer(&[Opout::new(spent, OWNED, 0)], &[0]);
+        let fascia = fixture.witness(vec![transition.clone()], mined(100));
+        stock.consume_fascia(fascia, &fixture.chain).unwrap();
+        let outputs = [
+            fixture.seal(fixture.outputs(transition.id())[0]),
+            fixture.seal(Opout::new(attached, ATTACH, 0)),
+        ];
+        let transfer = stock
+            .transfer(fixture.contract_id(), outputs, None)
+            .unwrap();
+
+        assert_eq!(
+            transfer
+                .extensions
+                .iter()
+                .map(Extension::id)
+                .collect::<BTreeSet<_>>(),
+            bset![spent, attached]
+        );
+        assert_eq!(transfer.attachments.len(), 1);
+        assert_eq!(transfer.attachments.get(&attach_id), Some(&attachment));
+        assert_eq!(transfer.bundles.len(), 1);
+        transfer
+            .validate(&fixture.chain, &DumbValidator, true)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_update_witnesses_after_replacement() {
+        let mut fixture = Fixture::issue(1);
+        let mut stock = Stock::in_memory();
+        stock
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+        let transition = fixture.transfer(&[Opout::new(fixture.genesis.id(), OWNED, 0)], &[0]);
+        let fascia = fixture.witness(vec![transition.clone()], WitnessOrd::Tentative);
+        let replaced = fascia.witness_id();
+        stock
+            .consume_fascia(fascia.clone(), &fixture.chain)
+            .unwrap();
+
+        let replacement = fixture.replace(&fascia, mined(100));
+        let witness_id = replacement.witness_id();
+        stock
+            .replace_witness(replaced, replacement, &fixture.chain)
+            .unwrap();
+        assert_eq!(
+            stock
+                .as_stash_provider()
+                .witness_replacements()
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![(replaced, witness_id)]
+        );
+
+        // The resolver still reports the replaced witness as tentative
+        let res = stock.update_witnesses(&fixture.chain, 0).unwrap();
+        assert!(!res.updated.contains_key(&replaced));
+        let state = stock.as_state_provider();
+        assert_eq!(state.witness_ord(replaced).unwrap(), WitnessOrd::Archived);
+        assert_eq!(state.witness_ord(witness_id).unwrap(), mined(100));
+        let opout = fixture.outputs(transition.id())[0];
+        let contract = stock.contract_state(fixture.contract_id()).unwrap();
+        let allocation = contract.rights_all().find(|a| a.opout == opout).unwrap();
+        assert_eq!(allocation.seal, fixture.seal(opout));
+        assert_eq!(allocation.witness, Some(witness_id));
+
+        let mut stock = stock;
+        stock
+            .rebuild_state(MemState::in_memory(), &fixture.chain)
+            .unwrap();
+        assert_eq!(stock.as_state_provider().witness_ord(replaced).unwrap(), WitnessOrd::Archived);
+    }
+
+    #[test]
+    fn test_update_witnesses_reorg() {
+        let (mut stock, transfers) = transferred_stock();
+        let mut fixture = transfers.fixture;
+        let witness_id = transfers.witnesses[1];
+        let opout = fixture.outputs(transfers.next.id())[0];
+        let allocation = Allocation {
+            contract_id: fixture.contract_id(),
+            opout,
+            seal: fixture.seal(opout),
+        };
+
+        fixture.chain.set_ord(witness_id, WitnessOrd::Archived);
+        let res = stock.update_witnesses(&fixture.chain, 0).unwrap();
+        assert_eq!(res.updated.keys().collect::<Vec<_>>(), vec![&witness_id]);
+        assert_eq!(res.updated[&witness_id].to, WitnessOrd::Archived);
+        assert!(res.appeared.is_empty());
+        assert_eq!(res.disappeared, bset![allocation]);
+        let contract = stock.contract_state(fixture.contract_id()).unwrap();
+        assert!(!contract.rights_all().any(|a| a.opout == opout));
+
+        fixture.chain.set_ord(witness_id, mined(102));
+        let res = stock.update_witnesses(&fixture.chain, 0).unwrap();
+        assert_eq!(res.updated[&witness_id].from, WitnessOrd::Archived);
+        assert_eq!(res.appeared, bset![allocation]);
+        assert!(res.disappeared.is_empty());
+
+        // Witnesses mined below `after_height` are not resolved again
+        fixture.chain.set_ord(witness_id, WitnessOrd::Archived);
+        let res = stock.update_witnesses(&fixture.chain, 103).unwrap();
+        assert!(res.updated.is_empty());
+        assert!(res.disappeared.is_empty());
+    }
+
+    #[test]
+    fn test_transfer_conceals_off_path() {
+        let (stock, transfers) = transferred_stock();
+        let fixture = &transfers.fixture;
+        let (first, sibling, next) =
+            (transfers.first.id(), transfers.sibling.id(), transfers.next.id());
+        let output = fixture.outputs(next)[0];
+        let transfer = stock
+            .transfer(fixture.contract_id(), [fixture.seal(output)], None)
+            .unwrap();
+
+        let transitions = transfer
+            .bundles
+            .iter()
+            .flat_map(|wb| wb.anchored_bundles.bundles())
+            .flat_map(|bundle| bundle.known_transitions.iter())
+            .map(|(opid, transition)| (*opid, transition.clone()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(transitions.keys().copied().collect::<BTreeSet<_>>(), bset![
+            first, sibling, next
+        ]);
+        let first_outputs = transitions[&first].assignments.get(&OWNED).unwrap();
+        assert!(first_outputs.is_revealed_at(0));
+        assert!(matches!(first_outputs.as_declarative()[1], Assign::Confidential { .. }));
+        let sibling_outputs = transitions[&sibling].assignments.get(&OWNED).unwrap();
+        assert!(matches!(sibling_outputs.as_declarative()[0], Assign::Confidential { .. }));
+        assert!(transitions[&next]
+            .assignments
+            .get(&OWNED)
+            .unwrap()
+            .is_revealed_at(0));
+        let genesis_outputs = transfer.genesis.assignments.get(&OWNED).unwrap();
+        assert!(genesis_outputs.is_revealed_at(0));
+        assert!(genesis_outputs.is_revealed_at(1));
+        assert!(!genesis_outputs.is_revealed_at(2));
+
+        let transfer = transfer
+            .validate(&fixture.chain, &DumbValidator, true)
+            .unwrap();
+        assert!(transfer.validation_status().failures.is_empty());
+        let mut receiver = Stock::in_memory();
+        receiver.accept_transfer(transfer, &fixture.chain).unwrap();
+        assert!(receiver
+            .contract_state(fixture.contract_id())
+            .unwrap()
+            .rights_all()
+            .all(|a| a.opout.op != sibling));
+        assert!(receiver.check_consistency().unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_disclosure_round_trip() {
+        let (stock, transfers) = transferred_stock();
+        let fixture = &transfers.fixture;
+        let contract_id = fixture.contract_id();
+        let opout = fixture.outputs(transfers.first.id())[1];
+
+        let disclosure = stock.disclose(contract_id, [opout]).unwrap();
+        assert_eq!(disclosure.opouts.iter().copied().collect::<Vec<_>>(), vec![opout]);
+        let disclosure = disclosure
+            .validate(&fixture.schema, &fixture.chain, &DumbValidator)
+            .unwrap();
+
+        let mut receiver = Stock::in_memory();
+        receiver
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+        let disclosed = receiver
+            .inspect_disclosure(&disclosure, &fixture.chain)
+            .unwrap();
+
+        let revealed = disclosed
+            .rights_all()
+            .map(|a| (a.opout, a.seal, a.witness))
+            .collect::<Vec<_>>();
+        assert_eq!(revealed, vec![(opout, fixture.seal(opout), Some(transfers.witnesses[0]))]);
+        assert!(receiver
+            .contract_state(contract_id)
+            .unwrap()
+            .rights_all()
+            .all(|a| a.opout.op != transfers.first.id()));
+        assert!(receiver
+            .as_index_provider()
+            .bundle_id_for_op(transfers.first.id())
+            .is_err());
+        assert!(receiver.check_consistency().unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_contract_state_at() {
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let contract_id = contract.contract_id();
+        let witness = |no: u8| XChain::Bitcoin(bp::Txid::from_byte_array([no; 32]));
+        let mined = |height| {
+            WitnessOrd::Mined(
+                WitnessPos::bitcoin(NonZeroU32::new(height).unwrap(), 1_700_000_000).unwrap(),
+            )
+        };
+        let liquid = |height| {
+            WitnessOrd::Mined(
+                WitnessPos::liquid(NonZeroU32::new(height).unwrap(), 1_700_000_000).unwrap(),
+            )
+        };
+        let mut state = State::new(MemState::in_memory());
+        let mut writer = state
+            .as_provider_mut()
+            .register_contract(contract.schema(), contract.genesis())
+            .unwrap();
+        for (no, ord) in
+            [(1, mined(100)), (2, mined(200)), (3, WitnessOrd::Tentative), (4, liquid(150))]
+        {
+            let seal = XChain::Bitcoin(GraphSeal::new_random_vout(
+                bp::dbc::Method::TapretFirst,
+                Vout::from_u32(no as u32),
+            ));
+            let mut transition = Transition::strict_dumb();
+            transition.nonce = no as u64;
+            transition.assignments = Assignments::from_inner(Confined::from_checked(bmap! {
+                AssignmentType::with(1) => TypedAssigns::Declarative(
+                    Confined::from_checked(vec![Assign::revealed(seal, VoidState::default())])
+                )
+            }));
+            writer
+                .add_transition(&transition, witness(no), ord)
+                .unwrap();
+        }
+        drop(writer);
+
+        let rights = |state: MemContract<&MemContractState>| {
+            state
+                .rights_all()
+                .filter_map(|assignment| assignment.witness)
+                .collect::<BTreeSet<_>>()
+        };
+        let all = rights(state.contract_state(contract_id).unwrap());
+        assert_eq!(all, bset![witness(1), witness(2), witness(3), witness(4)]);
+        let at = |layer1, height| {
+            rights(
+                state
+                    .contract_state_at(contract_id, layer1, height)
+                    .unwrap(),
+            )
+        };
+        assert_eq!(at(Layer1::Bitcoin, 99), bset![]);
+        assert_eq!(at(Layer1::Bitcoin, 150), bset![witness(1)]);
+        assert_eq!(at(Layer1::Bitcoin, 200), bset![witness(1), witness(2)]);
+        assert_eq!(at(Layer1::Liquid, 149), bset![]);
+        assert_eq!(at(Layer1::Liquid, 200), bset![witness(4)]);
+    }
 }
@@ -0,0 +1,333 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Enforcement of the identity trust levels when importing kits and contracts.
+
+use std::collections::BTreeSet;
+use std::iter;
+
+use super::{IndexProvider, StashError, StashProvider, StateProvider, Stock, StockError};
+use crate::containers::{
+    Bip340Validator, Consignment, ContentId, ContentSigs, SigValidator, TrustLevel,
+};
+
+/// Policy of accepting schemata, interfaces and interface implementations
+/// depending on the [`TrustLevel`] of the identities which have signed them.
+///
+/// The signers are taken both from the imported container and the signatures
+/// already known to the stash. Only signatures which are valid BIP-340
+/// signatures of their identities are counted; identities unknown to the stash
+/// have the default trust level.
+///
+/// The default policy accepts any content; the enforcement must be enabled
+/// explicitly with [`Stock::set_trust_policy`], for instance using
+/// [`TrustPolicy::strict`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TrustPolicy {
+    /// Reject content which is signed only by malicious or untrusted
+    /// identities.
+    pub reject_distrusted: bool,
+    /// Require content used by contracts on production networks to be signed
+    /// by at least one trusted identity.
+    pub require_trusted_prod: bool,
+}
+
+impl Default for TrustPolicy {
+    fn default() -> Self { TrustPolicy::permissive() }
+}
+
+impl TrustPolicy {
+    /// Policy which accepts any content regardless of its signers.
+    pub const fn permissive() -> Self {
+        TrustPolicy {
+            reject_distrusted: false,
+            require_trusted_prod: false,
+        }
+    }
+
+    /// Policy which rejects distrusted content and requires content used by
+    /// production network contracts to be signed by a trusted identity.
+    pub const fn strict() -> Self {
+        TrustPolicy {
+            reject_distrusted: true,
+            require_trusted_prod: true,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TrustError {
+    /// {0} is signed only by malicious or untrusted identities.
+    Distrusted(ContentId),
+
+    /// {0} used by a production network contract is not signed by any trusted
+    /// identity.
+    NoTrustedSigner(ContentId),
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
+    /// Checks signers of the `content_id` against the stock [`TrustPolicy`].
+    ///
+    /// Signatures which are not valid according to the [`Bip340Validator`] are
+    /// ignored, such that a forged signature can't be used to claim the trust
+    /// level of its identity.
+    pub(super) fn check_trust(
+        &self,
+        content_id: ContentId,
+        sigs: Option<&ContentSigs>,
+        is_prod: bool,
+    ) -> Result<(), StockError<S, H, P, TrustError>> {
+        let policy = self.trust_policy();
+        let stash = self.as_stash_provider();
+        let known = stash
+            .sigs_for(&content_id)
+            .map_err(StashError::ReadProvider)?;
+        let mut levels = Vec::<TrustLevel>::new();
+        for (identity, sig) in sigs.into_iter().chain(known).flat_map(|sigs| sigs.iter()) {
+            if !Bip340Validator.validate_sig(content_id, identity, sig) {
+                continue;
+            }
+            levels.push(
+                stash
+                    .get_trust(identity)
+                    .map_err(StashError::ReadProvider)?,
+            );
+        }
+
+        if policy.reject_distrusted
+            && !levels.is_empty()
+            && levels.iter().all(|level| level.is_distrusted())
+        {
+            return Err(StockError::InvalidInput(TrustError::Distrusted(content_id)));
+        }
+        if is_prod && policy.require_trusted_prod && !levels.iter().any(|level| level.should_use())
+        {
+            return Err(StockError::InvalidInput(TrustError::NoTrustedSigner(content_id)));
+        }
+        Ok(())
+    }
+
+    /// Checks signers of the schema, interfaces and interface implementations
+    /// imported with a consignment. Contracts which are not issued on testnets
+    /// are treated as production network contracts.
+    pub(super) fn check_consignment_trust<const TRANSFER: bool>(
+        &self,
+        consignment: &Consignment<TRANSFER>,
+    ) -> Result<(), StockError<S, H, P, TrustError>> {
+        let is_prod = !consignment.genesis.testnet;
+        let content_ids = iter::once(ContentId::Schema(consignment.schema.schema_id())).chain(
+            consignment.ifaces.iter().flat_map(|(iface, iimpl)| {
+                [ContentId::Iface(iface.iface_id()), ContentId::IfaceImpl(iimpl.impl_id())]
+            }),
+        );
+        for content_id in content_ids {
+            self.check_trust(content_id, consignment.signatures.get(&content_id), is_prod)?;
+        }
+        Ok(())
+    }
+
+    /// Checks signers of the schemata, interfaces and interface
+    /// implementations known to another stash, which are going to be merged
+    /// into this stock. Content used by any production network contract of
+    /// the other stash is checked as production network content.
+    pub(super) fn check_stash_trust(
+        &self,
+        other: &S,
+    ) -> Result<(), StockError<S, H, P, TrustError>> {
+        let mut prod_schemata = BTreeSet::new();
+        for genesis in other.geneses().map_err(StashError::ReadProvider)? {
+            if !genesis.testnet {
+                prod_schemata.insert(genesis.schema_id);
+            }
+        }
+        let mut checked = BTreeSet::new();
+        for schema_ifaces in other.schemata().map_err(StashError::ReadProvider)? {
+            let schema_id = schema_ifaces.schema.schema_id();
+            let is_prod = prod_schemata.contains(&schema_id);
+            let content_ids = iter::once(ContentId::Schema(schema_id)).chain(
+                schema_ifaces.iimpls.values().flat_map(|iimpl| {
+                    [ContentId::Iface(iimpl.iface_id), ContentId::IfaceImpl(iimpl.impl_id())]
+                }),
+            );
+            for content_id in content_ids {
+                let sigs = other
+                    .sigs_for(&content_id)
+                    .map_err(StashError::ReadProvider)?;
+                self.check_trust(content_id, sigs, is_prod)?;
+                checked.insert(content_id);
+            }
+        }
+        for iface in other.ifaces().map_err(StashError::ReadProvider)? {
+            let content_id = ContentId::Iface(iface.iface_id());
+            if !checked.contains(&content_id) {
+                let sigs = other
+                    .sigs_for(&content_id)
+                    .map_err(StashError::ReadProvider)?;
+                self.check_trust(content_id, sigs, false)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use amplify::confinement::NonEmptyOrdMap;
+    use bp::secp256k1::{Keypair, SecretKey, SECP256K1};
+    use rgb::{Operation, Opout};
+
+    use super::*;
+    use crate::containers::{
+        bip340_identity, Bip340Validator, Contract, DumbValidator, Kit, SigBlob,
+    };
+    use crate::persistence::fixture::{Fixture, OWNED};
+    use crate::persistence::StashWriteProvider;
+
+    #[test]
+    fn test_trust_policy() {
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let content_id = ContentId::Schema(contract.schema.schema_id());
+        let keypair =
+            Keypair::from_secret_key(SECP256K1, &SecretKey::from_slice(&[1u8; 32]).unwrap());
+        let identity = bip340_identity(keypair.x_only_public_key().0);
+        let mut kit = Kit::default();
+        kit.schemata.push(contract.schema.clone()).unwrap();
+        kit.signatures
+            .insert(
+                content_id,
+                ContentSigs::from(NonEmptyOrdMap::with_key_value(
+                    identity.clone(),
+                    SigBlob::bip340(content_id, &keypair),
+                )),
+            )
+            .unwrap();
+        let kit = kit.validate(&Bip340Validator).unwrap();
+
+        let mut stock = Stock::in_memory();
+        stock.set_trust_policy(TrustPolicy::strict());
+        stock
+            .as_stash_provider_mut()
+            .set_trust(identity.clone(), TrustLevel::Untrusted)
+            .unwrap();
+        assert!(matches!(
+            stock.import_kit(kit.clone()),
+            Err(StockError::InvalidInput(TrustError::Distrusted(id))) if id == content_id
+        ));
+        assert!(matches!(
+            stock.check_trust(content_id, None, true),
+            Err(StockError::InvalidInput(TrustError::NoTrustedSigner(id))) if id == content_id
+        ));
+
+        stock.set_trust_policy(TrustPolicy::permissive());
+        assert!(stock.check_trust(content_id, None, true).is_ok());
+        stock.set_trust_policy(TrustPolicy::strict());
+
+        stock
+            .as_stash_provider_mut()
+            .set_trust(identity, TrustLevel::Trusted)
+            .unwrap();
+        stock.import_kit(kit).unwrap();
+        assert!(stock.check_trust(content_id, None, true).is_ok());
+    }
+
+    #[test]
+    fn test_forged_sigs_are_ignored() {
+        let contract =
+            Contract::from_str(include_str!("../../asset/armored_contract.default")).unwrap();
+        let content_id = ContentId::Schema(contract.schema.schema_id());
+        let keypair =
+            Keypair::from_secret_key(SECP256K1, &SecretKey::from_slice(&[1u8; 32]).unwrap());
+        let forger =
+            Keypair::from_secret_key(SECP256K1, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+        let identity = bip340_identity(keypair.x_only_public_key().0);
+        let forged = ContentSigs::from(NonEmptyOrdMap::with_key_value(
+            identity.clone(),
+            SigBlob::bip340(content_id, &forger),
+        ));
+
+        let mut stock = Stock::in_memory();
+        stock.set_trust_policy(TrustPolicy::strict());
+        stock
+            .as_stash_provider_mut()
+            .set_trust(identity.clone(), TrustLevel::Trusted)
+            .unwrap();
+        stock
+            .as_stash_provider_mut()
+            .import_sigs(content_id, forged.clone())
+            .unwrap();
+        assert!(matches!(
+            stock.check_trust(content_id, Some(&forged), true),
+            Err(StockError::InvalidInput(TrustError::NoTrustedSigner(id))) if id == content_id
+        ));
+
+        let valid = ContentSigs::from(NonEmptyOrdMap::with_key_value(
+            identity,
+            SigBlob::bip340(content_id, &keypair),
+        ));
+        assert!(stock.check_trust(content_id, Some(&valid), true).is_ok());
+    }
+
+    #[test]
+    fn test_default_policy_is_permissive() {
+        let fixture = Fixture::issue_on(1, false);
+        let mut stock = Stock::in_memory();
+        assert_eq!(stock.trust_policy(), TrustPolicy::permissive());
+        stock
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transfer_trust() {
+        let fixture = Fixture::issue_on(1, false);
+        let schema_id = ContentId::Schema(fixture.schema.schema_id());
+        let mut sender = Stock::in_memory();
+        sender
+            .import_contract(fixture.contract(), &fixture.chain)
+            .unwrap();
+        let genesis_id = fixture.genesis.id();
+        let transfer = sender
+            .transfer(fixture.contract_id(), [fixture.seal(Opout::new(genesis_id, OWNED, 0))], None)
+            .unwrap()
+            .validate(&fixture.chain, &DumbValidator, false)
+            .unwrap();
+
+        let mut receiver = Stock::in_memory();
+        receiver.set_trust_policy(TrustPolicy::strict());
+        assert!(matches!(
+            receiver.accept_transfer(transfer.clone(), &fixture.chain),
+            Err(StockError::InvalidInput(TrustError::NoTrustedSigner(id))) if id == schema_id
+        ));
+        assert!(matches!(
+            receiver.merge(&sender, &fixture.chain),
+            Err(StockError::InvalidInput(TrustError::NoTrustedSigner(id))) if id == schema_id
+        ));
+        assert!(receiver.contracts().unwrap().next().is_none());
+
+        receiver.set_trust_policy(TrustPolicy::permissive());
+        receiver.accept_transfer(transfer, &fixture.chain).unwrap();
+        assert!(receiver.merge(&sender, &fixture.chain).is_ok());
+    }
+}
@@ -33,7 +33,7 @@ use super::{
     AssetSpec, BurnMeta, ContractSpec, ContractTerms, Error, IssueMeta, MediaType,
     LIB_NAME_RGB_CONTRACT, LIB_NAME_RGB_STORAGE,
 };
-use crate::containers::{Contract, Kit, Transfer};
+use crate::containers::{Contract, Disclosure, Kit, Transfer};
 use crate::persistence::{MemIndex, MemStash, MemState};
 use crate::stl::ProofOfReserves;
 use crate::LIB_NAME_RGB_STD;
@@ -41,7 +41,7 @@ use crate::LIB_NAME_RGB_STD;
 /// Strict types id for the library providing standard data types which may be
 /// used in RGB smart contracts.
 pub const LIB_ID_RGB_STORAGE: &str =
-    "stl:mG$H7b6I-$T8qp18-07PSNeA-rbEBNS5-$J5X4y0-1vPxRWg#channel-vortex-bandit";
+    "stl:orR4HjtT-csPF9o5-ycb0nJl-F3NFP8D-xtMWPvk-4rfgvtg#cricket-final-good";
 
 /// Strict types id for the library providing standard data types which may be
 /// used in RGB smart contracts.
@@ -50,8 +50,9 @@ pub const LIB_ID_RGB_CONTRACT: &str =
 
 /// Strict types id for the library representing of RGB StdLib data types.
 pub const LIB_ID_RGB_STD: &str =
-    "stl:JhUC5JgH-Kwps4cO-ZNUklUj-UP6boFp-OY!18Kx-xOSJaVQ#hair-magnum-helena";
+    "stl:Ajj0Ubnu-YxMz2i!-cOikBMi-HQ44Hwd-YAH3VSj-1InWfRk#shave-mango-canyon";
 
+#[allow(clippy::result_large_err)]
 fn _rgb_std_stl() -> Result<TypeLib, CompileError> {
     LibBuilder::new(libname!(LIB_NAME_RGB_STD), tiny_bset! {
         std_stl().to_dependency(),
@@ -66,9 +67,11 @@ fn _rgb_std_stl() -> Result<TypeLib, CompileError> {
     .transpile::<Transfer>()
     .transpile::<Contract>()
     .transpile::<Kit>()
+    .transpile::<Disclosure>()
     .compile()
 }
 
+#[allow(clippy::result_large_err)]
 fn _rgb_contract_stl() -> Result<TypeLib, CompileError> {
     LibBuilder::new(libname!(LIB_NAME_RGB_CONTRACT), tiny_bset! {
         std_stl().to_dependency(),
@@ -86,6 +89,7 @@ fn _rgb_contract_stl() -> Result<TypeLib, CompileError> {
     .compile()
 }
 
+#[allow(clippy::result_large_err)]
 fn _rgb_storage_stl() -> Result<TypeLib, CompileError> {
     LibBuilder::new(libname!(LIB_NAME_RGB_STORAGE), tiny_bset! {
         std_stl().to_dependency(),